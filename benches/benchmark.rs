@@ -5,6 +5,8 @@ extern crate protobuf;
 
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate prost_derive;
 
 use std::fs::File;
 use std::io::Read;
@@ -13,6 +15,7 @@ use std::result;
 use criterion::{
     Benchmark,
     Criterion,
+    ParameterizedBenchmark,
     Throughput,
 };
 use prost::Message;
@@ -72,9 +75,62 @@ where M: prost::Message + Default + 'static {
     Ok(())
 }
 
+/// A recursive message, for benchmarking the encoding of deeply nested messages.
+#[derive(Clone, PartialEq, Message)]
+struct Node {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(int64, repeated, tag="2")]
+    values: Vec<i64>,
+    #[prost(message, optional, boxed, tag="3")]
+    child: Option<Box<Node>>,
+}
+
+/// Returns a chain of nested messages of the given depth.
+fn nested_node(depth: usize) -> Node {
+    let mut node = None;
+    for i in 0..depth {
+        node = Some(Box::new(Node {
+            name: format!("node {}", i),
+            values: vec![i as i64; 4],
+            child: node,
+        }));
+    }
+    *node.unwrap()
+}
+
+/// Benchmarks encoding a chain of nested messages of increasing depth. Encoding time should grow
+/// linearly with the depth.
+fn benchmark_nested(criterion: &mut Criterion) {
+    let encode = ParameterizedBenchmark::new("encode", |b, &depth| {
+        let node = nested_node(depth);
+        let mut buf = Vec::with_capacity(node.encoded_len());
+        b.iter(|| {
+            buf.clear();
+            node.encode(&mut buf).unwrap();
+            criterion::black_box(&buf);
+        })
+    }, vec![10, 100, 1000])
+    .with_function("encode_field", |b, &depth| {
+        // Encodes the chain as a field of a hand-written message, which goes through
+        // `encoding::message::encode` rather than `Message::encode`.
+        let node = nested_node(depth);
+        let mut buf = Vec::with_capacity(node.encoded_len() + 16);
+        b.iter(|| {
+            buf.clear();
+            prost::encoding::message::encode(1, &node, &mut buf);
+            criterion::black_box(&buf);
+        })
+    });
+
+    criterion.bench("nested", encode);
+}
+
 fn main() -> Result {
     let mut criterion = Criterion::default().configure_from_args();
 
+    benchmark_nested(&mut criterion);
+
     for dataset in protobuf::benchmarks::datasets() {
         let dataset = {
             let mut f = File::open(dataset)?;
@@ -1,6 +1,7 @@
 extern crate bytes;
 extern crate env_logger;
 extern crate prost;
+extern crate prost_types;
 extern crate protobuf;
 extern crate tests;
 
@@ -15,12 +16,21 @@ use bytes::{
     LittleEndian,
 };
 use prost::Message;
+use prost_types::json::{
+    JsonMessage,
+    JsonOptions,
+};
+use prost_types::text_format::{
+    TextFormatMessage,
+    TextFormatOptions,
+};
 
 use protobuf::test_messages::proto2::TestAllTypesProto2;
 use protobuf::test_messages::proto3::TestAllTypesProto3;
 use protobuf::conformance::{
     ConformanceRequest,
     ConformanceResponse,
+    TestCategory,
     WireFormat,
     conformance_request,
     conformance_response,
@@ -69,31 +79,94 @@ fn main() {
 }
 
 fn handle_request(request: ConformanceRequest) -> conformance_response::Result {
-    match request.requested_output_format() {
+    let output_format = match request.requested_output_format() {
         WireFormat::Unspecified => {
             return conformance_response::Result::ParseError("output format unspecified".to_string());
         },
-        WireFormat::Json => {
-            return conformance_response::Result::Skipped("JSON output is not supported".to_string());
+        WireFormat::Jspb => {
+            return conformance_response::Result::Skipped("JSPB output is not supported".to_string());
         },
-        WireFormat::Protobuf => (),
+        output_format => output_format,
     };
 
-    let buf = match request.payload {
+    let mut json_options = JsonOptions::new();
+    json_options.ignore_unknown_fields(request.test_category() == TestCategory::JsonIgnoreUnknownParsingTest);
+    let mut text_format_options = TextFormatOptions::new();
+    text_format_options.print_unknown_fields(request.print_unknown_fields);
+
+    let payload = match request.payload {
         None => return conformance_response::Result::ParseError("no payload".to_string()),
-        Some(conformance_request::Payload::JsonPayload(_)) =>
-            return conformance_response::Result::Skipped("JSON input is not supported".to_string()),
-        Some(conformance_request::Payload::ProtobufPayload(buf)) => buf,
+        Some(conformance_request::Payload::JspbPayload(_)) => {
+            return conformance_response::Result::Skipped("JSPB input is not supported".to_string());
+        },
+        Some(payload) => payload,
     };
 
-    let roundtrip = match &*request.message_type {
-        "protobuf_test_messages.proto2.TestAllTypesProto2" => roundtrip::<TestAllTypesProto2>(&buf),
-        "protobuf_test_messages.proto3.TestAllTypesProto3" => roundtrip::<TestAllTypesProto3>(&buf),
-         _ => return conformance_response::Result::ParseError(
-             format!("unknown message type: {}", request.message_type)),
+    match &*request.message_type {
+        // The test runner asks for the list of tests which are expected to fail; prost maintains
+        // its list in failing_tests.txt instead, so reply with an empty `FailureSet`.
+        "conformance.FailureSet" => conformance_response::Result::ProtobufPayload(Vec::new()),
+        "protobuf_test_messages.proto2.TestAllTypesProto2" => {
+            handle::<TestAllTypesProto2>(payload, output_format, &json_options, &text_format_options)
+        },
+        "protobuf_test_messages.proto3.TestAllTypesProto3" => {
+            handle::<TestAllTypesProto3>(payload, output_format, &json_options, &text_format_options)
+        },
+        _ => conformance_response::Result::ParseError(
+            format!("unknown message type: {}", request.message_type)),
+    }
+}
+
+fn handle<M>(payload: conformance_request::Payload,
+             output_format: WireFormat,
+             json_options: &JsonOptions,
+             text_format_options: &TextFormatOptions) -> conformance_response::Result
+where M: JsonMessage + TextFormatMessage + Default {
+    let message = match payload {
+        conformance_request::Payload::ProtobufPayload(buf) => {
+            if output_format == WireFormat::Protobuf {
+                return protobuf_roundtrip::<M>(&buf);
+            }
+            match M::decode(&buf) {
+                Ok(message) => message,
+                Err(error) => return conformance_response::Result::ParseError(error.to_string()),
+            }
+        },
+        conformance_request::Payload::JsonPayload(json) => {
+            match M::from_json_with_options(&json, json_options) {
+                Ok(message) => message,
+                Err(error) => return conformance_response::Result::ParseError(error.to_string()),
+            }
+        },
+        conformance_request::Payload::TextPayload(text) => {
+            match M::from_text_format(&text) {
+                Ok(message) => message,
+                Err(error) => return conformance_response::Result::ParseError(error.to_string()),
+            }
+        },
+        conformance_request::Payload::JspbPayload(_) => unreachable!(),
     };
 
-    match roundtrip {
+    match output_format {
+        WireFormat::Json => match message.to_json() {
+            Ok(json) => conformance_response::Result::JsonPayload(json),
+            Err(error) => conformance_response::Result::SerializeError(error.to_string()),
+        },
+        WireFormat::TextFormat => {
+            conformance_response::Result::TextPayload(message.to_text_format_with_options(text_format_options))
+        },
+        _ => {
+            let mut buf = Vec::with_capacity(message.encoded_len());
+            match message.encode(&mut buf) {
+                Ok(()) => conformance_response::Result::ProtobufPayload(buf),
+                Err(error) => conformance_response::Result::SerializeError(error.to_string()),
+            }
+        },
+    }
+}
+
+fn protobuf_roundtrip<M>(buf: &[u8]) -> conformance_response::Result where M: Message + Default {
+    match roundtrip::<M>(buf) {
         RoundtripResult::Ok(buf) => {
             conformance_response::Result::ProtobufPayload(buf)
         },
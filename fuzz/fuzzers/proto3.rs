@@ -1,12 +1,36 @@
 #![no_main]
 
 #[macro_use] extern crate libfuzzer_sys;
+extern crate prost;
 extern crate protobuf;
 extern crate tests;
 
+use prost::encoding::{encode_key, encode_varint, WireType};
 use protobuf::test_messages::proto3::TestAllTypesProto3;
 use tests::roundtrip;
 
+/// The tag of the `TestAllTypesProto3.recursive_message` field.
+const RECURSIVE_MESSAGE_TAG: u32 = 27;
+
+/// Wraps the encoded message in `depth` levels of `recursive_message` fields.
+fn nest(data: &[u8], depth: usize) -> Vec<u8> {
+    let mut buf = data.to_vec();
+    for _ in 0..depth {
+        let mut nested = Vec::with_capacity(buf.len() + 12);
+        encode_key(RECURSIVE_MESSAGE_TAG, WireType::LengthDelimited, &mut nested);
+        encode_varint(buf.len() as u64, &mut nested);
+        nested.extend_from_slice(&buf);
+        buf = nested;
+    }
+    buf
+}
+
 fuzz_target!(|data: &[u8]| {
     let _ = roundtrip::<TestAllTypesProto3>(data).unwrap_error();
+
+    // Use the first byte of the input as a nesting depth, and wrap the remaining input in that
+    // many nested messages in order to exercise the recursion limit.
+    if let Some((&depth, data)) = data.split_first() {
+        let _ = roundtrip::<TestAllTypesProto3>(&nest(data, depth as usize)).unwrap_error();
+    }
 });
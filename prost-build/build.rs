@@ -7,7 +7,9 @@
 //!     2. The bundled `protoc`.
 //!     3. The `protoc` on the `PATH`.
 //!
-//! If no `protoc` binary is available in these locations, the build fails.
+//! If no `protoc` binary is available in these locations, a build warning is emitted and `protoc`
+//! is expected to be on the `PATH` when it is used. It is only needed when `prost-build` is
+//! configured to use `protoc` instead of its built-in compiler.
 //!
 //! The following locations are checked for the Protobuf include directory in decreasing priority:
 //!
@@ -49,7 +51,12 @@ fn bundled_protoc() -> Option<PathBuf> {
         _ => return None,
     };
 
-    Some(bundle_path().join(protoc_bin_name))
+    let protoc = bundle_path().join(protoc_bin_name);
+    if protoc.exists() {
+        Some(protoc)
+    } else {
+        None
+    }
 }
 
 /// Returns the path to the `protoc` included on the `PATH`, if it exists.
@@ -83,9 +90,14 @@ fn bundled_protoc_include() -> PathBuf {
 }
 
 fn main() {
-    let protoc = env_protoc().or_else(bundled_protoc).or_else(path_protoc).expect(
-        "Failed to find the protoc binary. The PROTOC environment variable is not set, \
-        there is no bundled protoc for this platform, and protoc is not in the PATH");
+    let protoc = env_protoc().or_else(bundled_protoc)
+                             .or_else(path_protoc)
+                             .unwrap_or_else(|| {
+                                 println!("cargo:warning=no protoc binary found; set the PROTOC \
+                                           environment variable to use protoc instead of the \
+                                           built-in compiler");
+                                 PathBuf::from("protoc")
+                             });
 
     let protoc_include = env_protoc_include().unwrap_or_else(bundled_protoc_include);
 
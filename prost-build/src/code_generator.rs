@@ -4,12 +4,14 @@ use std::collections::{HashMap, HashSet};
 
 use itertools::{Either, Itertools};
 use multimap::MultiMap;
+use prost::Message;
 use prost_types::{
     DescriptorProto,
     EnumDescriptorProto,
     EnumValueDescriptorProto,
     FieldDescriptorProto,
     FileDescriptorProto,
+    FileDescriptorSet,
     OneofDescriptorProto,
     ServiceDescriptorProto,
     SourceCodeInfo,
@@ -39,6 +41,185 @@ pub fn module(file: &FileDescriptorProto) -> Module {
         .collect()
 }
 
+/// Returns the fully qualified names of the messages which should be generated as borrowed
+/// messages with a lifetime parameter.
+///
+/// A message is borrowed if it is matched by the `borrowed` configuration and has a `string` or
+/// `bytes` field, or if it contains a borrowed message field. Messages which are used as groups or
+/// extension values, messages which declare extension ranges, and the messages they contain are
+/// never borrowed.
+pub fn borrowed_messages(config: &Config, files: &[FileDescriptorProto]) -> HashSet<String> {
+    fn collect<'a>(package: &str,
+                   messages: &'a [DescriptorProto],
+                   all: &mut Vec<(String, &'a DescriptorProto)>,
+                   extensions: &mut Vec<&'a FieldDescriptorProto>) {
+        for message in messages {
+            let fq_message_name = format!("{}.{}", package, message.name());
+            collect(&fq_message_name, &message.nested_type, all, extensions);
+            extensions.extend(&message.extension);
+            if !message.options.as_ref().and_then(|options| options.map_entry).unwrap_or(false) {
+                all.push((fq_message_name, message));
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+    let mut extensions = Vec::new();
+    for file in files {
+        collect(&format!(".{}", file.package()), &file.message_type, &mut messages, &mut extensions);
+        extensions.extend(&file.extension);
+    }
+
+    let is_message = |field: &FieldDescriptorProto| match field.type_() {
+        Type::Message | Type::Group => true,
+        _ => false,
+    };
+
+    // Group and extension value types are decoded as `Message`s, and extendable messages must be
+    // `'static` to be registered, so these messages are always owned.
+    let mut owned = HashSet::new();
+    for &(ref name, message) in &messages {
+        if !message.extension_range.is_empty() {
+            owned.insert(name.clone());
+        }
+        for field in message.field.iter().filter(|field| field.type_() == Type::Group) {
+            owned.insert(field.type_name().to_string());
+        }
+    }
+    for extension in extensions.iter().filter(|field| is_message(field)) {
+        owned.insert(extension.type_name().to_string());
+    }
+
+    // Propagate to the messages contained in owned messages until a fixed point is reached.
+    loop {
+        let len = owned.len();
+        for &(ref name, message) in &messages {
+            if owned.contains(name) {
+                for field in message.field.iter().filter(|field| is_message(field)) {
+                    owned.insert(field.type_name().to_string());
+                }
+            }
+        }
+        if owned.len() == len {
+            break;
+        }
+    }
+
+    // Known types are not generated, so they can't be borrowed.
+    messages.retain(|&(ref name, _)| {
+        !config.mapped_types.contains_key(name)
+            && resolve_extern_path(config, name).is_none()
+            && !(config.prost_types && name.starts_with(".google.protobuf."))
+            && !owned.contains(name)
+    });
+
+    let mut borrowed = messages.iter()
+                               .filter(|&&(ref name, message)| {
+                                   config.borrowed.iter().any(|matcher| match_ident(matcher, name, None))
+                                       && message.field.iter().any(|field| match field.type_() {
+                                           Type::String | Type::Bytes => true,
+                                           _ => false,
+                                       })
+                               })
+                               .map(|&(ref name, _)| name.clone())
+                               .collect::<HashSet<_>>();
+
+    // Propagate to messages containing borrowed messages until a fixed point is reached.
+    loop {
+        let len = borrowed.len();
+        for &(ref name, message) in &messages {
+            if message.field.iter().any(|field| field.type_() == Type::Message
+                                                && borrowed.contains(field.type_name())) {
+                borrowed.insert(name.clone());
+            }
+        }
+        if borrowed.len() == len {
+            return borrowed;
+        }
+    }
+}
+
+/// Returns the Rust path of a fully-qualified Protobuf type which is covered by an extern path, or
+/// `None` if the type is not covered by any extern path.
+///
+/// The longest matching extern path wins. Packages and messages below the extern path are
+/// appended to its Rust path as modules.
+pub fn resolve_extern_path(config: &Config, pb_ident: &str) -> Option<String> {
+    config.extern_paths
+          .iter()
+          .filter(|&&(ref proto_path, _)| {
+              pb_ident == proto_path
+                  || (pb_ident.starts_with(proto_path.as_str())
+                      && pb_ident[proto_path.len()..].starts_with('.'))
+          })
+          .max_by_key(|&&(ref proto_path, _)| proto_path.len())
+          .map(|&(ref proto_path, ref rust_path)| {
+              let relative_path = &pb_ident[proto_path.len()..];
+              if relative_path.is_empty() {
+                  return rust_path.clone();
+              }
+
+              let mut ident_path = relative_path[1..].split('.');
+              let ident_type = ident_path.next_back().unwrap();
+              Some(rust_path.clone()).into_iter()
+                                     .chain(ident_path.map(to_snake))
+                                     .chain(Some(to_upper_camel(ident_type)))
+                                     .join("::")
+          })
+}
+
+/// Appends a `FILE_DESCRIPTOR_SET` static containing the encoded descriptors of the named files
+/// and their transitive dependencies, used by the `ReflectMessage` implementations of a module.
+pub fn append_file_descriptor_set(files: &[FileDescriptorProto], names: &[String], buf: &mut String) {
+    let mut included = HashSet::new();
+    let mut stack = names.to_vec();
+    while let Some(name) = stack.pop() {
+        if let Some(file) = files.iter().find(|file| file.name() == name) {
+            stack.extend(file.dependency.iter().cloned());
+        }
+        included.insert(name);
+    }
+
+    let set = FileDescriptorSet {
+        file: files.iter().filter(|file| included.contains(file.name())).cloned().collect(),
+    };
+    let mut bytes = Vec::with_capacity(set.encoded_len());
+    set.encode(&mut bytes).unwrap();
+
+    buf.push_str("/// The encoded `FileDescriptorSet` of the `.proto` files in this module and their dependencies.\n");
+    buf.push_str("pub static FILE_DESCRIPTOR_SET: &'static [u8] = &[\n");
+    append_bytes(&bytes, buf);
+    buf.push_str("];\n");
+}
+
+/// Appends a constant containing the encoded descriptor of a file, without source code info.
+///
+/// The constant is named after the file's path, so `foo/bar_baz.proto` is embedded as
+/// `FOO_BAR_BAZ_FILE_DESCRIPTOR`.
+pub fn append_file_descriptor(file: &FileDescriptorProto, buf: &mut String) {
+    let mut file = file.clone();
+    file.source_code_info = None;
+    let mut bytes = Vec::with_capacity(file.encoded_len());
+    file.encode(&mut bytes).unwrap();
+
+    let name = file.name().trim_end_matches(".proto").replace('/', "_");
+    buf.push_str(&format!("/// The encoded `FileDescriptorProto` of `{}`.\n", file.name()));
+    buf.push_str(&format!("pub const {}_FILE_DESCRIPTOR: &'static [u8] = &[\n",
+                          to_snake(&name).to_uppercase()));
+    append_bytes(&bytes, buf);
+    buf.push_str("];\n");
+}
+
+fn append_bytes(bytes: &[u8], buf: &mut String) {
+    for chunk in bytes.chunks(20) {
+        buf.push_str("   ");
+        for byte in chunk {
+            buf.push_str(&format!(" {},", byte));
+        }
+        buf.push('\n');
+    }
+}
+
 #[derive(PartialEq)]
 enum Syntax {
     Proto2,
@@ -51,18 +232,24 @@ pub struct CodeGenerator<'a> {
     source_info: SourceCodeInfo,
     syntax: Syntax,
     message_graph: &'a MessageGraph,
+    borrowed_messages: &'a HashSet<String>,
     depth: u8,
     path: Vec<i32>,
     buf: &'a mut String,
+    reflected: bool,
 }
 
 impl <'a> CodeGenerator<'a> {
+    /// Generates the code for a file, and returns `true` if it contains reflected messages.
     pub fn generate(config: &mut Config,
                     message_graph: &MessageGraph,
+                    borrowed_messages: &HashSet<String>,
                     file: FileDescriptorProto,
-                    buf: &mut String) {
+                    buf: &mut String) -> bool {
 
-        let mut source_info = file.source_code_info.expect("no source code info in request");
+        // Descriptor sets may be written without source code info, in which case the code is
+        // generated without doc comments.
+        let mut source_info = file.source_code_info.unwrap_or_default();
         source_info.location.retain(|location| {
             let len = location.path.len();
             len > 0 && len % 2 == 0
@@ -81,9 +268,11 @@ impl <'a> CodeGenerator<'a> {
             source_info: source_info,
             syntax: syntax,
             message_graph: message_graph,
+            borrowed_messages: borrowed_messages,
             depth: 0,
             path: Vec::new(),
             buf: buf,
+            reflected: false,
         };
 
         debug!("file: {:?}, package: {:?}", file.name.as_ref().unwrap(), code_gen.package);
@@ -104,6 +293,14 @@ impl <'a> CodeGenerator<'a> {
         }
         code_gen.path.pop();
 
+        code_gen.path.push(7);
+        for (idx, extension) in file.extension.into_iter().enumerate() {
+            code_gen.path.push(idx as i32);
+            code_gen.append_extension(extension);
+            code_gen.path.pop();
+        }
+        code_gen.path.pop();
+
         if code_gen.config.service_generator.is_some() {
             code_gen.path.push(6);
             for (idx, service) in file.service.into_iter().enumerate() {
@@ -119,6 +316,8 @@ impl <'a> CodeGenerator<'a> {
 
             code_gen.path.pop();
         }
+
+        code_gen.reflected
     }
 
     fn append_message(&mut self, message: DescriptorProto) {
@@ -172,6 +371,9 @@ impl <'a> CodeGenerator<'a> {
         self.push_indent();
         self.buf.push_str("pub struct ");
         self.buf.push_str(&to_upper_camel(&message_name));
+        if self.borrowed_messages.contains(&fq_message_name) {
+            self.buf.push_str("<'a>");
+        }
         self.buf.push_str(" {\n");
 
         self.depth += 1;
@@ -198,11 +400,49 @@ impl <'a> CodeGenerator<'a> {
         }
         self.path.pop();
 
+        if !message.extension_range.is_empty() {
+            self.push_indent();
+            self.buf.push_str("#[prost(extension_set)]\n");
+            self.push_indent();
+            self.buf.push_str("pub extension_set: ::prost::ExtensionSet,\n");
+        }
+
+        if self.config.preserve_unknown_fields.iter().any(|matcher| match_ident(matcher, &fq_message_name, None)) {
+            self.push_indent();
+            self.buf.push_str("#[prost(unknown_fields)]\n");
+            self.push_indent();
+            self.buf.push_str("pub unknown_fields: ::prost::UnknownFields,\n");
+        }
+
         self.depth -= 1;
         self.push_indent();
         self.buf.push_str("}\n");
 
-        if !message.enum_type.is_empty() || !nested_types.is_empty() || !oneof_fields.is_empty() {
+        if !self.borrowed_messages.contains(&fq_message_name) {
+            let matches = |matchers: &[String]| {
+                matchers.iter().any(|matcher| match_ident(matcher, &fq_message_name, None))
+            };
+            let json = matches(&self.config.json);
+            let text_format = matches(&self.config.text_format);
+            if json || text_format || matches(&self.config.reflect) {
+                self.append_reflect_impl(&message_name, &fq_message_name);
+            }
+            if json {
+                self.push_indent();
+                self.buf.push_str(&format!("impl ::prost_types::json::JsonMessage for {} {{}}\n",
+                                           to_upper_camel(&message_name)));
+            }
+            if text_format {
+                self.push_indent();
+                self.buf.push_str(&format!("impl ::prost_types::text_format::TextFormatMessage for {} {{}}\n",
+                                           to_upper_camel(&message_name)));
+            }
+        }
+
+        if !message.enum_type.is_empty()
+            || !nested_types.is_empty()
+            || !oneof_fields.is_empty()
+            || !message.extension.is_empty() {
             self.push_mod(&message_name);
             self.path.push(3);
             for (nested_type, idx) in nested_types {
@@ -225,10 +465,54 @@ impl <'a> CodeGenerator<'a> {
                 self.append_oneof(&fq_message_name, oneof, idx, oneof_fields.remove(&idx).unwrap());
             }
 
+            self.path.push(6);
+            for (idx, extension) in message.extension.into_iter().enumerate() {
+                self.path.push(idx as i32);
+                self.append_extension(extension);
+                self.path.pop();
+            }
+            self.path.pop();
+
             self.pop_mod();
         }
     }
 
+    fn append_reflect_impl(&mut self, message_name: &str, fq_message_name: &str) {
+        let full_name = &fq_message_name[1..];
+        let file_descriptor_set = format!("{}FILE_DESCRIPTOR_SET", "super::".repeat(self.depth as usize));
+        self.reflected = true;
+
+        self.push_indent();
+        self.buf.push_str(&format!("impl ::prost_types::reflect::ReflectMessage for {} {{\n",
+                                   to_upper_camel(message_name)));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str("fn full_name(&self) -> &'static str {\n");
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("\"{}\"\n", full_name));
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+        self.push_indent();
+        self.buf.push_str("fn descriptor(&self) -> ::prost_types::descriptor::MessageDescriptor {\n");
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("::prost_types::descriptor::DescriptorPool::decode_static({})\n",
+                                   file_descriptor_set));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!(".get_message_by_name(\"{}\")\n", full_name));
+        self.push_indent();
+        self.buf.push_str(".unwrap()\n");
+        self.depth -= 2;
+        self.push_indent();
+        self.buf.push_str("}\n");
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+    }
+
     fn append_type_attributes(&mut self, msg_name: &str) {
         assert_eq!(b'.', msg_name.as_bytes()[0]);
         // TODO: this clone is dirty, but expedious.
@@ -254,16 +538,20 @@ impl <'a> CodeGenerator<'a> {
     }
 
     fn append_field(&mut self, msg_name: &str, field: FieldDescriptorProto) {
-        // TODO(danburkert/prost#19): support groups.
         let type_ = field.type_();
-        if type_ == Type::Group { return; }
-
         let repeated = field.label == Some(Label::Repeated as i32);
         let optional = self.optional(&field);
-        let ty = self.resolve_type(&field);
+        // Required fields which track presence are stored in an `Option`, so that a missing field
+        // can be detected.
+        let required = field.label == Some(Label::Required as i32)
+            && self.config
+                   .required_presence
+                   .iter()
+                   .any(|matcher| match_ident(matcher, msg_name, Some(field.name())));
+        let (ty, type_tag) = self.field_type(msg_name, &field);
 
         let boxed = !repeated
-                 && type_ == Type::Message
+                 && (type_ == Type::Message || type_ == Type::Group)
                  && self.message_graph.is_nested(field.type_name(), msg_name);
 
         debug!("    field: {:?}, type: {:?}, boxed: {}", field.name(), ty, boxed);
@@ -271,14 +559,16 @@ impl <'a> CodeGenerator<'a> {
         self.append_doc();
         self.push_indent();
         self.buf.push_str("#[prost(");
-        let type_tag = self.field_type_tag(&field);
         self.buf.push_str(&type_tag);
 
         match field.label() {
             Label::Optional => if optional {
                 self.buf.push_str(", optional");
             },
-            Label::Required => self.buf.push_str(", required"),
+            Label::Required => {
+                self.buf.push_str(", required");
+                if required { self.buf.push_str(", presence"); }
+            },
             Label::Repeated => {
                 self.buf.push_str(", repeated");
                 if can_pack(&field) && !field.options.as_ref().map_or(self.syntax == Syntax::Proto3,
@@ -330,12 +620,12 @@ impl <'a> CodeGenerator<'a> {
         self.buf.push_str("pub ");
         self.buf.push_str(&to_snake(field.name()));
         self.buf.push_str(": ");
-        if repeated { self.buf.push_str("::std::vec::Vec<"); }
-        else if optional { self.buf.push_str("::std::option::Option<"); }
-        if boxed { self.buf.push_str("::std::boxed::Box<"); }
+        if repeated { self.buf.push_str(&format!("{}::vec::Vec<", self.alloc_path())); }
+        else if optional || required { self.buf.push_str(&format!("{}::option::Option<", self.core_path())); }
+        if boxed { self.buf.push_str(&format!("{}::boxed::Box<", self.alloc_path())); }
         self.buf.push_str(&ty);
         if boxed { self.buf.push_str(">"); }
-        if repeated || optional { self.buf.push_str(">"); }
+        if repeated || optional || required { self.buf.push_str(">"); }
         self.buf.push_str(",\n");
     }
 
@@ -353,10 +643,11 @@ impl <'a> CodeGenerator<'a> {
         self.append_doc();
         self.push_indent();
 
-        let btree_map = self.config
-                            .btree_map
-                            .iter()
-                            .any(|matcher| match_ident(matcher, msg_name, Some(field.name())));
+        // `HashMap` is not available without `std`.
+        let btree_map = self.config.no_std || self.config
+                                                  .btree_map
+                                                  .iter()
+                                                  .any(|matcher| match_ident(matcher, msg_name, Some(field.name())));
         let (annotation_ty, rust_ty) = if btree_map {
             ("btree_map", "BTreeMap")
         } else {
@@ -372,8 +663,8 @@ impl <'a> CodeGenerator<'a> {
                                    field.number()));
         self.append_field_attributes(msg_name, field.name());
         self.push_indent();
-        self.buf.push_str(&format!("pub {}: ::std::collections::{}<{}, {}>,\n",
-                                   to_snake(field.name()), rust_ty, key_ty, value_ty));
+        self.buf.push_str(&format!("pub {}: {}::collections::{}<{}, {}>,\n",
+                                   to_snake(field.name()), self.alloc_path(), rust_ty, key_ty, value_ty));
     }
 
     fn append_oneof_field(&mut self,
@@ -391,7 +682,9 @@ impl <'a> CodeGenerator<'a> {
                                    fields.iter().map(|&(ref field, _)| field.number()).join(", ")));
         self.append_field_attributes(fq_message_name, oneof.name());
         self.push_indent();
-        self.buf.push_str(&format!("pub {}: ::std::option::Option<{}>,\n", to_snake(oneof.name()), name));
+        let lifetime = if self.is_borrowed_oneof(fq_message_name, fields) { "<'a>" } else { "" };
+        self.buf.push_str(&format!("pub {}: {}::option::Option<{}{}>,\n",
+                                   to_snake(oneof.name()), self.core_path(), name, lifetime));
     }
 
     fn append_oneof(&mut self,
@@ -412,34 +705,35 @@ impl <'a> CodeGenerator<'a> {
         self.push_indent();
         self.buf.push_str("pub enum ");
         self.buf.push_str(&to_upper_camel(oneof.name()));
+        if self.is_borrowed_oneof(msg_name, &fields) {
+            self.buf.push_str("<'a>");
+        }
         self.buf.push_str(" {\n");
 
         self.path.push(2);
         self.depth += 1;
         for (field, idx) in fields {
-            // TODO(danburkert/prost#19): support groups.
             let type_ = field.type_();
-            if type_ == Type::Group { continue; }
 
             self.path.push(idx as i32);
             self.append_doc();
             self.path.pop();
 
             self.push_indent();
-            let ty_tag = self.field_type_tag(&field);
+            let (ty, ty_tag) = self.field_type(msg_name, &field);
             self.buf.push_str(&format!("#[prost({}, tag=\"{}\")]\n", ty_tag, field.number()));
             self.append_field_attributes(&oneof_name, field.name());
 
             self.push_indent();
-            let ty = self.resolve_type(&field);
 
-            let boxed = type_ == Type::Message
+            let boxed = (type_ == Type::Message || type_ == Type::Group)
                      && self.message_graph.is_nested(field.type_name(), msg_name);
 
             debug!("    oneof: {:?}, type: {:?}, boxed: {}", field.name(), ty, boxed);
 
             if boxed {
-                self.buf.push_str(&format!("{}(Box<{}>),\n", to_upper_camel(field.name()), ty));
+                self.buf.push_str(&format!("{}({}::boxed::Box<{}>),\n",
+                                           to_upper_camel(field.name()), self.alloc_path(), ty));
             } else {
                 self.buf.push_str(&format!("{}({}),\n", to_upper_camel(field.name()), ty));
             }
@@ -451,17 +745,46 @@ impl <'a> CodeGenerator<'a> {
         self.buf.push_str("}\n");
     }
 
-    fn location(&self) -> &Location {
-        let idx = self.source_info
-                      .location
-                      .binary_search_by_key(&&self.path[..], |location| &location.path[..])
-                      .unwrap();
+    /// Returns `true` if any of the fields of a oneof borrow from the decoded buffer.
+    fn is_borrowed_oneof(&self, fq_message_name: &str, fields: &[(FieldDescriptorProto, usize)]) -> bool {
+        fields.iter().any(|&(ref field, _)| {
+            self.borrowed_field_type(fq_message_name, field).is_some()
+        })
+    }
 
-        &self.source_info.location[idx]
+    fn location(&self) -> Option<&Location> {
+        self.source_info
+            .location
+            .binary_search_by_key(&&self.path[..], |location| &location.path[..])
+            .ok()
+            .map(|idx| &self.source_info.location[idx])
+    }
+
+    /// Returns the comments of the current element, which are empty if the file has no source
+    /// code info.
+    fn comments(&self) -> Comments {
+        self.location().map(Comments::from_location).unwrap_or_default()
     }
 
     fn append_doc(&mut self) {
-        Comments::from_location(self.location()).append_with_indent(self.depth, &mut self.buf);
+        self.comments().append_with_indent(self.depth, &mut self.buf);
+    }
+
+    fn append_extension(&mut self, field: FieldDescriptorProto) {
+        debug!("  extension: {:?}", field.name());
+
+        let extendee = self.resolve_message(field.extendee());
+        let ty = self.extension_type(&field);
+
+        self.append_doc();
+        self.push_indent();
+        self.buf.push_str(&format!("pub static {}: ::prost::Extension<{}, {}> =\n",
+                                   to_snake(field.name()).to_uppercase(), extendee, ty));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("::prost::Extension::new({}, \"{}.{}\");\n",
+                                   field.number(), self.package, field.name()));
+        self.depth -= 1;
     }
 
     fn append_enum(&mut self, desc: EnumDescriptorProto) {
@@ -482,24 +805,37 @@ impl <'a> CodeGenerator<'a> {
         self.buf.push_str(&to_upper_camel(desc.name()));
         self.buf.push_str(" {\n");
 
-        let mut numbers = HashSet::new();
+        // Values which reuse the number of an earlier value are aliases, which Protobuf allows when
+        // the 'allow_alias' option is set. Rust enums can't have duplicate discriminants, so
+        // aliases become associated constants instead of variants.
+        let mut canonical = HashMap::new();
+        let mut aliases = MultiMap::new();
+        for (idx, value) in enum_values.iter().enumerate() {
+            match canonical.get(&value.number()).cloned() {
+                Some(canonical_idx) => aliases.insert(canonical_idx, idx),
+                None => { canonical.insert(value.number(), idx); },
+            }
+        }
+
+        let stripped_prefix = if self.config.strip_enum_prefix {
+            Some(to_upper_camel(&enum_name))
+        } else {
+            None
+        };
 
         self.depth += 1;
         self.path.push(2);
-        for (idx, value) in enum_values.into_iter().enumerate() {
-            // Skip duplicate enum values. Protobuf allows this when the
-            // 'allow_alias' option is set.
-            if !numbers.insert(value.number()) {
+        for (idx, value) in enum_values.iter().enumerate() {
+            if canonical[&value.number()] != idx {
                 continue;
             }
 
+            let alias_names = aliases.get_vec(&idx)
+                                     .map_or(Vec::new(), |alias_idxs| {
+                                         alias_idxs.iter().map(|&alias| enum_values[alias].name()).collect()
+                                     });
             self.path.push(idx as i32);
-            let stripped_prefix = if self.config.strip_enum_prefix {
-                Some(to_upper_camel(&enum_name))
-            } else {
-                None
-            };
-            self.append_enum_value(&fq_enum_name, value, stripped_prefix);
+            self.append_enum_value(&fq_enum_name, value, &alias_names, stripped_prefix.as_ref());
             self.path.pop();
         }
         self.path.pop();
@@ -507,18 +843,63 @@ impl <'a> CodeGenerator<'a> {
 
         self.push_indent();
         self.buf.push_str("}\n");
+
+        if !aliases.is_empty() {
+            let enum_ident = to_upper_camel(desc.name());
+            let variant_names = canonical.values()
+                                         .map(|&idx| enum_value_ident(&enum_values[idx], stripped_prefix.as_ref()))
+                                         .collect::<HashSet<_>>();
+            self.push_indent();
+            self.buf.push_str("#[allow(non_upper_case_globals)]\n");
+            self.push_indent();
+            self.buf.push_str(&format!("impl {} {{\n", enum_ident));
+            self.depth += 1;
+            self.path.push(2);
+            for (idx, value) in enum_values.iter().enumerate() {
+                let canonical_idx = canonical[&value.number()];
+                if canonical_idx == idx {
+                    continue;
+                }
+                // An alias which only differs from a variant by case has the same Rust name.
+                let alias_ident = enum_value_ident(value, stripped_prefix.as_ref());
+                if variant_names.contains(&alias_ident) {
+                    continue;
+                }
+
+                self.path.push(idx as i32);
+                self.append_doc();
+                self.path.pop();
+                self.push_indent();
+                self.buf.push_str(&format!("pub const {}: {} = {}::{};\n",
+                                           alias_ident,
+                                           enum_ident,
+                                           enum_ident,
+                                           enum_value_ident(&enum_values[canonical_idx],
+                                                            stripped_prefix.as_ref())));
+            }
+            self.path.pop();
+            self.depth -= 1;
+            self.push_indent();
+            self.buf.push_str("}\n");
+        }
     }
 
-    fn append_enum_value(&mut self, fq_enum_name: &str, value: &EnumValueDescriptorProto, prefix_to_strip: Option<String>) {
+    fn append_enum_value(&mut self,
+                         fq_enum_name: &str,
+                         value: &EnumValueDescriptorProto,
+                         aliases: &[&str],
+                         prefix_to_strip: Option<&String>) {
         self.append_doc();
+        self.push_indent();
+        self.buf.push_str(&format!("#[prost(name={:?}", value.name()));
+        for alias in aliases {
+            self.buf.push_str(&format!(", alias={:?}", alias));
+        }
+        self.buf.push_str(")]\n");
         self.append_field_attributes(fq_enum_name, &value.name());
         self.push_indent();
-        let name = to_upper_camel(value.name());
-        let name_unprefixed = match prefix_to_strip {
-            Some(prefix) => strip_enum_prefix(&prefix, &name),
-            None => &name,
-        };
-        self.buf.push_str(name_unprefixed);
+        let name = enum_value_ident(value, prefix_to_strip);
+        self.buf.push_str(&name);
         self.buf.push_str(" = ");
         self.buf.push_str(&value.number().to_string());
         self.buf.push_str(",\n");
@@ -528,7 +909,7 @@ impl <'a> CodeGenerator<'a> {
         let name = service.name().to_owned();
         debug!("  service: {:?}", name);
 
-        let comments = Comments::from_location(self.location());
+        let comments = self.comments();
 
         self.path.push(2);
         let methods = service.method
@@ -537,7 +918,7 @@ impl <'a> CodeGenerator<'a> {
                              .map(|(idx, mut method)| {
                                  debug!("  method: {:?}", method.name());
                                  self.path.push(idx as i32);
-                                 let comments = Comments::from_location(self.location());
+                                 let comments = self.comments();
                                  self.path.pop();
 
                                  let name = method.name.take().unwrap();
@@ -614,15 +995,53 @@ impl <'a> CodeGenerator<'a> {
             Type::Int32 | Type::Sfixed32 | Type::Sint32 | Type::Enum => String::from("i32"),
             Type::Int64 | Type::Sfixed64 | Type::Sint64 => String::from("i64"),
             Type::Bool => String::from("bool"),
+            Type::String if self.config.no_std => String::from("::alloc::string::String"),
+            Type::Bytes if self.config.no_std => String::from("::alloc::vec::Vec<u8>"),
             Type::String => String::from("String"),
             Type::Bytes => String::from("Vec<u8>"),
-            Type::Group | Type::Message => {
-                if let Some(ty) = self.known_type(field.type_name()) {
-                    String::from(ty)
-                } else {
-                    self.resolve_ident(field.type_name())
-                }
-            },
+            Type::Group | Type::Message => self.resolve_message(field.type_name()),
+        }
+    }
+
+    /// Returns the Rust type name for a Protobuf message type.
+    fn resolve_message(&self, pb_ident: &str) -> String {
+        if let Some(ty) = self.known_type(pb_ident) {
+            String::from(ty)
+        } else {
+            self.resolve_ident(pb_ident)
+        }
+    }
+
+    /// Returns the `prost::extension` field type of an extension.
+    fn extension_type(&self, field: &FieldDescriptorProto) -> String {
+        let ty = match field.type_() {
+            Type::Float => "Float".to_string(),
+            Type::Double => "Double".to_string(),
+            Type::Int32 => "Int32".to_string(),
+            Type::Int64 => "Int64".to_string(),
+            Type::Uint32 => "Uint32".to_string(),
+            Type::Uint64 => "Uint64".to_string(),
+            Type::Sint32 => "Sint32".to_string(),
+            Type::Sint64 => "Sint64".to_string(),
+            Type::Fixed32 => "Fixed32".to_string(),
+            Type::Fixed64 => "Fixed64".to_string(),
+            Type::Sfixed32 => "Sfixed32".to_string(),
+            Type::Sfixed64 => "Sfixed64".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::String => "String".to_string(),
+            Type::Bytes => "Bytes".to_string(),
+            Type::Enum => "Enumeration".to_string(),
+            Type::Message => format!("Message<{}>", self.resolve_message(field.type_name())),
+            Type::Group => format!("Group<{}>", self.resolve_message(field.type_name())),
+        };
+
+        if field.label() != Label::Repeated {
+            format!("::prost::extension::{}", ty)
+        } else if can_pack(field) && field.options.as_ref().map_or(self.syntax == Syntax::Proto3,
+                                                                   |options| options.packed()) {
+            format!("::prost::extension::Packed<::prost::extension::{}>", ty)
+        } else {
+            format!("::prost::extension::Repeated<::prost::extension::{}>", ty)
         }
     }
 
@@ -630,6 +1049,10 @@ impl <'a> CodeGenerator<'a> {
         // protoc should always give fully qualified identifiers.
         assert_eq!(".", &pb_ident[..1]);
 
+        if let Some(path) = resolve_extern_path(self.config, pb_ident) {
+            return path;
+        }
+
         let mut local_path = self.package.split('.').peekable();
 
         let mut ident_path = pb_ident[1..].split('.');
@@ -672,6 +1095,85 @@ impl <'a> CodeGenerator<'a> {
         }
     }
 
+    /// Returns the Rust type and field type tag of a field in a message.
+    fn field_type(&self,
+                  fq_message_name: &str,
+                  field: &FieldDescriptorProto) -> (String, Cow<'static, str>) {
+        if let Some((ty, type_tag)) = self.borrowed_field_type(fq_message_name, field) {
+            (ty, Cow::Borrowed(type_tag))
+        } else if let Some((ty, type_tag)) = self.bytes_field_type(fq_message_name, field) {
+            (ty.to_string(), Cow::Borrowed(type_tag))
+        } else if let Some((ty, type_tag)) = self.typed_enum_field_type(fq_message_name, field) {
+            (ty, Cow::Owned(type_tag))
+        } else {
+            (self.resolve_type(field), self.field_type_tag(field))
+        }
+    }
+
+    /// Returns the Rust type and field type tag of a field which borrows from the decoded buffer,
+    /// or `None` if the field is owned.
+    fn borrowed_field_type(&self,
+                           fq_message_name: &str,
+                           field: &FieldDescriptorProto) -> Option<(String, &'static str)> {
+        if !self.borrowed_messages.contains(fq_message_name) {
+            return None;
+        }
+
+        match field.type_() {
+            Type::String => Some(("&'a str".to_string(), "string=\"str\"")),
+            Type::Bytes => Some(("&'a [u8]".to_string(), "bytes=\"slice\"")),
+            Type::Message if self.borrowed_messages.contains(field.type_name()) => {
+                Some((format!("{}<'a>", self.resolve_ident(field.type_name())), "message"))
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the Rust type and field type tag of a `string` or `bytes` field which should be
+    /// backed by `Bytes`, or `None` if the field uses the default representation.
+    fn bytes_field_type(&self,
+                        fq_message_name: &str,
+                        field: &FieldDescriptorProto) -> Option<(&'static str, &'static str)> {
+        let bytes = self.config
+                        .bytes
+                        .iter()
+                        .any(|matcher| match_ident(matcher, fq_message_name, Some(field.name())));
+        if !bytes {
+            return None;
+        }
+
+        match field.type_() {
+            Type::String => Some(("::prost::ByteString", "string=\"bytes\"")),
+            Type::Bytes => Some(("::prost::bytes::Bytes", "bytes=\"bytes\"")),
+            _ => None,
+        }
+    }
+
+    /// Returns the Rust type and field type tag of an `enum` field which should use the Rust enum
+    /// type, or `None` if the field uses the default `i32` representation.
+    fn typed_enum_field_type(&self,
+                             fq_message_name: &str,
+                             field: &FieldDescriptorProto) -> Option<(String, String)> {
+        if field.type_() != Type::Enum {
+            return None;
+        }
+
+        let typed = self.config
+                        .typed_enums
+                        .iter()
+                        .any(|matcher| match_ident(matcher, fq_message_name, Some(field.name())));
+        if !typed {
+            return None;
+        }
+
+        let ty = self.resolve_ident(field.type_name());
+        match self.syntax {
+            Syntax::Proto3 => Some((format!("::prost::OpenEnum<{}>", ty),
+                                    format!("open_enumeration={:?}", ty))),
+            Syntax::Proto2 => Some((ty.clone(), format!("closed_enumeration={:?}", ty))),
+        }
+    }
+
     fn map_value_type_tag(&self, field: &FieldDescriptorProto) -> Cow<'static, str> {
         match field.type_() {
             Type::Enum => Cow::Owned(format!("enumeration({})", self.resolve_ident(field.type_name()))),
@@ -679,13 +1181,23 @@ impl <'a> CodeGenerator<'a> {
         }
     }
 
+    /// Returns the root path of `core` items, such as `Option`, in generated code.
+    fn core_path(&self) -> &'static str {
+        if self.config.no_std { "::core" } else { "::std" }
+    }
+
+    /// Returns the root path of `alloc` items, such as `Vec`, in generated code.
+    fn alloc_path(&self) -> &'static str {
+        if self.config.no_std { "::alloc" } else { "::std" }
+    }
+
     fn optional(&self, field: &FieldDescriptorProto) -> bool {
         if field.label() != Label::Optional {
             return false;
         }
 
         match field.type_() {
-            Type::Message => true,
+            Type::Message | Type::Group => true,
             _ => self.syntax == Syntax::Proto2,
         }
     }
@@ -705,12 +1217,14 @@ impl <'a> CodeGenerator<'a> {
         if !self.config.prost_types { return None; }
         Some(match fq_msg_type {
             ".google.protobuf.BoolValue" => "bool",
+            ".google.protobuf.BytesValue" if self.config.no_std => "::alloc::vec::Vec<u8>",
             ".google.protobuf.BytesValue" => "::std::vec::Vec<u8>",
             ".google.protobuf.DoubleValue" => "f64",
             ".google.protobuf.Empty" => "()",
             ".google.protobuf.FloatValue" => "f32",
             ".google.protobuf.Int32Value" => "i32",
             ".google.protobuf.Int64Value" => "i64",
+            ".google.protobuf.StringValue" if self.config.no_std => "::alloc::string::String",
             ".google.protobuf.StringValue" => "::std::string::String",
             ".google.protobuf.UInt32Value" => "u32",
             ".google.protobuf.UInt64Value" => "u64",
@@ -872,6 +1386,15 @@ fn unescape_c_escape_string(s: &str) -> Vec<u8> {
 ///
 /// It also tries to handle cases where the stripped name would be
 /// invalid - for example, if it were to begin with a number.
+/// Returns the Rust identifier of an enum value.
+fn enum_value_ident(value: &EnumValueDescriptorProto, prefix_to_strip: Option<&String>) -> String {
+    let name = to_upper_camel(value.name());
+    match prefix_to_strip {
+        Some(prefix) => strip_enum_prefix(prefix, &name).to_string(),
+        None => name,
+    }
+}
+
 fn strip_enum_prefix<'a>(prefix: &str, name: &'a str) -> &'a str {
     let stripped = if name.starts_with(prefix) {
         &name[prefix.len()..]
@@ -910,6 +1433,228 @@ mod tests {
                    &unescape_c_escape_string(r#"\0\001\a\b\f\n\r\t\v\\\'\"\xfe"#)[..]);
     }
 
+    #[test]
+    fn test_enum_aliases() {
+        let value = |name: &str, number| EnumValueDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            ..Default::default()
+        };
+        let location = |path: Vec<i32>, comment: Option<&str>| Location {
+            path,
+            leading_comments: comment.map(str::to_string),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("state.proto".to_string()),
+            package: Some("state".to_string()),
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("State".to_string()),
+                value: vec![value("STATE_STOPPED", 0), value("STATE_RUNNING", 1), value("STATE_STARTED", 1)],
+                ..Default::default()
+            }],
+            source_code_info: Some(SourceCodeInfo {
+                location: vec![
+                    location(vec![5, 0], None),
+                    location(vec![5, 0, 2, 0], None),
+                    location(vec![5, 0, 2, 1], None),
+                    location(vec![5, 0, 2, 2], Some(" Deprecated name of RUNNING.\n")),
+                ],
+            }),
+            ..Default::default()
+        };
+
+        let mut config = Config::new();
+        let message_graph = MessageGraph::new(&[file.clone()]);
+        let mut buf = String::new();
+        CodeGenerator::generate(&mut config, &message_graph, &HashSet::new(), file, &mut buf);
+
+        assert!(buf.contains("    #[prost(name=\"STATE_RUNNING\", alias=\"STATE_STARTED\")]\n    Running = 1,\n"));
+        assert!(!buf.contains("Started = 1"));
+        assert!(buf.contains("#[allow(non_upper_case_globals)]\n\
+                              impl State {\n    \
+                                  /// Deprecated name of RUNNING.\n    \
+                                  pub const Started: State = State::Running;\n\
+                              }\n"));
+    }
+
+    #[test]
+    fn test_bytes_fields() {
+        let field = |name: &str, number, type_: Type| FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            type_: Some(type_ as i32),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("blob.proto".to_string()),
+            package: Some("blob".to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Blob".to_string()),
+                field: vec![field("data", 1, Type::Bytes), field("name", 2, Type::String)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut config = Config::new();
+        config.bytes(&["."]);
+        let message_graph = MessageGraph::new(&[file.clone()]);
+        let mut buf = String::new();
+        CodeGenerator::generate(&mut config, &message_graph, &HashSet::new(), file, &mut buf);
+
+        assert!(buf.contains("pub data: ::prost::bytes::Bytes,"));
+        assert!(buf.contains("pub name: ::prost::ByteString,"));
+    }
+
+    #[test]
+    fn test_required_fields() {
+        let field = |name: &str, number, type_: Type, type_name: Option<&str>| FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Required as i32),
+            type_: Some(type_ as i32),
+            type_name: type_name.map(ToString::to_string),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("required.proto".to_string()),
+            package: Some("required".to_string()),
+            message_type: vec![
+                DescriptorProto {
+                    name: Some("Inner".to_string()),
+                    field: vec![field("id", 1, Type::Int32, None)],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Outer".to_string()),
+                    field: vec![field("inner", 1, Type::Message, Some(".required.Inner"))],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let message_graph = MessageGraph::new(&[file.clone()]);
+        let generate = |config: &mut Config| {
+            let mut buf = String::new();
+            CodeGenerator::generate(config, &message_graph, &HashSet::new(), file.clone(), &mut buf);
+            buf
+        };
+
+        // By default, required fields are plain values.
+        let buf = generate(&mut Config::new());
+        assert!(buf.contains("#[prost(int32, required, tag=\"1\")]\n    pub id: i32,"));
+        assert!(buf.contains("#[prost(message, required, tag=\"1\")]\n    pub inner: Inner,"));
+
+        // Matched required fields track presence with an `Option`.
+        let mut config = Config::new();
+        config.required_presence(&[".required.Inner"]);
+        let buf = generate(&mut config);
+        assert!(buf.contains("#[prost(int32, required, presence, tag=\"1\")]\n    pub id: ::std::option::Option<i32>,"));
+        assert!(buf.contains("#[prost(message, required, tag=\"1\")]\n    pub inner: Inner,"));
+    }
+
+    #[test]
+    fn test_typed_enums() {
+        let field = |name: &str, number, oneof_index: Option<i32>| FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            type_: Some(Type::Enum as i32),
+            type_name: Some(".color.Color".to_string()),
+            oneof_index,
+            ..Default::default()
+        };
+        let file = |syntax: &str| FileDescriptorProto {
+            name: Some("color.proto".to_string()),
+            package: Some("color".to_string()),
+            syntax: Some(syntax.to_string()),
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("Color".to_string()),
+                value: vec![EnumValueDescriptorProto {
+                    name: Some("RED".to_string()),
+                    number: Some(0),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            message_type: vec![DescriptorProto {
+                name: Some("Paint".to_string()),
+                field: vec![field("color", 1, None), field("choice_color", 2, Some(0))],
+                oneof_decl: vec![OneofDescriptorProto {
+                    name: Some("choice".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let generate = |file: FileDescriptorProto| {
+            let mut config = Config::new();
+            config.typed_enums(&["."]);
+            let message_graph = MessageGraph::new(&[file.clone()]);
+            let mut buf = String::new();
+            CodeGenerator::generate(&mut config, &message_graph, &HashSet::new(), file, &mut buf);
+            buf
+        };
+
+        // Closed enumeration fields in a oneof use the enum type, like other fields.
+        let buf = generate(file("proto2"));
+        assert!(buf.contains("#[prost(closed_enumeration=\"Color\", optional, tag=\"1\")]\n    \
+                              pub color: ::std::option::Option<Color>,"));
+        assert!(buf.contains("#[prost(closed_enumeration=\"super::Color\", tag=\"2\")]\n        \
+                              ChoiceColor(super::Color),"));
+
+        let buf = generate(file("proto3"));
+        assert!(buf.contains("#[prost(open_enumeration=\"Color\", tag=\"1\")]\n    \
+                              pub color: ::prost::OpenEnum<Color>,"));
+        assert!(buf.contains("#[prost(open_enumeration=\"super::Color\", tag=\"2\")]\n        \
+                              ChoiceColor(::prost::OpenEnum<super::Color>),"));
+    }
+
+    #[test]
+    fn test_extern_path() {
+        let message_field = |name: &str, number, type_name: &str| FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            type_: Some(Type::Message as i32),
+            type_name: Some(type_name.to_string()),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("a.proto".to_string()),
+            package: Some("a".to_string()),
+            dependency: vec!["common.proto".to_string()],
+            message_type: vec![DescriptorProto {
+                name: Some("A".to_string()),
+                field: vec![
+                    message_field("common", 1, ".common.Common"),
+                    message_field("nested", 2, ".common.Common.Nested"),
+                    message_field("b", 3, ".b.B"),
+                    message_field("other", 4, ".common.other.Other"),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut config = Config::new();
+        config.extern_path(".common", "::common_protos")
+              .extern_path(".common.other", "::other_protos");
+        let message_graph = MessageGraph::new(&[file.clone()]);
+        let mut buf = String::new();
+        CodeGenerator::generate(&mut config, &message_graph, &HashSet::new(), file, &mut buf);
+
+        assert!(buf.contains("pub common: ::std::option::Option<::common_protos::Common>,"));
+        assert!(buf.contains("pub nested: ::std::option::Option<::common_protos::common::Nested>,"));
+        assert!(buf.contains("pub b: ::std::option::Option<super::b::B>,"));
+        assert!(buf.contains("pub other: ::std::option::Option<::other_protos::Other>,"));
+    }
+
     #[test]
     fn test_strip_enum_prefix() {
         assert_eq!(strip_enum_prefix("Foo", "FooBar"), "Bar");
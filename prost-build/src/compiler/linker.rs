@@ -0,0 +1,1971 @@
+//! Links parsed files into a pool of file descriptors.
+//!
+//! The linker follows `google::protobuf::DescriptorBuilder`: it adds the symbols declared by each
+//! file to the pool, resolves type names relative to their scope, validates numbers, ranges and
+//! options, interprets options, and finally normalizes the file descriptor the way `protoc` writes
+//! it. Errors are located with the source code info recorded by the parser.
+
+use std::collections::{HashMap, HashSet};
+use std::i32;
+use std::mem;
+
+use prost_types::{
+    DescriptorProto,
+    EnumDescriptorProto,
+    EnumValueDescriptorProto,
+    FieldDescriptorProto,
+    FileDescriptorProto,
+    MethodDescriptorProto,
+    OneofDescriptorProto,
+    ServiceDescriptorProto,
+};
+use prost_types::descriptor_proto::ExtensionRange;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::field_options::JsType;
+use prost_types::file_options::OptimizeMode;
+
+use compiler::{options, simple_dtoa, simple_ftoa, tag, tokenizer, MAX_FIELD_NUMBER};
+
+/// The first field number reserved for the Protobuf implementation.
+const FIRST_RESERVED_NUMBER: i32 = 19_000;
+
+/// The last field number reserved for the Protobuf implementation.
+const LAST_RESERVED_NUMBER: i32 = 19_999;
+
+/// A set of linked files, and the symbols they declare.
+pub struct Pool {
+    files: Vec<FileDescriptorProto>,
+    file_names: HashMap<String, usize>,
+    symbols: HashMap<String, Symbol>,
+}
+
+/// A named element of a file in the pool.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    /// The index of the declaring file in the pool.
+    pub file: usize,
+    pub kind: SymbolKind,
+    /// The path of the element in its file, in the format of source code info locations.
+    pub path: Vec<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Package,
+    Message,
+    Enum,
+    EnumValue,
+    Service,
+    Method,
+    Field,
+    Oneof,
+}
+
+impl SymbolKind {
+
+    /// Returns `true` if the symbol may contain other symbols.
+    fn is_aggregate(self) -> bool {
+        match self {
+            SymbolKind::Package | SymbolKind::Message | SymbolKind::Enum | SymbolKind::Service => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the symbol is a type, which can be used as the type of a field.
+    fn is_type(self) -> bool {
+        self == SymbolKind::Message || self == SymbolKind::Enum
+    }
+}
+
+/// The part of an element which an error refers to, like `DescriptorPool::ErrorCollector::ErrorLocation`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorLocation {
+    Name,
+    Number,
+    Type,
+    Extendee,
+    DefaultValue,
+    InputType,
+    OutputType,
+    Other,
+}
+
+/// The kind of an options message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionsKind {
+    File,
+    Message,
+    Field,
+    Oneof,
+    ExtensionRange,
+    Enum,
+    EnumValue,
+    Service,
+    Method,
+}
+
+/// Options which are waiting to be interpreted once the file has been cross-linked.
+pub struct OptionsToInterpret {
+    pub kind: OptionsKind,
+    /// The path of the element which the options belong to.
+    pub element_path: Vec<i32>,
+    /// The path of the options within the file.
+    pub options_path: Vec<i32>,
+    /// The scope which extension option names are resolved relative to.
+    pub name_scope: String,
+}
+
+/// The kind of an element of a file, which determines the meaning of the fields in its path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Element {
+    File,
+    Message,
+    Field,
+    Oneof,
+    ExtensionRange,
+    ReservedRange,
+    Enum,
+    EnumValue,
+    EnumReservedRange,
+    Service,
+    Method,
+}
+
+impl Pool {
+
+    /// Creates an empty pool.
+    pub fn new() -> Pool {
+        Pool {
+            files: Vec::new(),
+            file_names: HashMap::new(),
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Returns the file with the name, if it is in the pool.
+    pub fn file(&self, name: &str) -> Option<&FileDescriptorProto> {
+        self.file_names.get(name).map(|&index| &self.files[index])
+    }
+
+    /// Returns the files in the pool, in the order they were added.
+    pub fn files(&self) -> &[FileDescriptorProto] {
+        &self.files
+    }
+
+    /// Returns the symbol with the fully-qualified name, without a leading dot.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// Links a parsed file, whose dependencies must already be in the pool, and adds it to the pool.
+    ///
+    /// Standard options are interpreted with the options messages of the `fallback` pool if this
+    /// pool does not contain `descriptor.proto`. On failure, the pool is left unchanged and the
+    /// formatted errors are returned.
+    pub fn add_file(&mut self, proto: FileDescriptorProto, fallback: &Pool) -> Result<(), Vec<String>> {
+        let name = proto.name().to_string();
+        let proto = Builder::new(self, fallback, proto).build()?;
+        self.file_names.insert(name, self.files.len());
+        self.files.push(proto);
+        Ok(())
+    }
+}
+
+/// Links a single file into a pool.
+pub struct Builder<'a> {
+    pool: &'a mut Pool,
+    fallback: &'a Pool,
+    /// The file being built.
+    proto: FileDescriptorProto,
+    /// The index which the file will have in the pool.
+    file: usize,
+    name: String,
+    package: String,
+    /// The locations recorded by the parser, by path, in source order.
+    locations: HashMap<Vec<i32>, Vec<(i32, i32)>>,
+    /// The files whose symbols are visible: the dependencies and their public dependencies.
+    dependencies: HashSet<usize>,
+    /// The symbols added by the file, which are removed if it fails to build.
+    added_symbols: Vec<String>,
+    /// The fields and extensions declared by the file, by containing type and number.
+    fields_by_number: HashMap<(String, i32), (String, String)>,
+    options_to_interpret: Vec<OptionsToInterpret>,
+    /// The source code info paths of interpreted options, by the path of the uninterpreted option.
+    interpreted_paths: HashMap<Vec<i32>, Vec<i32>>,
+    /// The number of values of each repeated option which have been interpreted.
+    repeated_option_counts: HashMap<Vec<i32>, i32>,
+    /// A file which declares a symbol that failed to resolve, but which is not imported.
+    possible_undeclared_dependency: Option<(usize, String)>,
+    /// The name that a compound symbol was resolved to, when the rest of it was not found.
+    undefined_resolved_name: Option<String>,
+    errors: Vec<String>,
+}
+
+impl <'a> Builder<'a> {
+
+    fn new(pool: &'a mut Pool, fallback: &'a Pool, proto: FileDescriptorProto) -> Builder<'a> {
+        let mut locations = HashMap::new();
+        if let Some(ref info) = proto.source_code_info {
+            for location in &info.location {
+                if location.span.len() >= 2 {
+                    locations.entry(location.path.clone())
+                             .or_insert_with(Vec::new)
+                             .push((location.span[0], location.span[1]));
+                }
+            }
+        }
+
+        Builder {
+            file: pool.files.len(),
+            pool: pool,
+            fallback: fallback,
+            name: proto.name().to_string(),
+            package: proto.package().to_string(),
+            proto: proto,
+            locations: locations,
+            dependencies: HashSet::new(),
+            added_symbols: Vec::new(),
+            fields_by_number: HashMap::new(),
+            options_to_interpret: Vec::new(),
+            interpreted_paths: HashMap::new(),
+            repeated_option_counts: HashMap::new(),
+            possible_undeclared_dependency: None,
+            undefined_resolved_name: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn build(mut self) -> Result<FileDescriptorProto, Vec<String>> {
+        if !self.package.is_empty() {
+            let package = self.package.clone();
+            self.add_package(&package);
+        }
+        self.check_dependencies();
+
+        // Adding symbols does not need to look at the file, so it is taken out while its elements
+        // are borrowed.
+        let proto = mem::replace(&mut self.proto, FileDescriptorProto::default());
+        let package = self.package.clone();
+        for (i, message) in proto.message_type.iter().enumerate() {
+            self.build_message(message, &[tag::file::MESSAGE_TYPE, i as i32], &package);
+        }
+        for (i, enumeration) in proto.enum_type.iter().enumerate() {
+            self.build_enum(enumeration, &[tag::file::ENUM_TYPE, i as i32], &package);
+        }
+        for (i, service) in proto.service.iter().enumerate() {
+            self.build_service(service, &[tag::file::SERVICE, i as i32]);
+        }
+        for (i, extension) in proto.extension.iter().enumerate() {
+            self.build_field(extension, &[tag::file::EXTENSION, i as i32], &package);
+        }
+        if proto.options.is_some() {
+            let name_scope = join(&package, "dummy");
+            self.allocate_options(OptionsKind::File, &[], &[tag::file::OPTIONS], name_scope);
+        }
+        self.proto = proto;
+
+        self.cross_link_file();
+
+        if self.errors.is_empty() {
+            self.interpret_options();
+            self.update_source_code_info();
+        }
+        if self.errors.is_empty() {
+            self.validate_file_options();
+        }
+
+        if !self.errors.is_empty() {
+            for name in &self.added_symbols {
+                self.pool.symbols.remove(name);
+            }
+            return Err(self.errors);
+        }
+
+        self.normalize();
+        Ok(self.proto)
+    }
+
+    // Errors.
+
+    /// Records an error about an element of the file.
+    pub fn add_error(&mut self, element: &[i32], location: ErrorLocation, message: &str) {
+        let position = self.legacy_location(element, location);
+        self.errors.push(format_error(&self.name, position, message));
+    }
+
+    /// Records an error at the first location with a path of the file.
+    pub fn add_error_at_path(&mut self, path: &[i32], message: &str) {
+        let position = self.locations.get(path).and_then(|positions| positions.first().cloned());
+        self.errors.push(format_error(&self.name, position, message));
+    }
+
+    /// Returns `true` if the parser recorded a location with the path.
+    pub fn has_location(&self, path: &[i32]) -> bool {
+        self.locations.contains_key(path)
+    }
+
+    /// Finds the position which `protoc` reports an error about an element at.
+    fn legacy_location(&self, element: &[i32], location: ErrorLocation) -> Option<(i32, i32)> {
+        let field_path = |field_number: i32| {
+            let mut path = element.to_vec();
+            path.push(field_number);
+            path
+        };
+        let path = match (element_at(element), location) {
+            (Element::File, ErrorLocation::Name) => vec![tag::file::PACKAGE],
+            (Element::Message, ErrorLocation::Name) |
+            (Element::Field, ErrorLocation::Name) |
+            (Element::Oneof, ErrorLocation::Name) |
+            (Element::Enum, ErrorLocation::Name) |
+            (Element::EnumValue, ErrorLocation::Name) |
+            (Element::Service, ErrorLocation::Name) |
+            (Element::Method, ErrorLocation::Name) => field_path(tag::message::NAME),
+            (Element::Field, ErrorLocation::Number) => field_path(tag::field::NUMBER),
+            (Element::Field, ErrorLocation::Type) => {
+                let path = field_path(tag::field::TYPE);
+                if self.locations.contains_key(&path) {
+                    path
+                } else {
+                    field_path(tag::field::TYPE_NAME)
+                }
+            },
+            (Element::Field, ErrorLocation::Extendee) => field_path(tag::field::EXTENDEE),
+            (Element::Field, ErrorLocation::DefaultValue) => field_path(tag::field::DEFAULT_VALUE),
+            (Element::EnumValue, ErrorLocation::Number) => field_path(tag::enum_value::NUMBER),
+            (Element::ExtensionRange, ErrorLocation::Number) => element.to_vec(),
+            (Element::Method, ErrorLocation::InputType) => field_path(tag::method::INPUT_TYPE),
+            (Element::Method, ErrorLocation::OutputType) => field_path(tag::method::OUTPUT_TYPE),
+            _ => return None,
+        };
+        self.locations.get(&path).and_then(|positions| positions.first().cloned())
+    }
+
+    /// Records an error about the import at `index`.
+    fn add_import_error(&mut self, index: usize, message: &str) {
+        self.add_error_at_path(&[tag::file::DEPENDENCY, index as i32], message);
+    }
+
+    /// Records an error about a symbol which failed to resolve.
+    fn add_not_defined_error(&mut self, element: &[i32], location: ErrorLocation, undefined_symbol: &str) {
+        let possible_undeclared_dependency = self.possible_undeclared_dependency.take();
+        let undefined_resolved_name = self.undefined_resolved_name.take();
+        if possible_undeclared_dependency.is_none() && undefined_resolved_name.is_none() {
+            self.add_error(element, location, &format!("\"{}\" is not defined.", undefined_symbol));
+            return;
+        }
+        if let Some((file, name)) = possible_undeclared_dependency {
+            let message = format!("\"{}\" seems to be defined in \"{}\", which is not imported by \"{}\".  \
+                                   To use it here, please add the necessary import.",
+                                  name, self.pool.files[file].name(), self.name);
+            self.add_error(element, location, &message);
+        }
+        if let Some(resolved_name) = undefined_resolved_name {
+            let message = format!("\"{}\" is resolved to \"{}\", which is not defined. The innermost \
+                                   scope is searched first in name resolution. Consider using a \
+                                   leading '.'(i.e., \".{}\") to start from the outermost scope.",
+                                  undefined_symbol, resolved_name, undefined_symbol);
+            self.add_error(element, location, &message);
+        }
+    }
+
+    // Symbols.
+
+    fn add_package(&mut self, name: &str) {
+        if let Some(existing) = self.pool.symbols.get(name) {
+            // Packages may be declared by any number of files.
+            if existing.kind != SymbolKind::Package {
+                let message = format!("\"{}\" is already defined (as something other than a package) \
+                                       in file \"{}\".",
+                                      name, self.file_name(existing.file));
+                self.add_error(&[], ErrorLocation::Name, &message);
+            }
+            return;
+        }
+
+        self.insert_symbol(name, SymbolKind::Package, &[]);
+        if let Some(dot) = name.rfind('.') {
+            self.add_package(&name[..dot]);
+        }
+    }
+
+    /// Adds a symbol declared by the file, reporting conflicts with existing symbols.
+    fn add_symbol(&mut self, full_name: &str, kind: SymbolKind, path: &[i32]) -> bool {
+        let existing_file = match self.pool.symbols.get(full_name) {
+            Some(existing) => existing.file,
+            None => {
+                self.insert_symbol(full_name, kind, path);
+                return true;
+            },
+        };
+
+        let message = if existing_file == self.file {
+            match full_name.rfind('.') {
+                Some(dot) => format!("\"{}\" is already defined in \"{}\".",
+                                     &full_name[dot + 1..], &full_name[..dot]),
+                None => format!("\"{}\" is already defined.", full_name),
+            }
+        } else {
+            format!("\"{}\" is already defined in file \"{}\".", full_name, self.file_name(existing_file))
+        };
+        self.add_error(path, ErrorLocation::Name, &message);
+        false
+    }
+
+    fn insert_symbol(&mut self, full_name: &str, kind: SymbolKind, path: &[i32]) {
+        self.pool.symbols.insert(full_name.to_string(), Symbol {
+            file: self.file,
+            kind: kind,
+            path: path.to_vec(),
+        });
+        self.added_symbols.push(full_name.to_string());
+    }
+
+    fn file_name(&self, file: usize) -> &str {
+        if file == self.file {
+            &self.name
+        } else {
+            self.pool.files[file].name()
+        }
+    }
+
+    /// Returns the file at an index of the pool, which may be the file being built.
+    pub fn file_proto(&self, file: usize) -> &FileDescriptorProto {
+        if file == self.file {
+            &self.proto
+        } else {
+            &self.pool.files[file]
+        }
+    }
+
+    /// Returns the pool which contains the descriptor of the standard options messages.
+    pub fn fallback(&self) -> &Pool {
+        self.fallback
+    }
+
+    /// Returns the files of the pool, followed by the file being built.
+    pub fn all_files(&self) -> Vec<FileDescriptorProto> {
+        let mut files = self.pool.files.clone();
+        files.push(self.proto.clone());
+        files
+    }
+
+    /// Finds a symbol by its fully-qualified name, whether or not it is visible from the file.
+    pub fn find_symbol_not_enforcing_deps(&self, name: &str) -> Option<Symbol> {
+        self.pool.symbols.get(name).cloned()
+    }
+
+    /// Finds a symbol by its fully-qualified name, if it is declared by the file or its imports.
+    fn find_symbol(&mut self, name: &str) -> Option<Symbol> {
+        let symbol = match self.pool.symbols.get(name) {
+            Some(symbol) => symbol.clone(),
+            None => return None,
+        };
+
+        if symbol.file == self.file || self.dependencies.contains(&symbol.file) {
+            return Some(symbol);
+        }
+
+        if symbol.kind == SymbolKind::Package {
+            // The package may have first been declared by a file which is not imported, while
+            // also being declared by an imported file.
+            if is_in_package(&self.package, name) ||
+               self.dependencies.iter().any(|&file| is_in_package(self.pool.files[file].package(), name)) {
+                return Some(symbol);
+            }
+        }
+
+        self.possible_undeclared_dependency = Some((symbol.file, name.to_string()));
+        None
+    }
+
+    /// Resolves a name relative to a scope, like `DescriptorBuilder::LookupSymbol`.
+    ///
+    /// Returns the fully-qualified name of the symbol, without a leading dot.
+    pub fn lookup_symbol(&mut self, name: &str, relative_to: &str, types_only: bool) -> Option<(String, Symbol)> {
+        self.possible_undeclared_dependency = None;
+        self.undefined_resolved_name = None;
+
+        if name.starts_with('.') {
+            let name = &name[1..];
+            return self.find_symbol(name).map(|symbol| (name.to_string(), symbol));
+        }
+
+        // If the name is compound, like `Foo.Bar`, only the innermost scope which contains a
+        // `Foo` is searched for the rest of the name.
+        let first_part = name.split('.').next().unwrap();
+        let mut scope = relative_to.to_string();
+        loop {
+            match scope.rfind('.') {
+                Some(dot) => scope.truncate(dot),
+                None => return self.find_symbol(name).map(|symbol| (name.to_string(), symbol)),
+            }
+
+            let scope_len = scope.len();
+            scope.push('.');
+            scope.push_str(first_part);
+            if let Some(symbol) = self.find_symbol(&scope) {
+                if first_part.len() < name.len() {
+                    // Only the first part of a compound name was found; look up the rest of it.
+                    if symbol.kind.is_aggregate() {
+                        scope.push_str(&name[first_part.len()..]);
+                        return match self.find_symbol(&scope) {
+                            Some(symbol) => Some((scope, symbol)),
+                            None => {
+                                self.undefined_resolved_name = Some(scope);
+                                None
+                            },
+                        };
+                    }
+                } else if !types_only || symbol.kind.is_type() {
+                    return Some((scope, symbol));
+                }
+            }
+            scope.truncate(scope_len);
+        }
+    }
+
+    /// Takes the name that the last failed lookup resolved a compound name to.
+    pub fn take_undefined_resolved_name(&mut self) -> Option<String> {
+        self.undefined_resolved_name.take()
+    }
+
+    // Building.
+
+    fn check_dependencies(&mut self) {
+        let dependencies = self.proto.dependency.clone();
+        let mut seen = HashSet::new();
+        let mut direct = Vec::new();
+        for (i, dependency) in dependencies.iter().enumerate() {
+            if !seen.insert(dependency.as_str()) {
+                self.add_import_error(i, &format!("Import \"{}\" was listed twice.", dependency));
+            }
+            match self.pool.file_names.get(dependency) {
+                Some(&file) => direct.push(file),
+                None => self.add_import_error(i, &format!("Import \"{}\" was not found or had errors.", dependency)),
+            }
+        }
+
+        for file in direct {
+            self.record_public_dependencies(file);
+        }
+    }
+
+    fn record_public_dependencies(&mut self, file: usize) {
+        if !self.dependencies.insert(file) {
+            return;
+        }
+        let public_dependencies = {
+            let proto = &self.pool.files[file];
+            proto.public_dependency
+                 .iter()
+                 .filter_map(|&index| proto.dependency.get(index as usize))
+                 .filter_map(|name| self.pool.file_names.get(name).cloned())
+                 .collect::<Vec<_>>()
+        };
+        for dependency in public_dependencies {
+            self.record_public_dependencies(dependency);
+        }
+    }
+
+    fn allocate_options(&mut self,
+                        kind: OptionsKind,
+                        element_path: &[i32],
+                        options_path: &[i32],
+                        name_scope: String) {
+        self.options_to_interpret.push(OptionsToInterpret {
+            kind: kind,
+            element_path: element_path.to_vec(),
+            options_path: options_path.to_vec(),
+            name_scope: name_scope,
+        });
+    }
+
+    fn build_message(&mut self, message: &DescriptorProto, path: &[i32], scope: &str) {
+        let full_name = join(scope, message.name());
+
+        for (i, oneof) in message.oneof_decl.iter().enumerate() {
+            let oneof_path = child(path, tag::message::ONEOF_DECL, i);
+            self.build_oneof(oneof, &oneof_path, &full_name);
+        }
+        for (i, field) in message.field.iter().enumerate() {
+            self.build_field(field, &child(path, tag::message::FIELD, i), &full_name);
+        }
+        for (i, nested) in message.nested_type.iter().enumerate() {
+            self.build_message(nested, &child(path, tag::message::NESTED_TYPE, i), &full_name);
+        }
+        for (i, enumeration) in message.enum_type.iter().enumerate() {
+            self.build_enum(enumeration, &child(path, tag::message::ENUM_TYPE, i), &full_name);
+        }
+        for (i, range) in message.extension_range.iter().enumerate() {
+            self.build_extension_range(range, &child(path, tag::message::EXTENSION_RANGE, i), &full_name);
+        }
+        for (i, extension) in message.extension.iter().enumerate() {
+            self.build_field(extension, &child(path, tag::message::EXTENSION, i), &full_name);
+        }
+        for (i, range) in message.reserved_range.iter().enumerate() {
+            if range.start() <= 0 {
+                self.add_error(&child(path, tag::message::RESERVED_RANGE, i),
+                               ErrorLocation::Number,
+                               "Reserved numbers must be positive integers.");
+            }
+        }
+        if message.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::message::OPTIONS);
+            self.allocate_options(OptionsKind::Message, path, &options_path, full_name.clone());
+        }
+
+        self.add_symbol(&full_name, SymbolKind::Message, path);
+
+        for (i, range1) in message.reserved_range.iter().enumerate() {
+            for range2 in &message.reserved_range[i + 1..] {
+                if range1.end() > range2.start() && range2.end() > range1.start() {
+                    let message = format!("Reserved range {} to {} overlaps with already-defined range {} to {}.",
+                                          range2.start(), range2.end() - 1, range1.start(), range1.end() - 1);
+                    self.add_error(&child(path, tag::message::RESERVED_RANGE, i), ErrorLocation::Number, &message);
+                }
+            }
+        }
+
+        let mut reserved_names = HashSet::new();
+        for name in &message.reserved_name {
+            if !reserved_names.insert(name.as_str()) {
+                self.add_error(path, ErrorLocation::Name,
+                               &format!("Field name \"{}\" is reserved multiple times.", name));
+            }
+        }
+
+        for (i, field) in message.field.iter().enumerate() {
+            for (j, range) in message.extension_range.iter().enumerate() {
+                if range.start() <= field.number() && field.number() < range.end() {
+                    let message = format!("Extension range {} to {} includes field \"{}\" ({}).",
+                                          range.start(), range.end() - 1, field.name(), field.number());
+                    self.add_error(&child(path, tag::message::EXTENSION_RANGE, j), ErrorLocation::Number, &message);
+                }
+            }
+            for (j, range) in message.reserved_range.iter().enumerate() {
+                if range.start() <= field.number() && field.number() < range.end() {
+                    let message = format!("Field \"{}\" uses reserved number {}.", field.name(), field.number());
+                    self.add_error(&child(path, tag::message::RESERVED_RANGE, j), ErrorLocation::Number, &message);
+                }
+            }
+            if reserved_names.contains(field.name()) {
+                self.add_error(&child(path, tag::message::FIELD, i), ErrorLocation::Name,
+                               &format!("Field name \"{}\" is reserved.", field.name()));
+            }
+        }
+
+        for (i, range1) in message.extension_range.iter().enumerate() {
+            let range_path = child(path, tag::message::EXTENSION_RANGE, i);
+            for range2 in &message.reserved_range {
+                if range1.end() > range2.start() && range2.end() > range1.start() {
+                    let message = format!("Extension range {} to {} overlaps with reserved range {} to {}.",
+                                          range1.start(), range1.end() - 1, range2.start(), range2.end() - 1);
+                    self.add_error(&range_path, ErrorLocation::Number, &message);
+                }
+            }
+            for range2 in &message.extension_range[i + 1..] {
+                if range1.end() > range2.start() && range2.end() > range1.start() {
+                    let message = format!("Extension range {} to {} overlaps with already-defined range {} to {}.",
+                                          range2.start(), range2.end() - 1, range1.start(), range1.end() - 1);
+                    self.add_error(&range_path, ErrorLocation::Number, &message);
+                }
+            }
+        }
+    }
+
+    fn build_oneof(&mut self, oneof: &OneofDescriptorProto, path: &[i32], scope: &str) {
+        let full_name = join(scope, oneof.name());
+        if oneof.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::oneof::OPTIONS);
+            self.allocate_options(OptionsKind::Oneof, path, &options_path, full_name.clone());
+        }
+        self.add_symbol(&full_name, SymbolKind::Oneof, path);
+    }
+
+    fn build_extension_range(&mut self, range: &ExtensionRange, path: &[i32], scope: &str) {
+        if range.start() <= 0 {
+            self.add_error(path, ErrorLocation::Number, "Extension numbers must be positive integers.");
+        }
+        // The upper bound is checked once the options of the message are known, since message
+        // set extensions may use larger numbers.
+        if range.start() >= range.end() {
+            self.add_error(path, ErrorLocation::Number,
+                           "Extension range end number must be greater than start number.");
+        }
+        if range.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::extension_range::OPTIONS);
+            self.allocate_options(OptionsKind::ExtensionRange, path, &options_path, scope.to_string());
+        }
+    }
+
+    fn build_field(&mut self, field: &FieldDescriptorProto, path: &[i32], scope: &str) {
+        let full_name = join(scope, field.name());
+        let is_extension = is_extension_path(path);
+
+        if is_extension && field.label() == Label::Required {
+            self.add_error(path, ErrorLocation::Type, "Message extensions cannot have required fields.");
+        }
+
+        if field.default_value.is_some() && field.label() == Label::Repeated {
+            self.add_error(path, ErrorLocation::DefaultValue, "Repeated fields can't have default values.");
+        }
+
+        if let (Some(_), Some(default_value)) = (field.type_, field.default_value.as_ref()) {
+            let parses = match field.type_() {
+                Type::Int32 | Type::Sint32 | Type::Sfixed32 | Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+                    default_value.parse::<i64>().is_ok()
+                },
+                Type::Uint32 | Type::Fixed32 | Type::Uint64 | Type::Fixed64 => {
+                    default_value.parse::<u64>().is_ok()
+                },
+                Type::Float | Type::Double => parse_floating_point(default_value).is_some(),
+                Type::Bool => {
+                    if default_value != "true" && default_value != "false" {
+                        self.add_error(path, ErrorLocation::DefaultValue, "Boolean default must be true or false.");
+                    }
+                    true
+                },
+                Type::Message | Type::Group => {
+                    self.add_error(path, ErrorLocation::DefaultValue, "Messages can't have default values.");
+                    true
+                },
+                Type::String | Type::Bytes | Type::Enum => true,
+            };
+            if !parses {
+                self.add_error(path, ErrorLocation::DefaultValue,
+                               &format!("Couldn't parse default value \"{}\".", default_value));
+            }
+        }
+
+        if field.number() <= 0 {
+            self.add_error(path, ErrorLocation::Number, "Field numbers must be positive integers.");
+        } else if !is_extension && field.number() > MAX_FIELD_NUMBER {
+            // Extension numbers are checked against the extension ranges of the extendee instead.
+            self.add_error(path, ErrorLocation::Number,
+                           &format!("Field numbers cannot be greater than {}.", MAX_FIELD_NUMBER));
+        } else if field.number() >= FIRST_RESERVED_NUMBER && field.number() <= LAST_RESERVED_NUMBER {
+            self.add_error(path, ErrorLocation::Number,
+                           &format!("Field numbers {} through {} are reserved for the protocol buffer \
+                                     library implementation.",
+                                    FIRST_RESERVED_NUMBER, LAST_RESERVED_NUMBER));
+        }
+
+        if is_extension {
+            if field.extendee.is_none() {
+                self.add_error(path, ErrorLocation::Extendee,
+                               "FieldDescriptorProto.extendee not set for extension field.");
+            }
+            if field.oneof_index.is_some() {
+                self.add_error(path, ErrorLocation::Other,
+                               "FieldDescriptorProto.oneof_index should not be set for extensions.");
+            }
+        } else if field.extendee.is_some() {
+            self.add_error(path, ErrorLocation::Extendee, "FieldDescriptorProto.extendee set for non-extension field.");
+        }
+
+        if field.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::field::OPTIONS);
+            self.allocate_options(OptionsKind::Field, path, &options_path, full_name.clone());
+        }
+
+        self.add_symbol(&full_name, SymbolKind::Field, path);
+    }
+
+    fn build_enum(&mut self, enumeration: &EnumDescriptorProto, path: &[i32], scope: &str) {
+        let full_name = join(scope, enumeration.name());
+
+        if enumeration.value.is_empty() {
+            // Otherwise, fields of the enum would have no valid default value.
+            self.add_error(path, ErrorLocation::Name, "Enums must contain at least one value.");
+        }
+
+        let mut value_names = HashSet::new();
+        for (i, value) in enumeration.value.iter().enumerate() {
+            let value_path = child(path, tag::enumeration::VALUE, i);
+            self.build_enum_value(value, &value_path, scope, enumeration.name(), &mut value_names);
+        }
+        for (i, range) in enumeration.reserved_range.iter().enumerate() {
+            if range.start() > range.end() {
+                self.add_error(&child(path, tag::enumeration::RESERVED_RANGE, i), ErrorLocation::Number,
+                               "Reserved range end number must be greater than start number.");
+            }
+        }
+        if enumeration.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::enumeration::OPTIONS);
+            self.allocate_options(OptionsKind::Enum, path, &options_path, full_name.clone());
+        }
+
+        self.add_symbol(&full_name, SymbolKind::Enum, path);
+
+        for (i, range1) in enumeration.reserved_range.iter().enumerate() {
+            for range2 in &enumeration.reserved_range[i + 1..] {
+                if range1.end() >= range2.start() && range2.end() >= range1.start() {
+                    let message = format!("Reserved range {} to {} overlaps with already-defined range {} to {}.",
+                                          range2.start(), range2.end(), range1.start(), range1.end());
+                    self.add_error(&child(path, tag::enumeration::RESERVED_RANGE, i), ErrorLocation::Number, &message);
+                }
+            }
+        }
+
+        let mut reserved_names = HashSet::new();
+        for name in &enumeration.reserved_name {
+            if !reserved_names.insert(name.as_str()) {
+                self.add_error(path, ErrorLocation::Name,
+                               &format!("Enum value \"{}\" is reserved multiple times.", name));
+            }
+        }
+
+        for (i, value) in enumeration.value.iter().enumerate() {
+            for (j, range) in enumeration.reserved_range.iter().enumerate() {
+                if range.start() <= value.number() && value.number() <= range.end() {
+                    let message = format!("Enum value \"{}\" uses reserved number {}.", value.name(), value.number());
+                    self.add_error(&child(path, tag::enumeration::RESERVED_RANGE, j), ErrorLocation::Number, &message);
+                }
+            }
+            if reserved_names.contains(value.name()) {
+                self.add_error(&child(path, tag::enumeration::VALUE, i), ErrorLocation::Name,
+                               &format!("Enum value \"{}\" is reserved.", value.name()));
+            }
+        }
+    }
+
+    fn build_enum_value(&mut self,
+                        value: &EnumValueDescriptorProto,
+                        path: &[i32],
+                        scope: &str,
+                        enum_name: &str,
+                        value_names: &mut HashSet<String>) {
+        // Enum values are siblings of their type, rather than children of it.
+        let full_name = join(scope, value.name());
+
+        if value.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::enum_value::OPTIONS);
+            self.allocate_options(OptionsKind::EnumValue, path, &options_path, full_name.clone());
+        }
+
+        let added_to_outer_scope = self.add_symbol(&full_name, SymbolKind::EnumValue, path);
+        let added_to_inner_scope = value_names.insert(value.name().to_string());
+
+        if added_to_inner_scope && !added_to_outer_scope {
+            // The value conflicts with a symbol which is not a value of the same enum.
+            let outer_scope = if scope.is_empty() {
+                "the global scope".to_string()
+            } else {
+                format!("\"{}\"", scope)
+            };
+            let message = format!("Note that enum values use C++ scoping rules, meaning that enum values \
+                                   are siblings of their type, not children of it.  Therefore, \"{}\" must \
+                                   be unique within {}, not just within \"{}\".",
+                                  value.name(), outer_scope, enum_name);
+            self.add_error(path, ErrorLocation::Name, &message);
+        }
+    }
+
+    fn build_service(&mut self, service: &ServiceDescriptorProto, path: &[i32]) {
+        let full_name = join(&self.package, service.name());
+        for (i, method) in service.method.iter().enumerate() {
+            self.build_method(method, &child(path, tag::service::METHOD, i), &full_name);
+        }
+        if service.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::service::OPTIONS);
+            self.allocate_options(OptionsKind::Service, path, &options_path, full_name.clone());
+        }
+        self.add_symbol(&full_name, SymbolKind::Service, path);
+    }
+
+    fn build_method(&mut self, method: &MethodDescriptorProto, path: &[i32], scope: &str) {
+        let full_name = join(scope, method.name());
+        if method.options.is_some() {
+            let mut options_path = path.to_vec();
+            options_path.push(tag::method::OPTIONS);
+            self.allocate_options(OptionsKind::Method, path, &options_path, full_name.clone());
+        }
+        self.add_symbol(&full_name, SymbolKind::Method, path);
+    }
+
+    // Cross-linking.
+
+    fn cross_link_file(&mut self) {
+        let package = self.package.clone();
+        for i in 0..self.proto.message_type.len() {
+            self.cross_link_message(&[tag::file::MESSAGE_TYPE, i as i32], &package);
+        }
+        for i in 0..self.proto.extension.len() {
+            self.cross_link_field(&[tag::file::EXTENSION, i as i32], &package);
+        }
+        for i in 0..self.proto.service.len() {
+            self.cross_link_service(&[tag::file::SERVICE, i as i32]);
+        }
+    }
+
+    fn cross_link_message(&mut self, path: &[i32], scope: &str) {
+        let (full_name, nested_types, fields, extensions) = {
+            let message = message(&self.proto, path);
+            (join(scope, message.name()), message.nested_type.len(), message.field.len(), message.extension.len())
+        };
+
+        for i in 0..nested_types {
+            self.cross_link_message(&child(path, tag::message::NESTED_TYPE, i), &full_name);
+        }
+        for i in 0..fields {
+            self.cross_link_field(&child(path, tag::message::FIELD, i), &full_name);
+        }
+        for i in 0..extensions {
+            self.cross_link_field(&child(path, tag::message::EXTENSION, i), &full_name);
+        }
+
+        // Fields of the same oneof must be declared consecutively.
+        let message = message(&self.proto, path).clone();
+        let mut oneof_field_counts = vec![0; message.oneof_decl.len()];
+        for (i, field) in message.field.iter().enumerate() {
+            let oneof_index = match field.oneof_index {
+                Some(oneof_index) => oneof_index as usize,
+                None => continue,
+            };
+            if oneof_index >= oneof_field_counts.len() {
+                continue;
+            }
+            if oneof_field_counts[oneof_index] > 0 && message.field[i - 1].oneof_index != field.oneof_index {
+                let error = format!("Fields in the same oneof must be defined consecutively. \"{}\" cannot be \
+                                     defined before the completion of the \"{}\" oneof definition.",
+                                    message.field[i - 1].name(), message.oneof_decl[oneof_index].name());
+                self.add_error(&child(path, tag::message::FIELD, i - 1), ErrorLocation::Type, &error);
+            }
+            oneof_field_counts[oneof_index] += 1;
+        }
+        for (i, &count) in oneof_field_counts.iter().enumerate() {
+            if count == 0 {
+                self.add_error(&child(path, tag::message::ONEOF_DECL, i), ErrorLocation::Name,
+                               "Oneof must have at least one field.");
+            }
+        }
+    }
+
+    fn cross_link_field(&mut self, path: &[i32], scope: &str) {
+        let mut field = field(&self.proto, path).clone();
+        let full_name = join(scope, field.name());
+        let is_extension = is_extension_path(path);
+        let mut containing_type = if is_extension { None } else { Some(scope.to_string()) };
+
+        if let Some(extendee) = field.extendee.clone() {
+            let (extendee_name, symbol) = match self.lookup_symbol(&extendee, &full_name, false) {
+                Some(resolved) => resolved,
+                None => {
+                    self.add_not_defined_error(path, ErrorLocation::Extendee, &extendee);
+                    return;
+                },
+            };
+            if symbol.kind != SymbolKind::Message {
+                self.add_error(path, ErrorLocation::Extendee, &format!("\"{}\" is not a message type.", extendee));
+                return;
+            }
+
+            let declares_number = self.symbol_message(&symbol)
+                                      .extension_range
+                                      .iter()
+                                      .any(|range| range.start() <= field.number() && field.number() < range.end());
+            if !declares_number {
+                let message = format!("\"{}\" does not declare {} as an extension number.",
+                                      extendee_name, field.number());
+                self.add_error(path, ErrorLocation::Number, &message);
+            }
+
+            field.extendee = Some(format!(".{}", extendee_name));
+            containing_type = Some(extendee_name);
+        }
+
+        if let Some(type_name) = field.type_name.clone() {
+            let (resolved_name, symbol) = match self.lookup_symbol(&type_name, &full_name, true) {
+                Some(resolved) => resolved,
+                None => {
+                    self.add_not_defined_error(path, ErrorLocation::Type, &type_name);
+                    return;
+                },
+            };
+
+            if field.type_.is_none() {
+                // The parser could not tell whether the type is a message or an enum.
+                match symbol.kind {
+                    SymbolKind::Message => field.type_ = Some(Type::Message as i32),
+                    SymbolKind::Enum => field.type_ = Some(Type::Enum as i32),
+                    _ => {
+                        self.add_error(path, ErrorLocation::Type, &format!("\"{}\" is not a type.", type_name));
+                        return;
+                    },
+                }
+            }
+
+            match field.type_() {
+                Type::Message | Type::Group => {
+                    if symbol.kind != SymbolKind::Message {
+                        self.add_error(path, ErrorLocation::Type,
+                                       &format!("\"{}\" is not a message type.", type_name));
+                        return;
+                    }
+                    if field.default_value.is_some() {
+                        self.add_error(path, ErrorLocation::DefaultValue, "Messages can't have default values.");
+                    }
+                    field.type_name = Some(format!(".{}", resolved_name));
+                },
+                Type::Enum => {
+                    if symbol.kind != SymbolKind::Enum {
+                        self.add_error(path, ErrorLocation::Type,
+                                       &format!("\"{}\" is not an enum type.", type_name));
+                        return;
+                    }
+                    if let Some(default_value) = field.default_value.clone() {
+                        self.check_enum_default(path, &resolved_name, &symbol, &default_value);
+                    }
+                    field.type_name = Some(format!(".{}", resolved_name));
+                },
+                _ => {
+                    self.add_error(path, ErrorLocation::Type, "Field with primitive type has type_name.");
+                },
+            }
+        } else if field.type_ == Some(Type::Message as i32) ||
+                  field.type_ == Some(Type::Group as i32) ||
+                  field.type_ == Some(Type::Enum as i32) {
+            self.add_error(path, ErrorLocation::Type, "Field with message or enum type missing type_name.");
+        }
+
+        if let Some(containing_type) = containing_type {
+            let key = (containing_type.clone(), field.number());
+            let conflict = self.fields_by_number.get(&key).cloned();
+            match conflict {
+                Some((conflict_name, conflict_full_name)) => {
+                    let message = if is_extension {
+                        format!("Extension number {} has already been used in \"{}\" by extension \"{}\".",
+                                field.number(), containing_type, conflict_full_name)
+                    } else {
+                        format!("Field number {} has already been used in \"{}\" by field \"{}\".",
+                                field.number(), containing_type, conflict_name)
+                    };
+                    self.add_error(path, ErrorLocation::Number, &message);
+                },
+                None => {
+                    self.fields_by_number.insert(key, (field.name().to_string(), full_name));
+                },
+            }
+        }
+
+        *field_mut(&mut self.proto, path) = field;
+    }
+
+    /// Checks that the default value of an enum field names a value of the enum.
+    fn check_enum_default(&mut self, path: &[i32], enum_name: &str, enum_symbol: &Symbol, default_value: &str) {
+        if !tokenizer::is_identifier(default_value) {
+            self.add_error(path, ErrorLocation::DefaultValue,
+                           "Default value for an enum field must be an identifier.");
+            return;
+        }
+
+        let is_value = match self.lookup_symbol(default_value, enum_name, false) {
+            Some((_, ref value)) => {
+                value.kind == SymbolKind::EnumValue &&
+                value.file == enum_symbol.file &&
+                value.path.len() == enum_symbol.path.len() + 2 &&
+                value.path.starts_with(&enum_symbol.path)
+            },
+            None => false,
+        };
+        if !is_value {
+            let message = format!("Enum type \"{}\" has no value named \"{}\".", enum_name, default_value);
+            self.add_error(path, ErrorLocation::DefaultValue, &message);
+        }
+    }
+
+    fn cross_link_service(&mut self, path: &[i32]) {
+        let (full_name, methods) = {
+            let service = &self.proto.service[path[1] as usize];
+            (join(&self.package, service.name()), service.method.len())
+        };
+        for i in 0..methods {
+            self.cross_link_method(&child(path, tag::service::METHOD, i), &full_name);
+        }
+    }
+
+    fn cross_link_method(&mut self, path: &[i32], scope: &str) {
+        let mut method = method(&self.proto, path).clone();
+        let full_name = join(scope, method.name());
+
+        let input_type = method.input_type().to_string();
+        if let Some(resolved_name) = self.cross_link_method_type(path, &full_name, &input_type, ErrorLocation::InputType) {
+            method.input_type = Some(format!(".{}", resolved_name));
+        }
+        let output_type = method.output_type().to_string();
+        if let Some(resolved_name) = self.cross_link_method_type(path, &full_name, &output_type, ErrorLocation::OutputType) {
+            method.output_type = Some(format!(".{}", resolved_name));
+        }
+
+        *method_mut(&mut self.proto, path) = method;
+    }
+
+    fn cross_link_method_type(&mut self,
+                              path: &[i32],
+                              full_name: &str,
+                              type_name: &str,
+                              location: ErrorLocation) -> Option<String> {
+        match self.lookup_symbol(type_name, full_name, false) {
+            None => {
+                self.add_not_defined_error(path, location, type_name);
+                None
+            },
+            Some((_, ref symbol)) if symbol.kind != SymbolKind::Message => {
+                self.add_error(path, location, &format!("\"{}\" is not a message type.", type_name));
+                None
+            },
+            Some((resolved_name, _)) => Some(resolved_name),
+        }
+    }
+
+    // Options.
+
+    fn interpret_options(&mut self) {
+        for target in mem::replace(&mut self.options_to_interpret, Vec::new()) {
+            match target.kind {
+                OptionsKind::File => options::interpret(self, &target, |file, _| &mut file.options),
+                OptionsKind::Message => {
+                    options::interpret(self, &target, |file, path| &mut message_mut(file, path).options)
+                },
+                OptionsKind::Field => {
+                    options::interpret(self, &target, |file, path| &mut field_mut(file, path).options)
+                },
+                OptionsKind::Oneof => {
+                    options::interpret(self, &target, |file, path| {
+                        let (parent, index) = split_path(path);
+                        &mut message_mut(file, parent).oneof_decl[index].options
+                    })
+                },
+                OptionsKind::ExtensionRange => {
+                    options::interpret(self, &target, |file, path| {
+                        let (parent, index) = split_path(path);
+                        &mut message_mut(file, parent).extension_range[index].options
+                    })
+                },
+                OptionsKind::Enum => {
+                    options::interpret(self, &target, |file, path| &mut enumeration_mut(file, path).options)
+                },
+                OptionsKind::EnumValue => {
+                    options::interpret(self, &target, |file, path| {
+                        let (parent, index) = split_path(path);
+                        &mut enumeration_mut(file, parent).value[index].options
+                    })
+                },
+                OptionsKind::Service => {
+                    options::interpret(self, &target, |file, path| &mut file.service[path[1] as usize].options)
+                },
+                OptionsKind::Method => {
+                    options::interpret(self, &target, |file, path| &mut method_mut(file, path).options)
+                },
+            }
+        }
+    }
+
+    /// Returns the file being built, for interpreting its options.
+    pub fn proto_mut(&mut self) -> &mut FileDescriptorProto {
+        &mut self.proto
+    }
+
+    /// Records the source code info path of an interpreted option.
+    ///
+    /// Values of repeated options are given consecutive indices.
+    pub fn record_interpreted_path(&mut self, src_path: Vec<i32>, mut dest_path: Vec<i32>, repeated: bool) {
+        if repeated {
+            let count = self.repeated_option_counts.entry(dest_path.clone()).or_insert(0);
+            dest_path.push(*count);
+            *count += 1;
+        }
+        self.interpreted_paths.insert(src_path, dest_path);
+    }
+
+    /// Replaces the paths of the locations of uninterpreted options with the paths of the
+    /// interpreted options, and removes the locations within them.
+    fn update_source_code_info(&mut self) {
+        if self.interpreted_paths.is_empty() {
+            return;
+        }
+        let info = match self.proto.source_code_info {
+            Some(ref mut info) => info,
+            None => return,
+        };
+
+        let mut locations = Vec::with_capacity(info.location.len());
+        let mut matched: Option<Vec<i32>> = None;
+        for mut location in info.location.drain(..) {
+            if let Some(ref prefix) = matched {
+                if location.path.starts_with(prefix) {
+                    continue;
+                }
+            }
+            matched = None;
+
+            if let Some(dest_path) = self.interpreted_paths.get(&location.path) {
+                matched = Some(location.path.clone());
+                location.path = dest_path.clone();
+            }
+            locations.push(location);
+        }
+        info.location = locations;
+    }
+
+    // Validation.
+
+    fn validate_file_options(&mut self) {
+        for i in 0..self.proto.message_type.len() {
+            self.validate_message_options(&[tag::file::MESSAGE_TYPE, i as i32]);
+        }
+        for i in 0..self.proto.enum_type.len() {
+            self.validate_enum_options(&[tag::file::ENUM_TYPE, i as i32], &self.package.clone());
+        }
+        for i in 0..self.proto.service.len() {
+            self.validate_service_options(&[tag::file::SERVICE, i as i32]);
+        }
+        for i in 0..self.proto.extension.len() {
+            self.validate_field_options(&[tag::file::EXTENSION, i as i32], &self.package.clone());
+        }
+
+        // Lite files can only be imported by other lite files.
+        if !is_lite(&self.proto) {
+            let lite_dependency = self.proto
+                                      .dependency
+                                      .iter()
+                                      .enumerate()
+                                      .filter(|&(_, name)| self.pool.file(name).map(is_lite).unwrap_or(false))
+                                      .map(|(i, name)| (i, name.clone()))
+                                      .next();
+            if let Some((i, name)) = lite_dependency {
+                let message = format!("Files that do not use optimize_for = LITE_RUNTIME cannot import files \
+                                       which do use this option.  This file is not lite, but it imports \"{}\" \
+                                       which is.",
+                                      name);
+                self.add_import_error(i, &message);
+            }
+        }
+
+        if self.proto.syntax() == "proto3" {
+            self.validate_proto3();
+        }
+    }
+
+    fn validate_message_options(&mut self, path: &[i32]) {
+        let message = message(&self.proto, path).clone();
+        let scope = self.scope_of(path);
+        let full_name = join(&scope, message.name());
+
+        for i in 0..message.field.len() {
+            self.validate_field_options(&child(path, tag::message::FIELD, i), &full_name);
+        }
+        for i in 0..message.nested_type.len() {
+            self.validate_message_options(&child(path, tag::message::NESTED_TYPE, i));
+        }
+        for i in 0..message.enum_type.len() {
+            self.validate_enum_options(&child(path, tag::message::ENUM_TYPE, i), &full_name);
+        }
+        for i in 0..message.extension.len() {
+            self.validate_field_options(&child(path, tag::message::EXTENSION, i), &full_name);
+        }
+
+        let max_extension_number = if is_message_set(&message) { i32::MAX } else { MAX_FIELD_NUMBER };
+        for (i, range) in message.extension_range.iter().enumerate() {
+            if i64::from(range.end()) > i64::from(max_extension_number) + 1 {
+                self.add_error(&child(path, tag::message::EXTENSION_RANGE, i), ErrorLocation::Number,
+                               &format!("Extension numbers cannot be greater than {}.", max_extension_number));
+            }
+        }
+    }
+
+    fn validate_field_options(&mut self, path: &[i32], scope: &str) {
+        let field = field(&self.proto, path).clone();
+        let is_extension = is_extension_path(path);
+        let options = field.options.clone().unwrap_or_default();
+
+        if options.lazy() && field.type_() != Type::Message {
+            self.add_error(path, ErrorLocation::Type, "[lazy = true] can only be specified for submessage fields.");
+        }
+
+        if options.packed() && !(field.label() == Label::Repeated && is_packable(field.type_())) {
+            self.add_error(path, ErrorLocation::Type,
+                           "[packed = true] can only be specified for repeated primitive fields.");
+        }
+
+        let containing_type = if is_extension { field.extendee()[1..].to_string() } else { scope.to_string() };
+        if let Some(symbol) = self.find_symbol_not_enforcing_deps(&containing_type) {
+            if is_message_set(self.symbol_message(&symbol)) {
+                if !is_extension {
+                    self.add_error(path, ErrorLocation::Name, "MessageSets cannot have fields, only extensions.");
+                } else if field.label() != Label::Optional || field.type_() != Type::Message {
+                    self.add_error(path, ErrorLocation::Type, "Extensions of MessageSets must be optional messages.");
+                }
+            }
+
+            if is_lite(&self.proto) && !is_lite(self.file_proto(symbol.file)) {
+                self.add_error(path, ErrorLocation::Extendee,
+                               "Extensions to non-lite types can only be declared in non-lite files.  Note that \
+                                you cannot extend a non-lite type to contain a lite type, but the reverse is \
+                                allowed.");
+            }
+        }
+
+        if field.type_() == Type::Message {
+            let entry = self.find_symbol_not_enforcing_deps(&field.type_name()[1..]);
+            if let Some(entry) = entry {
+                let is_map_entry = self.symbol_message(&entry)
+                                       .options
+                                       .as_ref()
+                                       .map(|options| options.map_entry())
+                                       .unwrap_or(false);
+                if is_map_entry && !self.validate_map_entry(path, &field, &containing_type, &entry) {
+                    self.add_error(path, ErrorLocation::Other,
+                                   "map_entry should not be set explicitly. Use map<KeyType, ValueType> instead.");
+                }
+            }
+        }
+
+        self.validate_js_type(path, &field, &options);
+    }
+
+    fn validate_map_entry(&mut self,
+                          path: &[i32],
+                          field: &FieldDescriptorProto,
+                          containing_type: &str,
+                          entry_symbol: &Symbol) -> bool {
+        let entry = self.symbol_message(entry_symbol).clone();
+        let entry_scope = {
+            let name = &field.type_name()[1..];
+            name[..name.rfind('.').unwrap_or(0)].to_string()
+        };
+
+        if !entry.extension.is_empty() ||
+           field.label() != Label::Repeated ||
+           !entry.extension_range.is_empty() ||
+           !entry.nested_type.is_empty() ||
+           entry.field.len() != 2 ||
+           entry.name() != format!("{}Entry", to_camel_case(field.name())) ||
+           entry_scope != containing_type {
+            return false;
+        }
+
+        let key = &entry.field[0];
+        let value = &entry.field[1];
+        if key.label() != Label::Optional || key.number() != 1 || key.name() != "key" {
+            return false;
+        }
+        if value.label() != Label::Optional || value.number() != 2 || value.name() != "value" {
+            return false;
+        }
+
+        match key.type_() {
+            Type::Enum => {
+                self.add_error(path, ErrorLocation::Type, "Key in map fields cannot be enum types.");
+            },
+            Type::Float | Type::Double | Type::Message | Type::Group | Type::Bytes => {
+                self.add_error(path, ErrorLocation::Type,
+                               "Key in map fields cannot be float/double, bytes or message types.");
+            },
+            _ => (),
+        }
+
+        if value.type_() == Type::Enum {
+            let first_value_is_zero = self.find_symbol_not_enforcing_deps(&value.type_name()[1..])
+                                          .map(|symbol| {
+                                              let proto = self.file_proto(symbol.file);
+                                              enumeration(proto, &symbol.path).value
+                                                                                .first()
+                                                                                .map(|value| value.number() == 0)
+                                                                                .unwrap_or(false)
+                                          })
+                                          .unwrap_or(false);
+            if !first_value_is_zero {
+                self.add_error(path, ErrorLocation::Type, "Enum value in map must define 0 as the first value.");
+            }
+        }
+
+        true
+    }
+
+    fn validate_js_type(&mut self, path: &[i32], field: &FieldDescriptorProto, options: &::prost_types::FieldOptions) {
+        let js_type = options.jstype();
+        if js_type == JsType::JsNormal {
+            return;
+        }
+
+        match field.type_() {
+            // 64-bit integers may be represented as JavaScript numbers or strings.
+            Type::Uint64 | Type::Int64 | Type::Sint64 | Type::Fixed64 | Type::Sfixed64 => (),
+            _ => {
+                self.add_error(path, ErrorLocation::Type,
+                               "jstype is only allowed on int64, uint64, sint64, fixed64 or sfixed64 fields.");
+            },
+        }
+    }
+
+    fn validate_enum_options(&mut self, path: &[i32], scope: &str) {
+        let enumeration = enumeration(&self.proto, path).clone();
+        let allow_alias = enumeration.options.as_ref().map(|options| options.allow_alias()).unwrap_or(false);
+        if allow_alias {
+            return;
+        }
+
+        let mut used_values: HashMap<i32, String> = HashMap::new();
+        for value in &enumeration.value {
+            let full_name = join(scope, value.name());
+            if let Some(existing) = used_values.get(&value.number()) {
+                let message = format!("\"{}\" uses the same enum value as \"{}\". If this is intended, set \
+                                       'option allow_alias = true;' to the enum definition.",
+                                      full_name, existing);
+                self.add_error(path, ErrorLocation::Number, &message);
+                continue;
+            }
+            used_values.insert(value.number(), full_name);
+        }
+    }
+
+    fn validate_service_options(&mut self, path: &[i32]) {
+        let file_options = self.proto.options.clone().unwrap_or_default();
+        if is_lite(&self.proto) && (file_options.cc_generic_services() || file_options.java_generic_services()) {
+            self.add_error(path, ErrorLocation::Name,
+                           "Files with optimize_for = LITE_RUNTIME cannot define services unless you set both \
+                            options cc_generic_services and java_generic_services to false.");
+        }
+    }
+
+    fn validate_proto3(&mut self) {
+        let package = self.package.clone();
+        for i in 0..self.proto.extension.len() {
+            self.validate_proto3_field(&[tag::file::EXTENSION, i as i32], &package);
+        }
+        for i in 0..self.proto.message_type.len() {
+            self.validate_proto3_message(&[tag::file::MESSAGE_TYPE, i as i32], &package);
+        }
+        for i in 0..self.proto.enum_type.len() {
+            self.validate_proto3_enum(&[tag::file::ENUM_TYPE, i as i32]);
+        }
+    }
+
+    fn validate_proto3_message(&mut self, path: &[i32], scope: &str) {
+        let message = message(&self.proto, path).clone();
+        let full_name = join(scope, message.name());
+
+        for i in 0..message.nested_type.len() {
+            self.validate_proto3_message(&child(path, tag::message::NESTED_TYPE, i), &full_name);
+        }
+        for i in 0..message.enum_type.len() {
+            self.validate_proto3_enum(&child(path, tag::message::ENUM_TYPE, i));
+        }
+        for i in 0..message.field.len() {
+            self.validate_proto3_field(&child(path, tag::message::FIELD, i), &full_name);
+        }
+        for i in 0..message.extension.len() {
+            self.validate_proto3_field(&child(path, tag::message::EXTENSION, i), &full_name);
+        }
+        if !message.extension_range.is_empty() {
+            self.add_error(path, ErrorLocation::Other, "Extension ranges are not allowed in proto3.");
+        }
+        if is_message_set(&message) {
+            self.add_error(path, ErrorLocation::Other, "MessageSet is not supported in proto3.");
+        }
+
+        // Field names must be unique after being converted to lowercase with underscores removed,
+        // which is stricter than their JSON names being unique.
+        let mut names: HashMap<String, &str> = HashMap::new();
+        for (i, field) in message.field.iter().enumerate() {
+            let name = field.name().replace('_', "").to_lowercase();
+            if let Some(existing) = names.get(&name).cloned() {
+                let error = format!("The JSON camel-case name of field \"{}\" conflicts with field \"{}\". This is \
+                                     not allowed in proto3.",
+                                    field.name(), existing);
+                self.add_error(&child(path, tag::message::FIELD, i), ErrorLocation::Other, &error);
+                continue;
+            }
+            names.insert(name, field.name());
+        }
+    }
+
+    fn validate_proto3_field(&mut self, path: &[i32], scope: &str) {
+        let field = field(&self.proto, path).clone();
+        let is_extension = is_extension_path(path);
+        let containing_type = if is_extension { field.extendee()[1..].to_string() } else { scope.to_string() };
+
+        if is_extension && !is_allowed_proto3_extendee(&containing_type) {
+            self.add_error(path, ErrorLocation::Other, "Extensions in proto3 are only allowed for defining options.");
+        }
+        if field.label() == Label::Required {
+            self.add_error(path, ErrorLocation::Other, "Required fields are not allowed in proto3.");
+        }
+        if field.default_value.is_some() {
+            self.add_error(path, ErrorLocation::Other, "Explicit default values are not allowed in proto3.");
+        }
+        if field.type_() == Type::Enum {
+            // Proto3 messages can only use proto3 enums, whose default value is zero.
+            let enum_name = &field.type_name()[1..];
+            let is_proto3 = self.find_symbol_not_enforcing_deps(enum_name)
+                                .map(|symbol| self.file_proto(symbol.file).syntax() == "proto3")
+                                .unwrap_or(true);
+            if !is_proto3 {
+                let message = format!("Enum type \"{}\" is not a proto3 enum, but is used in \"{}\" which is a \
+                                       proto3 message type.",
+                                      enum_name, containing_type);
+                self.add_error(path, ErrorLocation::Type, &message);
+            }
+        }
+        if field.type_() == Type::Group {
+            self.add_error(path, ErrorLocation::Type, "Groups are not supported in proto3 syntax.");
+        }
+    }
+
+    fn validate_proto3_enum(&mut self, path: &[i32]) {
+        let first_number = enumeration(&self.proto, path).value.first().map(|value| value.number());
+        if first_number.map(|number| number != 0).unwrap_or(false) {
+            self.add_error(&child(path, tag::enumeration::VALUE, 0), ErrorLocation::Number,
+                           "The first enum value must be zero in proto3.");
+        }
+    }
+
+    // Normalization.
+
+    /// Normalizes the file the way `protoc` writes descriptors: only proto3 files have a syntax,
+    /// every field has a JSON name, and default values are formatted canonically.
+    fn normalize(&mut self) {
+        if self.proto.syntax() != "proto3" {
+            self.proto.syntax = None;
+        }
+        for message in &mut self.proto.message_type {
+            normalize_message(message);
+        }
+        for extension in &mut self.proto.extension {
+            normalize_field(extension);
+        }
+    }
+
+    // Helpers.
+
+    /// Returns the message which a message symbol refers to.
+    pub fn symbol_message(&self, symbol: &Symbol) -> &DescriptorProto {
+        message(self.file_proto(symbol.file), &symbol.path)
+    }
+
+    /// Returns the fully-qualified name of the scope which contains the element at the path.
+    fn scope_of(&self, path: &[i32]) -> String {
+        let mut scope = self.package.clone();
+        let mut message: Option<&DescriptorProto> = None;
+        for pair in path[..path.len() - 2].chunks(2) {
+            let next = match message {
+                None => &self.proto.message_type[pair[1] as usize],
+                Some(message) => &message.nested_type[pair[1] as usize],
+            };
+            scope = join(&scope, next.name());
+            message = Some(next);
+        }
+        scope
+    }
+}
+
+/// Formats an error in the format of `protoc`.
+pub fn format_error(file: &str, position: Option<(i32, i32)>, message: &str) -> String {
+    match position {
+        Some((line, column)) => format!("{}:{}:{}: {}", file, line + 1, column + 1, message),
+        None => format!("{}: {}", file, message),
+    }
+}
+
+/// Finds the start of the first location with the path in the source code info of a file.
+pub fn find_location(proto: &FileDescriptorProto, path: &[i32]) -> Option<(i32, i32)> {
+    proto.source_code_info
+         .as_ref()?
+         .location
+         .iter()
+         .find(|location| location.path == path && location.span.len() >= 2)
+         .map(|location| (location.span[0], location.span[1]))
+}
+
+/// Joins a scope and a name into a fully-qualified name.
+pub fn join(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// Returns the path of the child of an element.
+fn child(path: &[i32], field_number: i32, index: usize) -> Vec<i32> {
+    let mut path = path.to_vec();
+    path.push(field_number);
+    path.push(index as i32);
+    path
+}
+
+/// Splits the path of a repeated element into the path of its parent and its index.
+fn split_path(path: &[i32]) -> (&[i32], usize) {
+    (&path[..path.len() - 2], path[path.len() - 1] as usize)
+}
+
+fn element_at(path: &[i32]) -> Element {
+    let mut element = Element::File;
+    for pair in path.chunks(2) {
+        element = match (element, pair[0]) {
+            (Element::File, tag::file::MESSAGE_TYPE) |
+            (Element::Message, tag::message::NESTED_TYPE) => Element::Message,
+            (Element::File, tag::file::ENUM_TYPE) |
+            (Element::Message, tag::message::ENUM_TYPE) => Element::Enum,
+            (Element::File, tag::file::SERVICE) => Element::Service,
+            (Element::File, tag::file::EXTENSION) |
+            (Element::Message, tag::message::FIELD) |
+            (Element::Message, tag::message::EXTENSION) => Element::Field,
+            (Element::Message, tag::message::ONEOF_DECL) => Element::Oneof,
+            (Element::Message, tag::message::EXTENSION_RANGE) => Element::ExtensionRange,
+            (Element::Message, tag::message::RESERVED_RANGE) => Element::ReservedRange,
+            (Element::Enum, tag::enumeration::VALUE) => Element::EnumValue,
+            (Element::Enum, tag::enumeration::RESERVED_RANGE) => Element::EnumReservedRange,
+            (Element::Service, tag::service::METHOD) => Element::Method,
+            _ => panic!("invalid element path: {:?}", path),
+        };
+    }
+    element
+}
+
+fn is_extension_path(path: &[i32]) -> bool {
+    let tag = path[path.len() - 2];
+    if path.len() == 2 {
+        tag == tag::file::EXTENSION
+    } else {
+        tag == tag::message::EXTENSION
+    }
+}
+
+/// Returns the message at a path, which must consist of message types and nested types.
+pub fn message<'p>(file: &'p FileDescriptorProto, path: &[i32]) -> &'p DescriptorProto {
+    let mut message = &file.message_type[path[1] as usize];
+    for pair in path[2..].chunks(2) {
+        message = &message.nested_type[pair[1] as usize];
+    }
+    message
+}
+
+fn message_mut<'p>(file: &'p mut FileDescriptorProto, path: &[i32]) -> &'p mut DescriptorProto {
+    let mut message = &mut file.message_type[path[1] as usize];
+    for pair in path[2..].chunks(2) {
+        message = &mut message.nested_type[pair[1] as usize];
+    }
+    message
+}
+
+/// Returns the field or extension at a path.
+pub fn field<'p>(file: &'p FileDescriptorProto, path: &[i32]) -> &'p FieldDescriptorProto {
+    let (parent, index) = split_path(path);
+    if parent.is_empty() {
+        &file.extension[index]
+    } else if path[path.len() - 2] == tag::message::FIELD {
+        &message(file, parent).field[index]
+    } else {
+        &message(file, parent).extension[index]
+    }
+}
+
+fn field_mut<'p>(file: &'p mut FileDescriptorProto, path: &[i32]) -> &'p mut FieldDescriptorProto {
+    let (parent, index) = split_path(path);
+    if parent.is_empty() {
+        &mut file.extension[index]
+    } else if path[path.len() - 2] == tag::message::FIELD {
+        &mut message_mut(file, parent).field[index]
+    } else {
+        &mut message_mut(file, parent).extension[index]
+    }
+}
+
+/// Returns the enum at a path.
+pub fn enumeration<'p>(file: &'p FileDescriptorProto, path: &[i32]) -> &'p EnumDescriptorProto {
+    let (parent, index) = split_path(path);
+    if parent.is_empty() {
+        &file.enum_type[index]
+    } else {
+        &message(file, parent).enum_type[index]
+    }
+}
+
+fn enumeration_mut<'p>(file: &'p mut FileDescriptorProto, path: &[i32]) -> &'p mut EnumDescriptorProto {
+    let (parent, index) = split_path(path);
+    if parent.is_empty() {
+        &mut file.enum_type[index]
+    } else {
+        &mut message_mut(file, parent).enum_type[index]
+    }
+}
+
+fn method<'p>(file: &'p FileDescriptorProto, path: &[i32]) -> &'p MethodDescriptorProto {
+    &file.service[path[1] as usize].method[path[3] as usize]
+}
+
+fn method_mut<'p>(file: &'p mut FileDescriptorProto, path: &[i32]) -> &'p mut MethodDescriptorProto {
+    &mut file.service[path[1] as usize].method[path[3] as usize]
+}
+
+fn normalize_message(message: &mut DescriptorProto) {
+    for field in &mut message.field {
+        normalize_field(field);
+    }
+    for nested in &mut message.nested_type {
+        normalize_message(nested);
+    }
+    for extension in &mut message.extension {
+        normalize_field(extension);
+    }
+}
+
+fn normalize_field(field: &mut FieldDescriptorProto) {
+    if field.json_name.is_none() {
+        field.json_name = Some(to_json_name(field.name()));
+    }
+
+    let default_value = match field.default_value {
+        Some(ref default_value) => match field.type_() {
+            Type::Float => parse_floating_point(default_value).map(|value| simple_ftoa(value as f32)),
+            Type::Double => parse_floating_point(default_value).map(simple_dtoa),
+            _ => None,
+        },
+        None => None,
+    };
+    if default_value.is_some() {
+        field.default_value = default_value;
+    }
+}
+
+/// Parses a floating point default value, which may also be `inf`, `-inf` or `nan`.
+fn parse_floating_point(value: &str) -> Option<f64> {
+    match value {
+        "inf" => Some(::std::f64::INFINITY),
+        "-inf" => Some(::std::f64::NEG_INFINITY),
+        "nan" => Some(::std::f64::NAN),
+        _ => value.parse().ok(),
+    }
+}
+
+/// Converts a field name to its default JSON name, like `ToJsonName`.
+fn to_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize_next = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            json_name.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+/// Converts a field name to upper camel case, like `ToCamelCase`.
+fn to_camel_case(name: &str) -> String {
+    let mut camel_case = String::with_capacity(name.len());
+    let mut capitalize_next = true;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            camel_case.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            camel_case.push(c);
+        }
+    }
+    camel_case
+}
+
+fn is_in_package(package: &str, name: &str) -> bool {
+    package.starts_with(name) && (package.len() == name.len() || package.as_bytes()[name.len()] == b'.')
+}
+
+fn is_lite(file: &FileDescriptorProto) -> bool {
+    file.options
+        .as_ref()
+        .map(|options| options.optimize_for() == OptimizeMode::LiteRuntime)
+        .unwrap_or(false)
+}
+
+fn is_message_set(message: &DescriptorProto) -> bool {
+    message.options.as_ref().map(|options| options.message_set_wire_format()).unwrap_or(false)
+}
+
+fn is_packable(field_type: Type) -> bool {
+    match field_type {
+        Type::String | Type::Group | Type::Message | Type::Bytes => false,
+        _ => true,
+    }
+}
+
+/// Returns `true` if proto3 files may declare extensions of the message, which is only allowed for
+/// custom options.
+fn is_allowed_proto3_extendee(name: &str) -> bool {
+    const OPTIONS: &[&str] = &[
+        "google.protobuf.FileOptions",
+        "google.protobuf.MessageOptions",
+        "google.protobuf.FieldOptions",
+        "google.protobuf.EnumOptions",
+        "google.protobuf.EnumValueOptions",
+        "google.protobuf.ServiceOptions",
+        "google.protobuf.MethodOptions",
+        "google.protobuf.OneofOptions",
+    ];
+    OPTIONS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::UnknownValue;
+    use compiler::parser;
+
+    fn link(files: &[(&str, &str)]) -> Result<Pool, Vec<String>> {
+        let fallback = ::compiler::descriptor_pool();
+        let mut pool = Pool::new();
+        for &(name, source) in files {
+            let mut proto = parser::parse(source.as_bytes()).expect("parse failed");
+            proto.name = Some(name.to_string());
+            pool.add_file(proto, &fallback)?;
+        }
+        Ok(pool)
+    }
+
+    fn link_error(files: &[(&str, &str)]) -> Vec<String> {
+        match link(files) {
+            Ok(_) => panic!("expected link failure"),
+            Err(errors) => errors,
+        }
+    }
+
+    #[test]
+    fn test_resolve_types() {
+        let pool = link(&[
+            ("a.proto", "syntax = \"proto3\"; package foo.bar; message A { message B {} }"),
+            ("b.proto", "package foo.baz;
+                         import \"a.proto\";
+                         message C {
+                           optional bar.A a = 1;
+                           optional .foo.bar.A.B b = 2;
+                           optional C c = 3;
+                           optional E e = 4 [default = Y];
+                           enum E { X = 1; Y = 2; }
+                           optional float f = 5 [default = 1e1];
+                           optional double d = 6 [default = -inf];
+                         }"),
+        ]).unwrap();
+
+        let file = pool.file("a.proto").unwrap();
+        assert_eq!(file.syntax(), "proto3");
+
+        let file = pool.file("b.proto").unwrap();
+        assert_eq!(file.syntax, None);
+        let fields = &file.message_type[0].field;
+        assert_eq!(fields[0].type_name(), ".foo.bar.A");
+        assert_eq!(fields[0].type_(), Type::Message);
+        assert_eq!(fields[1].type_name(), ".foo.bar.A.B");
+        assert_eq!(fields[2].type_name(), ".foo.baz.C");
+        assert_eq!(fields[3].type_name(), ".foo.baz.C.E");
+        assert_eq!(fields[3].type_(), Type::Enum);
+        assert_eq!(fields[3].default_value(), "Y");
+        assert_eq!(fields[3].json_name(), "e");
+        assert_eq!(fields[4].default_value(), "10");
+        assert_eq!(fields[5].default_value(), "-inf");
+    }
+
+    #[test]
+    fn test_options() {
+        let pool = link(&[
+            ("google/protobuf/descriptor.proto", ::compiler::DESCRIPTOR_PROTO),
+            ("options.proto", "syntax = \"proto3\";
+                               import \"google/protobuf/descriptor.proto\";
+                               message Custom { int32 a = 1; string b = 2; }
+                               extend google.protobuf.FileOptions { Custom custom = 50000; }
+                               extend google.protobuf.FieldOptions { repeated int32 tags = 50001; }
+                               extend google.protobuf.MessageOptions { Custom message_custom = 50002; }"),
+            ("test.proto", "syntax = \"proto3\";
+                            import \"options.proto\";
+                            option java_package = \"com.example\";
+                            option optimize_for = CODE_SIZE;
+                            option (custom).a = 1;
+                            option (custom).b = \"x\";
+                            message M {
+                              option (message_custom) = { a: 2 b: \"z\" };
+                              repeated int32 x = 1 [packed = false, (tags) = 1, (tags) = 2];
+                            }"),
+        ]).unwrap();
+
+        let file = pool.file("test.proto").unwrap();
+        let options = file.options.as_ref().unwrap();
+        assert_eq!(options.java_package(), "com.example");
+        assert_eq!(options.optimize_for(), OptimizeMode::CodeSize);
+        assert!(options.uninterpreted_option.is_empty());
+
+        let custom = options.unknown_fields.iter().map(|field| (field.tag(), field.value().clone())).collect::<Vec<_>>();
+        assert_eq!(custom, vec![
+            (50000, UnknownValue::LengthDelimited(vec![0x08, 0x01])),
+            (50000, UnknownValue::LengthDelimited(vec![0x12, 0x01, b'x'])),
+        ]);
+
+        let message_options = file.message_type[0].options.as_ref().unwrap();
+        let custom = message_options.unknown_fields.iter().map(|field| field.value().clone()).collect::<Vec<_>>();
+        assert_eq!(custom, vec![UnknownValue::LengthDelimited(vec![0x08, 0x02, 0x12, 0x01, b'z'])]);
+
+        let field_options = file.message_type[0].field[0].options.as_ref().unwrap();
+        assert_eq!(field_options.packed, Some(false));
+        assert!(field_options.uninterpreted_option.is_empty());
+
+        // The locations of the options point at the interpreted fields.
+        let paths = file.source_code_info
+                        .as_ref()
+                        .unwrap()
+                        .location
+                        .iter()
+                        .map(|location| location.path.clone())
+                        .collect::<Vec<_>>();
+        assert!(paths.contains(&vec![8, 1]));
+        assert!(paths.contains(&vec![8, 9]));
+        assert!(paths.contains(&vec![8, 50000, 1]));
+        assert!(paths.contains(&vec![8, 50000, 2]));
+        assert!(paths.contains(&vec![4, 0, 2, 0, 8, 50001, 0]));
+        assert!(paths.contains(&vec![4, 0, 2, 0, 8, 50001, 1]));
+        assert!(!paths.iter().any(|path| path.contains(&999)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(link_error(&[("a.proto", "message A { optional B b = 1; }")]),
+                   vec!["a.proto:1:22: \"B\" is not defined.".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "message A {}\nmessage A {}")]),
+                   vec!["a.proto:2:9: \"A\" is already defined.".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "message A { optional int32 a = 1; optional int32 b = 1; }")]),
+                   vec!["a.proto:1:54: Field number 1 has already been used in \"A\" by field \"a\".".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "package foo; enum E { A = 0; } enum F { A = 1; }")]),
+                   vec!["a.proto:1:41: \"A\" is already defined in \"foo\".".to_string(),
+                        "a.proto:1:41: Note that enum values use C++ scoping rules, meaning that enum values \
+                         are siblings of their type, not children of it.  Therefore, \"A\" must be unique \
+                         within \"foo\", not just within \"F\".".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "syntax = \"proto3\"; enum E { A = 1; }")]),
+                   vec!["a.proto:1:33: The first enum value must be zero in proto3.".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "message A { optional int32 a = 1 [(foo) = 1]; }")]),
+                   vec!["a.proto:1:35: Option \"(foo)\" unknown.".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "option java_package = 1;")]),
+                   vec!["a.proto:1:23: Value must be quoted string for string option \
+                         \"google.protobuf.FileOptions.java_package\".".to_string()]);
+
+        assert_eq!(link_error(&[("a.proto", "message A {}"), ("b.proto", "message B { optional A a = 1; }")]),
+                   vec!["b.proto:1:22: \"A\" seems to be defined in \"a.proto\", which is not imported by \
+                         \"b.proto\".  To use it here, please add the necessary import.".to_string()]);
+    }
+}
@@ -0,0 +1,498 @@
+//! A `.proto` compiler written in Rust.
+//!
+//! The compiler is a port of the parts of `protoc` which `prost-build` relies on: it parses
+//! `.proto` files and their imports, links them, interprets their options, and returns the same
+//! `FileDescriptorSet` which `protoc --include_imports --include_source_info` writes. Errors are
+//! reported in the `file:line:column: message` format of `protoc`.
+
+mod linker;
+mod options;
+mod parser;
+mod tokenizer;
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use prost_types::{FileDescriptorProto, FileDescriptorSet};
+
+use self::linker::Pool;
+
+/// The largest field number, `FieldDescriptor::kMaxNumber`.
+const MAX_FIELD_NUMBER: i32 = 536_870_911;
+
+/// The source of `descriptor.proto`, which is used to interpret the standard options of files
+/// which do not import it.
+const DESCRIPTOR_PROTO: &str = include_str!("../../third-party/protobuf/include/google/protobuf/descriptor.proto");
+
+/// The field numbers of the descriptor messages, which make up source code info paths.
+mod tag {
+    pub mod file {
+        pub const PACKAGE: i32 = 2;
+        pub const DEPENDENCY: i32 = 3;
+        pub const MESSAGE_TYPE: i32 = 4;
+        pub const ENUM_TYPE: i32 = 5;
+        pub const SERVICE: i32 = 6;
+        pub const EXTENSION: i32 = 7;
+        pub const OPTIONS: i32 = 8;
+        pub const PUBLIC_DEPENDENCY: i32 = 10;
+        pub const WEAK_DEPENDENCY: i32 = 11;
+        pub const SYNTAX: i32 = 12;
+    }
+
+    pub mod message {
+        pub const NAME: i32 = 1;
+        pub const FIELD: i32 = 2;
+        pub const NESTED_TYPE: i32 = 3;
+        pub const ENUM_TYPE: i32 = 4;
+        pub const EXTENSION_RANGE: i32 = 5;
+        pub const EXTENSION: i32 = 6;
+        pub const OPTIONS: i32 = 7;
+        pub const ONEOF_DECL: i32 = 8;
+        pub const RESERVED_RANGE: i32 = 9;
+        pub const RESERVED_NAME: i32 = 10;
+    }
+
+    pub mod extension_range {
+        pub const OPTIONS: i32 = 3;
+    }
+
+    /// The start and end of extension ranges and reserved ranges.
+    pub mod range {
+        pub const START: i32 = 1;
+        pub const END: i32 = 2;
+    }
+
+    pub mod field {
+        pub const NAME: i32 = 1;
+        pub const EXTENDEE: i32 = 2;
+        pub const NUMBER: i32 = 3;
+        pub const LABEL: i32 = 4;
+        pub const TYPE: i32 = 5;
+        pub const TYPE_NAME: i32 = 6;
+        pub const DEFAULT_VALUE: i32 = 7;
+        pub const OPTIONS: i32 = 8;
+        pub const JSON_NAME: i32 = 10;
+    }
+
+    pub mod oneof {
+        pub const NAME: i32 = 1;
+        pub const OPTIONS: i32 = 2;
+    }
+
+    pub mod enumeration {
+        pub const NAME: i32 = 1;
+        pub const VALUE: i32 = 2;
+        pub const OPTIONS: i32 = 3;
+        pub const RESERVED_RANGE: i32 = 4;
+        pub const RESERVED_NAME: i32 = 5;
+    }
+
+    pub mod enum_value {
+        pub const NAME: i32 = 1;
+        pub const NUMBER: i32 = 2;
+        pub const OPTIONS: i32 = 3;
+    }
+
+    pub mod service {
+        pub const NAME: i32 = 1;
+        pub const METHOD: i32 = 2;
+        pub const OPTIONS: i32 = 3;
+    }
+
+    pub mod method {
+        pub const NAME: i32 = 1;
+        pub const INPUT_TYPE: i32 = 2;
+        pub const OUTPUT_TYPE: i32 = 3;
+        pub const OPTIONS: i32 = 4;
+        pub const CLIENT_STREAMING: i32 = 5;
+        pub const SERVER_STREAMING: i32 = 6;
+    }
+
+    /// The field of every options message which holds the uninterpreted options.
+    pub mod options {
+        pub const UNINTERPRETED_OPTION: i32 = 999;
+    }
+
+    pub mod uninterpreted_option {
+        pub const NAME: i32 = 2;
+        pub const IDENTIFIER_VALUE: i32 = 3;
+        pub const POSITIVE_INT_VALUE: i32 = 4;
+        pub const NEGATIVE_INT_VALUE: i32 = 5;
+        pub const DOUBLE_VALUE: i32 = 6;
+        pub const STRING_VALUE: i32 = 7;
+        pub const AGGREGATE_VALUE: i32 = 8;
+    }
+
+    pub mod name_part {
+        pub const NAME_PART: i32 = 1;
+    }
+}
+
+/// Compiles `.proto` files into a file descriptor set.
+///
+/// The files, and the files they import, are searched for in the include directories. The
+/// returned set contains the files and all of their imports, with source code info, ordered so
+/// that every file follows its dependencies.
+pub fn compile(protos: &[PathBuf], includes: &[PathBuf]) -> io::Result<FileDescriptorSet> {
+    let mut compiler = Compiler {
+        includes: includes,
+        pool: Pool::new(),
+        fallback: descriptor_pool(),
+        pending: Vec::new(),
+        failed: HashSet::new(),
+        errors: Vec::new(),
+    };
+
+    let mut names = Vec::new();
+    for proto in protos {
+        let name = virtual_file_name(proto, includes)?;
+        if compiler.build(&name).is_err() {
+            return Err(Error::new(ErrorKind::Other, compiler.errors.join("\n")));
+        }
+        names.push(name);
+    }
+
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for name in &names {
+        add_transitive_dependencies(&compiler.pool, name, &mut visited, &mut files);
+    }
+    Ok(FileDescriptorSet { file: files })
+}
+
+/// Returns a pool containing `descriptor.proto`.
+///
+/// The standard options of files which do not import `descriptor.proto` are interpreted with the
+/// options messages of this pool, like `protoc` uses its compiled-in descriptors.
+fn descriptor_pool() -> Pool {
+    let mut proto = parser::parse(DESCRIPTOR_PROTO.as_bytes()).expect("failed to parse descriptor.proto");
+    proto.name = Some("google/protobuf/descriptor.proto".to_string());
+    let mut pool = Pool::new();
+    pool.add_file(proto, &Pool::new()).expect("failed to link descriptor.proto");
+    pool
+}
+
+/// Adds a file, preceded by its dependencies, to the output of the compiler.
+fn add_transitive_dependencies(pool: &Pool,
+                               name: &str,
+                               visited: &mut HashSet<String>,
+                               output: &mut Vec<FileDescriptorProto>) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+    let file = pool.file(name).expect("compiled file is missing from the pool");
+    for dependency in &file.dependency {
+        add_transitive_dependencies(pool, dependency, visited, output);
+    }
+    output.push(file.clone());
+}
+
+struct Compiler<'a> {
+    includes: &'a [PathBuf],
+    pool: Pool,
+    fallback: Pool,
+    /// The names of the files which are being built, outermost first.
+    pending: Vec<String>,
+    /// The names of the files which could not be built.
+    failed: HashSet<String>,
+    /// The formatted errors, in the order they were found.
+    errors: Vec<String>,
+}
+
+impl <'a> Compiler<'a> {
+
+    /// Loads, parses and links a file and its imports into the pool.
+    fn build(&mut self, name: &str) -> Result<(), ()> {
+        if self.pool.file(name).is_some() {
+            return Ok(());
+        }
+        if self.failed.contains(name) {
+            return Err(());
+        }
+
+        let proto = self.load(name)?;
+
+        if let Some(position) = self.pending.iter().position(|pending| pending == name) {
+            self.add_recursive_import_error(&proto, position);
+            return Err(());
+        }
+
+        self.pending.push(name.to_string());
+        for dependency in &proto.dependency {
+            let _ = self.build(dependency);
+        }
+        self.pending.pop();
+
+        match self.pool.add_file(proto, &self.fallback) {
+            Ok(()) => Ok(()),
+            Err(errors) => {
+                self.errors.extend(errors);
+                self.failed.insert(name.to_string());
+                Err(())
+            },
+        }
+    }
+
+    /// Reads and parses a file from the include directories.
+    fn load(&mut self, name: &str) -> Result<FileDescriptorProto, ()> {
+        let source = self.includes
+                         .iter()
+                         .map(|include| include.join(name))
+                         .filter(|path| path.is_file())
+                         .filter_map(|path| fs::read(path).ok())
+                         .next();
+        let source = match source {
+            Some(source) => source,
+            None => {
+                self.errors.push(format!("{}: File not found.", name));
+                self.failed.insert(name.to_string());
+                return Err(());
+            },
+        };
+
+        match parser::parse(&source) {
+            Ok(mut proto) => {
+                proto.name = Some(name.to_string());
+                Ok(proto)
+            },
+            Err(errors) => {
+                self.errors.extend(errors.into_iter().map(|(line, column, message)| {
+                    format!("{}:{}:{}: {}", name, line + 1, column + 1, message)
+                }));
+                self.failed.insert(name.to_string());
+                Err(())
+            },
+        }
+    }
+
+    /// Reports an import cycle, which starts with the pending file at `from`.
+    fn add_recursive_import_error(&mut self, proto: &FileDescriptorProto, from: usize) {
+        let mut cycle = self.pending[from..].join(" -> ");
+        cycle.push_str(" -> ");
+        cycle.push_str(proto.name());
+
+        // The error is reported at the import of the next file in the cycle.
+        let next = self.pending.get(from + 1).map(String::as_str).unwrap_or(proto.name());
+        let location = proto.dependency
+                            .iter()
+                            .position(|dependency| dependency == next)
+                            .and_then(|index| {
+                                linker::find_location(proto, &[tag::file::DEPENDENCY, index as i32])
+                            });
+        let message = format!("File recursively imports itself: {}", cycle);
+        self.errors.push(linker::format_error(proto.name(), location, &message));
+    }
+}
+
+/// Maps the path of an input file to its name relative to the include directory which contains
+/// it, which is the name used in imports and in the file descriptor.
+pub fn virtual_file_name(proto: &Path, includes: &[PathBuf]) -> io::Result<String> {
+    let proto_path = normalize(proto);
+    for include in includes {
+        if let Ok(relative) = proto_path.strip_prefix(&normalize(include)) {
+            return Ok(to_virtual_name(relative));
+        }
+    }
+
+    // The paths may be equivalent without being lexical prefixes, for instance when one is
+    // absolute and the other is relative.
+    let canonical = fs::canonicalize(proto).map_err(|error| {
+        Error::new(error.kind(), format!("{}: {}", proto.display(), error))
+    })?;
+    for include in includes {
+        if let Ok(include) = fs::canonicalize(include) {
+            if let Ok(relative) = canonical.strip_prefix(&include) {
+                return Ok(to_virtual_name(relative));
+            }
+        }
+    }
+
+    Err(Error::new(ErrorKind::Other,
+                   format!("{}: File does not reside within any path specified using \
+                            --proto_path (or -I).  You must specify a --proto_path which \
+                            encompasses this file.",
+                           proto.display())))
+}
+
+/// Lexically normalizes a path, removing `.` components.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|component| *component != Component::CurDir).collect()
+}
+
+fn to_virtual_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Formats a double like `SimpleDtoa`: with the shortest of 15 or 17 significant digits which
+/// round-trips.
+fn simple_dtoa(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    } else if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let short = format_g(value, 15);
+    if short.parse::<f64>().ok() == Some(value) {
+        short
+    } else {
+        format_g(value, 17)
+    }
+}
+
+/// Formats a float like `SimpleFtoa`: with the shortest of 6 or 9 significant digits which
+/// round-trips.
+fn simple_ftoa(value: f32) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    } else if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let short = format_g(f64::from(value), 6);
+    if short.parse::<f32>().ok() == Some(value) {
+        short
+    } else {
+        format_g(f64::from(value), 9)
+    }
+}
+
+/// Formats a finite value like the C `%.{precision}g` conversion.
+fn format_g(value: f64, precision: usize) -> String {
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let exponent_index = scientific.find('e').unwrap();
+    let exponent: i32 = scientific[exponent_index + 1..].parse().unwrap();
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let mantissa = strip_trailing_zeros(&scientific[..exponent_index]);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        let fixed = format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value);
+        strip_trailing_zeros(&fixed).to_string()
+    }
+}
+
+fn strip_trailing_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_dtoa() {
+        assert_eq!(simple_dtoa(0.0), "0");
+        assert_eq!(simple_dtoa(1.5), "1.5");
+        assert_eq!(simple_dtoa(-2.0), "-2");
+        assert_eq!(simple_dtoa(0.1), "0.1");
+        assert_eq!(simple_dtoa(1e100), "1e+100");
+        assert_eq!(simple_dtoa(1.25e-5), "1.25e-05");
+        assert_eq!(simple_dtoa(123456789012345680.0), "1.2345678901234568e+17");
+        assert_eq!(simple_dtoa(0.0001), "0.0001");
+        assert_eq!(simple_dtoa(::std::f64::INFINITY), "inf");
+        assert_eq!(simple_dtoa(::std::f64::NEG_INFINITY), "-inf");
+        assert_eq!(simple_dtoa(::std::f64::NAN), "nan");
+    }
+
+    #[test]
+    fn test_simple_ftoa() {
+        assert_eq!(simple_ftoa(1.5), "1.5");
+        assert_eq!(simple_ftoa(0.1), "0.1");
+        assert_eq!(simple_ftoa(3.14159274), "3.14159274");
+        assert_eq!(simple_ftoa(1e10), "1e+10");
+    }
+
+    #[test]
+    fn test_compile_well_known_types() {
+        let include = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/third-party/protobuf/include"));
+        let protos = ["any", "api", "descriptor", "duration", "empty", "field_mask", "source_context",
+                      "struct", "timestamp", "type", "wrappers"].iter()
+            .map(|name| include.join(format!("google/protobuf/{}.proto", name)))
+            .chain(Some(include.join("google/protobuf/compiler/plugin.proto")))
+            .collect::<Vec<_>>();
+
+        let set = compile(&protos, &[include]).unwrap();
+        let names = set.file.iter().map(|file| file.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec![
+            "google/protobuf/any.proto",
+            "google/protobuf/source_context.proto",
+            "google/protobuf/type.proto",
+            "google/protobuf/api.proto",
+            "google/protobuf/descriptor.proto",
+            "google/protobuf/duration.proto",
+            "google/protobuf/empty.proto",
+            "google/protobuf/field_mask.proto",
+            "google/protobuf/struct.proto",
+            "google/protobuf/timestamp.proto",
+            "google/protobuf/wrappers.proto",
+            "google/protobuf/compiler/plugin.proto",
+        ]);
+
+        let api = &set.file[3];
+        assert_eq!(api.syntax(), "proto3");
+        assert_eq!(api.options.as_ref().unwrap().java_package(), "com.google.protobuf");
+        let methods = &api.message_type[0].field[1];
+        assert_eq!(methods.type_name(), ".google.protobuf.Method");
+        assert_eq!(methods.json_name(), "methods");
+    }
+
+    /// Asserts that compiling `protos` produces the same descriptor set as `protoc`, which is
+    /// checked in under `golden/`. See `golden/README.md` for how the sets were generated.
+    fn assert_golden(golden: &[u8], protos: &[PathBuf], includes: &[PathBuf]) {
+        use prost::Message;
+
+        let expected = FileDescriptorSet::decode(golden).unwrap();
+        let actual = compile(protos, includes).unwrap();
+
+        let expected_names = expected.file.iter().map(|file| file.name()).collect::<Vec<_>>();
+        let actual_names = actual.file.iter().map(|file| file.name()).collect::<Vec<_>>();
+        assert_eq!(expected_names, actual_names);
+
+        for (expected, actual) in expected.file.iter().zip(&actual.file) {
+            assert_eq!(expected, actual, "descriptor mismatch for {}", expected.name());
+        }
+    }
+
+    #[test]
+    fn test_golden_tests_protos() {
+        let tests = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/src"));
+        let include = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/third-party/protobuf/include"));
+        let mut protos = fs::read_dir(&tests).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "proto"))
+            .collect::<Vec<_>>();
+        protos.sort();
+
+        assert_golden(include_bytes!("golden/tests.bin"), &protos, &[tests, include]);
+    }
+
+    #[test]
+    fn test_golden_well_known_types() {
+        let include = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/third-party/protobuf/include"));
+        let protos = ["any", "api", "descriptor", "duration", "empty", "field_mask", "source_context",
+                      "struct", "timestamp", "type", "wrappers"].iter()
+            .map(|name| include.join(format!("google/protobuf/{}.proto", name)))
+            .chain(Some(include.join("google/protobuf/compiler/plugin.proto")))
+            .collect::<Vec<_>>();
+
+        assert_golden(include_bytes!("golden/wkt.bin"), &protos, &[include]);
+    }
+
+    #[test]
+    fn test_virtual_file_name() {
+        let includes = vec![PathBuf::from("./protos"), PathBuf::from("src")];
+        assert_eq!(virtual_file_name(Path::new("protos/foo/bar.proto"), &includes).unwrap(),
+                   "foo/bar.proto");
+        assert_eq!(virtual_file_name(Path::new("./src/baz.proto"), &includes).unwrap(),
+                   "baz.proto");
+    }
+}
@@ -0,0 +1,623 @@
+//! Interprets options, like `DescriptorBuilder::OptionInterpreter`.
+//!
+//! The parser records every option as an `UninterpretedOption`. Once a file has been cross-linked,
+//! each option name is resolved to a field of the options message, or to an extension of it, and
+//! the value is encoded as an unknown field. The options message is then re-decoded, so standard
+//! options become fields and custom options remain in its unknown fields.
+
+use std::i32;
+use std::i64;
+use std::mem;
+use std::u32;
+
+use bytes::{Buf, IntoBuf};
+use prost::{Message, UnknownFields, UnknownValue};
+use prost::encoding::{decode_key, DecodeContext, WireType};
+use prost_types::{
+    DescriptorProto,
+    EnumOptions,
+    EnumValueOptions,
+    ExtensionRangeOptions,
+    FieldDescriptorProto,
+    FieldOptions,
+    FileDescriptorProto,
+    FileOptions,
+    MessageOptions,
+    MethodOptions,
+    OneofOptions,
+    ServiceOptions,
+    UninterpretedOption,
+};
+use prost_types::descriptor::DescriptorPool;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::text_format;
+
+use compiler::linker::{self, Builder, OptionsToInterpret, Symbol, SymbolKind};
+use compiler::tag;
+
+/// An options message.
+pub trait Options: Message + Default {
+    /// The fully-qualified name of the message.
+    const FULL_NAME: &'static str;
+
+    fn uninterpreted_option(&mut self) -> &mut Vec<UninterpretedOption>;
+}
+
+macro_rules! impl_options {
+    ($ty:ident, $full_name:expr) => {
+        impl Options for $ty {
+            const FULL_NAME: &'static str = $full_name;
+
+            fn uninterpreted_option(&mut self) -> &mut Vec<UninterpretedOption> {
+                &mut self.uninterpreted_option
+            }
+        }
+    };
+}
+
+impl_options!(FileOptions, "google.protobuf.FileOptions");
+impl_options!(MessageOptions, "google.protobuf.MessageOptions");
+impl_options!(FieldOptions, "google.protobuf.FieldOptions");
+impl_options!(OneofOptions, "google.protobuf.OneofOptions");
+impl_options!(ExtensionRangeOptions, "google.protobuf.ExtensionRangeOptions");
+impl_options!(EnumOptions, "google.protobuf.EnumOptions");
+impl_options!(EnumValueOptions, "google.protobuf.EnumValueOptions");
+impl_options!(ServiceOptions, "google.protobuf.ServiceOptions");
+impl_options!(MethodOptions, "google.protobuf.MethodOptions");
+
+/// The pool which the descriptor of an options message, and its fields, belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PoolId {
+    /// The pool being built, which contains `descriptor.proto` and the extensions of the options.
+    Builder,
+    /// The pool containing the embedded `descriptor.proto`, used when the file does not import it.
+    Fallback,
+}
+
+/// A field of an options message, or an extension of it.
+struct OptionField {
+    pool: PoolId,
+    full_name: String,
+    proto: FieldDescriptorProto,
+}
+
+/// Interprets the uninterpreted options of an element.
+///
+/// `options` returns the options of the element at a path of the file.
+pub fn interpret<T>(builder: &mut Builder,
+                    target: &OptionsToInterpret,
+                    options: for<'p> fn(&'p mut FileDescriptorProto, &[i32]) -> &'p mut Option<T>)
+where T: Options {
+    let mut value = match *options(builder.proto_mut(), &target.element_path) {
+        Some(ref mut value) => mem::replace(value, T::default()),
+        None => return,
+    };
+    let uninterpreted_options = mem::replace(value.uninterpreted_option(), Vec::new());
+    if uninterpreted_options.is_empty() {
+        *options(builder.proto_mut(), &target.element_path) = Some(value);
+        return;
+    }
+
+    let mut interpreter = Interpreter {
+        builder: builder,
+        target: target,
+        index: 0,
+        unknown_fields: UnknownFields::new(),
+    };
+    let mut failed = false;
+    for (i, option) in uninterpreted_options.iter().enumerate() {
+        interpreter.index = i;
+        if interpreter.interpret_option::<T>(option).is_err() {
+            failed = true;
+            break;
+        }
+    }
+    let unknown_fields = interpreter.unknown_fields;
+
+    if failed {
+        // Leave the options as they were, since the file fails to build anyway.
+        *value.uninterpreted_option() = uninterpreted_options;
+        *options(builder.proto_mut(), &target.element_path) = Some(value);
+        return;
+    }
+
+    // Decode the interpreted options, so that standard options become fields of the message.
+    let mut buf = Vec::new();
+    value.encode_raw(&mut buf);
+    unknown_fields.encode_raw(&mut buf);
+    match T::decode(&buf) {
+        Ok(interpreted) => value = interpreted,
+        Err(_) => {
+            let message = format!("Some options could not be correctly parsed using the proto descriptors \
+                                   compiled into this binary.\nUnparsed options: {:?}\nParsed options: {:?}",
+                                  unknown_fields, value);
+            builder.add_error(&target.element_path, linker::ErrorLocation::Other, &message);
+        },
+    }
+    *options(builder.proto_mut(), &target.element_path) = Some(value);
+}
+
+struct Interpreter<'a, 'b: 'a> {
+    builder: &'a mut Builder<'b>,
+    target: &'a OptionsToInterpret,
+    /// The index of the option being interpreted.
+    index: usize,
+    /// The interpreted options, encoded as fields of the options message.
+    unknown_fields: UnknownFields,
+}
+
+impl <'a, 'b> Interpreter<'a, 'b> {
+
+    fn interpret_option<T>(&mut self, option: &UninterpretedOption) -> Result<(), ()> where T: Options {
+        if option.name.is_empty() {
+            return self.add_name_error("Option must have a name.");
+        }
+        if option.name[0].name_part == "uninterpreted_option" {
+            return self.add_name_error("Option must not use reserved name \"uninterpreted_option\".");
+        }
+
+        // The options message is described by `descriptor.proto` in the pool being built, if it is
+        // in there, so that the extensions of the options can be found.
+        let mut pool = match self.builder.find_symbol_not_enforcing_deps(T::FULL_NAME) {
+            Some(ref symbol) if symbol.kind == SymbolKind::Message => PoolId::Builder,
+            _ => PoolId::Fallback,
+        };
+        let mut message_name = T::FULL_NAME.to_string();
+
+        let mut debug_msg_name = String::new();
+        let mut intermediate_fields = Vec::new();
+        let mut field: Option<OptionField> = None;
+        for (i, part) in option.name.iter().enumerate() {
+            if !debug_msg_name.is_empty() {
+                debug_msg_name.push('.');
+            }
+
+            let found = if part.is_extension {
+                debug_msg_name.push_str(&format!("({})", part.name_part));
+                let name_scope = self.target.name_scope.clone();
+                self.builder
+                    .lookup_symbol(&part.name_part, &name_scope, false)
+                    .and_then(|(full_name, symbol)| {
+                        if symbol.kind != SymbolKind::Field {
+                            return None;
+                        }
+                        let proto = linker::field(self.builder.file_proto(symbol.file), &symbol.path).clone();
+                        Some(OptionField {
+                            pool: PoolId::Builder,
+                            full_name: full_name,
+                            proto: proto,
+                        })
+                    })
+            } else {
+                debug_msg_name.push_str(&part.name_part);
+                self.message(pool, &message_name)
+                    .and_then(|message| message.field.iter().find(|field| field.name() == part.name_part))
+                    .map(|proto| OptionField {
+                        pool: pool,
+                        full_name: linker::join(&message_name, proto.name()),
+                        proto: proto.clone(),
+                    })
+            };
+
+            let found = match found {
+                Some(found) => found,
+                None => {
+                    return match self.builder.take_undefined_resolved_name() {
+                        Some(resolved_name) => {
+                            let message = format!("Option \"{}\" is resolved to \"({})\", which is not defined. \
+                                                   The innermost scope is searched first in name resolution. \
+                                                   Consider using a leading '.'(i.e., \"(.{}\") to start from \
+                                                   the outermost scope.",
+                                                  debug_msg_name, resolved_name, &debug_msg_name[1..]);
+                            self.add_name_error(&message)
+                        },
+                        None => self.add_name_error(&format!("Option \"{}\" unknown.", debug_msg_name)),
+                    };
+                },
+            };
+
+            let containing_type = if part.is_extension {
+                found.proto.extendee()[1..].to_string()
+            } else {
+                message_name.clone()
+            };
+            if containing_type != message_name {
+                let short_name = message_name.rsplit('.').next().unwrap().to_string();
+                let message = format!("Option field \"{}\" is not a field or extension of message \"{}\".",
+                                      debug_msg_name, short_name);
+                return self.add_name_error(&message);
+            }
+
+            if i + 1 < option.name.len() {
+                if found.proto.type_() != Type::Message && found.proto.type_() != Type::Group {
+                    return self.add_name_error(&format!("Option \"{}\" is an atomic type, not a message.",
+                                                        debug_msg_name));
+                }
+                if found.proto.label() == Label::Repeated {
+                    let message = format!("Option field \"{}\" is a repeated message. Repeated message options \
+                                           must be initialized using an aggregate value.",
+                                          debug_msg_name);
+                    return self.add_name_error(&message);
+                }
+                pool = found.pool;
+                message_name = found.proto.type_name()[1..].to_string();
+                intermediate_fields.push(found);
+            } else {
+                field = Some(found);
+            }
+        }
+        let field = field.expect("option name has no parts");
+
+        let repeated = field.proto.label() == Label::Repeated;
+        if !repeated && is_option_set(&self.unknown_fields, &intermediate_fields, &field) {
+            return self.add_name_error(&format!("Option \"{}\" was already set.", debug_msg_name));
+        }
+
+        let mut unknown_fields = UnknownFields::new();
+        self.set_option_value(option, &field, &mut unknown_fields)?;
+
+        // Wrap the value in the intermediate messages.
+        for intermediate in intermediate_fields.iter().rev() {
+            let mut parent = UnknownFields::new();
+            let number = intermediate.proto.number() as u32;
+            if intermediate.proto.type_() == Type::Group {
+                parent.push(number, UnknownValue::Group(unknown_fields));
+            } else {
+                let mut buf = Vec::new();
+                unknown_fields.encode_raw(&mut buf);
+                parent.push(number, UnknownValue::LengthDelimited(buf));
+            }
+            unknown_fields = parent;
+        }
+        for unknown_field in unknown_fields.iter() {
+            self.unknown_fields.push(unknown_field.tag(), unknown_field.value().clone());
+        }
+
+        // Point the source code info of the option at the interpreted field.
+        let mut src_path = self.target.options_path.clone();
+        src_path.push(tag::options::UNINTERPRETED_OPTION);
+        src_path.push(self.index as i32);
+        let mut dest_path = self.target.options_path.clone();
+        dest_path.extend(intermediate_fields.iter().map(|intermediate| intermediate.proto.number()));
+        dest_path.push(field.proto.number());
+        self.builder.record_interpreted_path(src_path, dest_path, repeated);
+
+        Ok(())
+    }
+
+    fn set_option_value(&mut self,
+                        option: &UninterpretedOption,
+                        field: &OptionField,
+                        unknown_fields: &mut UnknownFields) -> Result<(), ()> {
+        let number = field.proto.number() as u32;
+        let full_name = &field.full_name;
+        match field.proto.type_() {
+            Type::Int32 | Type::Sint32 | Type::Sfixed32 => {
+                let value = match (option.positive_int_value, option.negative_int_value) {
+                    (Some(value), _) if value <= i32::MAX as u64 => value as i32,
+                    (None, Some(value)) if value >= i64::from(i32::MIN) => value as i32,
+                    (Some(_), _) | (None, Some(_)) => {
+                        return self.add_value_error(&format!("Value out of range for int32 option \"{}\".",
+                                                             full_name));
+                    },
+                    (None, None) => {
+                        return self.add_value_error(&format!("Value must be integer for int32 option \"{}\".",
+                                                             full_name));
+                    },
+                };
+                let value = match field.proto.type_() {
+                    Type::Int32 => UnknownValue::Varint(i64::from(value) as u64),
+                    Type::Sint32 => UnknownValue::Varint(u64::from(((value << 1) ^ (value >> 31)) as u32)),
+                    _ => UnknownValue::ThirtyTwoBit(value as u32),
+                };
+                unknown_fields.push(number, value);
+            },
+            Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+                let value = match (option.positive_int_value, option.negative_int_value) {
+                    (Some(value), _) if value <= i64::MAX as u64 => value as i64,
+                    (None, Some(value)) => value,
+                    (Some(_), _) => {
+                        return self.add_value_error(&format!("Value out of range for int64 option \"{}\".",
+                                                             full_name));
+                    },
+                    (None, None) => {
+                        return self.add_value_error(&format!("Value must be integer for int64 option \"{}\".",
+                                                             full_name));
+                    },
+                };
+                let value = match field.proto.type_() {
+                    Type::Int64 => UnknownValue::Varint(value as u64),
+                    Type::Sint64 => UnknownValue::Varint(((value << 1) ^ (value >> 63)) as u64),
+                    _ => UnknownValue::SixtyFourBit(value as u64),
+                };
+                unknown_fields.push(number, value);
+            },
+            Type::Uint32 | Type::Fixed32 => {
+                let value = match option.positive_int_value {
+                    Some(value) if value <= u64::from(u32::MAX) => value as u32,
+                    Some(_) => {
+                        return self.add_value_error(&format!("Value out of range for uint32 option \"{}\".",
+                                                             field.proto.name()));
+                    },
+                    None => {
+                        let message = format!("Value must be non-negative integer for uint32 option \"{}\".",
+                                              full_name);
+                        return self.add_value_error(&message);
+                    },
+                };
+                let value = if field.proto.type_() == Type::Uint32 {
+                    UnknownValue::Varint(u64::from(value))
+                } else {
+                    UnknownValue::ThirtyTwoBit(value)
+                };
+                unknown_fields.push(number, value);
+            },
+            Type::Uint64 | Type::Fixed64 => {
+                let value = match option.positive_int_value {
+                    Some(value) => value,
+                    None => {
+                        let message = format!("Value must be non-negative integer for uint64 option \"{}\".",
+                                              full_name);
+                        return self.add_value_error(&message);
+                    },
+                };
+                let value = if field.proto.type_() == Type::Uint64 {
+                    UnknownValue::Varint(value)
+                } else {
+                    UnknownValue::SixtyFourBit(value)
+                };
+                unknown_fields.push(number, value);
+            },
+            Type::Float | Type::Double => {
+                let value = match (option.double_value, option.positive_int_value, option.negative_int_value) {
+                    (Some(value), _, _) => value,
+                    (None, Some(value), _) => value as f64,
+                    (None, None, Some(value)) => value as f64,
+                    (None, None, None) => {
+                        let type_name = if field.proto.type_() == Type::Float { "float" } else { "double" };
+                        return self.add_value_error(&format!("Value must be number for {} option \"{}\".",
+                                                             type_name, full_name));
+                    },
+                };
+                let value = if field.proto.type_() == Type::Float {
+                    UnknownValue::ThirtyTwoBit((value as f32).to_bits())
+                } else {
+                    UnknownValue::SixtyFourBit(value.to_bits())
+                };
+                unknown_fields.push(number, value);
+            },
+            Type::Bool => {
+                let value = match option.identifier_value {
+                    Some(ref value) if value == "true" => 1,
+                    Some(ref value) if value == "false" => 0,
+                    Some(_) => {
+                        let message = format!("Value must be \"true\" or \"false\" for boolean option \"{}\".",
+                                              full_name);
+                        return self.add_value_error(&message);
+                    },
+                    None => {
+                        return self.add_value_error(&format!("Value must be identifier for boolean option \"{}\".",
+                                                             full_name));
+                    },
+                };
+                unknown_fields.push(number, UnknownValue::Varint(value));
+            },
+            Type::Enum => {
+                let value_name = match option.identifier_value {
+                    Some(ref value_name) => value_name.clone(),
+                    None => {
+                        let message = format!("Value must be identifier for enum-valued option \"{}\".",
+                                              full_name);
+                        return self.add_value_error(&message);
+                    },
+                };
+                let value = self.find_enum_value(field, &value_name)?;
+                unknown_fields.push(number, UnknownValue::Varint(i64::from(value) as u64));
+            },
+            Type::String | Type::Bytes => {
+                let value = match option.string_value {
+                    Some(ref value) => value.clone(),
+                    None => {
+                        return self.add_value_error(&format!("Value must be quoted string for string option \"{}\".",
+                                                             full_name));
+                    },
+                };
+                unknown_fields.push(number, UnknownValue::LengthDelimited(value));
+            },
+            Type::Message | Type::Group => self.set_aggregate_option(option, field, unknown_fields)?,
+        }
+        Ok(())
+    }
+
+    /// Finds the number of the value of an enum-valued option.
+    fn find_enum_value(&mut self, field: &OptionField, value_name: &str) -> Result<i32, ()> {
+        let enum_name = field.proto.type_name()[1..].to_string();
+        let value = match field.pool {
+            PoolId::Builder => {
+                // Enum values are siblings of their type.
+                let scope = &enum_name[..enum_name.rfind('.').unwrap_or(0)];
+                let value_full_name = linker::join(scope, value_name);
+                let enum_symbol = self.builder.find_symbol_not_enforcing_deps(&enum_name);
+                match (self.builder.find_symbol_not_enforcing_deps(&value_full_name), enum_symbol) {
+                    (Some(ref value), Some(ref enum_symbol)) if value.kind == SymbolKind::EnumValue => {
+                        if !is_value_of(value, enum_symbol) {
+                            let message = format!("Enum type \"{}\" has no value named \"{}\" for option \"{}\". \
+                                                   This appears to be a value from a sibling type.",
+                                                  enum_name, value_name, field.full_name);
+                            return self.add_value_error(&message);
+                        }
+                        let file = self.builder.file_proto(value.file);
+                        let (parent, index) = value.path.split_at(value.path.len() - 2);
+                        Some(linker::enumeration(file, parent).value[index[1] as usize].number())
+                    },
+                    _ => None,
+                }
+            },
+            PoolId::Fallback => {
+                let fallback = self.builder.fallback();
+                fallback.symbol(&enum_name)
+                        .filter(|symbol| symbol.kind == SymbolKind::Enum)
+                        .and_then(|symbol| {
+                            linker::enumeration(&fallback.files()[symbol.file], &symbol.path)
+                                .value
+                                .iter()
+                                .find(|value| value.name() == value_name)
+                                .map(|value| value.number())
+                        })
+            },
+        };
+
+        match value {
+            Some(value) => Ok(value),
+            None => {
+                let message = format!("Enum type \"{}\" has no value named \"{}\" for option \"{}\".",
+                                      enum_name, value_name, field.full_name);
+                self.add_value_error(&message)
+            },
+        }
+    }
+
+    fn set_aggregate_option(&mut self,
+                            option: &UninterpretedOption,
+                            field: &OptionField,
+                            unknown_fields: &mut UnknownFields) -> Result<(), ()> {
+        let aggregate_value = match option.aggregate_value {
+            Some(ref aggregate_value) => aggregate_value,
+            None => {
+                let name = field.proto.name();
+                let message = format!("Option \"{}\" is a message. To set the entire message, use syntax like \
+                                       \"{} = {{ <proto text format> }}\". To set fields within it, use syntax \
+                                       like \"{}.foo = value\".",
+                                      field.full_name, name, name);
+                return self.add_value_error(&message);
+            },
+        };
+
+        let files = match field.pool {
+            PoolId::Builder => self.builder.all_files(),
+            PoolId::Fallback => self.builder.fallback().files().to_vec(),
+        };
+        let type_name = &field.proto.type_name()[1..];
+        let decoded = DescriptorPool::from_files(files)
+            .map_err(|error| error.to_string())
+            .and_then(|pool| {
+                pool.get_message_by_name(type_name)
+                    .ok_or_else(|| format!("message type \"{}\" not found", type_name))
+            })
+            .and_then(|descriptor| {
+                text_format::decode(descriptor, aggregate_value).map_err(|error| {
+                    error.to_string().trim_start_matches("failed to parse text format: ").to_string()
+                })
+            });
+        let message = match decoded {
+            Ok(message) => message,
+            Err(error) => {
+                return self.add_value_error(&format!("Error while parsing option value for \"{}\": {}",
+                                                     field.proto.name(), error));
+            },
+        };
+
+        let mut buf = Vec::new();
+        message.encode_raw(&mut buf);
+        let number = field.proto.number() as u32;
+        if field.proto.type_() == Type::Group {
+            let group = decode_unknown_fields(&buf).expect("failed to decode aggregate option");
+            unknown_fields.push(number, UnknownValue::Group(group));
+        } else {
+            unknown_fields.push(number, UnknownValue::LengthDelimited(buf));
+        }
+        Ok(())
+    }
+
+    /// Returns the message with the full name in a pool.
+    fn message(&self, pool: PoolId, name: &str) -> Option<&DescriptorProto> {
+        let (symbol, file) = match pool {
+            PoolId::Builder => {
+                let symbol = self.builder.find_symbol_not_enforcing_deps(name)?;
+                let file = self.builder.file_proto(symbol.file);
+                (symbol, file)
+            },
+            PoolId::Fallback => {
+                let fallback = self.builder.fallback();
+                let symbol = fallback.symbol(name)?.clone();
+                let file = &fallback.files()[symbol.file];
+                (symbol, file)
+            },
+        };
+        if symbol.kind == SymbolKind::Message {
+            Some(linker::message(file, &symbol.path))
+        } else {
+            None
+        }
+    }
+
+    fn options_path(&self) -> Vec<i32> {
+        let mut path = self.target.options_path.clone();
+        path.push(tag::options::UNINTERPRETED_OPTION);
+        path.push(self.index as i32);
+        path
+    }
+
+    /// Records an error located at the name of the option.
+    fn add_name_error<R>(&mut self, message: &str) -> Result<R, ()> {
+        let mut path = self.options_path();
+        path.push(tag::uninterpreted_option::NAME);
+        self.builder.add_error_at_path(&path, message);
+        Err(())
+    }
+
+    /// Records an error located at the value of the option.
+    fn add_value_error<R>(&mut self, message: &str) -> Result<R, ()> {
+        // The parser records the location of the value under the field which holds it.
+        let path = (tag::uninterpreted_option::IDENTIFIER_VALUE..=tag::uninterpreted_option::AGGREGATE_VALUE)
+            .map(|field| {
+                let mut path = self.options_path();
+                path.push(field);
+                path
+            })
+            .find(|path| self.builder.has_location(path))
+            .unwrap_or_else(|| self.options_path());
+        self.builder.add_error_at_path(&path, message);
+        Err(())
+    }
+}
+
+/// Returns `true` if an enum value symbol belongs to an enum symbol.
+fn is_value_of(value: &Symbol, enumeration: &Symbol) -> bool {
+    value.file == enumeration.file &&
+    value.path.len() == enumeration.path.len() + 2 &&
+    value.path.starts_with(&enumeration.path)
+}
+
+/// Returns `true` if the option has already been set in the interpreted options.
+fn is_option_set(unknown_fields: &UnknownFields, intermediate_fields: &[OptionField], field: &OptionField) -> bool {
+    let (intermediate, rest) = match intermediate_fields.split_first() {
+        Some(split) => split,
+        None => return unknown_fields.iter().any(|unknown| unknown.tag() == field.proto.number() as u32),
+    };
+
+    unknown_fields.iter()
+                  .filter(|unknown| unknown.tag() == intermediate.proto.number() as u32)
+                  .any(|unknown| match *unknown.value() {
+                      UnknownValue::LengthDelimited(ref bytes) => {
+                          decode_unknown_fields(bytes).map(|nested| is_option_set(&nested, rest, field))
+                                                      .unwrap_or(false)
+                      },
+                      UnknownValue::Group(ref nested) => is_option_set(nested, rest, field),
+                      _ => false,
+                  })
+}
+
+/// Decodes a message as a set of unknown fields.
+fn decode_unknown_fields(bytes: &[u8]) -> Option<UnknownFields> {
+    let mut buf = bytes.into_buf();
+    let mut unknown_fields = UnknownFields::new();
+    while buf.has_remaining() {
+        let (tag, wire_type) = decode_key(&mut buf).ok()?;
+        if wire_type == WireType::EndGroup {
+            return None;
+        }
+        unknown_fields.merge_field(tag, wire_type, &mut buf, &DecodeContext::default()).ok()?;
+    }
+    Some(unknown_fields)
+}
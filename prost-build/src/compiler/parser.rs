@@ -0,0 +1,1887 @@
+//! Parses the tokens of a `.proto` file into a `FileDescriptorProto`.
+//!
+//! The parser follows `google::protobuf::compiler::Parser`: it records the same source code info
+//! locations and comments, leaves options uninterpreted, and reports the same errors. Names are
+//! not resolved; that is left to the linker.
+
+use std::mem;
+
+use prost_types::{
+    DescriptorProto,
+    EnumDescriptorProto,
+    EnumValueDescriptorProto,
+    ExtensionRangeOptions,
+    FieldDescriptorProto,
+    FileDescriptorProto,
+    MessageOptions,
+    MethodDescriptorProto,
+    MethodOptions,
+    OneofDescriptorProto,
+    ServiceDescriptorProto,
+    SourceCodeInfo,
+    UninterpretedOption,
+};
+use prost_types::descriptor_proto::{ExtensionRange, ReservedRange};
+use prost_types::enum_descriptor_proto::EnumReservedRange;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::source_code_info::Location;
+use prost_types::uninterpreted_option::NamePart;
+
+use compiler::{simple_dtoa, tag, MAX_FIELD_NUMBER};
+use compiler::tokenizer::{self, Token, TokenType, Tokenizer};
+
+/// The error marker of a parse function. The error itself has already been recorded.
+type ParseResult<T = ()> = Result<T, ()>;
+
+/// The end of a range declared with `to max`, which depends on the options of the message.
+const MAX_RANGE_SENTINEL: i32 = -1;
+
+/// A location in the source code info which is being recorded.
+#[derive(Clone, Copy)]
+struct Loc(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OptionStyle {
+    /// An `option name = value;` statement.
+    Statement,
+    /// A `name = value` entry in a bracketed option list.
+    Assignment,
+}
+
+/// The key and value types of a map field.
+struct MapField {
+    key: (Option<Type>, String),
+    value: (Option<Type>, String),
+}
+
+/// Parses the source of a `.proto` file.
+///
+/// On failure, returns the errors as zero-based line, column, and message.
+pub fn parse(source: &[u8]) -> Result<FileDescriptorProto, Vec<(i32, i32, String)>> {
+    let mut parser = Parser {
+        input: Tokenizer::new(source),
+        locations: Vec::new(),
+        syntax: String::new(),
+        upcoming_doc_comments: String::new(),
+        upcoming_detached_comments: Vec::new(),
+    };
+
+    let mut file = FileDescriptorProto::default();
+    parser.parse_file(&mut file);
+
+    if parser.input.errors().is_empty() {
+        Ok(file)
+    } else {
+        Err(parser.input.errors().to_vec())
+    }
+}
+
+struct Parser<'a> {
+    input: Tokenizer<'a>,
+    locations: Vec<Location>,
+    syntax: String,
+    /// The leading comment of the next declaration.
+    upcoming_doc_comments: String,
+    /// The detached comments preceding the next declaration.
+    upcoming_detached_comments: Vec<String>,
+}
+
+impl <'a> Parser<'a> {
+
+    // Token helpers.
+
+    fn add_error(&mut self, message: &str) {
+        let (line, column) = (self.input.current().line, self.input.current().column);
+        self.input.add_error_at(line, column, message);
+    }
+
+    fn at_end(&self) -> bool {
+        self.input.current().token_type == TokenType::End
+    }
+
+    fn looking_at(&self, text: &str) -> bool {
+        self.input.current().text == text
+    }
+
+    fn looking_at_type(&self, token_type: TokenType) -> bool {
+        self.input.current().token_type == token_type
+    }
+
+    fn try_consume(&mut self, text: &str) -> bool {
+        if self.looking_at(text) {
+            self.input.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume(&mut self, text: &str) -> ParseResult {
+        if self.try_consume(text) {
+            Ok(())
+        } else {
+            self.add_error(&format!("Expected \"{}\".", text));
+            Err(())
+        }
+    }
+
+    fn consume_or(&mut self, text: &str, error: &str) -> ParseResult {
+        if self.try_consume(text) {
+            Ok(())
+        } else {
+            self.add_error(error);
+            Err(())
+        }
+    }
+
+    fn consume_identifier(&mut self, error: &str) -> ParseResult<String> {
+        if self.looking_at_type(TokenType::Identifier) {
+            let identifier = self.input.current().text.clone();
+            self.input.next();
+            Ok(identifier)
+        } else {
+            self.add_error(error);
+            Err(())
+        }
+    }
+
+    fn consume_integer(&mut self, error: &str) -> ParseResult<i32> {
+        self.consume_integer64(i32::max_value() as u64, error).map(|value| value as i32)
+    }
+
+    fn consume_signed_integer(&mut self, error: &str) -> ParseResult<i32> {
+        let mut max_value = i32::max_value() as u64;
+        let negative = self.try_consume("-");
+        if negative {
+            max_value += 1;
+        }
+        let value = self.consume_integer64(max_value, error)?;
+        Ok(if negative { (value as i64).wrapping_neg() as i32 } else { value as i32 })
+    }
+
+    fn consume_integer64(&mut self, max_value: u64, error: &str) -> ParseResult<u64> {
+        if self.looking_at_type(TokenType::Integer) {
+            let value = match tokenizer::parse_integer(&self.input.current().text, max_value) {
+                Some(value) => value,
+                None => {
+                    // The integer was still consumed, so parsing continues.
+                    self.add_error("Integer out of range.");
+                    0
+                },
+            };
+            self.input.next();
+            Ok(value)
+        } else {
+            self.add_error(error);
+            Err(())
+        }
+    }
+
+    fn consume_number(&mut self, error: &str) -> ParseResult<f64> {
+        let value = if self.looking_at_type(TokenType::Float) {
+            tokenizer::parse_float(&self.input.current().text)
+        } else if self.looking_at_type(TokenType::Integer) {
+            // Integers are accepted as well.
+            match tokenizer::parse_integer(&self.input.current().text, u64::max_value()) {
+                Some(value) => value as f64,
+                None => {
+                    self.add_error("Integer out of range.");
+                    0.0
+                },
+            }
+        } else if self.looking_at("inf") {
+            ::std::f64::INFINITY
+        } else if self.looking_at("nan") {
+            ::std::f64::NAN
+        } else {
+            self.add_error(error);
+            return Err(());
+        };
+        self.input.next();
+        Ok(value)
+    }
+
+    fn consume_string(&mut self, error: &str) -> ParseResult<Vec<u8>> {
+        if self.looking_at_type(TokenType::String) {
+            let mut value = Vec::new();
+            // Adjacent string literals are concatenated, like in C.
+            while self.looking_at_type(TokenType::String) {
+                tokenizer::parse_string(&self.input.current().text, &mut value);
+                self.input.next();
+            }
+            Ok(value)
+        } else {
+            self.add_error(error);
+            Err(())
+        }
+    }
+
+    fn consume_string_lossy(&mut self, error: &str) -> ParseResult<String> {
+        self.consume_string(error).map(|value| String::from_utf8_lossy(&value).into_owned())
+    }
+
+    /// Consumes the token which ends a declaration, and attaches the surrounding comments to the
+    /// location of the declaration.
+    fn try_consume_end_of_declaration(&mut self, text: &str, location: Option<Loc>) -> bool {
+        if !self.looking_at(text) {
+            return false;
+        }
+
+        let comments = self.input.next_with_comments();
+
+        // Save the leading comment for the next declaration, and recall the one saved at the
+        // start of this declaration.
+        let leading = mem::replace(&mut self.upcoming_doc_comments, comments.leading);
+
+        match location {
+            Some(location) => {
+                let detached = mem::replace(&mut self.upcoming_detached_comments, comments.detached);
+                let location = &mut self.locations[location.0];
+                if !leading.is_empty() {
+                    location.leading_comments = Some(leading);
+                }
+                if !comments.trailing.is_empty() {
+                    location.trailing_comments = Some(comments.trailing);
+                }
+                location.leading_detached_comments = detached;
+            },
+            // At the end of a scope, the pending detached comments are dropped.
+            None if text == "}" => self.upcoming_detached_comments = comments.detached,
+            None => self.upcoming_detached_comments.extend(comments.detached),
+        }
+        true
+    }
+
+    fn consume_end_of_declaration(&mut self, text: &str, location: Option<Loc>) -> ParseResult {
+        if self.try_consume_end_of_declaration(text, location) {
+            Ok(())
+        } else {
+            self.add_error(&format!("Expected \"{}\".", text));
+            Err(())
+        }
+    }
+
+    // Location helpers, which mirror `Parser::LocationRecorder`.
+
+    /// Starts recording a location at the current token, with the path of `parent` extended by
+    /// `path`.
+    fn location(&mut self, parent: Loc, path: &[i32]) -> Loc {
+        let mut full_path = self.locations[parent.0].path.clone();
+        full_path.extend_from_slice(path);
+        self.location_at(full_path)
+    }
+
+    fn location_at(&mut self, path: Vec<i32>) -> Loc {
+        let span = vec![self.input.current().line, self.input.current().column];
+        self.locations.push(Location {
+            path,
+            span,
+            ..Location::default()
+        });
+        Loc(self.locations.len() - 1)
+    }
+
+    fn add_path(&mut self, location: Loc, component: i32) {
+        self.locations[location.0].path.push(component);
+    }
+
+    fn start_at(&mut self, location: Loc, token: &Token) {
+        let span = &mut self.locations[location.0].span;
+        span[0] = token.line;
+        span[1] = token.column;
+    }
+
+    fn start_at_location(&mut self, location: Loc, other: Loc) {
+        let (line, column) = (self.locations[other.0].span[0], self.locations[other.0].span[1]);
+        let span = &mut self.locations[location.0].span;
+        span[0] = line;
+        span[1] = column;
+    }
+
+    fn end_at(&mut self, location: Loc, token: &Token) {
+        let span = &mut self.locations[location.0].span;
+        if token.line != span[0] {
+            span.push(token.line);
+        }
+        span.push(token.end_column);
+    }
+
+    /// Finishes recording a location at the previous token, unless it has already been ended.
+    fn end(&mut self, location: Loc) {
+        if self.locations[location.0].span.len() <= 2 {
+            let previous = self.input.previous().clone();
+            self.end_at(location, &previous);
+        }
+    }
+
+    /// Starts a location, runs `f`, and finishes the location.
+    fn with_location<T, F>(&mut self, parent: Loc, path: &[i32], f: F) -> ParseResult<T>
+    where F: FnOnce(&mut Self, Loc) -> ParseResult<T> {
+        let location = self.location(parent, path);
+        let result = f(self, location);
+        self.end(location);
+        result
+    }
+
+    // Error recovery.
+
+    fn skip_statement(&mut self) {
+        loop {
+            if self.at_end() {
+                return;
+            } else if self.looking_at_type(TokenType::Symbol) {
+                if self.try_consume_end_of_declaration(";", None) {
+                    return;
+                } else if self.try_consume("{") {
+                    self.skip_rest_of_block();
+                    return;
+                } else if self.looking_at("}") {
+                    return;
+                }
+            }
+            self.input.next();
+        }
+    }
+
+    fn skip_rest_of_block(&mut self) {
+        loop {
+            if self.at_end() {
+                return;
+            } else if self.looking_at_type(TokenType::Symbol) {
+                if self.try_consume_end_of_declaration("}", None) {
+                    return;
+                } else if self.try_consume("{") {
+                    self.skip_rest_of_block();
+                }
+            }
+            self.input.next();
+        }
+    }
+
+    // Files.
+
+    fn parse_file(&mut self, file: &mut FileDescriptorProto) {
+        if self.looking_at_type(TokenType::Start) {
+            // Advance to the first token.
+            let comments = self.input.next_with_comments();
+            self.upcoming_detached_comments = comments.detached;
+            self.upcoming_doc_comments = comments.leading;
+        }
+
+        let root = self.location_at(Vec::new());
+
+        if self.looking_at("syntax") {
+            if self.parse_syntax_identifier(root).is_err() {
+                // The rest of the file can't be parsed without knowing the syntax.
+                return;
+            }
+            file.syntax = Some(self.syntax.clone());
+        } else {
+            self.syntax = "proto2".to_string();
+        }
+
+        while !self.at_end() {
+            if self.parse_top_level_statement(file, root).is_err() {
+                self.skip_statement();
+                if self.looking_at("}") {
+                    self.add_error("Unmatched \"}\".");
+                    let comments = self.input.next_with_comments();
+                    self.upcoming_detached_comments = comments.detached;
+                    self.upcoming_doc_comments = comments.leading;
+                }
+            }
+        }
+        self.end(root);
+
+        file.source_code_info = Some(SourceCodeInfo {
+            location: mem::replace(&mut self.locations, Vec::new()),
+        });
+    }
+
+    fn parse_syntax_identifier(&mut self, root: Loc) -> ParseResult {
+        let location = self.location(root, &[tag::file::SYNTAX]);
+        self.consume_or("syntax", "File must begin with a syntax statement, e.g. 'syntax = \"proto2\";'.")?;
+        self.consume("=")?;
+        let syntax_token = self.input.current().clone();
+        let syntax = self.consume_string_lossy("Expected syntax identifier.")?;
+        self.consume_end_of_declaration(";", Some(location))?;
+        self.end(location);
+
+        if syntax != "proto2" && syntax != "proto3" {
+            self.input.add_error_at(syntax_token.line, syntax_token.column, format!(
+                "Unrecognized syntax identifier \"{}\".  This parser only recognizes \"proto2\" \
+                 and \"proto3\".", syntax));
+            return Err(());
+        }
+        self.syntax = syntax;
+        Ok(())
+    }
+
+    fn parse_top_level_statement(&mut self, file: &mut FileDescriptorProto, root: Loc) -> ParseResult {
+        if self.try_consume_end_of_declaration(";", None) {
+            // An empty statement.
+            Ok(())
+        } else if self.looking_at("message") {
+            let index = file.message_type.len() as i32;
+            let mut message = DescriptorProto::default();
+            let result = self.with_location(root, &[tag::file::MESSAGE_TYPE, index], |p, location| {
+                p.parse_message_definition(&mut message, location)
+            });
+            file.message_type.push(message);
+            result
+        } else if self.looking_at("enum") {
+            let index = file.enum_type.len() as i32;
+            let mut enum_type = EnumDescriptorProto::default();
+            let result = self.with_location(root, &[tag::file::ENUM_TYPE, index], |p, location| {
+                p.parse_enum_definition(&mut enum_type, location)
+            });
+            file.enum_type.push(enum_type);
+            result
+        } else if self.looking_at("service") {
+            let index = file.service.len() as i32;
+            let mut service = ServiceDescriptorProto::default();
+            let result = self.with_location(root, &[tag::file::SERVICE, index], |p, location| {
+                p.parse_service_definition(&mut service, location)
+            });
+            file.service.push(service);
+            result
+        } else if self.looking_at("extend") {
+            let extensions = &mut file.extension;
+            let messages = &mut file.message_type;
+            self.with_location(root, &[tag::file::EXTENSION], |p, location| {
+                p.parse_extend(extensions, messages, root, tag::file::MESSAGE_TYPE, location)
+            })
+        } else if self.looking_at("import") {
+            self.parse_import(file, root)
+        } else if self.looking_at("package") {
+            self.parse_package(file, root)
+        } else if self.looking_at("option") {
+            let options = &mut file.options.get_or_insert_with(Default::default).uninterpreted_option;
+            self.with_location(root, &[tag::file::OPTIONS], |p, location| {
+                p.parse_option(options, location, OptionStyle::Statement)
+            })
+        } else {
+            self.add_error("Expected top-level statement (e.g. \"message\").");
+            Err(())
+        }
+    }
+
+    fn parse_import(&mut self, file: &mut FileDescriptorProto, root: Loc) -> ParseResult {
+        let index = file.dependency.len() as i32;
+        self.with_location(root, &[tag::file::DEPENDENCY, index], |p, location| {
+            p.consume("import")?;
+
+            if p.looking_at("public") {
+                let public_index = file.public_dependency.len() as i32;
+                p.with_location(root, &[tag::file::PUBLIC_DEPENDENCY, public_index], |p, _| {
+                    p.consume("public")?;
+                    file.public_dependency.push(index);
+                    Ok(())
+                })?;
+            } else if p.looking_at("weak") {
+                let weak_index = file.weak_dependency.len() as i32;
+                p.with_location(root, &[tag::file::WEAK_DEPENDENCY, weak_index], |p, _| {
+                    p.consume("weak")?;
+                    file.weak_dependency.push(index);
+                    Ok(())
+                })?;
+            }
+
+            let import = p.consume_string_lossy("Expected a string naming the file to import.")?;
+            file.dependency.push(import);
+
+            p.consume_end_of_declaration(";", Some(location))
+        })
+    }
+
+    fn parse_package(&mut self, file: &mut FileDescriptorProto, root: Loc) -> ParseResult {
+        if file.package.is_some() {
+            self.add_error("Multiple package definitions.");
+            // The new package replaces the old one.
+            file.package = None;
+        }
+
+        self.with_location(root, &[tag::file::PACKAGE], |p, location| {
+            p.consume("package")?;
+
+            let mut package = String::new();
+            loop {
+                let identifier = p.consume_identifier("Expected identifier.");
+                let identifier = match identifier {
+                    Ok(identifier) => identifier,
+                    Err(()) => {
+                        file.package = Some(package);
+                        return Err(());
+                    },
+                };
+                package.push_str(&identifier);
+                if !p.try_consume(".") {
+                    break;
+                }
+                package.push('.');
+            }
+            file.package = Some(package);
+
+            p.consume_end_of_declaration(";", Some(location))
+        })
+    }
+
+    // Options.
+
+    fn parse_option(&mut self,
+                    options: &mut Vec<UninterpretedOption>,
+                    options_location: Loc,
+                    style: OptionStyle) -> ParseResult {
+        let index = options.len() as i32;
+        let location = self.location(options_location, &[tag::options::UNINTERPRETED_OPTION, index]);
+
+        if style == OptionStyle::Statement {
+            self.consume("option")?;
+        }
+
+        options.push(UninterpretedOption::default());
+        let option = options.last_mut().unwrap();
+
+        // Parse the dot-separated name.
+        self.with_location(location, &[tag::uninterpreted_option::NAME], |p, name_location| {
+            p.with_location(name_location, &[0], |p, part_location| {
+                p.parse_option_name_part(option, part_location)
+            })?;
+
+            while p.looking_at(".") {
+                p.consume(".")?;
+                let part_index = option.name.len() as i32;
+                p.with_location(name_location, &[part_index], |p, part_location| {
+                    p.parse_option_name_part(option, part_location)
+                })?;
+            }
+            Ok(())
+        })?;
+
+        self.consume("=")?;
+
+        let value_location = self.location(location, &[]);
+        self.parse_option_value(option, value_location)?;
+        self.end(value_location);
+
+        if style == OptionStyle::Statement {
+            self.consume_end_of_declaration(";", Some(location))?;
+        }
+        self.end(location);
+        Ok(())
+    }
+
+    fn parse_option_name_part(&mut self, option: &mut UninterpretedOption, part_location: Loc) -> ParseResult {
+        option.name.push(NamePart::default());
+        let name = option.name.last_mut().unwrap();
+
+        if self.looking_at("(") {
+            // An extension, whose name consists of dot-separated identifiers and may begin with
+            // a dot.
+            self.consume("(")?;
+            self.with_location(part_location, &[tag::name_part::NAME_PART], |p, _| {
+                if p.looking_at_type(TokenType::Identifier) {
+                    let identifier = p.consume_identifier("Expected identifier.")?;
+                    name.name_part.push_str(&identifier);
+                }
+                while p.looking_at(".") {
+                    p.consume(".")?;
+                    name.name_part.push('.');
+                    let identifier = p.consume_identifier("Expected identifier.")?;
+                    name.name_part.push_str(&identifier);
+                }
+                Ok(())
+            })?;
+            self.consume(")")?;
+            name.is_extension = true;
+        } else {
+            // A regular field.
+            self.with_location(part_location, &[tag::name_part::NAME_PART], |p, _| {
+                let identifier = p.consume_identifier("Expected identifier.")?;
+                name.name_part.push_str(&identifier);
+                Ok(())
+            })?;
+            name.is_extension = false;
+        }
+        Ok(())
+    }
+
+    fn parse_option_value(&mut self, option: &mut UninterpretedOption, value_location: Loc) -> ParseResult {
+        // All values are a single token, except for negative numbers, which consist of a '-'
+        // symbol followed by a positive number.
+        let negative = self.try_consume("-");
+
+        match self.input.current().token_type {
+            TokenType::Start => unreachable!("option value read before the first token"),
+            TokenType::End => {
+                self.add_error("Unexpected end of stream while parsing option value.");
+                Err(())
+            },
+            TokenType::Identifier => {
+                self.add_path(value_location, tag::uninterpreted_option::IDENTIFIER_VALUE);
+                if negative {
+                    self.add_error("Invalid '-' symbol before identifier.");
+                    return Err(());
+                }
+                option.identifier_value = Some(self.consume_identifier("Expected identifier.")?);
+                Ok(())
+            },
+            TokenType::Integer => {
+                let max_value = if negative {
+                    i64::max_value() as u64 + 1
+                } else {
+                    u64::max_value()
+                };
+                let value = self.consume_integer64(max_value, "Expected integer.")?;
+                if negative {
+                    self.add_path(value_location, tag::uninterpreted_option::NEGATIVE_INT_VALUE);
+                    option.negative_int_value = Some((value as i64).wrapping_neg());
+                } else {
+                    self.add_path(value_location, tag::uninterpreted_option::POSITIVE_INT_VALUE);
+                    option.positive_int_value = Some(value);
+                }
+                Ok(())
+            },
+            TokenType::Float => {
+                self.add_path(value_location, tag::uninterpreted_option::DOUBLE_VALUE);
+                let value = self.consume_number("Expected number.")?;
+                option.double_value = Some(if negative { -value } else { value });
+                Ok(())
+            },
+            TokenType::String => {
+                self.add_path(value_location, tag::uninterpreted_option::STRING_VALUE);
+                if negative {
+                    self.add_error("Invalid '-' symbol before string.");
+                    return Err(());
+                }
+                option.string_value = Some(self.consume_string("Expected string.")?);
+                Ok(())
+            },
+            TokenType::Symbol => {
+                if self.looking_at("{") {
+                    self.add_path(value_location, tag::uninterpreted_option::AGGREGATE_VALUE);
+                    option.aggregate_value = Some(self.parse_uninterpreted_block()?);
+                    Ok(())
+                } else {
+                    self.add_error("Expected option value.");
+                    Err(())
+                }
+            },
+        }
+    }
+
+    /// Parses the value of an aggregate option, which is left as text for the linker. The
+    /// enclosing braces are not included in the value.
+    fn parse_uninterpreted_block(&mut self) -> ParseResult<String> {
+        self.consume("{")?;
+        let mut value = String::new();
+        let mut brace_depth = 1;
+        while !self.at_end() {
+            if self.looking_at("{") {
+                brace_depth += 1;
+            } else if self.looking_at("}") {
+                brace_depth -= 1;
+                if brace_depth == 0 {
+                    self.input.next();
+                    return Ok(value);
+                }
+            }
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(&self.input.current().text);
+            self.input.next();
+        }
+        self.add_error("Unexpected end of stream while parsing aggregate value.");
+        Err(())
+    }
+
+    // Messages.
+
+    fn parse_message_definition(&mut self, message: &mut DescriptorProto, message_location: Loc) -> ParseResult {
+        self.consume("message")?;
+        self.with_location(message_location, &[tag::message::NAME], |p, _| {
+            message.name = Some(p.consume_identifier("Expected message name.")?);
+            Ok(())
+        })?;
+        self.parse_message_block(message, message_location)
+    }
+
+    fn parse_message_block(&mut self, message: &mut DescriptorProto, message_location: Loc) -> ParseResult {
+        self.consume_end_of_declaration("{", Some(message_location))?;
+
+        while !self.try_consume_end_of_declaration("}", None) {
+            if self.at_end() {
+                self.add_error("Reached end of input in message definition (missing '}').");
+                return Err(());
+            }
+            if self.parse_message_statement(message, message_location).is_err() {
+                // Skip the statement, but keep parsing the rest of the message.
+                self.skip_statement();
+            }
+        }
+
+        if !message.extension_range.is_empty() || !message.reserved_range.is_empty() {
+            let max_extension_number = if is_message_set_wire_format(message) {
+                i32::max_value()
+            } else {
+                MAX_FIELD_NUMBER + 1
+            };
+            for range in &mut message.extension_range {
+                if range.end == Some(MAX_RANGE_SENTINEL) {
+                    range.end = Some(max_extension_number);
+                }
+            }
+            for range in &mut message.reserved_range {
+                if range.end == Some(MAX_RANGE_SENTINEL) {
+                    range.end = Some(max_extension_number);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_message_statement(&mut self, message: &mut DescriptorProto, message_location: Loc) -> ParseResult {
+        if self.try_consume_end_of_declaration(";", None) {
+            // An empty statement.
+            Ok(())
+        } else if self.looking_at("message") {
+            let index = message.nested_type.len() as i32;
+            let mut nested = DescriptorProto::default();
+            let result = self.with_location(message_location, &[tag::message::NESTED_TYPE, index], |p, location| {
+                p.parse_message_definition(&mut nested, location)
+            });
+            message.nested_type.push(nested);
+            result
+        } else if self.looking_at("enum") {
+            let index = message.enum_type.len() as i32;
+            let mut enum_type = EnumDescriptorProto::default();
+            let result = self.with_location(message_location, &[tag::message::ENUM_TYPE, index], |p, location| {
+                p.parse_enum_definition(&mut enum_type, location)
+            });
+            message.enum_type.push(enum_type);
+            result
+        } else if self.looking_at("extensions") {
+            self.with_location(message_location, &[tag::message::EXTENSION_RANGE], |p, location| {
+                p.parse_extensions(message, location)
+            })
+        } else if self.looking_at("reserved") {
+            self.parse_message_reserved(message, message_location)
+        } else if self.looking_at("extend") {
+            let extensions = &mut message.extension;
+            let messages = &mut message.nested_type;
+            self.with_location(message_location, &[tag::message::EXTENSION], |p, location| {
+                p.parse_extend(extensions, messages, message_location, tag::message::NESTED_TYPE, location)
+            })
+        } else if self.looking_at("option") {
+            let options = &mut message.options.get_or_insert_with(Default::default).uninterpreted_option;
+            self.with_location(message_location, &[tag::message::OPTIONS], |p, location| {
+                p.parse_option(options, location, OptionStyle::Statement)
+            })
+        } else if self.looking_at("oneof") {
+            let index = message.oneof_decl.len() as i32;
+            message.oneof_decl.push(OneofDescriptorProto::default());
+            self.with_location(message_location, &[tag::message::ONEOF_DECL, index], |p, location| {
+                p.parse_oneof(message, index, location, message_location)
+            })
+        } else {
+            let index = message.field.len() as i32;
+            let mut field = FieldDescriptorProto::default();
+            let result = {
+                let messages = &mut message.nested_type;
+                self.with_location(message_location, &[tag::message::FIELD, index], |p, location| {
+                    p.parse_message_field(&mut field, messages, message_location, tag::message::NESTED_TYPE, location)
+                })
+            };
+            message.field.push(field);
+            result
+        }
+    }
+
+    fn parse_message_field(&mut self,
+                           field: &mut FieldDescriptorProto,
+                           messages: &mut Vec<DescriptorProto>,
+                           parent_location: Loc,
+                           nested_type_field_number: i32,
+                           field_location: Loc) -> ParseResult {
+        if let Some(label) = self.parse_label(field_location) {
+            field.label = Some(label as i32);
+            if label == Label::Optional && self.syntax == "proto3" {
+                self.add_error("Explicit 'optional' labels are disallowed in the Proto3 syntax. \
+                                To define 'optional' fields in Proto3, simply remove the \
+                                'optional' label, as fields are 'optional' by default.");
+            }
+        }
+        self.parse_message_field_no_label(field, messages, parent_location, nested_type_field_number, field_location)
+    }
+
+    fn parse_label(&mut self, field_location: Loc) -> Option<Label> {
+        if !self.looking_at("optional") && !self.looking_at("repeated") && !self.looking_at("required") {
+            return None;
+        }
+        let location = self.location(field_location, &[tag::field::LABEL]);
+        let label = if self.try_consume("optional") {
+            Label::Optional
+        } else if self.try_consume("repeated") {
+            Label::Repeated
+        } else {
+            self.input.next();
+            Label::Required
+        };
+        self.end(location);
+        Some(label)
+    }
+
+    fn parse_message_field_no_label(&mut self,
+                                    field: &mut FieldDescriptorProto,
+                                    messages: &mut Vec<DescriptorProto>,
+                                    parent_location: Loc,
+                                    nested_type_field_number: i32,
+                                    field_location: Loc) -> ParseResult {
+        // Parse the type.
+        let type_location = self.location(field_location, &[]);
+        let mut is_map = false;
+        let mut type_parsed = false;
+        let mut type_name = String::new();
+
+        // A field is only a map field if the type name "map" is followed by '<'.
+        if self.try_consume("map") {
+            if self.looking_at("<") {
+                is_map = true;
+            } else {
+                // A message or enum type named "map".
+                type_parsed = true;
+                type_name = "map".to_string();
+            }
+        }
+
+        let map_field = if is_map {
+            if field.oneof_index.is_some() {
+                self.add_error("Map fields are not allowed in oneofs.");
+                return Err(());
+            }
+            if field.label.is_some() {
+                self.add_error("Field labels (required/optional/repeated) are not allowed on map fields.");
+                return Err(());
+            }
+            if field.extendee.is_some() {
+                self.add_error("Map fields are not allowed to be extensions.");
+                return Err(());
+            }
+            field.label = Some(Label::Repeated as i32);
+            self.consume("<")?;
+            let key = self.parse_type()?;
+            self.consume(",")?;
+            let value = self.parse_type()?;
+            self.consume(">")?;
+            // The type name is set once the field name is known.
+            self.add_path(type_location, tag::field::TYPE_NAME);
+            Some(MapField { key, value })
+        } else {
+            if field.label.is_none() && self.syntax == "proto3" {
+                field.label = Some(Label::Optional as i32);
+            }
+            if field.label.is_none() {
+                self.add_error("Expected \"required\", \"optional\", or \"repeated\".");
+                // Assume the label was forgotten, and carry on.
+                field.label = Some(Label::Optional as i32);
+            }
+
+            let mut field_type = None;
+            if !type_parsed {
+                let (parsed_type, parsed_type_name) = self.parse_type()?;
+                field_type = parsed_type;
+                type_name = parsed_type_name;
+            }
+            match field_type {
+                Some(field_type) => {
+                    self.add_path(type_location, tag::field::TYPE);
+                    field.type_ = Some(field_type as i32);
+                },
+                None => {
+                    self.add_path(type_location, tag::field::TYPE_NAME);
+                    field.type_name = Some(type_name);
+                },
+            }
+            None
+        };
+        self.end(type_location);
+
+        // Parse the name and '='.
+        let name_token = self.input.current().clone();
+        self.with_location(field_location, &[tag::field::NAME], |p, _| {
+            field.name = Some(p.consume_identifier("Expected field name.")?);
+            Ok(())
+        })?;
+        self.consume_or("=", "Missing field number.")?;
+
+        // Parse the field number.
+        self.with_location(field_location, &[tag::field::NUMBER], |p, _| {
+            field.number = Some(p.consume_integer("Expected field number.")?);
+            Ok(())
+        })?;
+
+        self.parse_field_options(field, field_location)?;
+
+        if field.type_ == Some(Type::Group as i32) {
+            // A group declares both a message type and a field, so their locations overlap.
+            let index = messages.len() as i32;
+            let group_location = self.location(parent_location, &[nested_type_field_number, index]);
+            self.start_at_location(group_location, field_location);
+
+            let mut group = DescriptorProto::default();
+            group.name = field.name.clone();
+
+            // The name and type name both come from the field name.
+            let location = self.location(group_location, &[tag::message::NAME]);
+            self.start_at(location, &name_token);
+            self.end_at(location, &name_token);
+            let location = self.location(field_location, &[tag::field::TYPE_NAME]);
+            self.start_at(location, &name_token);
+            self.end_at(location, &name_token);
+
+            // For backwards compatibility, group names must start with a capital letter, and
+            // the field name is the lowercased group name.
+            if !group.name().starts_with(|c: char| c.is_ascii_uppercase()) {
+                self.input.add_error_at(name_token.line, name_token.column,
+                                        "Group names must start with a capital letter.");
+            }
+            field.name = Some(field.name().to_ascii_lowercase());
+            field.type_name = group.name.clone();
+
+            let result = if self.looking_at("{") {
+                self.parse_message_block(&mut group, group_location)
+            } else {
+                self.add_error("Missing group body.");
+                Err(())
+            };
+            self.end(group_location);
+            messages.push(group);
+            result?;
+        } else {
+            self.consume_end_of_declaration(";", Some(field_location))?;
+        }
+
+        if let Some(map_field) = map_field {
+            messages.push(map_entry(map_field, field));
+        }
+        Ok(())
+    }
+
+    /// Parses a field type, returning either the scalar type or the type name.
+    fn parse_type(&mut self) -> ParseResult<(Option<Type>, String)> {
+        match scalar_type(&self.input.current().text) {
+            Some(field_type) => {
+                self.input.next();
+                Ok((Some(field_type), String::new()))
+            },
+            None => self.parse_user_defined_type().map(|type_name| (None, type_name)),
+        }
+    }
+
+    fn parse_user_defined_type(&mut self) -> ParseResult<String> {
+        if scalar_type(&self.input.current().text).is_some() {
+            // Enum types are only allowed as field types, where scalar types are allowed as well,
+            // so this is always a message type.
+            self.add_error("Expected message type.");
+            // Pretend the type is valid, and carry on.
+            let type_name = self.input.current().text.clone();
+            self.input.next();
+            return Ok(type_name);
+        }
+
+        let mut type_name = String::new();
+        // A leading '.' means the name is fully qualified.
+        if self.try_consume(".") {
+            type_name.push('.');
+        }
+        type_name.push_str(&self.consume_identifier("Expected type name.")?);
+        while self.try_consume(".") {
+            type_name.push('.');
+            type_name.push_str(&self.consume_identifier("Expected identifier.")?);
+        }
+        Ok(type_name)
+    }
+
+    fn parse_field_options(&mut self, field: &mut FieldDescriptorProto, field_location: Loc) -> ParseResult {
+        if !self.looking_at("[") {
+            return Ok(());
+        }
+
+        self.with_location(field_location, &[tag::field::OPTIONS], |p, location| {
+            p.consume("[")?;
+            loop {
+                if p.looking_at("default") {
+                    // The default value is not an option, so it is located within the field.
+                    p.parse_default_assignment(field, field_location)?;
+                } else if p.looking_at("json_name") {
+                    // Neither is the JSON name.
+                    p.parse_json_name(field, field_location)?;
+                } else {
+                    let options = &mut field.options.get_or_insert_with(Default::default).uninterpreted_option;
+                    p.parse_option(options, location, OptionStyle::Assignment)?;
+                }
+                if !p.try_consume(",") {
+                    break;
+                }
+            }
+            p.consume("]")
+        })
+    }
+
+    fn parse_default_assignment(&mut self, field: &mut FieldDescriptorProto, field_location: Loc) -> ParseResult {
+        if field.default_value.is_some() {
+            self.add_error("Already set option \"default\".");
+            field.default_value = None;
+        }
+
+        self.consume("default")?;
+        self.consume("=")?;
+
+        self.with_location(field_location, &[tag::field::DEFAULT_VALUE], |p, _| {
+            let field_type = match field.type_.and_then(Type::from_i32) {
+                Some(field_type) => field_type,
+                None => {
+                    // The type is a message or an enum, which isn't known until the type name is
+                    // resolved. The token is taken as is, and checked by the linker.
+                    field.default_value = Some(p.input.current().text.clone());
+                    p.input.next();
+                    return Ok(());
+                },
+            };
+
+            let default_value = match field_type {
+                Type::Int32 | Type::Int64 | Type::Sint32 | Type::Sint64 | Type::Sfixed32 | Type::Sfixed64 => {
+                    let mut max_value = match field_type {
+                        Type::Int32 | Type::Sint32 | Type::Sfixed32 => i32::max_value() as u64,
+                        _ => i64::max_value() as u64,
+                    };
+                    let mut default_value = String::new();
+                    if p.try_consume("-") {
+                        default_value.push('-');
+                        // Two's complement has one more negative value than positive.
+                        max_value += 1;
+                    }
+                    let value = p.consume_integer64(max_value, "Expected integer for field default value.")?;
+                    default_value.push_str(&value.to_string());
+                    default_value
+                },
+                Type::Uint32 | Type::Uint64 | Type::Fixed32 | Type::Fixed64 => {
+                    let max_value = match field_type {
+                        Type::Uint32 | Type::Fixed32 => u64::from(u32::max_value()),
+                        _ => u64::max_value(),
+                    };
+                    if p.try_consume("-") {
+                        p.add_error("Unsigned field can't have negative default value.");
+                    }
+                    p.consume_integer64(max_value, "Expected integer for field default value.")?.to_string()
+                },
+                Type::Float | Type::Double => {
+                    let mut default_value = String::new();
+                    if p.try_consume("-") {
+                        default_value.push('-');
+                    }
+                    // Hex and octal integers are converted to decimal floats.
+                    let value = p.consume_number("Expected number.")?;
+                    default_value.push_str(&simple_dtoa(value));
+                    default_value
+                },
+                Type::Bool => {
+                    if p.try_consume("true") {
+                        "true".to_string()
+                    } else if p.try_consume("false") {
+                        "false".to_string()
+                    } else {
+                        p.add_error("Expected \"true\" or \"false\".");
+                        return Err(());
+                    }
+                },
+                Type::String => p.consume_string_lossy("Expected string for field default value.")?,
+                Type::Bytes => c_escape(&p.consume_string("Expected string.")?),
+                Type::Enum => p.consume_identifier("Expected enum identifier for field default value.")?,
+                Type::Message | Type::Group => {
+                    p.add_error("Messages can't have default values.");
+                    return Err(());
+                },
+            };
+            field.default_value = Some(default_value);
+            Ok(())
+        })
+    }
+
+    fn parse_json_name(&mut self, field: &mut FieldDescriptorProto, field_location: Loc) -> ParseResult {
+        if field.json_name.is_some() {
+            self.add_error("Already set option \"json_name\".");
+            field.json_name = None;
+        }
+
+        self.with_location(field_location, &[tag::field::JSON_NAME], |p, location| {
+            p.consume("json_name")?;
+            p.consume("=")?;
+            p.with_location(location, &[], |p, _| {
+                field.json_name = Some(p.consume_string_lossy("Expected string for JSON name.")?);
+                Ok(())
+            })
+        })
+    }
+
+    fn parse_extensions(&mut self, message: &mut DescriptorProto, extensions_location: Loc) -> ParseResult {
+        self.consume("extensions")?;
+
+        let first_range = message.extension_range.len();
+        loop {
+            let index = message.extension_range.len() as i32;
+            message.extension_range.push(ExtensionRange::default());
+            let range = message.extension_range.last_mut().unwrap();
+            self.with_location(extensions_location, &[index], |p, location| {
+                let (start, end) = p.parse_range(location, "Expected field number range.", "Expected integer.",
+                                                 MAX_RANGE_SENTINEL - 1, false)?;
+                // Ranges are declared inclusive, but stored exclusive.
+                range.start = Some(start);
+                range.end = Some(end.wrapping_add(1));
+                Ok(())
+            })?;
+            if !self.try_consume(",") {
+                break;
+            }
+        }
+
+        if self.looking_at("[") {
+            // The options apply to every range of the statement. They are parsed with the first
+            // range, and their locations are recorded separately so they can be copied for each
+            // range.
+            let range_index_position = self.locations[extensions_location.0].path.len();
+            let mut path = self.locations[extensions_location.0].path.clone();
+            path.push(0);
+            let locations = mem::replace(&mut self.locations, Vec::new());
+
+            let mut options = Vec::new();
+            let result = {
+                let index_location = self.location_at(path);
+                let result = self.with_location(index_location, &[tag::extension_range::OPTIONS], |p, location| {
+                    p.consume("[")?;
+                    loop {
+                        p.parse_option(&mut options, location, OptionStyle::Assignment)?;
+                        if !p.try_consume(",") {
+                            break;
+                        }
+                    }
+                    p.consume("]")
+                });
+                self.end(index_location);
+                result
+            };
+            let option_locations = mem::replace(&mut self.locations, locations);
+            result?;
+
+            for index in first_range..message.extension_range.len() {
+                message.extension_range[index].options = Some(ExtensionRangeOptions {
+                    uninterpreted_option: options.clone(),
+                    ..Default::default()
+                });
+                for location in &option_locations {
+                    // The location of the range index itself duplicates the range location.
+                    if location.path.len() == range_index_position + 1 {
+                        continue;
+                    }
+                    let mut location = location.clone();
+                    location.path[range_index_position] = index as i32;
+                    self.locations.push(location);
+                }
+            }
+        }
+
+        self.consume_end_of_declaration(";", Some(extensions_location))
+    }
+
+    /// Parses the start and optional end of a field or enum number range, recording their
+    /// locations. Returns the inclusive range, with `max` standing for `max_value`.
+    fn parse_range(&mut self,
+                   location: Loc,
+                   start_error: &str,
+                   end_error: &str,
+                   max_value: i32,
+                   signed: bool) -> ParseResult<(i32, i32)> {
+        let start_token = self.input.current().clone();
+        let start = self.with_location(location, &[tag::range::START], |p, _| {
+            if signed {
+                p.consume_signed_integer(start_error)
+            } else {
+                p.consume_integer(start_error)
+            }
+        })?;
+
+        if self.try_consume("to") {
+            let end = self.with_location(location, &[tag::range::END], |p, _| {
+                if p.try_consume("max") {
+                    Ok(max_value)
+                } else if signed {
+                    p.consume_signed_integer(end_error)
+                } else {
+                    p.consume_integer(end_error)
+                }
+            })?;
+            Ok((start, end))
+        } else {
+            let end_location = self.location(location, &[tag::range::END]);
+            self.start_at(end_location, &start_token);
+            self.end_at(end_location, &start_token);
+            Ok((start, start))
+        }
+    }
+
+    fn parse_message_reserved(&mut self, message: &mut DescriptorProto, message_location: Loc) -> ParseResult {
+        let start_token = self.input.current().clone();
+        self.consume("reserved")?;
+        if self.looking_at_type(TokenType::String) {
+            self.with_location(message_location, &[tag::message::RESERVED_NAME], |p, location| {
+                p.start_at(location, &start_token);
+                p.parse_reserved_names(&mut message.reserved_name, location)
+            })
+        } else {
+            self.with_location(message_location, &[tag::message::RESERVED_RANGE], |p, location| {
+                p.start_at(location, &start_token);
+                let mut first = true;
+                loop {
+                    let index = message.reserved_range.len() as i32;
+                    message.reserved_range.push(ReservedRange::default());
+                    let range = message.reserved_range.last_mut().unwrap();
+                    let start_error = if first {
+                        "Expected field name or number range."
+                    } else {
+                        "Expected field number range."
+                    };
+                    p.with_location(location, &[index], |p, range_location| {
+                        let (start, end) = p.parse_range(range_location, start_error, "Expected integer.",
+                                                         MAX_RANGE_SENTINEL - 1, false)?;
+                        // Ranges are declared inclusive, but stored exclusive.
+                        range.start = Some(start);
+                        range.end = Some(end.wrapping_add(1));
+                        Ok(())
+                    })?;
+                    first = false;
+                    if !p.try_consume(",") {
+                        break;
+                    }
+                }
+                p.consume_end_of_declaration(";", Some(location))
+            })
+        }
+    }
+
+    fn parse_reserved_names(&mut self, names: &mut Vec<String>, parent_location: Loc) -> ParseResult {
+        loop {
+            let index = names.len() as i32;
+            self.with_location(parent_location, &[index], |p, _| {
+                names.push(p.consume_string_lossy("Expected field name.")?);
+                Ok(())
+            })?;
+            if !self.try_consume(",") {
+                break;
+            }
+        }
+        self.consume_end_of_declaration(";", Some(parent_location))
+    }
+
+    fn parse_extend(&mut self,
+                    extensions: &mut Vec<FieldDescriptorProto>,
+                    messages: &mut Vec<DescriptorProto>,
+                    parent_location: Loc,
+                    nested_type_field_number: i32,
+                    extend_location: Loc) -> ParseResult {
+        self.consume("extend")?;
+
+        // Parse the extendee type.
+        let extendee_start = self.input.current().clone();
+        let extendee = self.parse_user_defined_type()?;
+        let extendee_end = self.input.previous().clone();
+
+        self.consume_end_of_declaration("{", Some(extend_location))?;
+
+        loop {
+            if self.at_end() {
+                self.add_error("Reached end of input in extend definition (missing '}').");
+                return Err(());
+            }
+
+            let index = extensions.len() as i32;
+            let mut field = FieldDescriptorProto::default();
+            let location = self.location(extend_location, &[index]);
+
+            let extendee_location = self.location(location, &[tag::field::EXTENDEE]);
+            self.start_at(extendee_location, &extendee_start);
+            self.end_at(extendee_location, &extendee_end);
+
+            field.extendee = Some(extendee.clone());
+            let result = self.parse_message_field(&mut field, messages, parent_location, nested_type_field_number, location);
+            self.end(location);
+            extensions.push(field);
+            if result.is_err() {
+                // Skip the statement, but keep parsing the rest of the block.
+                self.skip_statement();
+            }
+
+            if self.try_consume_end_of_declaration("}", None) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_oneof(&mut self,
+                   message: &mut DescriptorProto,
+                   oneof_index: i32,
+                   oneof_location: Loc,
+                   message_location: Loc) -> ParseResult {
+        self.consume("oneof")?;
+
+        self.with_location(oneof_location, &[tag::oneof::NAME], |p, _| {
+            message.oneof_decl[oneof_index as usize].name = Some(p.consume_identifier("Expected oneof name.")?);
+            Ok(())
+        })?;
+
+        self.consume_end_of_declaration("{", Some(oneof_location))?;
+
+        loop {
+            if self.at_end() {
+                self.add_error("Reached end of input in oneof definition (missing '}').");
+                return Err(());
+            }
+
+            if self.looking_at("option") {
+                let options = &mut message.oneof_decl[oneof_index as usize]
+                    .options.get_or_insert_with(Default::default).uninterpreted_option;
+                self.with_location(oneof_location, &[tag::oneof::OPTIONS], |p, location| {
+                    p.parse_option(options, location, OptionStyle::Statement)
+                })?;
+            } else {
+                // Labels are an easy mistake to make, and it's clear what was meant.
+                if self.looking_at("required") || self.looking_at("optional") || self.looking_at("repeated") {
+                    self.add_error("Fields in oneofs must not have labels (required / optional / repeated).");
+                    self.input.next();
+                }
+
+                let index = message.field.len() as i32;
+                let mut field = FieldDescriptorProto::default();
+                field.label = Some(Label::Optional as i32);
+                field.oneof_index = Some(oneof_index);
+
+                let result = {
+                    let messages = &mut message.nested_type;
+                    self.with_location(message_location, &[tag::message::FIELD, index], |p, location| {
+                        p.parse_message_field_no_label(&mut field, messages, message_location,
+                                                       tag::message::NESTED_TYPE, location)
+                    })
+                };
+                message.field.push(field);
+                if result.is_err() {
+                    // Skip the statement, but keep parsing the rest of the oneof.
+                    self.skip_statement();
+                }
+            }
+
+            if self.try_consume_end_of_declaration("}", None) {
+                return Ok(());
+            }
+        }
+    }
+
+    // Enums.
+
+    fn parse_enum_definition(&mut self, enum_type: &mut EnumDescriptorProto, enum_location: Loc) -> ParseResult {
+        self.consume("enum")?;
+        self.with_location(enum_location, &[tag::enumeration::NAME], |p, _| {
+            enum_type.name = Some(p.consume_identifier("Expected enum name.")?);
+            Ok(())
+        })?;
+        self.parse_enum_block(enum_type, enum_location)?;
+        self.validate_enum(enum_type)
+    }
+
+    fn parse_enum_block(&mut self, enum_type: &mut EnumDescriptorProto, enum_location: Loc) -> ParseResult {
+        self.consume_end_of_declaration("{", Some(enum_location))?;
+
+        while !self.try_consume_end_of_declaration("}", None) {
+            if self.at_end() {
+                self.add_error("Reached end of input in enum definition (missing '}').");
+                return Err(());
+            }
+            if self.parse_enum_statement(enum_type, enum_location).is_err() {
+                // Skip the statement, but keep parsing the rest of the enum.
+                self.skip_statement();
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_enum_statement(&mut self, enum_type: &mut EnumDescriptorProto, enum_location: Loc) -> ParseResult {
+        if self.try_consume_end_of_declaration(";", None) {
+            // An empty statement.
+            Ok(())
+        } else if self.looking_at("option") {
+            let options = &mut enum_type.options.get_or_insert_with(Default::default).uninterpreted_option;
+            self.with_location(enum_location, &[tag::enumeration::OPTIONS], |p, location| {
+                p.parse_option(options, location, OptionStyle::Statement)
+            })
+        } else if self.looking_at("reserved") {
+            self.parse_enum_reserved(enum_type, enum_location)
+        } else {
+            let index = enum_type.value.len() as i32;
+            let mut value = EnumValueDescriptorProto::default();
+            let result = self.with_location(enum_location, &[tag::enumeration::VALUE, index], |p, location| {
+                p.parse_enum_constant(&mut value, location)
+            });
+            enum_type.value.push(value);
+            result
+        }
+    }
+
+    fn parse_enum_constant(&mut self, value: &mut EnumValueDescriptorProto, value_location: Loc) -> ParseResult {
+        self.with_location(value_location, &[tag::enum_value::NAME], |p, _| {
+            value.name = Some(p.consume_identifier("Expected enum constant name.")?);
+            Ok(())
+        })?;
+
+        self.consume_or("=", "Missing numeric value for enum constant.")?;
+
+        self.with_location(value_location, &[tag::enum_value::NUMBER], |p, _| {
+            value.number = Some(p.consume_signed_integer("Expected integer.")?);
+            Ok(())
+        })?;
+
+        if self.looking_at("[") {
+            self.with_location(value_location, &[tag::enum_value::OPTIONS], |p, location| {
+                p.consume("[")?;
+                loop {
+                    let options = &mut value.options.get_or_insert_with(Default::default).uninterpreted_option;
+                    p.parse_option(options, location, OptionStyle::Assignment)?;
+                    if !p.try_consume(",") {
+                        break;
+                    }
+                }
+                p.consume("]")
+            })?;
+        }
+
+        self.consume_end_of_declaration(";", Some(value_location))
+    }
+
+    fn parse_enum_reserved(&mut self, enum_type: &mut EnumDescriptorProto, enum_location: Loc) -> ParseResult {
+        let start_token = self.input.current().clone();
+        self.consume("reserved")?;
+        if self.looking_at_type(TokenType::String) {
+            self.with_location(enum_location, &[tag::enumeration::RESERVED_NAME], |p, location| {
+                p.start_at(location, &start_token);
+                p.parse_reserved_names(&mut enum_type.reserved_name, location)
+            })
+        } else {
+            self.with_location(enum_location, &[tag::enumeration::RESERVED_RANGE], |p, location| {
+                p.start_at(location, &start_token);
+                let mut first = true;
+                loop {
+                    let index = enum_type.reserved_range.len() as i32;
+                    enum_type.reserved_range.push(EnumReservedRange::default());
+                    let range = enum_type.reserved_range.last_mut().unwrap();
+                    let start_error = if first {
+                        "Expected enum value or number range."
+                    } else {
+                        "Expected enum number range."
+                    };
+                    p.with_location(location, &[index], |p, range_location| {
+                        // Enum ranges are stored inclusive, and aren't affected by message sets.
+                        let (start, end) = p.parse_range(range_location, start_error, "Expected integer.",
+                                                         i32::max_value(), true)?;
+                        range.start = Some(start);
+                        range.end = Some(end);
+                        Ok(())
+                    })?;
+                    first = false;
+                    if !p.try_consume(",") {
+                        break;
+                    }
+                }
+                p.consume_end_of_declaration(";", Some(location))
+            })
+        }
+    }
+
+    fn validate_enum(&mut self, enum_type: &EnumDescriptorProto) -> ParseResult {
+        let allow_alias = enum_type.options.iter()
+            .flat_map(|options| options.uninterpreted_option.iter())
+            .find(|option| {
+                option.name.len() == 1 && !option.name[0].is_extension
+                    && option.name[0].name_part == "allow_alias"
+            })
+            .map(|option| option.identifier_value() == "true");
+
+        if allow_alias == Some(false) {
+            self.add_error(&format!("\"{}\" declares 'option allow_alias = false;' which has no \
+                                     effect. Please remove the declaration.", enum_type.name()));
+            return Err(());
+        }
+
+        let mut numbers = enum_type.value.iter().map(|value| value.number()).collect::<Vec<_>>();
+        numbers.sort();
+        let has_duplicates = numbers.windows(2).any(|pair| pair[0] == pair[1]);
+        if allow_alias == Some(true) && !has_duplicates {
+            self.add_error(&format!("\"{}\" declares support for enum aliases but no enum values \
+                                     share field numbers. Please remove the unnecessary 'option \
+                                     allow_alias = true;' declaration.", enum_type.name()));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    // Services.
+
+    fn parse_service_definition(&mut self, service: &mut ServiceDescriptorProto, service_location: Loc) -> ParseResult {
+        self.consume("service")?;
+        self.with_location(service_location, &[tag::service::NAME], |p, _| {
+            service.name = Some(p.consume_identifier("Expected service name.")?);
+            Ok(())
+        })?;
+
+        self.consume_end_of_declaration("{", Some(service_location))?;
+
+        while !self.try_consume_end_of_declaration("}", None) {
+            if self.at_end() {
+                self.add_error("Reached end of input in service definition (missing '}').");
+                return Err(());
+            }
+            if self.parse_service_statement(service, service_location).is_err() {
+                // Skip the statement, but keep parsing the rest of the service.
+                self.skip_statement();
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_service_statement(&mut self, service: &mut ServiceDescriptorProto, service_location: Loc) -> ParseResult {
+        if self.try_consume_end_of_declaration(";", None) {
+            // An empty statement.
+            Ok(())
+        } else if self.looking_at("option") {
+            let options = &mut service.options.get_or_insert_with(Default::default).uninterpreted_option;
+            self.with_location(service_location, &[tag::service::OPTIONS], |p, location| {
+                p.parse_option(options, location, OptionStyle::Statement)
+            })
+        } else {
+            let index = service.method.len() as i32;
+            let mut method = MethodDescriptorProto::default();
+            let result = self.with_location(service_location, &[tag::service::METHOD, index], |p, location| {
+                p.parse_service_method(&mut method, location)
+            });
+            service.method.push(method);
+            result
+        }
+    }
+
+    fn parse_service_method(&mut self, method: &mut MethodDescriptorProto, method_location: Loc) -> ParseResult {
+        self.consume("rpc")?;
+        self.with_location(method_location, &[tag::method::NAME], |p, _| {
+            method.name = Some(p.consume_identifier("Expected method name.")?);
+            Ok(())
+        })?;
+
+        // Parse the input type.
+        self.consume("(")?;
+        if self.looking_at("stream") {
+            self.with_location(method_location, &[tag::method::CLIENT_STREAMING], |p, _| {
+                method.client_streaming = Some(true);
+                p.consume("stream")
+            })?;
+        }
+        self.with_location(method_location, &[tag::method::INPUT_TYPE], |p, _| {
+            method.input_type = Some(p.parse_user_defined_type()?);
+            Ok(())
+        })?;
+        self.consume(")")?;
+
+        // Parse the output type.
+        self.consume("returns")?;
+        self.consume("(")?;
+        if self.looking_at("stream") {
+            self.with_location(method_location, &[tag::method::SERVER_STREAMING], |p, _| {
+                p.consume("stream")?;
+                method.server_streaming = Some(true);
+                Ok(())
+            })?;
+        }
+        self.with_location(method_location, &[tag::method::OUTPUT_TYPE], |p, _| {
+            method.output_type = Some(p.parse_user_defined_type()?);
+            Ok(())
+        })?;
+        self.consume(")")?;
+
+        if self.looking_at("{") {
+            self.parse_method_options(method.options.get_or_insert_with(MethodOptions::default), method_location)
+        } else {
+            self.consume_end_of_declaration(";", Some(method_location))
+        }
+    }
+
+    fn parse_method_options(&mut self, options: &mut MethodOptions, method_location: Loc) -> ParseResult {
+        // An error here is recovered from by parsing the block anyway.
+        let _ = self.consume_end_of_declaration("{", Some(method_location));
+
+        while !self.try_consume_end_of_declaration("}", None) {
+            if self.at_end() {
+                self.add_error("Reached end of input in method options (missing '}').");
+                return Err(());
+            }
+
+            if !self.try_consume_end_of_declaration(";", None) {
+                let options = &mut options.uninterpreted_option;
+                let result = self.with_location(method_location, &[tag::method::OPTIONS], |p, location| {
+                    p.parse_option(options, location, OptionStyle::Statement)
+                });
+                if result.is_err() {
+                    // Skip the statement, but keep parsing the rest of the block.
+                    self.skip_statement();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the scalar type with the given name.
+fn scalar_type(name: &str) -> Option<Type> {
+    let field_type = match name {
+        "double" => Type::Double,
+        "float" => Type::Float,
+        "int64" => Type::Int64,
+        "uint64" => Type::Uint64,
+        "int32" => Type::Int32,
+        "fixed64" => Type::Fixed64,
+        "fixed32" => Type::Fixed32,
+        "bool" => Type::Bool,
+        "string" => Type::String,
+        "group" => Type::Group,
+        "bytes" => Type::Bytes,
+        "uint32" => Type::Uint32,
+        "sfixed32" => Type::Sfixed32,
+        "sfixed64" => Type::Sfixed64,
+        "sint32" => Type::Sint32,
+        "sint64" => Type::Sint64,
+        _ => return None,
+    };
+    Some(field_type)
+}
+
+/// Returns `true` if the message has the uninterpreted `message_set_wire_format = true` option.
+fn is_message_set_wire_format(message: &DescriptorProto) -> bool {
+    message.options.iter()
+        .flat_map(|options| options.uninterpreted_option.iter())
+        .any(|option| {
+            option.name.len() == 1 && option.name[0].name_part == "message_set_wire_format"
+                && option.identifier_value() == "true"
+        })
+}
+
+/// Generates the nested entry message type of a map field, and points the field at it.
+fn map_entry(map_field: MapField, field: &mut FieldDescriptorProto) -> DescriptorProto {
+    let entry_name = map_entry_name(field.name());
+    field.type_name = Some(entry_name.clone());
+
+    let entry_field = |name: &str, number: i32, (field_type, type_name): (Option<Type>, String)| {
+        let mut entry_field = FieldDescriptorProto::default();
+        entry_field.name = Some(name.to_string());
+        entry_field.label = Some(Label::Optional as i32);
+        entry_field.number = Some(number);
+        match field_type {
+            Some(field_type) => entry_field.type_ = Some(field_type as i32),
+            None => entry_field.type_name = Some(type_name),
+        }
+        entry_field
+    };
+
+    let mut entry = DescriptorProto::default();
+    entry.name = Some(entry_name);
+    entry.options = Some(MessageOptions {
+        map_entry: Some(true),
+        ..Default::default()
+    });
+    entry.field.push(entry_field("key", 1, map_field.key));
+    entry.field.push(entry_field("value", 2, map_field.value));
+    entry
+}
+
+/// Returns the name of the entry message type of a map field: the field name in camel case,
+/// followed by `Entry`.
+fn map_entry_name(field_name: &str) -> String {
+    let mut name = String::with_capacity(field_name.len() + 5);
+    let mut capitalize_next = true;
+    for c in field_name.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            name.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            name.push(c);
+        }
+    }
+    name.push_str("Entry");
+    name
+}
+
+/// Escapes bytes like `CEscape`, as used for the default values of bytes fields.
+pub fn c_escape(value: &[u8]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for &byte in value {
+        match byte {
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'"' => escaped.push_str("\\\""),
+            b'\'' => escaped.push_str("\\'"),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(source: &str) -> FileDescriptorProto {
+        match parse(source.as_bytes()) {
+            Ok(file) => file,
+            Err(errors) => panic!("unexpected errors: {:?}", errors),
+        }
+    }
+
+    fn parse_err(source: &str) -> Vec<(i32, i32, String)> {
+        match parse(source.as_bytes()) {
+            Ok(file) => panic!("unexpected success: {:?}", file),
+            Err(errors) => errors,
+        }
+    }
+
+    fn location<'a>(file: &'a FileDescriptorProto, path: &[i32]) -> &'a Location {
+        file.source_code_info.as_ref().unwrap().location.iter()
+            .find(|location| location.path == path)
+            .unwrap_or_else(|| panic!("no location for {:?}", path))
+    }
+
+    #[test]
+    fn test_message() {
+        let file = parse_ok("\
+            syntax = \"proto2\";\n\
+            package foo.bar;\n\
+            message Foo {\n\
+              required int32 a = 1 [default = 0x10];\n\
+              repeated .foo.Bar b = 2 [packed = true, json_name = \"bee\"];\n\
+              optional group Baz = 3 { optional bytes c = 4 [default = \"\\001a\"]; }\n\
+              map<string, Foo> d = 5;\n\
+              oneof e { string f = 6; }\n\
+              extensions 100 to max;\n\
+              reserved 7, 9 to 11;\n\
+              reserved \"g\";\n\
+            }\n");
+
+        assert_eq!(file.syntax(), "proto2");
+        assert_eq!(file.package(), "foo.bar");
+
+        let message = &file.message_type[0];
+        assert_eq!(message.name(), "Foo");
+
+        let fields = message.field.iter().map(|field| {
+            (field.name(), field.number(), field.label, field.type_, field.type_name(), field.default_value())
+        }).collect::<Vec<_>>();
+        assert_eq!(fields, vec![
+            ("a", 1, Some(Label::Required as i32), Some(Type::Int32 as i32), "", "16"),
+            ("b", 2, Some(Label::Repeated as i32), None, ".foo.Bar", ""),
+            ("baz", 3, Some(Label::Optional as i32), Some(Type::Group as i32), "Baz", ""),
+            ("d", 5, Some(Label::Repeated as i32), None, "DEntry", ""),
+            ("f", 6, Some(Label::Optional as i32), Some(Type::String as i32), "", ""),
+        ]);
+        assert_eq!(message.field[1].json_name(), "bee");
+        assert_eq!(message.field[1].options.as_ref().unwrap().uninterpreted_option[0].identifier_value(), "true");
+        assert_eq!(message.field[4].oneof_index, Some(0));
+
+        assert_eq!(message.nested_type[0].name(), "Baz");
+        assert_eq!(message.nested_type[0].field[0].default_value(), "\\001a");
+        assert_eq!(message.nested_type[1].name(), "DEntry");
+        assert!(message.nested_type[1].options.as_ref().unwrap().map_entry());
+
+        assert_eq!(message.extension_range[0].start, Some(100));
+        assert_eq!(message.extension_range[0].end, Some(536_870_912));
+        let reserved = message.reserved_range.iter().map(|range| (range.start(), range.end())).collect::<Vec<_>>();
+        assert_eq!(reserved, vec![(7, 8), (9, 12)]);
+        assert_eq!(message.reserved_name, vec!["g".to_string()]);
+
+        assert_eq!(location(&file, &[4, 0]).span, vec![2, 0, 11, 1]);
+        assert_eq!(location(&file, &[4, 0, 2, 0, 5]).span, vec![3, 9, 14]);
+        assert_eq!(location(&file, &[4, 0, 3, 0]).span, vec![5, 0, 68]);
+        assert_eq!(location(&file, &[4, 0, 2, 2, 6]).span, vec![5, 15, 18]);
+        assert_eq!(location(&file, &[4, 0, 2, 1, 8, 999, 0]).span, vec![4, 25, 38]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let file = parse_ok("\
+            // Detached.\n\
+            \n\
+            // Leading.\n\
+            message Foo { // Trailing.\n\
+              // Field.\n\
+              optional int32 a = 1;\n\
+            }\n");
+
+        let message = location(&file, &[4, 0]);
+        assert_eq!(message.leading_detached_comments, vec![" Detached.\n".to_string()]);
+        assert_eq!(message.leading_comments(), " Leading.\n");
+        assert_eq!(message.trailing_comments(), " Trailing.\n");
+        assert_eq!(location(&file, &[4, 0, 2, 0]).leading_comments(), " Field.\n");
+    }
+
+    #[test]
+    fn test_enum_and_service() {
+        let file = parse_ok("\
+            syntax = \"proto3\";\n\
+            enum E { option allow_alias = true; A = 0; B = 0; C = -1 [deprecated = true]; reserved 5 to max; }\n\
+            service S { rpc M(stream .a.B) returns (C) { option deprecated = true; } }\n");
+
+        let enum_type = &file.enum_type[0];
+        assert_eq!(enum_type.value[2].number(), -1);
+        assert_eq!(enum_type.reserved_range[0].end(), i32::max_value());
+
+        let method = &file.service[0].method[0];
+        assert_eq!(method.input_type(), ".a.B");
+        assert_eq!(method.output_type(), "C");
+        assert_eq!(method.client_streaming, Some(true));
+        assert_eq!(method.server_streaming, None);
+        assert_eq!(method.options.as_ref().unwrap().uninterpreted_option.len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_err("syntax = \"proto4\";"), vec![
+            (0, 9, "Unrecognized syntax identifier \"proto4\".  This parser only recognizes \
+                    \"proto2\" and \"proto3\".".to_string()),
+        ]);
+        assert_eq!(parse_err("message Foo {\n  int32 a = 1;\n}"), vec![
+            (1, 2, "Expected \"required\", \"optional\", or \"repeated\".".to_string()),
+        ]);
+        assert_eq!(parse_err("syntax = \"proto3\"; message Foo { optional group a = 1 {} }"), vec![
+            (0, 42, "Explicit 'optional' labels are disallowed in the Proto3 syntax. To define \
+                     'optional' fields in Proto3, simply remove the 'optional' label, as fields \
+                     are 'optional' by default.".to_string()),
+            (0, 48, "Group names must start with a capital letter.".to_string()),
+        ]);
+        assert_eq!(parse_err("enum E { A = 0; } }"), vec![
+            (0, 18, "Expected top-level statement (e.g. \"message\").".to_string()),
+            (0, 18, "Unmatched \"}\".".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_map_entry_name() {
+        assert_eq!(map_entry_name("foo_bar"), "FooBarEntry");
+        assert_eq!(map_entry_name("fooBar_1"), "FooBar1Entry");
+    }
+}
@@ -0,0 +1,857 @@
+//! Splits `.proto` source text into tokens.
+//!
+//! The tokenizer follows `google::protobuf::io::Tokenizer`, including its column numbering and
+//! its rules for attaching comments to declarations, so that the source locations and comments
+//! recorded by the parser match `protoc`.
+
+use std::char;
+use std::str;
+
+const TAB_WIDTH: i32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenType {
+    /// No token has been read yet.
+    Start,
+    /// The end of the input has been reached.
+    End,
+    Identifier,
+    Integer,
+    Float,
+    String,
+    /// Any other printable character.
+    Symbol,
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub text: String,
+    /// Zero-based line of the first character.
+    pub line: i32,
+    /// Zero-based column of the first character, with tabs advancing to the next multiple of 8.
+    pub column: i32,
+    /// Zero-based column one past the last character.
+    pub end_column: i32,
+}
+
+impl Token {
+    fn start() -> Token {
+        Token {
+            token_type: TokenType::Start,
+            text: String::new(),
+            line: 0,
+            column: 0,
+            end_column: 0,
+        }
+    }
+}
+
+/// The comments surrounding a declaration boundary.
+#[derive(Debug, Default)]
+pub struct Comments {
+    /// The comment trailing the previous token.
+    pub trailing: String,
+    /// Comments which are attached to neither token.
+    pub detached: Vec<String>,
+    /// The comment leading the next token.
+    pub leading: String,
+}
+
+pub struct Tokenizer<'a> {
+    input: &'a [u8],
+    /// The offset of `current_char`.
+    pos: usize,
+    current_char: u8,
+    line: i32,
+    column: i32,
+    token_start: usize,
+    current: Token,
+    previous: Token,
+    errors: Vec<(i32, i32, String)>,
+}
+
+enum CommentStart {
+    Line,
+    Block,
+    SlashNotComment,
+    None,
+}
+
+/// Collects comments while skipping from one token to the next, and sorts them into the trailing
+/// comment of the previous token, detached comments, and the leading comment of the next token.
+struct CommentCollector {
+    comments: Comments,
+    buffer: String,
+    has_comment: bool,
+    is_line_comment: bool,
+    can_attach_to_prev: bool,
+}
+
+impl CommentCollector {
+    fn new() -> CommentCollector {
+        CommentCollector {
+            comments: Comments::default(),
+            buffer: String::new(),
+            has_comment: false,
+            is_line_comment: false,
+            can_attach_to_prev: true,
+        }
+    }
+
+    /// Returns the buffer to read a line comment into. Consecutive line comments are combined.
+    fn buffer_for_line_comment(&mut self) -> &mut String {
+        if self.has_comment && !self.is_line_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = true;
+        &mut self.buffer
+    }
+
+    /// Returns the buffer to read a block comment into.
+    fn buffer_for_block_comment(&mut self) -> &mut String {
+        if self.has_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = false;
+        &mut self.buffer
+    }
+
+    fn clear_buffer(&mut self) {
+        self.buffer.clear();
+        self.has_comment = false;
+    }
+
+    /// Called once the buffered comment is known not to be attached to the next token.
+    fn flush(&mut self) {
+        if self.has_comment {
+            if self.can_attach_to_prev {
+                self.comments.trailing.push_str(&self.buffer);
+                self.can_attach_to_prev = false;
+            } else {
+                self.comments.detached.push(self.buffer.clone());
+            }
+            self.clear_buffer();
+        }
+    }
+
+    fn detach_from_prev(&mut self) {
+        self.can_attach_to_prev = false;
+    }
+
+    fn finish(mut self) -> Comments {
+        // Whatever is still buffered is the leading comment of the next token.
+        if self.has_comment {
+            self.comments.leading = self.buffer;
+        }
+        self.comments
+    }
+}
+
+impl <'a> Tokenizer<'a> {
+    pub fn new(input: &'a [u8]) -> Tokenizer<'a> {
+        Tokenizer {
+            input,
+            pos: 0,
+            current_char: input.first().cloned().unwrap_or(0),
+            line: 0,
+            column: 0,
+            token_start: 0,
+            current: Token::start(),
+            previous: Token::start(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Returns the current token.
+    pub fn current(&self) -> &Token {
+        &self.current
+    }
+
+    /// Returns the token preceding the current token.
+    pub fn previous(&self) -> &Token {
+        &self.previous
+    }
+
+    /// Records an error at the provided zero-based line and column.
+    pub fn add_error_at<S>(&mut self, line: i32, column: i32, message: S) where S: Into<String> {
+        self.errors.push((line, column, message.into()));
+    }
+
+    /// Returns the errors encountered so far, as zero-based line, column, and message.
+    pub fn errors(&self) -> &[(i32, i32, String)] {
+        &self.errors
+    }
+
+    fn add_error(&mut self, message: &str) {
+        let (line, column) = (self.line, self.column);
+        self.add_error_at(line, column, message);
+    }
+
+    fn at_eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn next_char(&mut self) {
+        if self.at_eof() {
+            return;
+        }
+        match self.current_char {
+            b'\n' => {
+                self.line += 1;
+                self.column = 0;
+            },
+            b'\t' => self.column += TAB_WIDTH - self.column % TAB_WIDTH,
+            _ => self.column += 1,
+        }
+        self.pos += 1;
+        self.current_char = self.input.get(self.pos).cloned().unwrap_or(0);
+    }
+
+    fn try_consume(&mut self, c: u8) -> bool {
+        if self.current_char == c && !self.at_eof() {
+            self.next_char();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn try_consume_one(&mut self, class: fn(u8) -> bool) -> bool {
+        if class(self.current_char) && !self.at_eof() {
+            self.next_char();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_zero_or_more(&mut self, class: fn(u8) -> bool) {
+        while self.try_consume_one(class) {}
+    }
+
+    fn consume_one_or_more(&mut self, class: fn(u8) -> bool, error: &str) {
+        if !self.try_consume_one(class) {
+            self.add_error(error);
+        }
+        self.consume_zero_or_more(class);
+    }
+
+    fn start_token(&mut self) {
+        self.token_start = self.pos;
+        self.current = Token {
+            token_type: TokenType::Start,
+            text: String::new(),
+            line: self.line,
+            column: self.column,
+            end_column: self.column,
+        };
+    }
+
+    fn end_token(&mut self, token_type: TokenType) {
+        self.current.token_type = token_type;
+        self.current.text = String::from_utf8_lossy(&self.input[self.token_start..self.pos]).into_owned();
+        self.current.end_column = self.column;
+    }
+
+    /// Advances to the next token, skipping whitespace and comments. Returns `false` at the end
+    /// of the input.
+    pub fn next(&mut self) -> bool {
+        self.previous = self.current.clone();
+
+        while !self.at_eof() {
+            self.consume_zero_or_more(is_whitespace);
+
+            match self.try_consume_comment_start() {
+                CommentStart::Line => {
+                    self.consume_line_comment(None);
+                    continue;
+                },
+                CommentStart::Block => {
+                    self.consume_block_comment(None);
+                    continue;
+                },
+                CommentStart::SlashNotComment => return true,
+                CommentStart::None => (),
+            }
+
+            if self.at_eof() {
+                break;
+            }
+
+            if is_unprintable(self.current_char) || self.current_char == 0 {
+                self.add_error("Invalid control characters encountered in text.");
+                self.next_char();
+                while self.try_consume_one(is_unprintable) || self.try_consume(0) {}
+                continue;
+            }
+
+            self.start_token();
+            let token_type = if self.try_consume_one(is_letter) {
+                self.consume_zero_or_more(is_alphanumeric);
+                TokenType::Identifier
+            } else if self.try_consume(b'0') {
+                self.consume_number(true, false)
+            } else if self.try_consume(b'.') {
+                // This could be the beginning of a floating-point number, or just a '.' symbol.
+                if self.try_consume_one(is_digit) {
+                    if self.previous.token_type == TokenType::Identifier
+                        && self.current.line == self.previous.line
+                        && self.current.column == self.previous.end_column {
+                        // Syntax like "blah.123" is not accepted.
+                        let (line, column) = (self.line, self.column - 2);
+                        self.add_error_at(line, column, "Need space between identifier and decimal point.");
+                    }
+                    self.consume_number(false, true)
+                } else {
+                    TokenType::Symbol
+                }
+            } else if self.try_consume_one(is_digit) {
+                self.consume_number(false, false)
+            } else if self.try_consume(b'"') {
+                self.consume_string(b'"');
+                TokenType::String
+            } else if self.try_consume(b'\'') {
+                self.consume_string(b'\'');
+                TokenType::String
+            } else {
+                if self.current_char & 0x80 != 0 {
+                    let message = format!("Interpreting non ascii codepoint {}.", self.current_char);
+                    self.add_error(&message);
+                }
+                self.next_char();
+                TokenType::Symbol
+            };
+            self.end_token(token_type);
+            return true;
+        }
+
+        self.current = Token {
+            token_type: TokenType::End,
+            text: String::new(),
+            line: self.line,
+            column: self.column,
+            end_column: self.column,
+        };
+        false
+    }
+
+    /// Advances to the next token like `next`, and returns the comments which were skipped.
+    pub fn next_with_comments(&mut self) -> Comments {
+        let mut collector = CommentCollector::new();
+        self.collect_comments(&mut collector);
+        collector.finish()
+    }
+
+    fn collect_comments(&mut self, collector: &mut CommentCollector) {
+        if self.current.token_type == TokenType::Start {
+            // Ignore a UTF-8 byte order mark at the start of the file.
+            if self.try_consume(0xEF) && (!self.try_consume(0xBB) || !self.try_consume(0xBF)) {
+                self.add_error("Proto file starts with 0xEF but not UTF-8 BOM. \
+                                Only UTF-8 is accepted for proto file.");
+                return;
+            }
+            collector.detach_from_prev();
+        } else {
+            // A comment on the same line as the previous token is attached to it.
+            self.consume_zero_or_more(is_whitespace_no_newline);
+            match self.try_consume_comment_start() {
+                CommentStart::Line => {
+                    self.consume_line_comment(Some(collector.buffer_for_line_comment()));
+                    // Comments on subsequent lines may not be attached to the trailing comment.
+                    collector.flush();
+                },
+                CommentStart::Block => {
+                    self.consume_block_comment(Some(collector.buffer_for_block_comment()));
+                    self.consume_zero_or_more(is_whitespace_no_newline);
+                    if !self.try_consume(b'\n') {
+                        // The next token is on the same line, so there's no telling which token
+                        // the comment belongs to.
+                        collector.clear_buffer();
+                        self.next();
+                        return;
+                    }
+                    collector.flush();
+                },
+                CommentStart::SlashNotComment => return,
+                CommentStart::None => {
+                    if !self.try_consume(b'\n') {
+                        // The next token is on the same line, so there are no comments.
+                        self.next();
+                        return;
+                    }
+                },
+            }
+        }
+
+        // We are now on the line after the previous token.
+        loop {
+            self.consume_zero_or_more(is_whitespace_no_newline);
+            match self.try_consume_comment_start() {
+                CommentStart::Line => {
+                    self.consume_line_comment(Some(collector.buffer_for_line_comment()));
+                },
+                CommentStart::Block => {
+                    self.consume_block_comment(Some(collector.buffer_for_block_comment()));
+                    // Consume the rest of the line, so that it isn't taken for a blank line.
+                    self.consume_zero_or_more(is_whitespace_no_newline);
+                    self.try_consume(b'\n');
+                },
+                CommentStart::SlashNotComment => return,
+                CommentStart::None => {
+                    if self.try_consume(b'\n') {
+                        // A blank line.
+                        collector.flush();
+                        collector.detach_from_prev();
+                    } else {
+                        let more = self.next();
+                        if !more || self.current.text == "}" || self.current.text == "]"
+                            || self.current.text == ")" {
+                            // At the end of a scope, a comment can't belong to the next token.
+                            collector.flush();
+                        }
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    fn try_consume_comment_start(&mut self) -> CommentStart {
+        if self.try_consume(b'/') {
+            if self.try_consume(b'/') {
+                CommentStart::Line
+            } else if self.try_consume(b'*') {
+                CommentStart::Block
+            } else {
+                // Oops, it was just a slash.
+                self.previous = self.current.clone();
+                self.current = Token {
+                    token_type: TokenType::Symbol,
+                    text: "/".to_string(),
+                    line: self.line,
+                    column: self.column - 1,
+                    end_column: self.column,
+                };
+                CommentStart::SlashNotComment
+            }
+        } else {
+            CommentStart::None
+        }
+    }
+
+    fn record(&self, start: usize, content: &mut Option<&mut String>) {
+        if let Some(ref mut content) = *content {
+            content.push_str(&String::from_utf8_lossy(&self.input[start..self.pos]));
+        }
+    }
+
+    /// Consumes the rest of a line comment, including the newline.
+    fn consume_line_comment(&mut self, mut content: Option<&mut String>) {
+        let start = self.pos;
+        while !self.at_eof() && self.current_char != b'\n' {
+            self.next_char();
+        }
+        self.try_consume(b'\n');
+        self.record(start, &mut content);
+    }
+
+    /// Consumes the rest of a block comment. Leading whitespace and asterisks are stripped from
+    /// each line of the recorded content.
+    fn consume_block_comment(&mut self, mut content: Option<&mut String>) {
+        let start_line = self.line;
+        let start_column = self.column - 2;
+        let mut start = self.pos;
+
+        loop {
+            while !self.at_eof() && self.current_char != b'*' && self.current_char != b'/'
+                && self.current_char != b'\n' {
+                self.next_char();
+            }
+
+            if self.try_consume(b'\n') {
+                self.record(start, &mut content);
+
+                // Consume leading whitespace and asterisk.
+                self.consume_zero_or_more(is_whitespace_no_newline);
+                if self.try_consume(b'*') && self.try_consume(b'/') {
+                    break;
+                }
+                start = self.pos;
+            } else if self.try_consume(b'*') {
+                if self.try_consume(b'/') {
+                    // Record the content without the trailing "*/".
+                    let end = self.pos - 2;
+                    if let Some(ref mut content) = content {
+                        content.push_str(&String::from_utf8_lossy(&self.input[start..end]));
+                    }
+                    break;
+                }
+            } else if self.try_consume(b'/') {
+                if self.current_char == b'*' {
+                    // The '*' is not consumed, since a following '/' ends the comment.
+                    self.add_error("\"/*\" inside block comment.  Block comments cannot be nested.");
+                }
+            } else if self.at_eof() {
+                self.add_error("End-of-file inside block comment.");
+                self.add_error_at(start_line, start_column, "  Comment started here.");
+                self.record(start, &mut content);
+                break;
+            }
+        }
+    }
+
+    fn consume_number(&mut self, started_with_zero: bool, started_with_dot: bool) -> TokenType {
+        let mut is_float = false;
+
+        if started_with_zero && (self.try_consume(b'x') || self.try_consume(b'X')) {
+            self.consume_one_or_more(is_hex_digit, "\"0x\" must be followed by hex digits.");
+        } else if started_with_zero && is_digit(self.current_char) {
+            self.consume_zero_or_more(is_octal_digit);
+            if is_digit(self.current_char) {
+                self.add_error("Numbers starting with leading zero must be in octal.");
+                self.consume_zero_or_more(is_digit);
+            }
+        } else {
+            if started_with_dot {
+                is_float = true;
+                self.consume_zero_or_more(is_digit);
+            } else {
+                self.consume_zero_or_more(is_digit);
+                if self.try_consume(b'.') {
+                    is_float = true;
+                    self.consume_zero_or_more(is_digit);
+                }
+            }
+
+            if self.try_consume(b'e') || self.try_consume(b'E') {
+                is_float = true;
+                let _ = self.try_consume(b'-') || self.try_consume(b'+');
+                self.consume_one_or_more(is_digit, "\"e\" must be followed by exponent.");
+            }
+        }
+
+        if is_letter(self.current_char) {
+            self.add_error("Need space between number and identifier.");
+        } else if self.current_char == b'.' {
+            if is_float {
+                self.add_error("Already saw decimal point or exponent; can't have another one.");
+            } else {
+                self.add_error("Hex and octal numbers must be integers.");
+            }
+        }
+
+        if is_float { TokenType::Float } else { TokenType::Integer }
+    }
+
+    fn consume_string(&mut self, delimiter: u8) {
+        loop {
+            if self.at_eof() {
+                self.add_error("Unexpected end of string.");
+                return;
+            }
+            match self.current_char {
+                b'\n' => {
+                    self.add_error("String literals cannot cross line boundaries.");
+                    return;
+                },
+                b'\\' => {
+                    self.next_char();
+                    if self.try_consume_one(is_escape) || self.try_consume_one(is_octal_digit) {
+                        // Any further octal digits are consumed by the main loop.
+                    } else if self.try_consume(b'x') {
+                        if !self.try_consume_one(is_hex_digit) {
+                            self.add_error("Expected hex digits for escape sequence.");
+                        }
+                    } else if self.try_consume(b'u') {
+                        if !(0..4).all(|_| self.try_consume_one(is_hex_digit)) {
+                            self.add_error("Expected four hex digits for \\u escape sequence.");
+                        }
+                    } else if self.try_consume(b'U') {
+                        // Only code points up to 0x10ffff are valid.
+                        if !self.try_consume(b'0')
+                            || !self.try_consume(b'0')
+                            || !(self.try_consume(b'0') || self.try_consume(b'1'))
+                            || !(0..5).all(|_| self.try_consume_one(is_hex_digit)) {
+                            self.add_error("Expected eight hex digits up to 10ffff for \\U escape sequence");
+                        }
+                    } else {
+                        self.add_error("Invalid escape sequence in string literal.");
+                    }
+                },
+                c => {
+                    self.next_char();
+                    if c == delimiter {
+                        return;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Parses the text of an integer token, returning `None` if it is larger than `max_value`.
+pub fn parse_integer(text: &str, max_value: u64) -> Option<u64> {
+    let (digits, base) = if text.starts_with("0x") || text.starts_with("0X") {
+        (&text[2..], 16)
+    } else if text.starts_with('0') {
+        (text, 8)
+    } else {
+        (text, 10)
+    };
+
+    let mut result: u64 = 0;
+    for c in digits.chars() {
+        let digit = match c.to_digit(base) {
+            Some(digit) => u64::from(digit),
+            None => return None,
+        };
+        if digit > max_value || result > (max_value - digit) / u64::from(base) {
+            return None;
+        }
+        result = result * u64::from(base) + digit;
+    }
+    Some(result)
+}
+
+/// Parses the text of a float token.
+pub fn parse_float(text: &str) -> f64 {
+    let text = text.trim_end_matches(|c| c == 'f' || c == 'F');
+    // A trailing exponent marker only occurs in erroneous input, which has already been reported.
+    text.trim_end_matches(|c| c == 'e' || c == 'E' || c == '-' || c == '+')
+        .parse()
+        .unwrap_or(0.0)
+}
+
+/// Parses the text of a string token, interpreting escape sequences.
+pub fn parse_string(text: &str, output: &mut Vec<u8>) {
+    let text = text.as_bytes();
+    if text.is_empty() {
+        return;
+    }
+    let quote = text[0];
+    let digit = |c: u8| (c as char).to_digit(16).unwrap_or(0);
+
+    let mut i = 1;
+    while i < text.len() {
+        let c = text[i];
+        if c == b'\\' && i + 1 < text.len() {
+            i += 1;
+            let c = text[i];
+            if is_octal_digit(c) {
+                // An octal escape of one to three digits.
+                let mut code = digit(c);
+                for _ in 0..2 {
+                    if i + 1 < text.len() && is_octal_digit(text[i + 1]) {
+                        i += 1;
+                        code = code * 8 + digit(text[i]);
+                    }
+                }
+                output.push(code as u8);
+            } else if c == b'x' {
+                // A hex escape of one or two digits.
+                let mut code = 0;
+                for _ in 0..2 {
+                    if i + 1 < text.len() && is_hex_digit(text[i + 1]) {
+                        i += 1;
+                        code = code * 16 + digit(text[i]);
+                    }
+                }
+                output.push(code as u8);
+            } else if c == b'u' || c == b'U' {
+                match fetch_unicode_point(&text[i..]) {
+                    Some((code_point, len)) => {
+                        let mut utf8 = [0; 4];
+                        output.extend_from_slice(code_point.encode_utf8(&mut utf8).as_bytes());
+                        i += len - 1;
+                    },
+                    None => output.push(c),
+                }
+            } else {
+                output.push(translate_escape(c));
+            }
+        } else if c == quote && i + 1 == text.len() {
+            // The closing quote.
+        } else {
+            output.push(c);
+        }
+        i += 1;
+    }
+}
+
+/// Reads the code point of a `\u` or `\U` escape, starting at the `u`. Returns the code point
+/// and the length of the escape, including the `u`. A `\u` escape of a high surrogate may be
+/// followed by a `\u` escape of a low surrogate, in which case they are combined.
+fn fetch_unicode_point(text: &[u8]) -> Option<(char, usize)> {
+    fn hex(text: &[u8]) -> Option<u32> {
+        str::from_utf8(text).ok()
+            .filter(|text| text.bytes().all(is_hex_digit))
+            .and_then(|text| u32::from_str_radix(text, 16).ok())
+    }
+
+    let len = if text[0] == b'u' { 4 } else { 8 };
+    let code = text.get(1..len + 1).and_then(hex)?;
+    let mut end = len + 1;
+    let mut code_point = code;
+    if code >= 0xd800 && code < 0xdc00 && text.get(end..end + 2) == Some(b"\\u") {
+        if let Some(trail) = text.get(end + 2..end + 6).and_then(hex) {
+            if trail >= 0xdc00 && trail < 0xe000 {
+                code_point = 0x10000 + ((code - 0xd800) << 10) + (trail - 0xdc00);
+                end += 6;
+            }
+        }
+    }
+    char::from_u32(code_point).map(|c| (c, end))
+}
+
+fn translate_escape(c: u8) -> u8 {
+    match c {
+        b'a' => 0x07,
+        b'b' => 0x08,
+        b'f' => 0x0c,
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'v' => 0x0b,
+        // '\\', '?', '\'', '"', and invalid escapes, which have already been reported.
+        c => c,
+    }
+}
+
+/// Returns `true` if `text` is a valid identifier.
+pub fn is_identifier(text: &str) -> bool {
+    let mut bytes = text.bytes();
+    bytes.next().map_or(false, is_letter) && bytes.all(is_alphanumeric)
+}
+
+fn is_whitespace(c: u8) -> bool {
+    c == b' ' || c == b'\n' || c == b'\t' || c == b'\r' || c == 0x0b || c == 0x0c
+}
+
+fn is_whitespace_no_newline(c: u8) -> bool {
+    c != b'\n' && is_whitespace(c)
+}
+
+fn is_unprintable(c: u8) -> bool {
+    c < b' ' && c > 0
+}
+
+fn is_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'9'
+}
+
+fn is_octal_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'7'
+}
+
+fn is_hex_digit(c: u8) -> bool {
+    is_digit(c) || (c >= b'a' && c <= b'f') || (c >= b'A' && c <= b'F')
+}
+
+fn is_letter(c: u8) -> bool {
+    (c >= b'a' && c <= b'z') || (c >= b'A' && c <= b'Z') || c == b'_'
+}
+
+fn is_alphanumeric(c: u8) -> bool {
+    is_letter(c) || is_digit(c)
+}
+
+fn is_escape(c: u8) -> bool {
+    match c {
+        b'a' | b'b' | b'f' | b'n' | b'r' | b't' | b'v' | b'\\' | b'?' | b'\'' | b'"' => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<(TokenType, String)> {
+        let mut tokenizer = Tokenizer::new(input.as_bytes());
+        let mut tokens = Vec::new();
+        while tokenizer.next() {
+            let token = tokenizer.current();
+            tokens.push((token.token_type, token.text.clone()));
+        }
+        assert!(tokenizer.errors().is_empty(), "{:?}", tokenizer.errors());
+        tokens
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(tokens("foo = 0x1F 017 1.5e3 .5 'a\\'b' ;"), vec![
+            (TokenType::Identifier, "foo".to_string()),
+            (TokenType::Symbol, "=".to_string()),
+            (TokenType::Integer, "0x1F".to_string()),
+            (TokenType::Integer, "017".to_string()),
+            (TokenType::Float, "1.5e3".to_string()),
+            (TokenType::Float, ".5".to_string()),
+            (TokenType::String, "'a\\'b'".to_string()),
+            (TokenType::Symbol, ";".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_columns() {
+        let mut tokenizer = Tokenizer::new(b"a\n\tbb /* x */ c");
+        tokenizer.next();
+        tokenizer.next();
+        assert_eq!((tokenizer.current().line, tokenizer.current().column), (1, 8));
+        assert_eq!(tokenizer.current().end_column, 10);
+        tokenizer.next();
+        assert_eq!((tokenizer.current().line, tokenizer.current().column), (1, 19));
+    }
+
+    #[test]
+    fn test_parse_literals() {
+        assert_eq!(parse_integer("0x10", u64::max_value()), Some(16));
+        assert_eq!(parse_integer("010", u64::max_value()), Some(8));
+        assert_eq!(parse_integer("18446744073709551615", u64::max_value()), Some(u64::max_value()));
+        assert_eq!(parse_integer("18446744073709551616", u64::max_value()), None);
+        assert_eq!(parse_integer("2147483648", 2147483647), None);
+
+        let mut output = Vec::new();
+        parse_string("\"a\\n\\101\\x41\\u00e9\\ud83d\\ude00\"", &mut output);
+        assert_eq!(output, "a\nAAé😀".as_bytes());
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut tokenizer = Tokenizer::new(b"\
+            // detached\n\
+            \n\
+            // leading\n\
+            a; // trailing\n\
+            // next\n\
+            b; /* same line */ c;\n\
+            ");
+        let comments = tokenizer.next_with_comments();
+        assert_eq!(comments.detached, vec![" detached\n".to_string()]);
+        assert_eq!(comments.leading, " leading\n");
+
+        tokenizer.next();
+        let comments = tokenizer.next_with_comments();
+        assert_eq!(comments.trailing, " trailing\n");
+        assert!(comments.detached.is_empty());
+        assert_eq!(comments.leading, " next\n");
+
+        tokenizer.next();
+        let comments = tokenizer.next_with_comments();
+        assert_eq!(tokenizer.current().text, "c");
+        assert_eq!(comments.trailing, "");
+        assert_eq!(comments.leading, "");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut tokenizer = Tokenizer::new(b"123abc \"unterminated\n");
+        while tokenizer.next() {}
+        assert_eq!(tokenizer.errors(), &[
+            (0, 3, "Need space between number and identifier.".to_string()),
+            (0, 20, "String literals cannot cross line boundaries.".to_string()),
+        ]);
+    }
+}
@@ -84,11 +84,12 @@
 //!
 //! ## Sourcing `protoc`
 //!
-//! `prost-build` depends on the Protocol Buffers compiler, `protoc`, to parse `.proto` files into
-//! a representation that can be transformed into Rust. If set, `prost-build` uses the `PROTOC` and
-//! `PROTOC_INCLUDE` environment variables for locating `protoc` and the Protobuf includes
-//! directory. For example, on a macOS system where Protobuf is installed with Homebrew, set the
-//! environment to:
+//! `prost-build` parses `.proto` files with a built-in compiler, which produces the same
+//! descriptors as the Protocol Buffers compiler, `protoc`. `protoc` can be used instead by
+//! configuring [`Config::protoc`](struct.Config.html#method.protoc). If set, `prost-build` uses the
+//! `PROTOC` and `PROTOC_INCLUDE` environment variables for locating `protoc` and the Protobuf
+//! includes directory. For example, on a macOS system where Protobuf is installed with Homebrew,
+//! set the environment to:
 //!
 //! ```bash
 //! PROTOC=/usr/local/bin/protoc
@@ -105,12 +106,13 @@
 //! If `PROTOC` is not found in the environment, then a pre-compiled `protoc` binary bundled in
 //! the prost-build crate is used. Pre-compiled `protoc` binaries exist for Linux, macOS, and
 //! Windows systems. If no pre-compiled `protoc` is available for the host platform, then the
-//! `protoc` or `protoc.exe` binary on the `PATH` is used. If `protoc` is not available in any of
-//! these fallback locations, then the build fails.
+//! `protoc` or `protoc.exe` binary on the `PATH` is used.
 //!
 //! If `PROTOC_INCLUDE` is not found in the environment, then the Protobuf include directory bundled
-//! in the prost-build crate is be used.
+//! in the prost-build crate is be used. The include directory is searched for imports by both the
+//! built-in compiler and `protoc`.
 
+extern crate bytes;
 extern crate heck;
 extern crate itertools;
 extern crate multimap;
@@ -124,6 +126,7 @@ extern crate log;
 
 mod ast;
 mod code_generator;
+mod compiler;
 mod ident;
 mod message_graph;
 
@@ -154,6 +157,9 @@ pub use ast::{
 };
 use code_generator::{
     CodeGenerator,
+    append_file_descriptor,
+    append_file_descriptor_set,
+    borrowed_messages,
     module,
 };
 use message_graph::MessageGraph;
@@ -201,12 +207,25 @@ pub trait ServiceGenerator {
 pub struct Config {
     service_generator: Option<Box<ServiceGenerator>>,
     btree_map: Vec<String>,
+    bytes: Vec<String>,
+    borrowed: Vec<String>,
+    preserve_unknown_fields: Vec<String>,
+    typed_enums: Vec<String>,
+    required_presence: Vec<String>,
+    reflect: Vec<String>,
+    json: Vec<String>,
+    text_format: Vec<String>,
     type_attributes: Vec<(String, String)>,
     field_attributes: Vec<(String, String)>,
     prost_types: bool,
     strip_enum_prefix: bool,
+    no_std: bool,
     mapped_types: HashMap<String, String>,
+    extern_paths: Vec<(String, String)>,
     out_dir: Option<PathBuf>,
+    file_descriptor_set_path: Option<PathBuf>,
+    file_descriptors: bool,
+    protoc: bool,
 }
 
 impl Config {
@@ -271,6 +290,287 @@ impl Config {
         self
     }
 
+    /// Configure the code generator to generate Rust [`bytes::Bytes`][1] fields for Protobuf
+    /// `bytes` type fields, and [`prost::ByteString`][2] fields for Protobuf `string` type fields.
+    ///
+    /// By default, `bytes` and `string` fields are generated as `Vec<u8>` and `String`, which
+    /// always copy their data out of the buffer being decoded. `Bytes` backed fields instead share
+    /// memory with the buffer when the message is decoded with [`Message::decode_bytes`][3],
+    /// which avoids copying large payloads. Generated code refers to `Bytes` through its
+    /// re-export from `prost`, `::prost::bytes::Bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific fields, messages, or packages which should use `Bytes`
+    /// backed fields. For details about matching fields see [`btree_map`](#method.btree_map).
+    /// Map fields are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Use `Bytes` for a specific field in a message type.
+    /// config.bytes(&[".my_messages.MyMessageType.my_blob_field"]);
+    ///
+    /// // Use `Bytes` for all `bytes` and `string` fields.
+    /// config.bytes(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/bytes/0.4/bytes/struct.Bytes.html
+    /// [2]: https://docs.rs/prost/*/prost/struct.ByteString.html
+    /// [3]: https://docs.rs/prost/*/prost/trait.Message.html#method.decode_bytes
+    pub fn bytes<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.bytes = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to generate borrowed variants of matched messages, which
+    /// decode from a `&'a [u8]` without allocating for `string` and `bytes` fields.
+    ///
+    /// Borrowed messages have a lifetime parameter, and represent `string` and `bytes` fields as
+    /// `&'a str` and `&'a [u8]`. They implement [`prost::BorrowedMessage`][1] instead of
+    /// `prost::Message`. Messages which contain a borrowed message field are borrowed as well, so
+    /// that the lifetime can be threaded through. Matched messages without any `string`, `bytes`
+    /// or borrowed message fields are left unchanged.
+    ///
+    /// Map fields are not affected, and messages used as map values may not be borrowed.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific messages or packages which should be borrowed. For details
+    /// about matching messages see [`btree_map`](#method.btree_map), just with the field name
+    /// omitted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Generate a borrowed variant of a specific message type.
+    /// config.borrowed(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Generate borrowed variants of all messages in a package.
+    /// config.borrowed(&[".my_messages"]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost/*/prost/trait.BorrowedMessage.html
+    pub fn borrowed<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.borrowed = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to preserve unknown fields in matched messages.
+    ///
+    /// By default, fields which are not recognized while decoding a message are skipped and
+    /// dropped. Messages matching any of the paths get an additional `unknown_fields` field of
+    /// type [`prost::UnknownFields`][1], which stores unrecognized fields as they are decoded and
+    /// re-emits them when the message is encoded.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific messages or packages which should preserve unknown fields.
+    /// For details about matching messages see [`btree_map`](#method.btree_map), just with the
+    /// field name omitted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Preserve unknown fields in a specific message type.
+    /// config.preserve_unknown_fields(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Preserve unknown fields in all messages.
+    /// config.preserve_unknown_fields(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost/*/prost/struct.UnknownFields.html
+    pub fn preserve_unknown_fields<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.preserve_unknown_fields = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to generate strongly typed enumeration fields.
+    ///
+    /// By default, Protobuf `enum` fields are generated as raw `i32` fields, with getters which
+    /// map unknown values to the default variant. Matched fields instead use the Rust enum type,
+    /// following the Protobuf enum semantics of the `.proto` file's syntax:
+    ///
+    ///  * In `proto3` files enums are open, and fields are generated as
+    ///    [`prost::OpenEnum<E>`][1], which holds either a known variant or the `Unknown(i32)`
+    ///    value that was decoded.
+    ///  * In `proto2` files enums are closed, and fields are generated as the enum itself.
+    ///    Unknown values are not stored in the field, but are kept as unknown fields if the
+    ///    message preserves them (see
+    ///    [`preserve_unknown_fields`](#method.preserve_unknown_fields)), and are dropped otherwise.
+    ///    An unknown value of a `oneof` field leaves the `oneof` unchanged.
+    ///
+    /// Map fields and extensions keep the raw `i32` representation.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific fields, messages, or packages which should use typed
+    /// enumeration fields. For details about matching fields see
+    /// [`btree_map`](#method.btree_map).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Use a typed enumeration for a specific field in a message type.
+    /// config.typed_enums(&[".my_messages.MyMessageType.my_enum_field"]);
+    ///
+    /// // Use typed enumerations for all fields.
+    /// config.typed_enums(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost/*/prost/enum.OpenEnum.html
+    pub fn typed_enums<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.typed_enums = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to track the presence of matched proto2 `required` fields.
+    ///
+    /// By default, `required` fields are generated as plain values, like `proto3` fields. They are
+    /// always encoded, and are always considered to be set, so [`Message::is_initialized`][1],
+    /// [`Message::encode_checked`][2] and [`DecodeOptions::check_required`][3] only check the
+    /// messages nested in them. Matched `required` fields are instead generated as `Option`
+    /// values, like `optional` fields, so that a missing field is detected by those checks.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific fields, messages, or packages whose `required` fields
+    /// should track presence. For details about matching fields see
+    /// [`btree_map`](#method.btree_map).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Track the presence of the required fields of a specific message type.
+    /// config.required_presence(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Track the presence of all required fields.
+    /// config.required_presence(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost/*/prost/trait.Message.html#method.is_initialized
+    /// [2]: https://docs.rs/prost/*/prost/trait.Message.html#method.encode_checked
+    /// [3]: https://docs.rs/prost/*/prost/struct.DecodeOptions.html#method.check_required
+    pub fn required_presence<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.required_presence = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to implement runtime reflection for matched messages.
+    ///
+    /// Messages matching any of the paths implement [`prost_types::reflect::ReflectMessage`][1],
+    /// which returns the message's descriptor and a dynamic view of its fields. Each generated
+    /// module which contains a reflected message embeds the encoded `FileDescriptorSet` of its
+    /// `.proto` files and their dependencies as a `FILE_DESCRIPTOR_SET` static, so the crate
+    /// including the generated code must depend on `prost-types`. Borrowed messages do not
+    /// implement reflection.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific messages or packages which should implement reflection.
+    /// For details about matching messages see [`btree_map`](#method.btree_map), just with the
+    /// field name omitted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Implement reflection for a specific message type.
+    /// config.reflect(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Implement reflection for all messages.
+    /// config.reflect(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost-types/*/prost_types/reflect/trait.ReflectMessage.html
+    pub fn reflect<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.reflect = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to implement the Protobuf canonical JSON mapping for matched
+    /// messages.
+    ///
+    /// Messages matching any of the paths implement [`prost_types::json::JsonMessage`][1], which
+    /// converts the message to and from its proto3 JSON representation. JSON support is built on
+    /// runtime reflection, so matched messages also implement `ReflectMessage`, as if they were
+    /// matched by [`reflect`](#method.reflect).
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific messages or packages which should support JSON. For
+    /// details about matching messages see [`btree_map`](#method.btree_map), just with the field
+    /// name omitted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Support JSON for a specific message type.
+    /// config.json(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Support JSON for all messages.
+    /// config.json(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost-types/*/prost_types/json/trait.JsonMessage.html
+    pub fn json<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.json = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configure the code generator to implement the Protobuf text format for matched messages.
+    ///
+    /// Messages matching any of the paths implement
+    /// [`prost_types::text_format::TextFormatMessage`][1], which prints the message in, and
+    /// parses it from, the text format used by `protoc --decode` and the debug output of other
+    /// Protobuf implementations. Like JSON support, the text format is built on runtime
+    /// reflection, so matched messages also implement `ReflectMessage`.
+    ///
+    /// # Arguments
+    ///
+    /// **`paths`** - paths to specific messages or packages which should support the text
+    /// format. For details about matching messages see [`btree_map`](#method.btree_map), just
+    /// with the field name omitted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let mut config = prost_build::Config::new();
+    /// // Support the text format for a specific message type.
+    /// config.text_format(&[".my_messages.MyMessageType"]);
+    ///
+    /// // Support the text format for all messages.
+    /// config.text_format(&["."]);
+    /// ```
+    ///
+    /// [1]: https://docs.rs/prost-types/*/prost_types/text_format/trait.TextFormatMessage.html
+    pub fn text_format<I, S>(&mut self, paths: I) -> &mut Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str> {
+        self.text_format = paths.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
     /// Add additional attribute to matched fields.
     ///
     /// # Arguments
@@ -372,6 +672,38 @@ impl Config {
         self
     }
 
+    /// Configures the code generator to refer to the types in a Protobuf package, or to a single
+    /// Protobuf type, through an existing Rust path.
+    ///
+    /// `compile_protos` only generates code for the `.proto` files passed to it, so types from
+    /// imported files are referred to by their path relative to the generated module. This is
+    /// appropriate when the imported files are compiled into sibling modules of the same crate.
+    /// Extern paths are needed when the imported types live elsewhere, for instance in another
+    /// crate. When multiple extern paths match a type, the longest one is used.
+    ///
+    /// # Arguments
+    ///
+    /// **`proto_path`** - A fully-qualified Protobuf package or type name, for instance
+    /// `.common` or `.common.Status`.
+    ///
+    /// **`rust_path`** - The Rust path of the module containing the package's types, or of the
+    /// type itself. Nested packages and messages are appended to it as modules, so with the
+    /// extern path `.common` ⇒ `::common_protos::common`, the type `.common.rpc.Status` is
+    /// referred to as `::common_protos::common::rpc::Status`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # let mut config = prost_build::Config::new();
+    /// config.extern_path(".common", "::common_protos::common");
+    /// ```
+    pub fn extern_path<P, R>(&mut self, proto_path: P, rust_path: R) -> &mut Self
+    where P: AsRef<str>,
+          R: AsRef<str> {
+        self.extern_paths.push((proto_path.as_ref().to_string(), rust_path.as_ref().to_string()));
+        self
+    }
+
     /// Configures the code generator to not strip the enum name from variant names.
     ///
     /// Protobuf enum definitions commonly include the enum name as a prefix of every variant name.
@@ -383,6 +715,28 @@ impl Config {
         self
     }
 
+    /// Configures the code generator to generate code compatible with `#![no_std]` crates.
+    ///
+    /// Generated code refers to `::core` and `::alloc` paths instead of `::std` paths, so the
+    /// crate including it must declare `extern crate alloc;`. Protobuf `map` fields are always
+    /// generated as `BTreeMap` fields, since `HashMap` is not available without `std`. The
+    /// `prost` dependency should be declared with `default-features = false`.
+    pub fn no_std(&mut self) -> &mut Self {
+        self.no_std = true;
+        self
+    }
+
+    /// Configures the code generator to parse `.proto` files with `protoc`, instead of the built-in
+    /// compiler.
+    ///
+    /// The built-in compiler produces the same descriptors as `protoc`, so this is only needed to
+    /// work around differences between them. See the crate documentation for how `protoc` is
+    /// located.
+    pub fn protoc(&mut self) -> &mut Self {
+        self.protoc = true;
+        self
+    }
+
     /// Configures the output directory where generated Rust files will be written.
     ///
     /// If unset, defaults to the `OUT_DIR` environment variable. `OUT_DIR` is set by Cargo when
@@ -392,6 +746,45 @@ impl Config {
         self
     }
 
+    /// Configures the code generator to also write the encoded file descriptor set of the compiled
+    /// `.proto` files to a file.
+    ///
+    /// The set includes the descriptors of all imported files, along with their source code info.
+    /// It can be decoded into a `prost_types::FileDescriptorSet` at runtime for reflection,
+    /// dynamic decoding or gRPC server reflection.
+    ///
+    /// # Example `build.rs`
+    ///
+    /// ```norun
+    /// extern crate prost_build;
+    ///
+    /// use std::env;
+    /// use std::path::PathBuf;
+    ///
+    /// fn main() {
+    ///     let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    ///     prost_build::Config::new()
+    ///         .file_descriptor_set_path(out_dir.join("descriptors.bin"))
+    ///         .compile_protos(&["src/items.proto"], &["src"])
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn file_descriptor_set_path<P>(&mut self, path: P) -> &mut Self where P: Into<PathBuf> {
+        self.file_descriptor_set_path = Some(path.into());
+        self
+    }
+
+    /// Configures the code generator to embed the encoded `FileDescriptorProto` of each `.proto`
+    /// file in its generated module.
+    ///
+    /// Each descriptor is a `&'static [u8]` constant named after the path of the file, so the
+    /// descriptor of `foo/bar_baz.proto` is `FOO_BAR_BAZ_FILE_DESCRIPTOR`. Source code info is
+    /// not included.
+    pub fn file_descriptors(&mut self) -> &mut Self {
+        self.file_descriptors = true;
+        self
+    }
+
     /// Compile `.proto` files into Rust files during a Cargo build with additional code generator
     /// configuration options.
     ///
@@ -412,72 +805,157 @@ impl Config {
     /// }
     /// ```
     pub fn compile_protos<P>(&mut self, protos: &[P], includes: &[P]) -> Result<()> where P: AsRef<Path> {
-        let target: PathBuf = self.out_dir.clone().map(Ok).unwrap_or_else(|| {
-            env::var_os("OUT_DIR")
-                .ok_or_else(|| Error::new(ErrorKind::Other,
-                                          "OUT_DIR environment variable is not set"))
-                .map(Into::into)
-        })?;
-
-        // TODO: This should probably emit 'rerun-if-changed=PATH' directives for cargo, however
-        // according to [1] if any are output then those paths replace the default crate root,
-        // which is undesirable. Figure out how to do it in an additive way; perhaps gcc-rs has
-        // this figured out.
-        // [1]: http://doc.crates.io/build-script.html#outputs-of-the-build-script
-
-        let tmp = tempdir::TempDir::new("prost-build")?;
-        let descriptor_set = tmp.path().join("prost-descriptor-set");
+        // Add the Protobuf include directory after the user includes in case the user wants to
+        // override one of the built-in .protos.
+        let mut include_paths = includes.iter()
+                                        .map(|include| include.as_ref().to_path_buf())
+                                        .collect::<Vec<_>>();
+        include_paths.push(protoc_include().to_path_buf());
+        let protos = protos.iter().map(|proto| proto.as_ref().to_path_buf()).collect::<Vec<_>>();
 
-        let mut cmd = Command::new(protoc());
-        cmd.arg("--include_imports")
-           .arg("--include_source_info")
-           .arg("-o").arg(&descriptor_set);
+        let descriptor_set = if self.protoc {
+            run_protoc(&protos, &include_paths)?
+        } else {
+            compiler::compile(&protos, &include_paths)?
+        };
 
-        for include in includes {
-            cmd.arg("-I").arg(include.as_ref());
+        // Rebuild when any of the compiled files, or the files they import, change. Once a build
+        // script emits a `rerun-if-changed` directive, Cargo no longer reruns it for changes to
+        // other files in the package, so every source of the descriptor set is listed.
+        for file in &descriptor_set.file {
+            if let Some(path) = source_path(file.name(), &include_paths) {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
         }
 
-        // Set the protoc include after the user includes in case the user wants to
-        // override one of the built-in .protos.
-        cmd.arg("-I").arg(protoc_include());
+        // Only the requested files are generated; imported files are used to resolve types.
+        let names = protos.iter()
+                          .map(|proto| compiler::virtual_file_name(proto, &include_paths))
+                          .collect::<Result<Vec<_>>>()?;
+        self.compile_files(descriptor_set, &names)
+    }
 
-        for proto in protos {
-            cmd.arg(proto.as_ref());
+    /// Generate Rust files from a precompiled file descriptor set, for instance one written by
+    /// `protoc --descriptor_set_out` or by another build system.
+    ///
+    /// **`files`** - Names of the files in the set to generate code for, as they appear in the
+    /// set, such as `foo/bar.proto`. Like with `compile_protos`, the other files of the set are
+    /// only read to resolve the types they define. An error is returned if a name isn't in the
+    /// set.
+    ///
+    /// Code is written to the output directory like `compile_protos` does. Doc comments are only
+    /// generated for files which include source code info, such as the files written by
+    /// `protoc --include_source_info`.
+    ///
+    /// # Example `build.rs`
+    ///
+    /// ```norun
+    /// extern crate prost;
+    /// extern crate prost_build;
+    /// extern crate prost_types;
+    ///
+    /// use prost::Message;
+    ///
+    /// fn main() {
+    ///     let bytes = include_bytes!("descriptors.bin");
+    ///     let file_descriptor_set = prost_types::FileDescriptorSet::decode(&bytes[..]).unwrap();
+    ///     prost_build::Config::new().compile_fds(file_descriptor_set,
+    ///                                            &["frontend.proto", "backend.proto"]).unwrap();
+    /// }
+    /// ```
+    pub fn compile_fds<S>(&mut self, file_descriptor_set: FileDescriptorSet, files: &[S]) -> Result<()>
+    where S: AsRef<str> {
+        let names = files.iter().map(|file| file.as_ref().to_string()).collect::<Vec<_>>();
+        for name in &names {
+            if !file_descriptor_set.file.iter().any(|file| file.name() == name) {
+                return Err(Error::new(ErrorKind::Other,
+                                      format!("file descriptor set does not contain {}", name)));
+            }
         }
+        self.compile_files(file_descriptor_set, &names)
+    }
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err(Error::new(ErrorKind::Other,
-                                format!("protoc failed: {}",
-                                        String::from_utf8_lossy(&output.stderr))));
-        }
+    /// Generate Rust files for the named files of a file descriptor set. The other files of the
+    /// set are only used to resolve types.
+    fn compile_files(&mut self, file_descriptor_set: FileDescriptorSet, names: &[String]) -> Result<()> {
+        let target: PathBuf = self.out_dir.clone().map(Ok).unwrap_or_else(|| {
+            env::var_os("OUT_DIR")
+                .ok_or_else(|| Error::new(ErrorKind::Other,
+                                          "OUT_DIR environment variable is not set"))
+                .map(Into::into)
+        })?;
 
-        let mut buf = Vec::new();
-        fs::File::open(descriptor_set)?.read_to_end(&mut buf)?;
-        let descriptor_set = FileDescriptorSet::decode(&buf)?;
+        if let Some(ref path) = self.file_descriptor_set_path {
+            let mut buf = Vec::with_capacity(file_descriptor_set.encoded_len());
+            file_descriptor_set.encode(&mut buf)?;
+            write_file_if_changed(path, &buf)?;
+        }
 
-        let modules = self.generate(descriptor_set.file);
+        let modules = self.generate(file_descriptor_set.file, names);
         for (module, content) in modules {
             let mut filename = module.join(".");
             filename.push_str(".rs");
-            trace!("writing: {:?}", filename);
-            let mut file = fs::File::create(target.join(filename))?;
-            file.write_all(content.as_bytes())?;
-            file.flush()?;
+            write_file_if_changed(&target.join(filename), content.as_bytes())?;
         }
 
         Ok(())
     }
 
-    fn generate(&mut self, files: Vec<FileDescriptorProto>) -> HashMap<Module, String> {
+    /// Generate Rust files from a file containing an encoded file descriptor set.
+    ///
+    /// This method is like `compile_fds`, except that the file descriptor set is read from a
+    /// file. Cargo is told to rerun the build script when the file changes.
+    pub fn compile_fds_path<P, S>(&mut self, path: P, files: &[S]) -> Result<()>
+    where P: AsRef<Path>, S: AsRef<str> {
+        let path = path.as_ref();
+        let mut buf = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut buf)?;
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let file_descriptor_set = FileDescriptorSet::decode(&buf)?;
+        self.compile_fds(file_descriptor_set, files)
+    }
+
+    fn generate(&mut self, files: Vec<FileDescriptorProto>, names: &[String]) -> HashMap<Module, String> {
         let mut modules = HashMap::new();
 
         let message_graph = MessageGraph::new(&files);
+        let borrowed_messages = borrowed_messages(self, &files);
+
+        // Reflected messages need the descriptors of their files and dependencies at runtime.
+        let descriptors = if self.reflect.is_empty()
+            && self.json.is_empty()
+            && self.text_format.is_empty() {
+            Vec::new()
+        } else {
+            files.iter().cloned().map(|mut file| {
+                file.source_code_info = None;
+                file
+            }).collect()
+        };
+        let mut reflected_modules: HashMap<Module, Vec<String>> = HashMap::new();
 
         for file in files {
+            let name = file.name().to_string();
+            if !names.contains(&name) {
+                continue;
+            }
             let module = module(&file);
-            let mut buf = modules.entry(module).or_insert_with(String::new);
-            CodeGenerator::generate(self, &message_graph, file, &mut buf);
+            let reflected = {
+                let mut buf = modules.entry(module.clone()).or_insert_with(String::new);
+                if self.file_descriptors {
+                    append_file_descriptor(&file, buf);
+                }
+                CodeGenerator::generate(self, &message_graph, &borrowed_messages, file, &mut buf)
+            };
+            if reflected {
+                reflected_modules.entry(module).or_insert_with(Vec::new).push(name);
+            }
+        }
+
+        for (module, names) in reflected_modules {
+            let buf = modules.get_mut(&module).unwrap();
+            append_file_descriptor_set(&descriptors, &names, buf);
         }
         modules
     }
@@ -488,12 +966,25 @@ impl default::Default for Config {
         Config {
             service_generator: None,
             btree_map: Vec::new(),
+            bytes: Vec::new(),
+            borrowed: Vec::new(),
+            preserve_unknown_fields: Vec::new(),
+            typed_enums: Vec::new(),
+            required_presence: Vec::new(),
+            reflect: Vec::new(),
+            json: Vec::new(),
+            text_format: Vec::new(),
             type_attributes: Vec::new(),
             field_attributes: Vec::new(),
             prost_types: true,
             strip_enum_prefix: true,
+            no_std: false,
             mapped_types: HashMap::new(),
+            extern_paths: Vec::new(),
             out_dir: None,
+            file_descriptor_set_path: None,
+            file_descriptors: false,
+            protoc: false,
         }
     }
 }
@@ -507,8 +998,10 @@ impl default::Default for Config {
 ///
 /// # Arguments
 ///
-/// **`protos`** - Paths to `.proto` files to compile. Any transitively [imported][3] `.proto`
-/// files are automatically be included.
+/// **`protos`** - Paths to `.proto` files to compile. Code is only generated for these files, but
+/// any transitively [imported][3] `.proto` files are read to resolve the types they define. Types
+/// from imported files are referred to by their path relative to the generated module, unless an
+/// extern path is configured with [`Config::extern_path`](struct.Config.html#method.extern_path).
 ///
 /// **`includes`** - Paths to directories in which to search for imports. Directories are searched
 /// in order. The `.proto` files passed in **`protos`** must be found in one of the provided
@@ -518,7 +1011,6 @@ impl default::Default for Config {
 ///
 /// This function can fail for a number of reasons:
 ///
-///   - Failure to locate or download `protoc`.
 ///   - Failure to parse the `.proto`s.
 ///   - Failure to locate an imported `.proto`.
 ///
@@ -543,6 +1035,63 @@ pub fn compile_protos<P>(protos: &[P], includes: &[P]) -> Result<()> where P: As
     Config::new().compile_protos(protos, includes)
 }
 
+/// Maps the name of a file in a descriptor set back to its path, by searching the include
+/// directories in order.
+fn source_path(name: &str, includes: &[PathBuf]) -> Option<PathBuf> {
+    includes.iter()
+            .map(|include| include.join(name))
+            .find(|path| path.is_file())
+}
+
+/// Writes a generated file, unless it already has the same content.
+///
+/// Leaving unchanged files untouched preserves their modification times, so that crates which
+/// include them are not needlessly recompiled.
+fn write_file_if_changed(path: &Path, content: &[u8]) -> Result<()> {
+    if let Ok(mut file) = fs::File::open(path) {
+        let mut existing = Vec::new();
+        if file.read_to_end(&mut existing).is_ok() && existing == content {
+            trace!("unchanged: {:?}", path);
+            return Ok(());
+        }
+    }
+
+    trace!("writing: {:?}", path);
+    let mut file = fs::File::create(path)?;
+    file.write_all(content)?;
+    file.flush()
+}
+
+/// Compiles `.proto` files into a file descriptor set with `protoc`.
+fn run_protoc(protos: &[PathBuf], includes: &[PathBuf]) -> Result<FileDescriptorSet> {
+    let tmp = tempdir::TempDir::new("prost-build")?;
+    let descriptor_set = tmp.path().join("prost-descriptor-set");
+
+    let mut cmd = Command::new(protoc());
+    cmd.arg("--include_imports")
+       .arg("--include_source_info")
+       .arg("-o").arg(&descriptor_set);
+
+    for include in includes {
+        cmd.arg("-I").arg(include);
+    }
+
+    for proto in protos {
+        cmd.arg(proto);
+    }
+
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(Error::new(ErrorKind::Other,
+                              format!("protoc failed: {}",
+                                      String::from_utf8_lossy(&output.stderr))));
+    }
+
+    let mut buf = Vec::new();
+    fs::File::open(descriptor_set)?.read_to_end(&mut buf)?;
+    Ok(FileDescriptorSet::decode(&buf)?)
+}
+
 /// Returns the path to the `protoc` binary.
 pub fn protoc() -> &'static Path {
     #[cfg(not(feature = "docs-rs"))]
@@ -573,6 +1122,7 @@ pub fn protoc_include() -> &'static Path {
 mod tests {
     extern crate env_logger;
     use super::*;
+    use std::time::UNIX_EPOCH;
 
     /// An example service generator that generates a trait with methods corresponding to the
     /// service methods.
@@ -601,6 +1151,125 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_source_path() {
+        let includes = vec![
+            PathBuf::from("src/missing"),
+            PathBuf::from("src"),
+            protoc_include().to_path_buf(),
+        ];
+        assert_eq!(source_path("smoke_test.proto", &includes),
+                   Some(PathBuf::from("src/smoke_test.proto")));
+        assert_eq!(source_path("google/protobuf/any.proto", &includes),
+                   Some(protoc_include().join("google/protobuf/any.proto")));
+        assert_eq!(source_path("missing.proto", &includes), None);
+    }
+
+    #[test]
+    fn test_write_file_if_changed() {
+        let tmp = tempdir::TempDir::new("prost-build-test").unwrap();
+        let path = tmp.path().join("foo.rs");
+        let modified = || fs::metadata(&path).unwrap().modified().unwrap();
+
+        write_file_if_changed(&path, b"foo").unwrap();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_modified(UNIX_EPOCH).unwrap();
+
+        write_file_if_changed(&path, b"foo").unwrap();
+        assert_eq!(modified(), UNIX_EPOCH);
+
+        write_file_if_changed(&path, b"bar").unwrap();
+        assert_ne!(modified(), UNIX_EPOCH);
+        assert_eq!(fs::read(&path).unwrap(), b"bar");
+    }
+
+    #[test]
+    fn test_compile_fds_without_source_info() {
+        let tmp = tempdir::TempDir::new("prost-build-test").unwrap();
+        let mut file_descriptor_set = compiler::compile(&[PathBuf::from("src/smoke_test.proto")],
+                                                        &[PathBuf::from("src")]).unwrap();
+        for file in &mut file_descriptor_set.file {
+            file.source_code_info = None;
+        }
+
+        let path = tmp.path().join("descriptors.bin");
+        let mut buf = Vec::new();
+        file_descriptor_set.encode(&mut buf).unwrap();
+        fs::write(&path, buf).unwrap();
+
+        Config::new().out_dir(tmp.path())
+                     .service_generator(Box::new(ServiceTraitGenerator))
+                     .compile_fds_path(&path, &["smoke_test.proto"])
+                     .unwrap();
+        let generated = fs::read_to_string(tmp.path().join("smoke_test.rs")).unwrap();
+        assert!(generated.contains("pub struct SmokeRequest"));
+        assert!(!generated.contains("///"));
+    }
+
+    #[test]
+    fn test_file_descriptors() {
+        let tmp = tempdir::TempDir::new("prost-build-test").unwrap();
+        let path = tmp.path().join("descriptors.bin");
+        Config::new().out_dir(tmp.path())
+                     .file_descriptor_set_path(&path)
+                     .file_descriptors()
+                     .service_generator(Box::new(ServiceTraitGenerator))
+                     .compile_protos(&["src/smoke_test.proto"], &["src"])
+                     .unwrap();
+
+        let buf = fs::read(&path).unwrap();
+        let file_descriptor_set = FileDescriptorSet::decode(&buf).unwrap();
+        let file = file_descriptor_set.file.iter()
+                                           .find(|file| file.name() == "smoke_test.proto")
+                                           .unwrap();
+        assert!(file.source_code_info.is_some());
+
+        let generated = fs::read_to_string(tmp.path().join("smoke_test.rs")).unwrap();
+        assert!(generated.contains("pub const SMOKE_TEST_FILE_DESCRIPTOR: &'static [u8] = &[\n"));
+    }
+
+    #[test]
+    fn test_compile_requested_files() {
+        let tmp = tempdir::TempDir::new("prost-build-test").unwrap();
+        fs::write(tmp.path().join("common.proto"),
+                  "syntax = \"proto3\";\npackage common;\nmessage Status {}\n").unwrap();
+        fs::write(tmp.path().join("a.proto"),
+                  "syntax = \"proto3\";\npackage a;\nimport \"common.proto\";\n\
+                   message A { common.Status status = 1; }\n").unwrap();
+
+        Config::new().out_dir(tmp.path())
+                     .extern_path(".common", "::common_protos::common")
+                     .compile_protos(&[tmp.path().join("a.proto")], &[tmp.path().to_path_buf()])
+                     .unwrap();
+        let generated = fs::read_to_string(tmp.path().join("a.rs")).unwrap();
+        assert!(generated.contains("::common_protos::common::Status"));
+        assert!(!tmp.path().join("common.rs").exists());
+    }
+
+    #[test]
+    fn test_compile_fds_requested_files() {
+        let tmp = tempdir::TempDir::new("prost-build-test").unwrap();
+        fs::write(tmp.path().join("common.proto"),
+                  "syntax = \"proto3\";\npackage common;\nmessage Status {}\n").unwrap();
+        fs::write(tmp.path().join("a.proto"),
+                  "syntax = \"proto3\";\npackage a;\nimport \"common.proto\";\n\
+                   message A { common.Status status = 1; }\n").unwrap();
+        let file_descriptor_set = compiler::compile(&[tmp.path().join("a.proto")],
+                                                    &[tmp.path().to_path_buf()]).unwrap();
+
+        let err = Config::new().out_dir(tmp.path())
+                               .compile_fds(file_descriptor_set.clone(), &["b.proto"])
+                               .unwrap_err();
+        assert_eq!(err.to_string(), "file descriptor set does not contain b.proto");
+
+        Config::new().out_dir(tmp.path())
+                     .extern_path(".common", "::common_protos::common")
+                     .compile_fds(file_descriptor_set, &["a.proto"])
+                     .unwrap();
+        let generated = fs::read_to_string(tmp.path().join("a.rs")).unwrap();
+        assert!(generated.contains("::common_protos::common::Status"));
+        assert!(!tmp.path().join("common.rs").exists());
+    }
+
     #[test]
     fn smoke_test() {
         let _ = env_logger::init();
@@ -48,7 +48,8 @@ impl MessageGraph {
         let msg_index = self.get_or_insert_index(msg_name.clone());
 
         for field in &msg.field {
-            if field.type_() == field_descriptor_proto::Type::Message
+            if (field.type_() == field_descriptor_proto::Type::Message
+                    || field.type_() == field_descriptor_proto::Type::Group)
                 && field.label() != field_descriptor_proto::Label::Repeated {
                 let field_index = self.get_or_insert_index(field.type_name.clone().unwrap());
                 self.graph.add_edge(msg_index, field_index, ());
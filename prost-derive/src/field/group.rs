@@ -0,0 +1,207 @@
+use failure::Error;
+use proc_macro2::TokenStream;
+use syn::Meta;
+
+use field::{
+    word_attr,
+    tag_attr,
+    set_option,
+    set_bool,
+    Label,
+};
+
+#[derive(Clone)]
+pub struct Field {
+    /// The label of the field. Proto2 `required` fields with the `presence` attribute are
+    /// `Optional`, with `required` set, and oneof variants are `Required`, since their value is
+    /// always present.
+    pub label: Label,
+    pub tag: u32,
+    /// Whether the field is a proto2 `required` field whose presence is tracked.
+    pub required: bool,
+}
+
+impl Field {
+    pub fn new(attrs: &[Meta], inferred_tag: Option<u32>) -> Result<Option<Field>, Error> {
+        let mut group = false;
+        let mut label = None;
+        let mut tag = None;
+        let mut boxed = false;
+        let mut presence = false;
+
+        let mut unknown_attrs = Vec::new();
+
+        for attr in attrs {
+            if word_attr("group", attr) {
+                set_bool(&mut group, "duplicate group attribute")?;
+            } else if word_attr("boxed", attr) {
+                set_bool(&mut boxed, "duplicate boxed attribute")?;
+            } else if word_attr("presence", attr) {
+                set_bool(&mut presence, "duplicate presence attribute")?;
+            } else if let Some(t) = tag_attr(attr)? {
+                set_option(&mut tag, t, "duplicate tag attributes")?;
+            } else if let Some(l) = Label::from_attr(attr) {
+                set_option(&mut label, l, "duplicate label attributes")?;
+            } else {
+                unknown_attrs.push(attr);
+            }
+        }
+
+        if !group {
+            return Ok(None);
+        }
+
+        match unknown_attrs.len() {
+            0 => (),
+            1 => bail!("unknown attribute for group field: {:?}", unknown_attrs[0]),
+            _ => bail!("unknown attributes for group field: {:?}", unknown_attrs),
+        }
+
+        let tag = match tag.or(inferred_tag) {
+            Some(tag) => tag,
+            None => bail!("group field is missing a tag attribute"),
+        };
+
+        if presence && label != Some(Label::Required) {
+            bail!("presence attribute may only be applied to required fields");
+        }
+
+        // Required fields which track presence are stored in an `Option`, like optional fields,
+        // so that a missing field can be detected.
+        let required = presence;
+        Ok(Some(Field {
+            label: if required { Label::Optional } else { label.unwrap_or(Label::Optional) },
+            tag: tag,
+            required: required,
+        }))
+    }
+
+    pub fn new_oneof(attrs: &[Meta]) -> Result<Option<Field>, Error> {
+        if let Some(mut field) = Field::new(attrs, None)? {
+            if let Some(attr) = attrs.iter().find(|attr| Label::from_attr(attr).is_some()) {
+                bail!("invalid atribute for oneof field: {}", attr.name());
+            }
+            field.label = Label::Required;
+            Ok(Some(field))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn encode(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                if let Some(ref msg) = #ident {
+                    _prost::encoding::group::encode(#tag, msg, buf);
+                }
+            },
+            Label::Required => quote! {
+                _prost::encoding::group::encode(#tag, &#ident, buf);
+            },
+            Label::Repeated => quote! {
+                for msg in &#ident {
+                    _prost::encoding::group::encode(#tag, msg, buf);
+                }
+            },
+        }
+    }
+
+    pub fn merge(&self, ident: TokenStream) -> TokenStream {
+        match self.label {
+            Label::Optional => quote! {
+                _prost::encoding::group::merge(tag,
+                                               wire_type,
+                                               #ident.get_or_insert_with(Default::default),
+                                               buf,
+                                               ctx)
+            },
+            Label::Required => quote! {
+                _prost::encoding::group::merge(tag, wire_type, &mut #ident, buf, ctx)
+            },
+            Label::Repeated => quote! {
+                _prost::encoding::group::merge_repeated(tag, wire_type, &mut #ident, buf, ctx)
+            },
+        }
+    }
+
+    pub fn encoded_len(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| _prost::encoding::group::encoded_len(#tag, msg))
+            },
+            Label::Required => quote! {
+                _prost::encoding::group::encoded_len(#tag, &#ident)
+            },
+            Label::Repeated => quote! {
+                _prost::encoding::group::encoded_len_repeated(#tag, &#ident)
+            },
+        }
+    }
+
+    /// Returns a statement which encodes the field, taking the lengths of nested messages from the
+    /// size cache.
+    pub fn encode_cached(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                if let Some(ref msg) = #ident {
+                    _prost::encoding::group::encode_cached(#tag, msg, buf, cache);
+                }
+            },
+            Label::Required => quote! {
+                _prost::encoding::group::encode_cached(#tag, &#ident, buf, cache);
+            },
+            Label::Repeated => quote! {
+                _prost::encoding::group::encode_repeated_cached(#tag, &#ident, buf, cache);
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the field, recording the
+    /// lengths of nested messages in the size cache.
+    pub fn cached_len(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| _prost::encoding::group::cached_len(#tag, msg, cache))
+            },
+            Label::Required => quote! {
+                _prost::encoding::group::cached_len(#tag, &#ident, cache)
+            },
+            Label::Repeated => quote! {
+                _prost::encoding::group::cached_len_repeated(#tag, &#ident, cache)
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the result of checking that the required fields
+    /// of the nested groups are set, and that the field itself is set if it is required.
+    pub fn check_initialized(&self, ident: TokenStream) -> TokenStream {
+        let module = quote!(_prost::encoding::group);
+        let missing = if self.required {
+            quote!(_core::result::Result::Err(_prost::encoding::missing_required_field()))
+        } else {
+            quote!(_core::result::Result::Ok(()))
+        };
+        match self.label {
+            Label::Optional => quote! {
+                match #ident {
+                    _core::option::Option::Some(ref msg) => #module::check_initialized(msg),
+                    _core::option::Option::None => #missing,
+                }
+            },
+            Label::Required => quote!(#module::check_initialized(&#ident)),
+            Label::Repeated => quote!(#module::check_initialized_repeated(&#ident)),
+        }
+    }
+
+    pub fn clear(&self, ident: TokenStream) -> TokenStream {
+        match self.label {
+            Label::Optional => quote!(#ident = _core::option::Option::None),
+            Label::Required => quote!(#ident.clear()),
+            Label::Repeated => quote!(#ident.clear()),
+        }
+    }
+}
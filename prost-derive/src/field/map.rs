@@ -46,6 +46,7 @@ fn fake_scalar(ty: scalar::Ty) -> scalar::Field {
         ty,
         kind,
         tag: 0, // Not used here
+        required: false,
     }
 }
 
@@ -172,17 +173,17 @@ impl Field {
                 let default = quote!(#ty::default() as i32);
                 quote! {
                     _prost::encoding::#module::merge_with_default(#km, _prost::encoding::int32::merge,
-                                                                  #default, &mut #ident, buf)
+                                                                  #default, &mut #ident, buf, ctx)
                 }
             },
             ValueTy::Scalar(ref value_ty) => {
                 let val_mod = value_ty.module();
                 let vm = quote!(_prost::encoding::#val_mod::merge);
-                quote!(_prost::encoding::#module::merge(#km, #vm, &mut #ident, buf))
+                quote!(_prost::encoding::#module::merge(#km, #vm, &mut #ident, buf, ctx))
             },
             ValueTy::Message => {
                 quote!(_prost::encoding::#module::merge(#km, _prost::encoding::message::merge,
-                                                        &mut #ident, buf))
+                                                        &mut #ident, buf, ctx))
             },
         }
     }
@@ -214,6 +215,68 @@ impl Field {
         }
     }
 
+    /// Returns a statement which encodes the map field, taking the lengths of the map entries and
+    /// of nested messages from the size cache.
+    pub fn encode_cached(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        let key_mod = self.key_ty.module();
+        let ke = quote!(_prost::encoding::#key_mod::encode);
+        let module = self.map_ty.module();
+        let (ve, default) = match self.value_ty {
+            ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
+                (quote!(|tag, val, buf, _| _prost::encoding::int32::encode(tag, val, buf)),
+                 quote!(#ty::default() as i32))
+            },
+            ValueTy::Scalar(ref value_ty) => {
+                let val_mod = value_ty.module();
+                (quote!(|tag, val, buf, _| _prost::encoding::#val_mod::encode(tag, val, buf)),
+                 quote!(_core::default::Default::default()))
+            },
+            ValueTy::Message => {
+                (quote!(_prost::encoding::message::encode_cached),
+                 quote!(_core::default::Default::default()))
+            },
+        };
+        quote! {
+            _prost::encoding::#module::encode_cached(#ke, #ve, &(#default),
+                                                     #tag, &#ident, buf, cache);
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the map, recording the
+    /// lengths of the map entries and of nested messages in the size cache.
+    pub fn cached_len(&self, ident: TokenStream) -> TokenStream {
+        let tag = self.tag;
+        let key_mod = self.key_ty.module();
+        let kl = quote!(_prost::encoding::#key_mod::encoded_len);
+        let module = self.map_ty.module();
+        let (vl, default) = match self.value_ty {
+            ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
+                (quote!(|tag, val, _| _prost::encoding::int32::encoded_len(tag, val)),
+                 quote!(#ty::default() as i32))
+            },
+            ValueTy::Scalar(ref value_ty) => {
+                let val_mod = value_ty.module();
+                (quote!(|tag, val, _| _prost::encoding::#val_mod::encoded_len(tag, val)),
+                 quote!(_core::default::Default::default()))
+            },
+            ValueTy::Message => {
+                (quote!(_prost::encoding::message::cached_len),
+                 quote!(_core::default::Default::default()))
+            },
+        };
+        quote!(_prost::encoding::#module::cached_len(#kl, #vl, &(#default), #tag, &#ident, cache))
+    }
+
+    /// Returns an expression which evaluates to the result of checking that the required fields
+    /// of the map's message values are set, or `None` if the values are not messages.
+    pub fn check_initialized(&self, ident: TokenStream) -> Option<TokenStream> {
+        match self.value_ty {
+            ValueTy::Message => Some(quote!(_prost::encoding::message::check_initialized_repeated(#ident.values()))),
+            ValueTy::Scalar(_) => None,
+        }
+    }
+
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         quote!(#ident.clear())
     }
@@ -229,10 +292,10 @@ impl Field {
             let take_ref = if self.key_ty.is_numeric() { quote!(&) } else { quote!() };
 
             Some(quote! {
-                pub fn #get(&self, key: #key_ref_ty) -> ::std::option::Option<#ty> {
+                pub fn #get(&self, key: #key_ref_ty) -> _core::option::Option<#ty> {
                     self.#ident.get(#take_ref key).cloned().and_then(#ty::from_i32)
                 }
-                pub fn #insert(&mut self, key: #key_ty, value: #ty) -> ::std::option::Option<#ty> {
+                pub fn #insert(&mut self, key: #key_ty, value: #ty) -> _core::option::Option<#ty> {
                     self.#ident.insert(key, value as i32).and_then(#ty::from_i32)
                 }
             })
@@ -247,15 +310,15 @@ impl Field {
     /// outputting the raw numbers.
     pub fn debug(&self, wrapper_name: TokenStream) -> TokenStream {
         let type_name = match self.map_ty {
-            MapTy::HashMap => Ident::new("HashMap", Span::call_site()),
-            MapTy::BTreeMap => Ident::new("BTreeMap", Span::call_site()),
+            MapTy::HashMap => quote!(::std::collections::HashMap),
+            MapTy::BTreeMap => quote!(_alloc::collections::BTreeMap),
         };
         // A fake field for generating the debug wrapper
         let key_wrapper = fake_scalar(self.key_ty.clone()).debug(quote!(KeyWrapper));
         let key = self.key_ty.rust_type();
         let value_wrapper = self.value_ty.debug();
         let fmt = quote! {
-            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                 #key_wrapper
                 #value_wrapper
                 let mut builder = f.debug_map();
@@ -269,17 +332,17 @@ impl Field {
             ValueTy::Scalar(ref ty) => {
                 let value = ty.rust_type();
                 quote! {
-                    struct #wrapper_name<'a>(&'a ::std::collections::#type_name<#key, #value>);
-                    impl<'a> ::std::fmt::Debug for #wrapper_name<'a> {
+                    struct #wrapper_name<'a>(&'a #type_name<#key, #value>);
+                    impl<'a> _core::fmt::Debug for #wrapper_name<'a> {
                         #fmt
                     }
                 }
             },
             ValueTy::Message => quote! {
-                struct #wrapper_name<'a, V: 'a>(&'a ::std::collections::#type_name<#key, V>);
-                impl<'a, V> ::std::fmt::Debug for #wrapper_name<'a, V>
+                struct #wrapper_name<'a, V: 'a>(&'a #type_name<#key, V>);
+                impl<'a, V> _core::fmt::Debug for #wrapper_name<'a, V>
                 where
-                    V: ::std::fmt::Debug + 'a,
+                    V: _core::fmt::Debug + 'a,
                 {
                     #fmt
                 }
@@ -294,7 +357,7 @@ fn key_ty_from_str(s: &str) -> Result<scalar::Ty, Error> {
         scalar::Ty::Int32 | scalar::Ty::Int64 | scalar::Ty::Uint32 |
             scalar::Ty::Uint64 | scalar::Ty::Sint32 | scalar::Ty::Sint64 |
             scalar::Ty::Fixed32 | scalar::Ty::Fixed64 | scalar::Ty::Sfixed32 |
-            scalar::Ty::Sfixed64 | scalar::Ty::Bool | scalar::Ty::String  => Ok(ty),
+            scalar::Ty::Sfixed64 | scalar::Ty::Bool | scalar::Ty::String(..) => Ok(ty),
         _ => bail!("invalid map key type: {}", s),
     }
 }
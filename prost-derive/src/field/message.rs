@@ -12,8 +12,15 @@ use field::{
 
 #[derive(Clone)]
 pub struct Field {
+    /// The label of the field. Proto2 `required` fields with the `presence` attribute are
+    /// `Optional`, with `required` set, and oneof variants are `Required`, since their value is
+    /// always present.
     pub label: Label,
     pub tag: u32,
+    /// Whether the field is a proto2 `required` field whose presence is tracked.
+    pub required: bool,
+    /// Whether the field is part of a borrowed message, and is decoded from a `&'a [u8]`.
+    pub borrowed: bool,
 }
 
 impl Field {
@@ -22,6 +29,7 @@ impl Field {
         let mut label = None;
         let mut tag = None;
         let mut boxed = false;
+        let mut presence = false;
 
         let mut unknown_attrs = Vec::new();
 
@@ -30,6 +38,8 @@ impl Field {
                 set_bool(&mut message, "duplicate message attribute")?;
             } else if word_attr("boxed", attr) {
                 set_bool(&mut boxed, "duplicate boxed attribute")?;
+            } else if word_attr("presence", attr) {
+                set_bool(&mut presence, "duplicate presence attribute")?;
             } else if let Some(t) = tag_attr(attr)? {
                 set_option(&mut tag, t, "duplicate tag attributes")?;
             } else if let Some(l) = Label::from_attr(attr) {
@@ -54,9 +64,18 @@ impl Field {
             None => bail!("message field is missing a tag attribute"),
         };
 
+        if presence && label != Some(Label::Required) {
+            bail!("presence attribute may only be applied to required fields");
+        }
+
+        // Required fields which track presence are stored in an `Option`, like optional fields,
+        // so that a missing field can be detected.
+        let required = presence;
         Ok(Some(Field {
-            label: label.unwrap_or(Label::Optional),
+            label: if required { Label::Optional } else { label.unwrap_or(Label::Optional) },
             tag: tag,
+            required: required,
+            borrowed: false,
         }))
     }
 
@@ -72,59 +91,131 @@ impl Field {
         }
     }
 
+    /// Returns the path of the encoding module for the field.
+    fn module(&self) -> TokenStream {
+        if self.borrowed {
+            quote!(_prost::encoding::borrowed_message)
+        } else {
+            quote!(_prost::encoding::message)
+        }
+    }
+
     pub fn encode(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
         let tag = self.tag;
         match self.label {
             Label::Optional => quote! {
                 if let Some(ref msg) = #ident {
-                    _prost::encoding::message::encode(#tag, msg, buf);
+                    #module::encode(#tag, msg, buf);
                 }
             },
             Label::Required => quote! {
-                _prost::encoding::message::encode(#tag, &#ident, buf);
+                #module::encode(#tag, &#ident, buf);
             },
             Label::Repeated => quote! {
                 for msg in &#ident {
-                    _prost::encoding::message::encode(#tag, msg, buf);
+                    #module::encode(#tag, msg, buf);
                 }
             },
         }
     }
 
     pub fn merge(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
         match self.label {
             Label::Optional => quote! {
-                _prost::encoding::message::merge(wire_type,
-                                                 #ident.get_or_insert_with(Default::default),
-                                                 buf)
+                #module::merge(wire_type,
+                               #ident.get_or_insert_with(Default::default),
+                               buf,
+                               ctx)
             },
             Label::Required => quote! {
-                _prost::encoding::message::merge(wire_type, &mut #ident, buf)
+                #module::merge(wire_type, &mut #ident, buf, ctx)
             },
             Label::Repeated => quote! {
-                _prost::encoding::message::merge_repeated(wire_type, &mut #ident, buf)
+                #module::merge_repeated(wire_type, &mut #ident, buf, ctx)
             },
         }
     }
 
     pub fn encoded_len(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| #module::encoded_len(#tag, msg))
+            },
+            Label::Required => quote! {
+                #module::encoded_len(#tag, &#ident)
+            },
+            Label::Repeated => quote! {
+                #module::encoded_len_repeated(#tag, &#ident)
+            },
+        }
+    }
+
+    /// Returns a statement which encodes the field, taking the message lengths from the size
+    /// cache.
+    pub fn encode_cached(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
         let tag = self.tag;
         match self.label {
             Label::Optional => quote! {
-                #ident.as_ref().map_or(0, |msg| _prost::encoding::message::encoded_len(#tag, msg))
+                if let Some(ref msg) = #ident {
+                    #module::encode_cached(#tag, msg, buf, cache);
+                }
             },
             Label::Required => quote! {
-                _prost::encoding::message::encoded_len(#tag, &#ident)
+                #module::encode_cached(#tag, &#ident, buf, cache);
             },
             Label::Repeated => quote! {
-                _prost::encoding::message::encoded_len_repeated(#tag, &#ident)
+                #module::encode_repeated_cached(#tag, &#ident, buf, cache);
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the field, recording the
+    /// message lengths in the size cache.
+    pub fn cached_len(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| #module::cached_len(#tag, msg, cache))
+            },
+            Label::Required => quote! {
+                #module::cached_len(#tag, &#ident, cache)
+            },
+            Label::Repeated => quote! {
+                #module::cached_len_repeated(#tag, &#ident, cache)
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the result of checking that the required fields
+    /// of the nested messages are set, and that the field itself is set if it is required.
+    pub fn check_initialized(&self, ident: TokenStream) -> TokenStream {
+        let module = self.module();
+        let missing = if self.required {
+            quote!(_core::result::Result::Err(_prost::encoding::missing_required_field()))
+        } else {
+            quote!(_core::result::Result::Ok(()))
+        };
+        match self.label {
+            Label::Optional => quote! {
+                match #ident {
+                    _core::option::Option::Some(ref msg) => #module::check_initialized(msg),
+                    _core::option::Option::None => #missing,
+                }
             },
+            Label::Required => quote!(#module::check_initialized(&#ident)),
+            Label::Repeated => quote!(#module::check_initialized_repeated(&#ident)),
         }
     }
 
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         match self.label {
-            Label::Optional => quote!(#ident = ::std::option::Option::None),
+            Label::Optional => quote!(#ident = _core::option::Option::None),
             Label::Required => quote!(#ident.clear()),
             Label::Repeated => quote!(#ident.clear()),
         }
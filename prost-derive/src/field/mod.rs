@@ -1,3 +1,4 @@
+mod group;
 mod map;
 mod message;
 mod oneof;
@@ -25,6 +26,8 @@ pub enum Field {
     Scalar(scalar::Field),
     /// A message field.
     Message(message::Field),
+    /// A group field.
+    Group(group::Field),
     /// A map field.
     Map(map::Field),
     /// A oneof field.
@@ -46,6 +49,8 @@ impl Field {
             Field::Scalar(field)
         } else if let Some(field) = message::Field::new(&attrs, inferred_tag)? {
             Field::Message(field)
+        } else if let Some(field) = group::Field::new(&attrs, inferred_tag)? {
+            Field::Group(field)
         } else if let Some(field) = map::Field::new(&attrs, inferred_tag)? {
             Field::Map(field)
         } else if let Some(field) = oneof::Field::new(&attrs)? {
@@ -70,6 +75,8 @@ impl Field {
             Field::Scalar(field)
         } else if let Some(field) = message::Field::new_oneof(&attrs)? {
             Field::Message(field)
+        } else if let Some(field) = group::Field::new_oneof(&attrs)? {
+            Field::Group(field)
         } else if let Some(field) = map::Field::new_oneof(&attrs)? {
             Field::Map(field)
         } else {
@@ -83,6 +90,7 @@ impl Field {
         match *self {
             Field::Scalar(ref scalar) => vec![scalar.tag],
             Field::Message(ref message) => vec![message.tag],
+            Field::Group(ref group) => vec![group.tag],
             Field::Map(ref map) => vec![map.tag],
             Field::Oneof(ref oneof) => oneof.tags.clone(),
         }
@@ -93,6 +101,7 @@ impl Field {
         match *self {
             Field::Scalar(ref scalar) => scalar.encode(ident),
             Field::Message(ref message) => message.encode(ident),
+            Field::Group(ref group) => group.encode(ident),
             Field::Map(ref map) => map.encode(ident),
             Field::Oneof(ref oneof) => oneof.encode(ident),
         }
@@ -104,8 +113,9 @@ impl Field {
         match *self {
             Field::Scalar(ref scalar) => scalar.merge(ident),
             Field::Message(ref message) => message.merge(ident),
+            Field::Group(ref group) => group.merge(ident),
             Field::Map(ref map) => map.merge(ident),
-            Field::Oneof(ref oneof) => oneof.merge(ident),
+            Field::Oneof(ref oneof) => oneof.merge(ident, quote!(_core::option::Option::None)),
         }
     }
 
@@ -115,15 +125,63 @@ impl Field {
             Field::Scalar(ref scalar) => scalar.encoded_len(ident),
             Field::Map(ref map) => map.encoded_len(ident),
             Field::Message(ref msg) => msg.encoded_len(ident),
+            Field::Group(ref group) => group.encoded_len(ident),
             Field::Oneof(ref oneof) => oneof.encoded_len(ident),
         }
     }
 
+    /// Returns an expression which evaluates to the result of checking that the field is set if
+    /// it is required, and that the required fields of its nested messages are set, or `None` if
+    /// there is nothing to check.
+    pub fn check_initialized(&self, ident: TokenStream) -> Option<TokenStream> {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.check_initialized(ident),
+            Field::Message(ref message) => Some(message.check_initialized(ident)),
+            Field::Group(ref group) => Some(group.check_initialized(ident)),
+            Field::Map(ref map) => map.check_initialized(ident),
+            Field::Oneof(ref oneof) => Some(oneof.check_initialized(ident)),
+        }
+    }
+
+    /// Returns `true` if the field holds nested messages whose encoded lengths are recorded in the
+    /// size cache while encoding.
+    pub fn has_cached_sizes(&self) -> bool {
+        match *self {
+            Field::Message(_) | Field::Group(_) | Field::Map(_) | Field::Oneof(_) => true,
+            Field::Scalar(_) => false,
+        }
+    }
+
+    /// Returns a statement which encodes the field, taking the lengths of nested messages from the
+    /// size cache.
+    pub fn encode_cached(&self, ident: TokenStream) -> TokenStream {
+        match *self {
+            Field::Message(ref message) => message.encode_cached(ident),
+            Field::Group(ref group) => group.encode_cached(ident),
+            Field::Map(ref map) => map.encode_cached(ident),
+            Field::Oneof(ref oneof) => oneof.encode_cached(ident),
+            _ => self.encode(ident),
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the field, recording the
+    /// lengths of nested messages in the size cache.
+    pub fn cached_len(&self, ident: TokenStream) -> TokenStream {
+        match *self {
+            Field::Message(ref message) => message.cached_len(ident),
+            Field::Group(ref group) => group.cached_len(ident),
+            Field::Map(ref map) => map.cached_len(ident),
+            Field::Oneof(ref oneof) => oneof.cached_len(ident),
+            _ => self.encoded_len(ident),
+        }
+    }
+
     /// Returns a statement which clears the field.
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         match *self {
             Field::Scalar(ref scalar) => scalar.clear(ident),
             Field::Message(ref message) => message.clear(ident),
+            Field::Group(ref group) => group.clear(ident),
             Field::Map(ref map) => map.clear(ident),
             Field::Oneof(ref oneof) => oneof.clear(ident),
         }
@@ -132,7 +190,7 @@ impl Field {
     pub fn default(&self) -> TokenStream {
         match *self {
             Field::Scalar(ref scalar) => scalar.default(),
-            _ => quote!(::std::default::Default::default()),
+            _ => quote!(_core::default::Default::default()),
         }
     }
 
@@ -168,6 +226,44 @@ impl Field {
             _ => None,
         }
     }
+
+    /// Marks the field as part of a borrowed message, which is decoded from a `&'a [u8]`.
+    pub fn set_borrowed(&mut self) {
+        if let Field::Message(ref mut message) = *self {
+            message.borrowed = true;
+        }
+    }
+
+    /// Returns `true` if the Rust type of the field borrows from the decoded buffer.
+    pub fn is_borrowed(&self) -> bool {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.ty.is_borrowed(),
+            _ => false,
+        }
+    }
+}
+
+/// Returns `true` if the field attributes mark the field as the message's set of unknown fields,
+/// e.g. `#[prost(unknown_fields)]`.
+pub fn is_unknown_fields(attrs: &[Attribute]) -> Result<bool, Error> {
+    is_special_field("unknown_fields", attrs)
+}
+
+/// Returns `true` if the field attributes mark the field as the message's set of extension fields,
+/// e.g. `#[prost(extension_set)]`.
+pub fn is_extension_set(attrs: &[Attribute]) -> Result<bool, Error> {
+    is_special_field("extension_set", attrs)
+}
+
+fn is_special_field(key: &str, attrs: &[Attribute]) -> Result<bool, Error> {
+    let attrs = prost_attrs(attrs.to_vec())?;
+    if !attrs.iter().any(|attr| word_attr(key, attr)) {
+        return Ok(false);
+    }
+    if attrs.len() > 1 {
+        bail!("{} attribute may not be combined with other attributes: {:?}", key, attrs);
+    }
+    Ok(true)
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -225,7 +321,7 @@ impl fmt::Display for Label {
 }
 
 /// Get the items belonging to the 'prost' list attribute, e.g. `#[prost(foo, bar="baz")]`.
-fn prost_attrs(attrs: Vec<Attribute>) -> Result<Vec<Meta>, Error> {
+pub fn prost_attrs(attrs: Vec<Attribute>) -> Result<Vec<Meta>, Error> {
     Ok(attrs.iter().flat_map(Attribute::interpret_meta).flat_map(|meta| match meta {
         Meta::List(MetaList { ident, nested, .. }) => if ident == "prost" {
             nested.into_iter().collect()
@@ -322,6 +418,17 @@ fn tag_attr(attr: &Meta) -> Result<Option<u32>, Error> {
     }
 }
 
+/// Unpacks a `key="..."` attribute, returning the string value.
+pub fn string_attr(key: &str, attr: &Meta) -> Result<Option<String>, Error> {
+    if attr.name() != key {
+        return Ok(None);
+    }
+    match *attr {
+        Meta::NameValue(MetaNameValue { lit: Lit::Str(ref lit), .. }) => Ok(Some(lit.value())),
+        _ => bail!("invalid {} attribute: {:?}", key, attr),
+    }
+}
+
 fn tags_attr(attr: &Meta) -> Result<Option<Vec<u32>>, Error> {
     if attr.name() != "tags" {
         return Ok(None);
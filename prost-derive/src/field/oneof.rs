@@ -81,11 +81,13 @@ impl Field {
         }
     }
 
-    /// Returns an expression which evaluates to the result of decoding the oneof field.
-    pub fn merge(&self, ident: TokenStream) -> TokenStream {
+    /// Returns an expression which evaluates to the result of decoding the oneof field. Unknown
+    /// values of closed enumeration variants are recorded in `unknown_fields`, an expression of
+    /// type `Option<&mut prost::UnknownFields>`.
+    pub fn merge(&self, ident: TokenStream, unknown_fields: TokenStream) -> TokenStream {
         let ty = &self.ty;
         quote! {
-            #ty::merge(&mut #ident, tag, wire_type, buf)
+            #ty::merge_with_unknown_fields(&mut #ident, tag, wire_type, #unknown_fields, buf, ctx)
         }
     }
 
@@ -97,7 +99,68 @@ impl Field {
         }
     }
 
+    /// Returns a statement which encodes the oneof field, taking the lengths of nested messages
+    /// from the size cache.
+    pub fn encode_cached(&self, ident: TokenStream) -> TokenStream {
+        quote! {
+            if let Some(ref oneof) = #ident {
+                oneof.encode_cached(buf, cache)
+            }
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the oneof field, recording
+    /// the lengths of nested messages in the size cache.
+    pub fn cached_len(&self, ident: TokenStream) -> TokenStream {
+        quote! {
+            #ident.as_ref().map_or(0, |oneof| oneof.cache_sizes(cache))
+        }
+    }
+
+    /// Returns a statement which encodes the oneof field if its active variant has the given tag.
+    pub fn encode_at(&self, ident: TokenStream, tag: u32) -> TokenStream {
+        quote! {
+            if let Some(ref oneof) = #ident {
+                if oneof.tag() == #tag {
+                    oneof.encode(buf)
+                }
+            }
+        }
+    }
+
+    /// Returns a statement which encodes the oneof field if its active variant has the given tag,
+    /// taking the lengths of nested messages from the size cache.
+    pub fn encode_cached_at(&self, ident: TokenStream, tag: u32) -> TokenStream {
+        quote! {
+            if let Some(ref oneof) = #ident {
+                if oneof.tag() == #tag {
+                    oneof.encode_cached(buf, cache)
+                }
+            }
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the oneof field if its
+    /// active variant has the given tag, or 0 otherwise, recording the lengths of nested messages
+    /// in the size cache.
+    pub fn cached_len_at(&self, ident: TokenStream, tag: u32) -> TokenStream {
+        quote! {
+            #ident.as_ref().map_or(0, |oneof| if oneof.tag() == #tag { oneof.cache_sizes(cache) } else { 0 })
+        }
+    }
+
+    /// Returns an expression which evaluates to the result of checking that the required fields
+    /// of the active variant's nested message are set.
+    pub fn check_initialized(&self, ident: TokenStream) -> TokenStream {
+        quote! {
+            match #ident {
+                _core::option::Option::Some(ref oneof) => oneof.check_initialized(),
+                _core::option::Option::None => _core::result::Result::Ok(()),
+            }
+        }
+    }
+
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
-        quote!(#ident = ::std::option::Option::None)
+        quote!(#ident = _core::option::Option::None)
     }
 }
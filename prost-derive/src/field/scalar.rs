@@ -24,8 +24,10 @@ use syn::{
 use field::{
     Label,
     bool_attr,
+    set_bool,
     set_option,
     tag_attr,
+    word_attr,
 };
 
 /// A scalar protobuf field.
@@ -34,6 +36,9 @@ pub struct Field {
     pub ty: Ty,
     pub kind: Kind,
     pub tag: u32,
+    /// Whether the field is a proto2 `required` field whose presence is tracked. Such fields are
+    /// stored in an `Option`, like optional fields, so that a missing field can be detected.
+    pub required: bool,
 }
 
 impl Field {
@@ -44,12 +49,15 @@ impl Field {
         let mut packed = None;
         let mut default = None;
         let mut tag = None;
+        let mut presence = false;
 
         let mut unknown_attrs = Vec::new();
 
         for attr in attrs {
             if let Some(t) = Ty::from_attr(attr)? {
                 set_option(&mut ty, t, "duplicate type attributes")?;
+            } else if word_attr("presence", attr) {
+                set_bool(&mut presence, "duplicate presence attribute")?;
             } else if let Some(p) = bool_attr("packed", attr)? {
                 set_option(&mut packed, p, "duplicate packed attributes")?;
             } else if let Some(t) = tag_attr(attr)? {
@@ -79,6 +87,10 @@ impl Field {
             None => bail!("missing tag attribute"),
         };
 
+        if presence && label != Some(Label::Required) {
+            bail!("presence attribute may only be applied to required fields");
+        }
+
         let has_default = default.is_some();
         let default = default.map_or_else(|| Ok(DefaultValue::new(&ty)),
                                           |lit| DefaultValue::from_lit(&ty, lit))?;
@@ -98,6 +110,7 @@ impl Field {
 
             (None, _, _) => Kind::Plain(default),
             (Some(Label::Optional), _, _) => Kind::Optional(default),
+            (Some(Label::Required), _, _) if presence => Kind::Optional(default),
             (Some(Label::Required), _, _) => Kind::Required(default),
             (Some(Label::Repeated), packed, false) if packed.unwrap_or(ty.is_numeric()) => Kind::Packed,
             (Some(Label::Repeated), _, false) => Kind::Repeated,
@@ -107,11 +120,15 @@ impl Field {
             ty: ty,
             kind: kind,
             tag: tag,
+            required: presence,
         }))
     }
 
     pub fn new_oneof(attrs: &[Meta]) -> Result<Option<Field>, Error> {
         if let Some(mut field) = Field::new(attrs, None)? {
+            if field.required {
+                bail!("invalid required attribute on oneof field");
+            }
             match field.kind {
                 Kind::Plain(default) => {
                     field.kind = Kind::Required(default);
@@ -138,7 +155,7 @@ impl Field {
 
         match self.kind {
             Kind::Plain(ref default) => {
-                let default = default.typed();
+                let default = self.ty.compare_default(default);
                 quote! {
                     if #ident != #default {
                         #encode_fn(#tag, &#ident, buf);
@@ -146,7 +163,7 @@ impl Field {
                 }
             },
             Kind::Optional(..) => quote! {
-                if let ::std::option::Option::Some(ref value) = #ident {
+                if let _core::option::Option::Some(ref value) = #ident {
                     #encode_fn(#tag, value, buf);
                 }
             },
@@ -159,25 +176,61 @@ impl Field {
     /// Returns an expression which evaluates to the result of merging a decoded
     /// scalar value into the field.
     pub fn merge(&self, ident: TokenStream) -> TokenStream {
+        if let Ty::ClosedEnumeration(..) = self.ty {
+            return self.merge_closed(ident, quote!(_core::option::Option::None));
+        }
+
         let module = self.ty.module();
-        let merge_fn = match self.kind {
-            Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => quote!(merge),
-            Kind::Repeated | Kind::Packed => quote!(merge_repeated),
+        let merge_fn = match (&self.kind, self.ty.is_borrowed()) {
+            (&Kind::Plain(..), false) | (&Kind::Optional(..), false) | (&Kind::Required(..), false) => quote!(merge),
+            (&Kind::Repeated, false) | (&Kind::Packed, false) => quote!(merge_repeated),
+            (&Kind::Plain(..), true) | (&Kind::Optional(..), true) | (&Kind::Required(..), true) => quote!(merge_borrowed),
+            (&Kind::Repeated, true) | (&Kind::Packed, true) => quote!(merge_repeated_borrowed),
         };
         let merge_fn = quote!(_prost::encoding::#module::#merge_fn);
 
         match self.kind {
             Kind::Plain(..) | Kind::Required(..) | Kind::Repeated | Kind::Packed => quote! {
-                #merge_fn(wire_type, &mut #ident, buf)
+                #merge_fn(wire_type, &mut #ident, buf, ctx)
             },
             Kind::Optional(..) => quote! {
                 #merge_fn(wire_type,
                           #ident.get_or_insert_with(Default::default),
-                          buf)
+                          buf,
+                          ctx)
             },
         }
     }
 
+    /// Returns an expression which evaluates to the result of merging a decoded closed enumeration
+    /// value into the field. Unknown values are recorded in `unknown_fields`, an expression of type
+    /// `Option<&mut prost::UnknownFields>`.
+    pub fn merge_closed(&self, ident: TokenStream, unknown_fields: TokenStream) -> TokenStream {
+        let merge_fn = match self.kind {
+            Kind::Plain(..) | Kind::Required(..) => quote!(merge),
+            Kind::Optional(..) => quote!(merge_optional),
+            Kind::Repeated | Kind::Packed => quote!(merge_repeated),
+        };
+        quote! {
+            _prost::encoding::closed_enum::#merge_fn(tag, wire_type, &mut #ident, #unknown_fields, buf, ctx)
+        }
+    }
+
+    /// Returns an expression which evaluates to the result of checking that a required field is
+    /// set, or `None` if the field is not required.
+    pub fn check_initialized(&self, ident: TokenStream) -> Option<TokenStream> {
+        if !self.required {
+            return None;
+        }
+        Some(quote! {
+            if #ident.is_some() {
+                _core::result::Result::Ok(())
+            } else {
+                _core::result::Result::Err(_prost::encoding::missing_required_field())
+            }
+        })
+    }
+
     /// Returns an expression which evaluates to the encoded length of the field.
     pub fn encoded_len(&self, ident: TokenStream) -> TokenStream {
         let module = self.ty.module();
@@ -191,7 +244,7 @@ impl Field {
 
         match self.kind {
             Kind::Plain(ref default) => {
-                let default = default.typed();
+                let default = self.ty.compare_default(default);
                 quote! {
                     if #ident != #default {
                         #encoded_len_fn(#tag, &#ident)
@@ -212,13 +265,19 @@ impl Field {
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         match self.kind {
             Kind::Plain(ref default) | Kind::Required(ref default) => {
-                let default = default.typed();
                 match self.ty {
-                    Ty::String | Ty::Bytes => quote!(#ident.clear()),
-                    _ => quote!(#ident = #default),
+                    Ty::String(StringTy::Str) | Ty::Bytes(BytesTy::Slice) => {
+                        let default = default.owned(&self.ty);
+                        quote!(#ident = #default)
+                    },
+                    Ty::String(..) | Ty::Bytes(..) => quote!(#ident.clear()),
+                    _ => {
+                        let default = default.typed();
+                        quote!(#ident = #default)
+                    },
                 }
             },
-            Kind::Optional(_) => quote!(#ident = ::std::option::Option::None),
+            Kind::Optional(_) => quote!(#ident = _core::option::Option::None),
             Kind::Repeated | Kind::Packed => quote!(#ident.clear()),
         }
     }
@@ -226,9 +285,9 @@ impl Field {
     /// Returns an expression which evaluates to the default value of the field.
     pub fn default(&self) -> TokenStream {
         match self.kind {
-            Kind::Plain(ref value) | Kind::Required(ref value) => value.owned(),
-            Kind::Optional(_) => quote!(::std::option::Option::None),
-            Kind::Repeated | Kind::Packed => quote!(::std::vec::Vec::new()),
+            Kind::Plain(ref value) | Kind::Required(ref value) => value.owned(&self.ty),
+            Kind::Optional(_) => quote!(_core::option::Option::None),
+            Kind::Repeated | Kind::Packed => quote!(_alloc::vec::Vec::new()),
         }
     }
 
@@ -237,11 +296,11 @@ impl Field {
         if let Ty::Enumeration(ref ty) = self.ty {
             quote! {
                 struct #wrap_name<'a>(&'a i32);
-                impl<'a> ::std::fmt::Debug for #wrap_name<'a> {
-                    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                impl<'a> _core::fmt::Debug for #wrap_name<'a> {
+                    fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                         match super::#ty::from_i32(*self.0) {
-                            None => ::std::fmt::Debug::fmt(&self.0, f),
-                            Some(en) => ::std::fmt::Debug::fmt(&en, f),
+                            None => _core::fmt::Debug::fmt(&self.0, f),
+                            Some(en) => _core::fmt::Debug::fmt(&en, f),
                         }
                     }
                 }
@@ -261,20 +320,20 @@ impl Field {
             Kind::Plain(_) |
             Kind::Required(_) => self.debug_inner(wrapper_name),
             Kind::Optional(_) => quote! {
-                struct #wrapper_name<'a>(&'a ::std::option::Option<#inner_ty>);
-                impl<'a> ::std::fmt::Debug for #wrapper_name<'a> {
-                    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                struct #wrapper_name<'a>(&'a _core::option::Option<#inner_ty>);
+                impl<'a> _core::fmt::Debug for #wrapper_name<'a> {
+                    fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                         #wrapper
-                        ::std::fmt::Debug::fmt(&self.0.as_ref().map(Inner), f)
+                        _core::fmt::Debug::fmt(&self.0.as_ref().map(Inner), f)
                     }
                 }
             },
             Kind::Repeated |
             Kind::Packed => {
                 quote! {
-                    struct #wrapper_name<'a>(&'a ::std::vec::Vec<#inner_ty>);
-                    impl<'a> ::std::fmt::Debug for #wrapper_name<'a> {
-                        fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    struct #wrapper_name<'a>(&'a _alloc::vec::Vec<#inner_ty>);
+                    impl<'a> _core::fmt::Debug for #wrapper_name<'a> {
+                        fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                             let mut vec_builder = f.debug_list();
                             for v in self.0 {
                                 #wrapper
@@ -312,13 +371,13 @@ impl Field {
                         }
 
                         pub fn #set(&mut self, value: super::#ty) {
-                            self.#ident = ::std::option::Option::Some(value as i32);
+                            self.#ident = _core::option::Option::Some(value as i32);
                         }
                     }
                 },
                 Kind::Repeated | Kind::Packed => {
                     quote! {
-                        pub fn #ident(&self) -> ::std::iter::FilterMap<::std::iter::Cloned<::std::slice::Iter<i32>>,
+                        pub fn #ident(&self) -> _core::iter::FilterMap<_core::iter::Cloned<_core::slice::Iter<i32>>,
                                                                        fn(i32) -> Option<super::#ty>> {
                             self.#ident.iter().cloned().filter_map(super::#ty::from_i32)
                         }
@@ -330,18 +389,19 @@ impl Field {
             })
         } else if let Kind::Optional(ref default) = self.kind {
             let ty = self.ty.rust_ref_type();
+            let typed_default = default.typed();
 
             let match_some = if self.ty.is_numeric() {
-                quote!(::std::option::Option::Some(val) => val,)
+                quote!(_core::option::Option::Some(val) => val,)
             } else {
-                quote!(::std::option::Option::Some(ref val) => &val[..],)
+                quote!(_core::option::Option::Some(ref val) => &val[..],)
             };
 
             Some(quote! {
                 pub fn #ident(&self) -> #ty {
                     match self.#ident {
                         #match_some
-                        ::std::option::Option::None => #default,
+                        _core::option::Option::None => #typed_default,
                     }
                 }
             })
@@ -367,9 +427,58 @@ pub enum Ty {
     Sfixed32,
     Sfixed64,
     Bool,
+    String(StringTy),
+    Bytes(BytesTy),
+    /// An enumeration stored as a raw `i32`.
+    Enumeration(Path),
+    /// An open enumeration stored as `prost::OpenEnum`, which preserves unknown values.
+    OpenEnumeration(Path),
+    /// A closed enumeration stored as the Rust enum. Unknown values are treated as unknown fields.
+    ClosedEnumeration(Path),
+}
+
+/// The Rust representation of a `string` field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringTy {
+    /// `String`.
     String,
+    /// `prost::ByteString`, which is backed by `bytes::Bytes`.
     Bytes,
-    Enumeration(Path),
+    /// `&'a str`, borrowed from the decoded buffer.
+    Str,
+}
+
+/// The Rust representation of a `bytes` field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesTy {
+    /// `Vec<u8>`.
+    Vec,
+    /// `bytes::Bytes`.
+    Bytes,
+    /// `&'a [u8]`, borrowed from the decoded buffer.
+    Slice,
+}
+
+impl StringTy {
+    fn from_str(s: &str) -> Result<StringTy, Error> {
+        match s {
+            "string" => Ok(StringTy::String),
+            "bytes" => Ok(StringTy::Bytes),
+            "str" => Ok(StringTy::Str),
+            _ => bail!("invalid string representation: {}", s),
+        }
+    }
+}
+
+impl BytesTy {
+    fn from_str(s: &str) -> Result<BytesTy, Error> {
+        match s {
+            "vec" => Ok(BytesTy::Vec),
+            "bytes" => Ok(BytesTy::Bytes),
+            "slice" => Ok(BytesTy::Slice),
+            _ => bail!("invalid bytes representation: {}", s),
+        }
+    }
 }
 
 impl Ty {
@@ -389,11 +498,23 @@ impl Ty {
             Meta::Word(ref name) if name == "sfixed32" => Ty::Sfixed32,
             Meta::Word(ref name) if name == "sfixed64" => Ty::Sfixed64,
             Meta::Word(ref name) if name == "bool" => Ty::Bool,
-            Meta::Word(ref name) if name == "string" => Ty::String,
-            Meta::Word(ref name) if name == "bytes" => Ty::Bytes,
+            Meta::Word(ref name) if name == "string" => Ty::String(StringTy::String),
+            Meta::Word(ref name) if name == "bytes" => Ty::Bytes(BytesTy::Vec),
+            Meta::NameValue(MetaNameValue { ref ident, lit: Lit::Str(ref l), .. }) if ident == "string" => {
+                Ty::String(StringTy::from_str(&l.value())?)
+            },
+            Meta::NameValue(MetaNameValue { ref ident, lit: Lit::Str(ref l), .. }) if ident == "bytes" => {
+                Ty::Bytes(BytesTy::from_str(&l.value())?)
+            },
             Meta::NameValue(MetaNameValue { ref ident, lit: Lit::Str(ref l), .. }) if ident == "enumeration" => {
                 Ty::Enumeration(parse_str::<Path>(&l.value())?)
             },
+            Meta::NameValue(MetaNameValue { ref ident, lit: Lit::Str(ref l), .. }) if ident == "open_enumeration" => {
+                Ty::OpenEnumeration(parse_str::<Path>(&l.value())?)
+            },
+            Meta::NameValue(MetaNameValue { ref ident, lit: Lit::Str(ref l), .. }) if ident == "closed_enumeration" => {
+                Ty::ClosedEnumeration(parse_str::<Path>(&l.value())?)
+            },
             Meta::List(MetaList { ref ident, ref nested, .. }) if ident == "enumeration" => {
                 // TODO(rustlang/rust#23121): slice pattern matching would make this much nicer.
                 if nested.len() == 1 {
@@ -428,8 +549,8 @@ impl Ty {
             "sfixed32" => Ty::Sfixed32,
             "sfixed64" => Ty::Sfixed64,
             "bool" => Ty::Bool,
-            "string" => Ty::String,
-            "bytes" => Ty::Bytes,
+            "string" => Ty::String(StringTy::String),
+            "bytes" => Ty::Bytes(BytesTy::Vec),
             s if s.len() > enumeration_len && &s[..enumeration_len] == "enumeration" => {
                 let s = &s[enumeration_len..].trim();
                 match s.chars().next() {
@@ -464,17 +585,21 @@ impl Ty {
             Ty::Sfixed32 => "sfixed32",
             Ty::Sfixed64 => "sfixed64",
             Ty::Bool => "bool",
-            Ty::String => "string",
-            Ty::Bytes => "bytes",
-            Ty::Enumeration(..) => "enum",
+            Ty::String(..) => "string",
+            Ty::Bytes(..) => "bytes",
+            Ty::Enumeration(..) | Ty::OpenEnumeration(..) | Ty::ClosedEnumeration(..) => "enum",
         }
     }
 
     // TODO: rename to 'owned_type'.
     pub fn rust_type(&self) -> TokenStream {
         match *self {
-            Ty::String => quote!(::std::string::String),
-            Ty::Bytes => quote!(::std::vec::Vec<u8>),
+            Ty::String(StringTy::String) => quote!(_alloc::string::String),
+            Ty::String(StringTy::Bytes) => quote!(_prost::ByteString),
+            Ty::String(StringTy::Str) => quote!(&'a str),
+            Ty::Bytes(BytesTy::Vec) => quote!(_alloc::vec::Vec<u8>),
+            Ty::Bytes(BytesTy::Bytes) => quote!(_bytes::Bytes),
+            Ty::Bytes(BytesTy::Slice) => quote!(&'a [u8]),
             _ => self.rust_ref_type(),
         }
     }
@@ -495,22 +620,57 @@ impl Ty {
             Ty::Sfixed32 => quote!(i32),
             Ty::Sfixed64 => quote!(i64),
             Ty::Bool => quote!(bool),
-            Ty::String => quote!(&str),
-            Ty::Bytes => quote!(&[u8]),
+            Ty::String(..) => quote!(&str),
+            Ty::Bytes(..) => quote!(&[u8]),
             Ty::Enumeration(..) => quote!(i32),
+            Ty::OpenEnumeration(ref path) => quote!(_prost::OpenEnum<super::#path>),
+            Ty::ClosedEnumeration(ref path) => quote!(super::#path),
         }
     }
 
     pub fn module(&self) -> Ident {
         match *self {
             Ty::Enumeration(..) => Ident::new("int32", Span::call_site()),
+            Ty::OpenEnumeration(..) => Ident::new("open_enum", Span::call_site()),
+            Ty::ClosedEnumeration(..) => Ident::new("closed_enum", Span::call_site()),
             _ => Ident::new(self.as_str(), Span::call_site()),
         }
     }
 
     /// Returns true if the scalar type is length delimited (i.e., `string` or `bytes`).
     pub fn is_numeric(&self) -> bool {
-        *self != Ty::String && *self != Ty::Bytes
+        match *self {
+            Ty::String(..) | Ty::Bytes(..) => false,
+            _ => true,
+        }
+    }
+
+    /// Returns true if the type is a closed enumeration, which records unknown values as unknown
+    /// fields of the containing message.
+    pub fn is_closed_enumeration(&self) -> bool {
+        match *self {
+            Ty::ClosedEnumeration(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the Rust representation of the type borrows from the decoded buffer.
+    pub fn is_borrowed(&self) -> bool {
+        match *self {
+            Ty::String(StringTy::Str) | Ty::Bytes(BytesTy::Slice) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns an expression which the field value can be compared against to check whether it
+    /// holds the default value.
+    fn compare_default(&self, default: &DefaultValue) -> TokenStream {
+        match *self {
+            // `Bytes` can't be compared to a byte string literal, only a slice.
+            Ty::Bytes(BytesTy::Bytes) => quote!(#default[..]),
+            Ty::Bytes(BytesTy::Slice) => quote!(&#default[..]),
+            _ => default.typed(),
+        }
     }
 }
 
@@ -533,7 +693,8 @@ pub enum Kind {
     Plain(DefaultValue),
     /// An optional scalar field.
     Optional(DefaultValue),
-    /// A required proto2 scalar field.
+    /// A scalar value which is always encoded: the value of a oneof variant. Proto2 `required`
+    /// fields are `Optional`, with `Field::required` set.
     Required(DefaultValue),
     /// A repeated scalar field.
     Repeated,
@@ -554,6 +715,8 @@ pub enum DefaultValue {
     String(String),
     Bytes(Vec<u8>),
     Enumeration(TokenStream),
+    OpenEnumeration(TokenStream),
+    ClosedEnumeration(TokenStream),
     Path(Path),
 }
 
@@ -576,6 +739,9 @@ impl DefaultValue {
         let is_u32 = *ty == Ty::Uint32 || *ty == Ty::Fixed32;
         let is_u64 = *ty == Ty::Uint64 || *ty == Ty::Fixed64;
 
+        let is_string = if let Ty::String(..) = *ty { true } else { false };
+        let is_bytes = if let Ty::Bytes(..) = *ty { true } else { false };
+
         let default = match lit {
             Lit::Int(ref lit) if is_i32
                               && (lit.suffix() == IntSuffix::I32
@@ -602,32 +768,43 @@ impl DefaultValue {
 
 
             Lit::Bool(ref lit) if *ty == Ty::Bool => DefaultValue::Bool(lit.value),
-            Lit::Str(ref lit) if *ty == Ty::String => DefaultValue::String(lit.value().clone()),
-            Lit::ByteStr(ref lit) if *ty == Ty::Bytes => DefaultValue::Bytes(lit.value().clone()),
+            Lit::Str(ref lit) if is_string => DefaultValue::String(lit.value().clone()),
+            Lit::ByteStr(ref lit) if is_bytes => DefaultValue::Bytes(lit.value().clone()),
 
             Lit::Str(ref lit) => {
                 let value = lit.value();
                 let value = value.trim();
 
-                if let Ty::Enumeration(ref path) = *ty {
-                    let variant = Ident::new(value, Span::call_site());
-                    return Ok(DefaultValue::Enumeration(quote!(#path::#variant)))
+                match *ty {
+                    Ty::Enumeration(ref path) => {
+                        let variant = Ident::new(value, Span::call_site());
+                        return Ok(DefaultValue::Enumeration(quote!(#path::#variant)))
+                    },
+                    Ty::OpenEnumeration(ref path) => {
+                        let variant = Ident::new(value, Span::call_site());
+                        return Ok(DefaultValue::OpenEnumeration(quote!(#path::#variant)))
+                    },
+                    Ty::ClosedEnumeration(ref path) => {
+                        let variant = Ident::new(value, Span::call_site());
+                        return Ok(DefaultValue::ClosedEnumeration(quote!(#path::#variant)))
+                    },
+                    _ => (),
                 }
 
                 // Parse special floating point values.
                 if *ty == Ty::Float {
                     match value {
-                        "inf" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f32::INFINITY")?)),
-                        "-inf" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f32::NEG_INFINITY")?)),
-                        "nan" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f32::NAN")?)),
+                        "inf" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f32::INFINITY")?)),
+                        "-inf" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f32::NEG_INFINITY")?)),
+                        "nan" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f32::NAN")?)),
                         _ => (),
                     }
                 }
                 if *ty == Ty::Double {
                     match value {
-                        "inf" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f64::INFINITY")?)),
-                        "-inf" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f64::NEG_INFINITY")?)),
-                        "nan" => return Ok(DefaultValue::Path(parse_str::<Path>("::std::f64::NAN")?)),
+                        "inf" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f64::INFINITY")?)),
+                        "-inf" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f64::NEG_INFINITY")?)),
+                        "nan" => return Ok(DefaultValue::Path(parse_str::<Path>("_core::f64::NAN")?)),
                         _ => (),
                     }
                 }
@@ -687,31 +864,54 @@ impl DefaultValue {
             Ty::Uint64 | Ty::Fixed64 => DefaultValue::U64(0),
 
             Ty::Bool => DefaultValue::Bool(false),
-            Ty::String => DefaultValue::String(String::new()),
-            Ty::Bytes => DefaultValue::Bytes(Vec::new()),
+            Ty::String(..) => DefaultValue::String(String::new()),
+            Ty::Bytes(..) => DefaultValue::Bytes(Vec::new()),
             Ty::Enumeration(ref path) => return DefaultValue::Enumeration(quote!(#path::default())),
+            Ty::OpenEnumeration(ref path) => DefaultValue::OpenEnumeration(quote!(#path::default())),
+            Ty::ClosedEnumeration(ref path) => DefaultValue::ClosedEnumeration(quote!(#path::default())),
         }
     }
 
-    pub fn owned(&self) -> TokenStream {
-        match *self {
-            DefaultValue::String(ref value) if value.is_empty() => quote!(::std::string::String::new()),
-            DefaultValue::String(ref value) => quote!(#value.to_owned()),
-            DefaultValue::Bytes(ref value) if value.is_empty() => quote!(::std::vec::Vec::new()),
-            DefaultValue::Bytes(ref value) => {
+    /// Returns an expression which evaluates to an owned default value of the Rust type which
+    /// represents `ty`.
+    pub fn owned(&self, ty: &Ty) -> TokenStream {
+        match (self, ty) {
+            (&DefaultValue::String(ref value), &Ty::String(StringTy::Bytes)) if value.is_empty() => {
+                quote!(_prost::ByteString::new())
+            },
+            (&DefaultValue::String(ref value), &Ty::String(StringTy::Bytes)) => {
+                quote!(_prost::ByteString::from_static(#value))
+            },
+            (&DefaultValue::String(ref value), &Ty::String(StringTy::Str)) => quote!(#value),
+            (&DefaultValue::String(ref value), _) if value.is_empty() => quote!(_alloc::string::String::new()),
+            (&DefaultValue::String(ref value), _) => quote!(_alloc::string::String::from(#value)),
+            (&DefaultValue::Bytes(ref value), &Ty::Bytes(BytesTy::Bytes)) if value.is_empty() => {
+                quote!(_bytes::Bytes::new())
+            },
+            (&DefaultValue::Bytes(ref value), &Ty::Bytes(BytesTy::Bytes)) => {
+                let lit = LitByteStr::new(value, Span::call_site());
+                quote!(_bytes::Bytes::from_static(#lit))
+            },
+            (&DefaultValue::Bytes(ref value), &Ty::Bytes(BytesTy::Slice)) => {
                 let lit = LitByteStr::new(value, Span::call_site());
-                quote!(#lit.to_owned())
+                quote!(&#lit[..])
+            },
+            (&DefaultValue::Bytes(ref value), _) if value.is_empty() => quote!(_alloc::vec::Vec::new()),
+            (&DefaultValue::Bytes(ref value), _) => {
+                let lit = LitByteStr::new(value, Span::call_site());
+                quote!(_alloc::vec::Vec::from(&#lit[..]))
             },
 
-            ref other => other.typed(),
+            (other, _) => other.typed(),
         }
     }
 
     pub fn typed(&self) -> TokenStream {
-        if let DefaultValue::Enumeration(_) = *self {
-            quote!(super::#self as i32)
-        } else {
-            quote!(#self)
+        match *self {
+            DefaultValue::Enumeration(_) => quote!(super::#self as i32),
+            DefaultValue::OpenEnumeration(_) => quote!(_prost::OpenEnum::Known(super::#self)),
+            DefaultValue::ClosedEnumeration(_) => quote!(super::#self),
+            _ => quote!(#self),
         }
     }
 }
@@ -728,7 +928,9 @@ impl quote::ToTokens for DefaultValue {
             DefaultValue::Bool(value) => value.to_tokens(tokens),
             DefaultValue::String(ref value) => value.to_tokens(tokens),
             DefaultValue::Bytes(ref value) => LitByteStr::new(value, Span::call_site()).to_tokens(tokens),
-            DefaultValue::Enumeration(ref value) => {
+            DefaultValue::Enumeration(ref value) |
+            DefaultValue::OpenEnumeration(ref value) |
+            DefaultValue::ClosedEnumeration(ref value) => {
                 value.to_tokens(tokens)
             },
             DefaultValue::Path(ref value) => value.to_tokens(tokens),
@@ -26,13 +26,36 @@ use syn::{
     Fields,
     FieldsNamed,
     FieldsUnnamed,
+    GenericParam,
+    Generics,
     Ident,
+    Lifetime,
+    LifetimeDef,
     Variant,
 };
 
 mod field;
 use field::Field;
 
+/// Returns the lifetime parameter of a borrowed message or oneof type, or `None` if the type has
+/// no generic parameters. Any other kind of generic parameter is an error.
+fn borrowed_lifetime(generics: &Generics, kind: &str) -> Result<Option<Lifetime>, Error> {
+    if generics.where_clause.is_some() {
+        bail!("{} may not be derived for generic type", kind);
+    }
+    if generics.params.is_empty() {
+        return Ok(None);
+    }
+    if generics.params.len() == 1 {
+        if let GenericParam::Lifetime(LifetimeDef { ref lifetime, ref bounds, .. }) = generics.params[0] {
+            if bounds.is_empty() {
+                return Ok(Some(lifetime.clone()));
+            }
+        }
+    }
+    bail!("{} may not be derived for generic type, except for a single lifetime parameter", kind);
+}
+
 fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
     let input: DeriveInput = syn::parse(input)?;
 
@@ -44,10 +67,7 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
         Data::Union(..) => bail!("Message can not be derived for a union"),
     };
 
-    if !input.generics.params.is_empty() ||
-       input.generics.where_clause.is_some() {
-        bail!("Message may not be derived for generic type");
-    }
+    let lifetime = borrowed_lifetime(&input.generics, "Message")?;
 
     let fields = match variant_data {
         DataStruct { fields: Fields::Named(FieldsNamed { named: fields, .. }), .. } |
@@ -58,11 +78,35 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
     };
 
     let mut next_tag: u32 = 0;
+    let mut unknown_fields = None;
+    let mut extension_set = None;
     let mut fields = fields.into_iter()
                            .enumerate()
                            .flat_map(|(idx, field)| {
                                let field_ident = field.ident
                                     .unwrap_or_else(|| Ident::new(&idx.to_string(), Span::call_site()));
+                               match field::is_unknown_fields(&field.attrs) {
+                                   Ok(true) => {
+                                       return field::set_option(&mut unknown_fields, field_ident,
+                                                                "duplicate unknown_fields fields")
+                                           .err()
+                                           .map(|err| Err(err.context(format!("invalid message {}", ident))));
+                                   },
+                                   Ok(false) => (),
+                                   Err(err) => return Some(Err(err.context(format!("invalid message field {}.{}",
+                                                                                   ident, field_ident)))),
+                               }
+                               match field::is_extension_set(&field.attrs) {
+                                   Ok(true) => {
+                                       return field::set_option(&mut extension_set, field_ident,
+                                                                "duplicate extension_set fields")
+                                           .err()
+                                           .map(|err| Err(err.context(format!("invalid message {}", ident))));
+                                   },
+                                   Ok(false) => (),
+                                   Err(err) => return Some(Err(err.context(format!("invalid message field {}.{}",
+                                                                                   ident, field_ident)))),
+                               }
                                match Field::new(field.attrs, Some(next_tag)) {
                                    Ok(Some(field)) => {
                                        next_tag = field.tags().iter().max().map(|t| t + 1).unwrap_or(next_tag);
@@ -75,16 +119,38 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                            })
                            .collect::<Result<Vec<(Ident, Field)>, failure::Context<String>>>()?;
 
+    if lifetime.is_some() && extension_set.is_some() {
+        bail!("invalid message {}: messages with a lifetime parameter may not have an extension_set field",
+              ident);
+    }
+
+    if lifetime.is_some() {
+        for &mut (_, ref mut field) in &mut fields {
+            field.set_borrowed();
+        }
+    } else if let Some(&(ref field_ident, _)) = fields.iter().find(|&&(_, ref field)| field.is_borrowed()) {
+        bail!("invalid message field {}.{}: borrowed field types may only be used in messages with a \
+               lifetime parameter", ident, field_ident);
+    }
+
     // We want Debug to be in declaration order
     let unsorted_fields = fields.clone();
 
     // Sort the fields by tag number so that fields will be encoded in tag order.
-    // TODO: This encodes oneof fields in the position of their lowest tag,
-    // regardless of the currently occupied variant, is that consequential?
-    // See: https://developers.google.com/protocol-buffers/docs/encoding#order
     fields.sort_by_key(|&(_, ref field)| field.tags().into_iter().min().unwrap());
     let fields = fields;
 
+    // A oneof field is encoded at the position of its active variant's tag, so each of its tags is
+    // a separate encoding position.
+    // See: https://developers.google.com/protocol-buffers/docs/encoding#order
+    let mut positions = fields.iter().flat_map(|&(ref field_ident, ref field)| {
+        match *field {
+            Field::Oneof(_) => field.tags().into_iter().map(|tag| (tag, field_ident, field, Some(tag))).collect(),
+            _ => vec![(field.tags()[0], field_ident, field, None)],
+        }
+    }).collect::<Vec<_>>();
+    positions.sort_by_key(|&(tag, _, _, _)| tag);
+
     let mut tags = fields.iter().flat_map(|&(_, ref field)| field.tags()).collect::<Vec<_>>();
     let num_tags = tags.len();
     tags.sort();
@@ -101,20 +167,81 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                                 field.encoded_len(quote!(self.#field_ident))
                             });
 
-    let encode = fields.iter()
-                       .map(|&(ref field_ident, ref field)| {
-                           field.encode(quote!(self.#field_ident))
-                       });
-
+    let encode = positions.iter()
+                          .map(|&(_, field_ident, field, oneof_tag)| {
+                              match (field, oneof_tag) {
+                                  (&Field::Oneof(ref oneof), Some(tag)) => oneof.encode_at(quote!(self.#field_ident), tag),
+                                  _ => field.encode(quote!(self.#field_ident)),
+                              }
+                          });
+
+    // Unknown values of closed enumerations, including those of oneof variants, are preserved as
+    // unknown fields.
+    let merge_unknown_fields_arg = match unknown_fields {
+        Some(ref unknown_fields) => quote!(_core::option::Option::Some(&mut self.#unknown_fields)),
+        None => quote!(_core::option::Option::None),
+    };
     let merge = fields.iter().map(|&(ref field_ident, ref field)| {
-        let merge = field.merge(quote!(self.#field_ident));
+        let merge = match *field {
+            Field::Scalar(ref scalar) if scalar.ty.is_closed_enumeration() => {
+                scalar.merge_closed(quote!(self.#field_ident), merge_unknown_fields_arg.clone())
+            },
+            Field::Oneof(ref oneof) => oneof.merge(quote!(self.#field_ident), merge_unknown_fields_arg.clone()),
+            _ => field.merge(quote!(self.#field_ident)),
+        };
         let tags = field.tags().into_iter().map(|tag| quote!(#tag)).intersperse(quote!(|));
         quote!(#(#tags)* => #merge.map_err(|mut error| {
-            error.push(STRUCT_NAME, stringify!(#field_ident));
+            error.push(STRUCT_NAME, stringify!(#field_ident), tag);
             error
         }),)
     });
 
+    let (encode_unknown_fields, merge_unknown_fields, unknown_fields_len, clear_unknown_fields) =
+        match unknown_fields {
+            Some(ref field_ident) => (
+                quote!(self.#field_ident.encode_raw(buf);),
+                quote!(self.#field_ident.merge_field(tag, wire_type, buf, ctx)),
+                quote!(+ self.#field_ident.encoded_len()),
+                quote!(self.#field_ident.clear();),
+            ),
+            None => (
+                quote!(),
+                quote!(_prost::encoding::skip_field(wire_type, tag, buf, ctx)),
+                quote!(),
+                quote!(),
+            ),
+        };
+
+    // Fields of registered extensions are merged into the extension set, and the remaining
+    // unrecognized fields are handled as unknown fields.
+    let (encode_extension_set, merge_unknown_fields, extension_set_len, clear_extension_set, extendable) =
+        match extension_set {
+            Some(ref field_ident) => (
+                quote!(self.#field_ident.encode_raw(buf);),
+                quote! {
+                    match ctx.extension(_core::any::TypeId::of::<Self>(), tag) {
+                        _core::option::Option::Some(extension) => {
+                            self.#field_ident.merge_field(extension, wire_type, buf, ctx)
+                        },
+                        _core::option::Option::None => #merge_unknown_fields,
+                    }
+                },
+                quote!(+ self.#field_ident.encoded_len()),
+                quote!(self.#field_ident.clear();),
+                quote! {
+                    impl _prost::Extendable for #ident {
+                        fn extension_set(&self) -> &_prost::ExtensionSet {
+                            &self.#field_ident
+                        }
+                        fn extension_set_mut(&mut self) -> &mut _prost::ExtensionSet {
+                            &mut self.#field_ident
+                        }
+                    }
+                },
+            ),
+            None => (quote!(), merge_unknown_fields, quote!(), quote!(), quote!()),
+        };
+
     let struct_name = if fields.is_empty() {
         quote!()
     } else {
@@ -129,11 +256,19 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                            field.clear(quote!(self.#field_ident))
                        });
 
+    let (impl_generics, ty_generics) = match lifetime {
+        Some(ref lifetime) => (quote!(<#lifetime>), quote!(<#lifetime>)),
+        None => (quote!(), quote!()),
+    };
+
     let default = fields.iter()
                         .map(|&(ref field_ident, ref field)| {
                             let value = field.default();
                             quote!(#field_ident: #value,)
-                        });
+                        })
+                        .chain(extension_set.iter().chain(unknown_fields.iter()).map(|field_ident| {
+                            quote!(#field_ident: _core::default::Default::default(),)
+                        }));
 
     let methods = fields.iter()
                         .flat_map(|&(ref field_ident, ref field)| field.methods(field_ident))
@@ -144,7 +279,7 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
 
         quote! {
             #[allow(dead_code)]
-            impl #ident {
+            impl #impl_generics #ident #ty_generics {
                 #(#methods)*
             }
         }
@@ -165,58 +300,243 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                                          };
                                     }
                                 });
+    // Extension and unknown fields are only included in the debug output when present.
+    let debug_unknown_fields = extension_set.iter().chain(unknown_fields.iter()).map(|field_ident| {
+        let call = if is_struct {
+            quote!(builder.field(stringify!(#field_ident), &self.#field_ident))
+        } else {
+            quote!(builder.field(&self.#field_ident))
+        };
+        quote! {
+            if !self.#field_ident.is_empty() {
+                #call;
+            }
+        }
+    });
     let debug_builder = if is_struct {
         quote!(f.debug_struct(stringify!(#ident)))
     } else {
         quote!(f.debug_tuple(stringify!(#ident)))
     };
 
+    // Messages with nested message fields record the encoded lengths of the nested messages in a
+    // size cache, so that encoding doesn't recompute the length of each nested message at every
+    // level of nesting.
+    let message_trait_path = if lifetime.is_some() {
+        quote!(_prost::BorrowedMessage)
+    } else {
+        quote!(_prost::Message)
+    };
+    let encode_methods = if fields.iter().any(|&(_, ref field)| field.has_cached_sizes()) {
+        let cached_len = positions.iter().map(|&(_, field_ident, field, oneof_tag)| {
+            match (field, oneof_tag) {
+                (&Field::Oneof(ref oneof), Some(tag)) => oneof.cached_len_at(quote!(self.#field_ident), tag),
+                _ => field.cached_len(quote!(self.#field_ident)),
+            }
+        });
+        let encode_cached = positions.iter().map(|&(_, field_ident, field, oneof_tag)| {
+            match (field, oneof_tag) {
+                (&Field::Oneof(ref oneof), Some(tag)) => oneof.encode_cached_at(quote!(self.#field_ident), tag),
+                _ => field.encode_cached(quote!(self.#field_ident)),
+            }
+        });
+        quote! {
+            fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
+                let mut cache = _prost::encoding::SizeCache::new();
+                #message_trait_path::cache_sizes(self, &mut cache);
+                #message_trait_path::encode_cached(self, buf, &mut cache);
+            }
+
+            fn cache_sizes(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                0 #(+ #cached_len)* #extension_set_len #unknown_fields_len
+            }
+
+            #[allow(unused_variables)]
+            fn encode_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+            where B: _bytes::BufMut {
+                #(#encode_cached)*
+                #encode_extension_set
+                #encode_unknown_fields
+            }
+        }
+    } else {
+        quote! {
+            #[allow(unused_variables)]
+            fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
+                #(#encode)*
+                #encode_extension_set
+                #encode_unknown_fields
+            }
+        }
+    };
+
+    // Required fields are checked in the message as a whole, after decoding or before encoding,
+    // recursing into nested messages.
+    let check_initialized = fields.iter().filter_map(|&(ref field_ident, ref field)| {
+        let check = field.check_initialized(quote!(self.#field_ident))?;
+        let tag = match *field {
+            Field::Oneof(_) => quote!(self.#field_ident.as_ref().map_or(0, |oneof| oneof.tag())),
+            _ => {
+                let tag = field.tags()[0];
+                quote!(#tag)
+            },
+        };
+        Some(quote! {
+            #check.map_err(|mut error| {
+                error.push(STRUCT_NAME, stringify!(#field_ident), #tag);
+                error
+            })?;
+        })
+    }).collect::<Vec<_>>();
+    let check_initialized = if check_initialized.is_empty() {
+        quote!()
+    } else {
+        quote! {
+            fn check_initialized(&self) -> _core::result::Result<(), _prost::DecodeError> {
+                #struct_name
+                #(#check_initialized)*
+                _core::result::Result::Ok(())
+            }
+        }
+    };
+
+    // Borrowed messages implement `BorrowedMessage` and decode from a slice, instead of
+    // implementing `Message` and decoding from any `Buf`.
+    let (message_trait, merge_field_signature, nested_impls) = match lifetime {
+        Some(ref lifetime) => (
+            quote!(_prost::BorrowedMessage<#lifetime>),
+            quote! {
+                fn merge_field(&mut self,
+                               tag: u32,
+                               wire_type: _prost::encoding::WireType,
+                               buf: &mut _prost::encoding::SliceBuf<#lifetime>,
+                               ctx: &_prost::encoding::DecodeContext)
+                               -> _core::result::Result<(), _prost::DecodeError>
+            },
+            quote! {
+                impl<#lifetime> _prost::encoding::borrowed_message::NestedMessage<#lifetime> for #ident<#lifetime> {
+                    fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
+                        _prost::BorrowedMessage::encode_raw(self, buf)
+                    }
+                    fn encoded_len(&self) -> usize {
+                        _prost::BorrowedMessage::encoded_len(self)
+                    }
+                    fn cache_sizes(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                        _prost::BorrowedMessage::cache_sizes(self, cache)
+                    }
+                    fn encode_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+                    where B: _bytes::BufMut {
+                        _prost::BorrowedMessage::encode_cached(self, buf, cache)
+                    }
+                    fn merge_field(&mut self,
+                                   tag: u32,
+                                   wire_type: _prost::encoding::WireType,
+                                   buf: &mut _prost::encoding::SliceBuf<#lifetime>,
+                                   ctx: &_prost::encoding::DecodeContext)
+                                   -> _core::result::Result<(), _prost::DecodeError> {
+                        _prost::BorrowedMessage::merge_field(self, tag, wire_type, buf, ctx)
+                    }
+                    fn check_initialized(&self) -> _core::result::Result<(), _prost::DecodeError> {
+                        _prost::BorrowedMessage::check_initialized(self)
+                    }
+                }
+
+                impl<#lifetime> _prost::encoding::borrowed_message::NestedMessage<#lifetime>
+                for _alloc::boxed::Box<#ident<#lifetime>> {
+                    fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
+                        _prost::BorrowedMessage::encode_raw(&**self, buf)
+                    }
+                    fn encoded_len(&self) -> usize {
+                        _prost::BorrowedMessage::encoded_len(&**self)
+                    }
+                    fn cache_sizes(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                        _prost::BorrowedMessage::cache_sizes(&**self, cache)
+                    }
+                    fn encode_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+                    where B: _bytes::BufMut {
+                        _prost::BorrowedMessage::encode_cached(&**self, buf, cache)
+                    }
+                    fn merge_field(&mut self,
+                                   tag: u32,
+                                   wire_type: _prost::encoding::WireType,
+                                   buf: &mut _prost::encoding::SliceBuf<#lifetime>,
+                                   ctx: &_prost::encoding::DecodeContext)
+                                   -> _core::result::Result<(), _prost::DecodeError> {
+                        _prost::BorrowedMessage::merge_field(&mut **self, tag, wire_type, buf, ctx)
+                    }
+                    fn check_initialized(&self) -> _core::result::Result<(), _prost::DecodeError> {
+                        _prost::BorrowedMessage::check_initialized(&**self)
+                    }
+                }
+            },
+        ),
+        None => (
+            quote!(_prost::Message),
+            quote! {
+                fn merge_field<B>(&mut self,
+                                  tag: u32,
+                                  wire_type: _prost::encoding::WireType,
+                                  buf: &mut B,
+                                  ctx: &_prost::encoding::DecodeContext)
+                                  -> _core::result::Result<(), _prost::DecodeError>
+                where B: _bytes::Buf
+            },
+            quote!(),
+        ),
+    };
+
     let expanded = quote! {
         #[allow(non_snake_case, unused_attributes)]
         mod #module {
+            extern crate alloc as _alloc;
+            extern crate core as _core;
             extern crate prost as _prost;
-            extern crate bytes as _bytes;
+            use self::_prost::bytes as _bytes;
             use super::*;
 
-            impl _prost::Message for #ident {
-                #[allow(unused_variables)]
-                fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
-                    #(#encode)*
-                }
+            impl #impl_generics #message_trait for #ident #ty_generics {
+                #encode_methods
 
                 #[allow(unused_variables)]
-                fn merge_field<B>(&mut self, buf: &mut B) -> ::std::result::Result<(), _prost::DecodeError>
-                where B: _bytes::Buf {
+                #merge_field_signature {
                     #struct_name
-                    let (tag, wire_type) = _prost::encoding::decode_key(buf)?;
                     match tag {
                         #(#merge)*
-                        _ => _prost::encoding::skip_field(wire_type, buf),
+                        _ => #merge_unknown_fields,
                     }
                 }
 
                 #[inline]
                 fn encoded_len(&self) -> usize {
-                    0 #(+ #encoded_len)*
+                    0 #(+ #encoded_len)* #extension_set_len #unknown_fields_len
                 }
 
+                #check_initialized
+
                 fn clear(&mut self) {
                     #(#clear;)*
+                    #clear_extension_set
+                    #clear_unknown_fields
                 }
             }
 
-            impl Default for #ident {
-                fn default() -> #ident {
+            #nested_impls
+
+            #extendable
+
+            impl #impl_generics Default for #ident #ty_generics {
+                fn default() -> #ident #ty_generics {
                     #ident {
                         #(#default)*
                     }
                 }
             }
 
-            impl ::std::fmt::Debug for #ident {
-                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            impl #impl_generics _core::fmt::Debug for #ident #ty_generics {
+                fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                     let mut builder = #debug_builder;
                     #(#debugs;)*
+                    #(#debug_unknown_fields)*
                     builder.finish()
                 }
             }
@@ -250,15 +570,30 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
     };
 
     // Map the variants into 'fields'.
-    let mut variants: Vec<(Ident, Expr)> = Vec::new();
-    for Variant { ident, fields, discriminant, .. } in punctuated_variants {
+    let mut variants: Vec<(Ident, Expr, String, Vec<String>)> = Vec::new();
+    for Variant { attrs, ident, fields, discriminant, .. } in punctuated_variants {
         match fields {
             Fields::Unit => (),
             Fields::Named(_) | Fields::Unnamed(_) => bail!("Enumeration variants may not have fields"),
         }
 
+        // The value name in the `.proto` file, which defaults to the variant name, and the names of
+        // values which alias the variant.
+        let mut name = None;
+        let mut aliases = Vec::new();
+        for attr in field::prost_attrs(attrs)? {
+            if let Some(n) = field::string_attr("name", &attr)? {
+                field::set_option(&mut name, n, "duplicate name attributes")?;
+            } else if let Some(alias) = field::string_attr("alias", &attr)? {
+                aliases.push(alias);
+            } else {
+                bail!("unknown attribute for enumeration variant {}: {:?}", ident, attr);
+            }
+        }
+        let name = name.unwrap_or_else(|| ident.to_string());
+
         match discriminant {
-            Some((_, expr)) => variants.push((ident, expr)),
+            Some((_, expr)) => variants.push((ident, expr, name, aliases)),
             None => bail!("Enumeration variants must have a disriminant"),
         }
     }
@@ -271,15 +606,27 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
 
     // Put impls in a special module, so that 'extern crate' can be used.
     let module = Ident::new(&format!("{}_ENUMERATION", ident), Span::call_site());
-    let is_valid = variants.iter().map(|&(_, ref value)| quote!(#value => true));
-    let from = variants.iter().map(|&(ref variant, ref value)| quote!(#value => ::std::option::Option::Some(#ident::#variant)));
+    let is_valid = variants.iter().map(|&(_, ref value, _, _)| quote!(#value => true));
+    let to_i32 = variants.iter().map(|&(ref variant, ref value, _, _)| quote!(#ident::#variant => #value));
+    let from = variants.iter().map(|&(ref variant, ref value, _, _)| quote!(#value => _core::option::Option::Some(#ident::#variant)));
+    let as_str_name = variants.iter().map(|&(ref variant, _, ref name, _)| quote!(#ident::#variant => #name));
+    let from_str_name = variants.iter().map(|&(ref variant, _, ref name, ref aliases)| {
+        quote!(#name #(| #aliases)* => _core::option::Option::Some(#ident::#variant))
+    });
+    let all_variants = variants.iter().map(|&(ref variant, _, _, _)| quote!(#ident::#variant));
 
     let is_valid_doc = format!("Returns `true` if `value` is a variant of `{}`.", ident);
     let from_i32_doc = format!("Converts an `i32` to a `{}`, or `None` if `value` is not a valid variant.", ident);
+    let as_str_name_doc = "Returns the name of the variant, as written in the `.proto` file.";
+    let from_str_name_doc = format!("Converts a value name or alias, as written in the `.proto` file, to a \
+                                     `{}`, or `None` if `name` is not a known value name.", ident);
+    let variants_doc = format!("All variants of `{}`, in declaration order.", ident);
 
     let expanded = quote! {
         #[allow(non_snake_case, unused_attributes)]
         mod #module {
+            extern crate core as _core;
+            extern crate prost as _prost;
             use super::*;
 
             impl #ident {
@@ -293,25 +640,85 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
                 }
 
                 #[doc=#from_i32_doc]
-                pub fn from_i32(value: i32) -> ::std::option::Option<#ident> {
+                pub fn from_i32(value: i32) -> _core::option::Option<#ident> {
                     match value {
                         #(#from,)*
-                        _ => ::std::option::Option::None,
+                        _ => _core::option::Option::None,
+                    }
+                }
+
+                #[doc=#as_str_name_doc]
+                pub fn as_str_name(&self) -> &'static str {
+                    match *self {
+                        #(#as_str_name,)*
                     }
                 }
+
+                #[doc=#from_str_name_doc]
+                pub fn from_str_name(name: &str) -> _core::option::Option<#ident> {
+                    match name {
+                        #(#from_str_name,)*
+                        _ => _core::option::Option::None,
+                    }
+                }
+
+                #[doc=#variants_doc]
+                pub const VARIANTS: &'static [#ident] = &[#(#all_variants),*];
             }
 
-            impl ::std::default::Default for #ident {
+            impl _core::default::Default for #ident {
                 fn default() -> #ident {
                     #ident::#default
                 }
             }
 
-            impl ::std::convert::From<#ident> for i32 {
+            impl _core::convert::From<#ident> for i32 {
                 fn from(value: #ident) -> i32 {
                     value as i32
                 }
             }
+
+            impl _prost::Enumeration for #ident {
+                fn from_i32(value: i32) -> _core::option::Option<#ident> {
+                    #ident::from_i32(value)
+                }
+
+                fn to_i32(&self) -> i32 {
+                    match *self {
+                        #(#to_i32,)*
+                    }
+                }
+
+                fn from_str_name(name: &str) -> _core::option::Option<#ident> {
+                    #ident::from_str_name(name)
+                }
+
+                fn as_str_name(&self) -> &'static str {
+                    #ident::as_str_name(self)
+                }
+            }
+
+            impl _core::convert::TryFrom<i32> for #ident {
+                type Error = _prost::UnknownEnumValue;
+
+                fn try_from(value: i32) -> _core::result::Result<#ident, _prost::UnknownEnumValue> {
+                    #ident::from_i32(value).ok_or(_prost::UnknownEnumValue(value))
+                }
+            }
+
+            impl _core::str::FromStr for #ident {
+                type Err = _prost::UnknownEnumName;
+
+                fn from_str(name: &str) -> _core::result::Result<#ident, _prost::UnknownEnumName> {
+                    #ident::from_str_name(name).ok_or_else(|| _prost::UnknownEnumName::new(name))
+                }
+            }
+
+            impl _core::fmt::Display for #ident {
+                fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
+                    f.write_str(self.as_str_name())
+                }
+            }
         };
     };
 
@@ -334,10 +741,7 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
         Data::Union(..) => bail!("Oneof can not be derived for a union"),
     };
 
-    if !input.generics.params.is_empty() ||
-       input.generics.where_clause.is_some() {
-        bail!("Message may not be derived for generic type");
-    }
+    let lifetime = borrowed_lifetime(&input.generics, "Oneof")?;
 
     // Map the variants into 'fields'.
     let mut fields: Vec<(Ident, Field)> = Vec::new();
@@ -351,7 +755,15 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
             bail!("Oneof enum variants must have a single field");
         }
         match Field::new_oneof(attrs)? {
-            Some(field) => fields.push((variant_ident, field)),
+            Some(mut field) => {
+                if lifetime.is_some() {
+                    field.set_borrowed();
+                } else if field.is_borrowed() {
+                    bail!("invalid oneof variant {}::{}: borrowed field types may only be used in \
+                           oneofs with a lifetime parameter", ident, variant_ident);
+                }
+                fields.push((variant_ident, field))
+            },
             None => bail!("invalid oneof variant: oneof variants may not be ignored"),
         }
     }
@@ -379,12 +791,27 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
 
     let merge = fields.iter().map(|&(ref variant_ident, ref field)| {
         let tag = field.tags()[0];
-        let merge = field.merge(quote!(value));
-        quote! {
-            #tag => {
-                let mut value = ::std::default::Default::default();
-                #merge.map(|_| *field = ::std::option::Option::Some(#ident::#variant_ident(value)))
-            }
+        match *field {
+            // An unknown value of a closed enumeration leaves the oneof unchanged, and is preserved
+            // as an unknown field of the containing message.
+            Field::Scalar(ref scalar) if scalar.ty.is_closed_enumeration() => quote! {
+                #tag => {
+                    let mut value = _core::option::Option::None;
+                    _prost::encoding::closed_enum::merge_optional(tag, wire_type, &mut value, unknown_fields, buf, ctx)
+                        .map(|_| if let _core::option::Option::Some(value) = value {
+                            *field = _core::option::Option::Some(#ident::#variant_ident(value));
+                        })
+                }
+            },
+            _ => {
+                let merge = field.merge(quote!(value));
+                quote! {
+                    #tag => {
+                        let mut value = _core::default::Default::default();
+                        #merge.map(|_| *field = _core::option::Option::Some(#ident::#variant_ident(value)))
+                    }
+                }
+            },
         }
     });
 
@@ -393,6 +820,46 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
         quote!(#ident::#variant_ident(ref value) => #encoded_len)
     });
 
+    let tag = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let tag = field.tags()[0];
+        quote!(#ident::#variant_ident(_) => #tag)
+    });
+
+    // Oneofs record the encoded lengths of nested messages in the size cache of the containing
+    // message.
+    let cached_methods = {
+        let encode_cached = fields.iter().map(|&(ref variant_ident, ref field)| {
+            let encode_cached = field.encode_cached(quote!(*value));
+            quote!(#ident::#variant_ident(ref value) => { #encode_cached })
+        });
+        let cached_len = fields.iter().map(|&(ref variant_ident, ref field)| {
+            let cached_len = field.cached_len(quote!(*value));
+            quote!(#ident::#variant_ident(ref value) => #cached_len)
+        });
+        quote! {
+            #[allow(unused_variables)]
+            pub fn encode_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+            where B: _bytes::BufMut {
+                match *self {
+                    #(#encode_cached,)*
+                }
+            }
+
+            #[allow(unused_variables)]
+            pub fn cache_sizes(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                match *self {
+                    #(#cached_len,)*
+                }
+            }
+        }
+    };
+
+    let check_initialized = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let check = field.check_initialized(quote!(*value))
+                         .unwrap_or_else(|| quote!(_core::result::Result::Ok(())));
+        quote!(#ident::#variant_ident(ref value) => #check)
+    });
+
     let debug = fields.iter().map(|&(ref variant_ident, ref field)| {
         let wrapper = field.debug(quote!(*value));
         quote!(#ident::#variant_ident(ref value) => {
@@ -403,26 +870,65 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
         })
     });
 
+    // Borrowed oneofs are decoded from a slice, instead of from any `Buf`.
+    let (impl_generics, ty_generics, field_ty, buf_ty, buf_generics, buf_bound) = match lifetime {
+        Some(ref lifetime) => (
+            quote!(<#lifetime>),
+            quote!(<#lifetime>),
+            quote!(_core::option::Option<#ident<#lifetime>>),
+            quote!(_prost::encoding::SliceBuf<#lifetime>),
+            quote!(),
+            quote!(),
+        ),
+        None => (
+            quote!(),
+            quote!(),
+            quote!(_core::option::Option<#ident>),
+            quote!(B),
+            quote!(<B>),
+            quote!(where B: _bytes::Buf),
+        ),
+    };
+
     let expanded = quote! {
         #[allow(non_snake_case, unused_attributes)]
         mod #module {
-            extern crate bytes as _bytes;
+            extern crate alloc as _alloc;
+            extern crate core as _core;
             extern crate prost as _prost;
+            use self::_prost::bytes as _bytes;
             use super::*;
 
-            impl #ident {
+            impl #impl_generics #ident #ty_generics {
                 pub fn encode<B>(&self, buf: &mut B) where B: _bytes::BufMut {
                     match *self {
                         #(#encode,)*
                     }
                 }
 
-                pub fn merge<B>(field: &mut ::std::option::Option<#ident>,
-                                tag: u32,
-                                wire_type: _prost::encoding::WireType,
-                                buf: &mut B)
-                                -> ::std::result::Result<(), _prost::DecodeError>
-                where B: _bytes::Buf {
+                pub fn merge #buf_generics (field: &mut #field_ty,
+                                            tag: u32,
+                                            wire_type: _prost::encoding::WireType,
+                                            buf: &mut #buf_ty,
+                                            ctx: &_prost::encoding::DecodeContext)
+                                            -> _core::result::Result<(), _prost::DecodeError>
+                #buf_bound {
+                    #ident::merge_with_unknown_fields(field, tag, wire_type, _core::option::Option::None, buf, ctx)
+                }
+
+                /// Decodes a variant of the oneof and merges it into `field`. Unknown values of
+                /// closed enumeration variants leave `field` unchanged, and are recorded in
+                /// `unknown_fields`, the unknown fields of the containing message, if provided.
+                #[allow(unused_variables)]
+                pub fn merge_with_unknown_fields #buf_generics (
+                    field: &mut #field_ty,
+                    tag: u32,
+                    wire_type: _prost::encoding::WireType,
+                    unknown_fields: _core::option::Option<&mut _prost::UnknownFields>,
+                    buf: &mut #buf_ty,
+                    ctx: &_prost::encoding::DecodeContext)
+                    -> _core::result::Result<(), _prost::DecodeError>
+                #buf_bound {
                     match tag {
                         #(#merge,)*
                         _ => unreachable!(concat!("invalid ", stringify!(#ident), " tag: {}"), tag),
@@ -435,10 +941,29 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
                         #(#encoded_len,)*
                     }
                 }
+
+                /// Returns the field tag of the active variant.
+                #[inline]
+                pub fn tag(&self) -> u32 {
+                    match *self {
+                        #(#tag,)*
+                    }
+                }
+
+                #cached_methods
+
+                /// Returns an error naming the first required field which is not set in the
+                /// active variant's nested message.
+                #[allow(unused_variables)]
+                pub fn check_initialized(&self) -> _core::result::Result<(), _prost::DecodeError> {
+                    match *self {
+                        #(#check_initialized,)*
+                    }
+                }
             }
 
-            impl ::std::fmt::Debug for #ident {
-                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            impl #impl_generics _core::fmt::Debug for #ident #ty_generics {
+                fn fmt(&self, f: &mut _core::fmt::Formatter) -> _core::fmt::Result {
                     match *self {
                         #(#debug,)*
                     }
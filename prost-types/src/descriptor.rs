@@ -0,0 +1,749 @@
+//! Runtime descriptors resolved from a set of Protobuf file descriptors.
+//!
+//! A `DescriptorPool` indexes the message and enum types declared by a `FileDescriptorSet`, and
+//! resolves the type of every field against the other types in the set. The message, field and
+//! enum descriptors handed out by the pool are cheap handles which keep the pool alive.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+
+use field_descriptor_proto::{Label, Type};
+use {
+    DescriptorProto,
+    EnumDescriptorProto,
+    FieldDescriptorProto,
+    FileDescriptorProto,
+    FileDescriptorSet,
+};
+
+/// An error which occurs when a set of file descriptors can't be resolved into a
+/// `DescriptorPool`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorError {
+    description: String,
+}
+
+impl DescriptorError {
+    fn new<S>(description: S) -> DescriptorError where S: Into<String> {
+        DescriptorError { description: description.into() }
+    }
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to resolve descriptors: {}", self.description)
+    }
+}
+
+impl error::Error for DescriptorError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// An indexed, resolved collection of message and enum types.
+///
+/// Cloning a pool is cheap: clones share the same underlying descriptors.
+#[derive(Clone)]
+pub struct DescriptorPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    messages: Vec<MessageInfo>,
+    enums: Vec<EnumInfo>,
+    names: HashMap<String, Definition>,
+}
+
+#[derive(Clone, Copy)]
+enum Definition {
+    Message(usize),
+    Enum(usize),
+}
+
+struct MessageInfo {
+    full_name: String,
+    proto: DescriptorProto,
+    proto3: bool,
+    map_entry: bool,
+    fields: Vec<FieldInfo>,
+    field_numbers: HashMap<u32, usize>,
+    field_names: HashMap<String, usize>,
+}
+
+pub(crate) struct FieldInfo {
+    pub(crate) number: u32,
+    pub(crate) kind: KindIndex,
+    pub(crate) cardinality: Cardinality,
+    pub(crate) packed: bool,
+    pub(crate) group: bool,
+    pub(crate) map: bool,
+    pub(crate) oneof_index: Option<u32>,
+    json_name: String,
+    pub(crate) proto: FieldDescriptorProto,
+}
+
+struct EnumInfo {
+    full_name: String,
+    proto: EnumDescriptorProto,
+}
+
+/// The type of a field, with message and enum types referenced by index into the pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum KindIndex {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+    Message(usize),
+    Enum(usize),
+}
+
+/// The cardinality of a field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Cardinality {
+    Optional,
+    Required,
+    Repeated,
+}
+
+/// The type of a field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+    /// A message or group field, or a map field, in which case the message is the map entry type.
+    Message(MessageDescriptor),
+    Enum(EnumDescriptor),
+}
+
+impl DescriptorPool {
+
+    /// Creates a pool containing the types declared by the files in a `FileDescriptorSet`.
+    ///
+    /// Every type referenced by a field must be declared by one of the files in the set, so the
+    /// set must include the dependencies of each file. Sets written by `protoc
+    /// --include_imports` satisfy this requirement.
+    pub fn new(file_descriptor_set: FileDescriptorSet) -> Result<DescriptorPool, DescriptorError> {
+        DescriptorPool::from_files(file_descriptor_set.file)
+    }
+
+    /// Creates a pool containing the types declared by the provided files.
+    ///
+    /// See `DescriptorPool::new` for the requirements on the files.
+    pub fn from_files<I>(files: I) -> Result<DescriptorPool, DescriptorError>
+    where I: IntoIterator<Item=FileDescriptorProto> {
+        let mut inner = PoolInner {
+            messages: Vec::new(),
+            enums: Vec::new(),
+            names: HashMap::new(),
+        };
+
+        let mut unresolved = Vec::new();
+        for file in files {
+            let proto3 = file.syntax.as_ref().map_or(false, |syntax| syntax == "proto3");
+            let scope = file.package.clone().unwrap_or_default();
+            for enum_type in file.enum_type {
+                inner.add_enum(&scope, enum_type)?;
+            }
+            for message_type in file.message_type {
+                inner.add_message(&scope, message_type, proto3, &mut unresolved)?;
+            }
+        }
+
+        for index in unresolved {
+            inner.resolve_fields(index)?;
+        }
+
+        Ok(DescriptorPool { inner: Arc::new(inner) })
+    }
+
+    /// Returns the pool for an encoded `FileDescriptorSet` embedded in generated code.
+    ///
+    /// The pool is decoded and resolved on first use, and cached for the lifetime of the program.
+    #[doc(hidden)]
+    pub fn decode_static(file_descriptor_set: &'static [u8]) -> DescriptorPool {
+        lazy_static! {
+            static ref POOLS: Mutex<HashMap<usize, DescriptorPool>> = Mutex::new(HashMap::new());
+        }
+
+        let mut pools = POOLS.lock().unwrap();
+        pools.entry(file_descriptor_set.as_ptr() as usize).or_insert_with(|| {
+            let set = FileDescriptorSet::decode(file_descriptor_set)
+                                        .expect("invalid embedded file descriptor set");
+            DescriptorPool::new(set).expect("invalid embedded file descriptor set")
+        }).clone()
+    }
+
+    /// Returns the message type with the provided fully-qualified name, for example
+    /// `"google.protobuf.Timestamp"`.
+    pub fn get_message_by_name(&self, name: &str) -> Option<MessageDescriptor> {
+        match self.inner.names.get(strip_leading_dot(name)) {
+            Some(&Definition::Message(index)) => Some(MessageDescriptor::new(self.clone(), index)),
+            _ => None,
+        }
+    }
+
+    /// Returns the message type named by an `Any` type URL, for example
+    /// `"type.googleapis.com/google.protobuf.Timestamp"`.
+    pub fn get_message_by_type_url(&self, type_url: &str) -> Option<MessageDescriptor> {
+        match type_url.rfind('/') {
+            Some(index) => self.get_message_by_name(&type_url[index + 1..]),
+            None => None,
+        }
+    }
+
+    /// Returns the enum type with the provided fully-qualified name.
+    pub fn get_enum_by_name(&self, name: &str) -> Option<EnumDescriptor> {
+        match self.inner.names.get(strip_leading_dot(name)) {
+            Some(&Definition::Enum(index)) => Some(EnumDescriptor::new(self.clone(), index)),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the message types in the pool, including nested types.
+    pub fn messages<'a>(&'a self) -> impl Iterator<Item=MessageDescriptor> + 'a {
+        (0..self.inner.messages.len()).map(move |index| MessageDescriptor::new(self.clone(), index))
+    }
+
+    /// Returns an iterator over the enum types in the pool, including nested types.
+    pub fn enums<'a>(&'a self) -> impl Iterator<Item=EnumDescriptor> + 'a {
+        (0..self.inner.enums.len()).map(move |index| EnumDescriptor::new(self.clone(), index))
+    }
+
+    fn ptr_eq(&self, other: &DescriptorPool) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for DescriptorPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DescriptorPool")
+         .field("messages", &self.inner.messages.iter().map(|m| &m.full_name).collect::<Vec<_>>())
+         .field("enums", &self.inner.enums.iter().map(|e| &e.full_name).collect::<Vec<_>>())
+         .finish()
+    }
+}
+
+fn strip_leading_dot(name: &str) -> &str {
+    if name.starts_with('.') { &name[1..] } else { name }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// Converts a field name to the lowerCamelCase name used by the JSON mapping.
+fn to_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+impl PoolInner {
+
+    fn define(&mut self, full_name: &str, definition: Definition) -> Result<(), DescriptorError> {
+        if self.names.insert(full_name.to_owned(), definition).is_some() {
+            return Err(DescriptorError::new(format!("duplicate type name {}", full_name)));
+        }
+        Ok(())
+    }
+
+    fn add_enum(&mut self, scope: &str, proto: EnumDescriptorProto) -> Result<(), DescriptorError> {
+        let full_name = qualify(scope, proto.name());
+        let index = self.enums.len();
+        self.define(&full_name, Definition::Enum(index))?;
+        self.enums.push(EnumInfo { full_name, proto });
+        Ok(())
+    }
+
+    fn add_message(&mut self,
+                   scope: &str,
+                   mut proto: DescriptorProto,
+                   proto3: bool,
+                   unresolved: &mut Vec<usize>) -> Result<(), DescriptorError> {
+        let full_name = qualify(scope, proto.name());
+        let index = self.messages.len();
+        self.define(&full_name, Definition::Message(index))?;
+
+        let nested_types = proto.nested_type.clone();
+        let enum_types = proto.enum_type.clone();
+        proto.nested_type.clear();
+        proto.enum_type.clear();
+        let map_entry = proto.options.as_ref().map_or(false, |options| options.map_entry());
+        self.messages.push(MessageInfo {
+            full_name: full_name.clone(),
+            proto,
+            proto3,
+            map_entry,
+            fields: Vec::new(),
+            field_numbers: HashMap::new(),
+            field_names: HashMap::new(),
+        });
+        unresolved.push(index);
+
+        for enum_type in enum_types {
+            self.add_enum(&full_name, enum_type)?;
+        }
+        for nested_type in nested_types {
+            self.add_message(&full_name, nested_type, proto3, unresolved)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a possibly relative type name referenced from within a scope, following the
+    /// Protobuf scoping rules: the innermost enclosing scope which declares the name wins.
+    fn resolve_name(&self, scope: &str, name: &str) -> Option<Definition> {
+        if name.starts_with('.') {
+            return self.names.get(&name[1..]).cloned();
+        }
+
+        let mut scope = scope;
+        loop {
+            if let Some(definition) = self.names.get(&qualify(scope, name)) {
+                return Some(*definition);
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = match scope.rfind('.') {
+                Some(idx) => &scope[..idx],
+                None => "",
+            };
+        }
+    }
+
+    fn resolve_fields(&mut self, index: usize) -> Result<(), DescriptorError> {
+        let full_name = self.messages[index].full_name.clone();
+        let proto3 = self.messages[index].proto3;
+
+        let mut fields = Vec::new();
+        for proto in self.messages[index].proto.field.clone() {
+            let field_name = qualify(&full_name, proto.name());
+            let number = match proto.number {
+                Some(number) if number > 0 => number as u32,
+                _ => return Err(DescriptorError::new(format!("field {} has an invalid number",
+                                                             field_name))),
+            };
+
+            let ty = proto.type_.and_then(Type::from_i32);
+            let kind = match ty {
+                Some(Type::Double) => KindIndex::Double,
+                Some(Type::Float) => KindIndex::Float,
+                Some(Type::Int32) => KindIndex::Int32,
+                Some(Type::Int64) => KindIndex::Int64,
+                Some(Type::Uint32) => KindIndex::Uint32,
+                Some(Type::Uint64) => KindIndex::Uint64,
+                Some(Type::Sint32) => KindIndex::Sint32,
+                Some(Type::Sint64) => KindIndex::Sint64,
+                Some(Type::Fixed32) => KindIndex::Fixed32,
+                Some(Type::Fixed64) => KindIndex::Fixed64,
+                Some(Type::Sfixed32) => KindIndex::Sfixed32,
+                Some(Type::Sfixed64) => KindIndex::Sfixed64,
+                Some(Type::Bool) => KindIndex::Bool,
+                Some(Type::String) => KindIndex::String,
+                Some(Type::Bytes) => KindIndex::Bytes,
+                Some(Type::Message) | Some(Type::Group) | Some(Type::Enum) | None => {
+                    let type_name = match proto.type_name {
+                        Some(ref type_name) => type_name,
+                        None => return Err(DescriptorError::new(format!("field {} has no type",
+                                                                        field_name))),
+                    };
+                    match (self.resolve_name(&full_name, type_name), ty) {
+                        (Some(Definition::Message(idx)), Some(Type::Message))
+                            | (Some(Definition::Message(idx)), Some(Type::Group))
+                            | (Some(Definition::Message(idx)), None) => KindIndex::Message(idx),
+                        (Some(Definition::Enum(idx)), Some(Type::Enum))
+                            | (Some(Definition::Enum(idx)), None) => KindIndex::Enum(idx),
+                        _ => return Err(DescriptorError::new(format!(
+                                    "field {} has unresolvable type {}", field_name, type_name))),
+                    }
+                },
+            };
+
+            let cardinality = match proto.label.and_then(Label::from_i32) {
+                Some(Label::Required) => Cardinality::Required,
+                Some(Label::Repeated) => Cardinality::Repeated,
+                Some(Label::Optional) | None => Cardinality::Optional,
+            };
+
+            let packable = match kind {
+                KindIndex::String | KindIndex::Bytes | KindIndex::Message(_) => false,
+                _ => true,
+            };
+            let packed = cardinality == Cardinality::Repeated && packable
+                && proto.options.as_ref()
+                                .and_then(|options| options.packed)
+                                .unwrap_or(proto3);
+
+            let map = cardinality == Cardinality::Repeated && match kind {
+                KindIndex::Message(idx) => self.messages[idx].map_entry,
+                _ => false,
+            };
+
+            let oneof_index = proto.oneof_index.map(|idx| idx as u32);
+            let json_name = proto.json_name.clone().unwrap_or_else(|| to_json_name(proto.name()));
+
+            fields.push(FieldInfo {
+                number,
+                kind,
+                cardinality,
+                packed,
+                group: ty == Some(Type::Group),
+                map,
+                oneof_index,
+                json_name,
+                proto,
+            });
+        }
+
+        let message = &mut self.messages[index];
+        for (idx, field) in fields.iter().enumerate() {
+            if message.field_numbers.insert(field.number, idx).is_some() {
+                return Err(DescriptorError::new(format!("duplicate field number {} in {}",
+                                                        field.number, full_name)));
+            }
+            message.field_names.insert(field.proto.name().to_owned(), idx);
+        }
+        message.fields = fields;
+        Ok(())
+    }
+}
+
+/// A message type in a `DescriptorPool`.
+#[derive(Clone)]
+pub struct MessageDescriptor {
+    pool: DescriptorPool,
+    index: usize,
+}
+
+impl MessageDescriptor {
+
+    fn new(pool: DescriptorPool, index: usize) -> MessageDescriptor {
+        MessageDescriptor { pool, index }
+    }
+
+    fn info(&self) -> &MessageInfo {
+        &self.pool.inner.messages[self.index]
+    }
+
+    pub(crate) fn field_info(&self, index: usize) -> &FieldInfo {
+        &self.info().fields[index]
+    }
+
+    pub(crate) fn field_index(&self, number: u32) -> Option<usize> {
+        self.info().field_numbers.get(&number).cloned()
+    }
+
+    pub(crate) fn message(&self, index: usize) -> MessageDescriptor {
+        MessageDescriptor::new(self.pool.clone(), index)
+    }
+
+    /// Returns the pool containing the message type.
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Returns the fully-qualified name of the message type, for example
+    /// `"google.protobuf.Timestamp"`.
+    pub fn full_name(&self) -> &str {
+        &self.info().full_name
+    }
+
+    /// Returns the unqualified name of the message type.
+    pub fn name(&self) -> &str {
+        self.info().proto.name()
+    }
+
+    /// Returns the message's `DescriptorProto`.
+    ///
+    /// The nested message and enum types of the returned descriptor are cleared; they are
+    /// available from the pool instead.
+    pub fn descriptor_proto(&self) -> &DescriptorProto {
+        &self.info().proto
+    }
+
+    /// Returns `true` if the message type is declared in a `proto3` file.
+    pub fn is_proto3(&self) -> bool {
+        self.info().proto3
+    }
+
+    /// Returns `true` if the message type is the synthetic entry type of a map field.
+    pub fn is_map_entry(&self) -> bool {
+        self.info().map_entry
+    }
+
+    /// Returns an iterator over the fields of the message, in declaration order.
+    pub fn fields<'a>(&'a self) -> impl Iterator<Item=FieldDescriptor> + 'a {
+        (0..self.info().fields.len()).map(move |index| FieldDescriptor::new(self.clone(), index))
+    }
+
+    /// Returns the field with the provided number.
+    pub fn get_field(&self, number: u32) -> Option<FieldDescriptor> {
+        self.field_index(number).map(|index| FieldDescriptor::new(self.clone(), index))
+    }
+
+    /// Returns the field with the provided name.
+    pub fn get_field_by_name(&self, name: &str) -> Option<FieldDescriptor> {
+        self.info()
+            .field_names
+            .get(name)
+            .map(|&index| FieldDescriptor::new(self.clone(), index))
+    }
+
+    /// Returns the field with the provided JSON name.
+    pub fn get_field_by_json_name(&self, json_name: &str) -> Option<FieldDescriptor> {
+        self.info()
+            .fields
+            .iter()
+            .position(|field| field.json_name == json_name)
+            .map(|index| FieldDescriptor::new(self.clone(), index))
+    }
+}
+
+impl PartialEq for MessageDescriptor {
+    fn eq(&self, other: &MessageDescriptor) -> bool {
+        self.pool.ptr_eq(&other.pool) && self.index == other.index
+    }
+}
+
+impl Eq for MessageDescriptor {}
+
+impl fmt::Debug for MessageDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MessageDescriptor").field(&self.full_name()).finish()
+    }
+}
+
+/// A field of a message type in a `DescriptorPool`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FieldDescriptor {
+    message: MessageDescriptor,
+    index: usize,
+}
+
+impl FieldDescriptor {
+
+    fn new(message: MessageDescriptor, index: usize) -> FieldDescriptor {
+        FieldDescriptor { message, index }
+    }
+
+    pub(crate) fn info(&self) -> &FieldInfo {
+        self.message.field_info(self.index)
+    }
+
+    /// Returns the message type containing the field.
+    pub fn containing_message(&self) -> &MessageDescriptor {
+        &self.message
+    }
+
+    /// Returns the name of the field.
+    pub fn name(&self) -> &str {
+        self.info().proto.name()
+    }
+
+    /// Returns the name of the field in the JSON mapping.
+    pub fn json_name(&self) -> &str {
+        &self.info().json_name
+    }
+
+    /// Returns the field number.
+    pub fn number(&self) -> u32 {
+        self.info().number
+    }
+
+    /// Returns the field's `FieldDescriptorProto`.
+    pub fn field_descriptor_proto(&self) -> &FieldDescriptorProto {
+        &self.info().proto
+    }
+
+    /// Returns the type of the field.
+    pub fn kind(&self) -> Kind {
+        match self.info().kind {
+            KindIndex::Double => Kind::Double,
+            KindIndex::Float => Kind::Float,
+            KindIndex::Int32 => Kind::Int32,
+            KindIndex::Int64 => Kind::Int64,
+            KindIndex::Uint32 => Kind::Uint32,
+            KindIndex::Uint64 => Kind::Uint64,
+            KindIndex::Sint32 => Kind::Sint32,
+            KindIndex::Sint64 => Kind::Sint64,
+            KindIndex::Fixed32 => Kind::Fixed32,
+            KindIndex::Fixed64 => Kind::Fixed64,
+            KindIndex::Sfixed32 => Kind::Sfixed32,
+            KindIndex::Sfixed64 => Kind::Sfixed64,
+            KindIndex::Bool => Kind::Bool,
+            KindIndex::String => Kind::String,
+            KindIndex::Bytes => Kind::Bytes,
+            KindIndex::Message(index) => Kind::Message(self.message.message(index)),
+            KindIndex::Enum(index) => Kind::Enum(EnumDescriptor::new(self.message.pool.clone(),
+                                                                     index)),
+        }
+    }
+
+    /// Returns the cardinality of the field.
+    pub fn cardinality(&self) -> Cardinality {
+        self.info().cardinality
+    }
+
+    /// Returns `true` if the field is repeated and not a map field.
+    pub fn is_list(&self) -> bool {
+        self.info().cardinality == Cardinality::Repeated && !self.info().map
+    }
+
+    /// Returns `true` if the field is a map field.
+    pub fn is_map(&self) -> bool {
+        self.info().map
+    }
+
+    /// Returns `true` if the field is repeated and encoded in packed form.
+    pub fn is_packed(&self) -> bool {
+        self.info().packed
+    }
+
+    /// Returns `true` if the field is a proto2 group.
+    pub fn is_group(&self) -> bool {
+        self.info().group
+    }
+
+    /// Returns the index of the oneof containing the field in the message's `oneof_decl`, if
+    /// the field is a member of a oneof.
+    pub fn oneof_index(&self) -> Option<u32> {
+        self.info().oneof_index
+    }
+
+    /// Returns `true` if presence is tracked for the field: setting it to its default value is
+    /// distinguishable from not setting it.
+    pub fn supports_presence(&self) -> bool {
+        let info = self.info();
+        info.cardinality != Cardinality::Repeated
+            && (!self.message.is_proto3() || info.oneof_index.is_some() || match info.kind {
+                KindIndex::Message(_) => true,
+                _ => false,
+            })
+    }
+}
+
+impl fmt::Debug for FieldDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FieldDescriptor")
+         .field("message", &self.message.full_name())
+         .field("name", &self.name())
+         .field("number", &self.number())
+         .finish()
+    }
+}
+
+/// An enum type in a `DescriptorPool`.
+#[derive(Clone)]
+pub struct EnumDescriptor {
+    pool: DescriptorPool,
+    index: usize,
+}
+
+impl EnumDescriptor {
+
+    fn new(pool: DescriptorPool, index: usize) -> EnumDescriptor {
+        EnumDescriptor { pool, index }
+    }
+
+    fn info(&self) -> &EnumInfo {
+        &self.pool.inner.enums[self.index]
+    }
+
+    /// Returns the fully-qualified name of the enum type.
+    pub fn full_name(&self) -> &str {
+        &self.info().full_name
+    }
+
+    /// Returns the unqualified name of the enum type.
+    pub fn name(&self) -> &str {
+        self.info().proto.name()
+    }
+
+    /// Returns the enum's `EnumDescriptorProto`.
+    pub fn enum_descriptor_proto(&self) -> &EnumDescriptorProto {
+        &self.info().proto
+    }
+
+    /// Returns the number of the enum's default value, which is its first declared value.
+    pub fn default_value(&self) -> i32 {
+        self.info().proto.value.first().map_or(0, |value| value.number())
+    }
+
+    /// Returns the name of the first value with the provided number.
+    pub fn get_value_name(&self, number: i32) -> Option<&str> {
+        self.info()
+            .proto
+            .value
+            .iter()
+            .find(|value| value.number() == number)
+            .map(|value| value.name())
+    }
+
+    /// Returns the number of the value with the provided name.
+    pub fn get_value_number(&self, name: &str) -> Option<i32> {
+        self.info()
+            .proto
+            .value
+            .iter()
+            .find(|value| value.name() == name)
+            .map(|value| value.number())
+    }
+}
+
+impl PartialEq for EnumDescriptor {
+    fn eq(&self, other: &EnumDescriptor) -> bool {
+        self.pool.ptr_eq(&other.pool) && self.index == other.index
+    }
+}
+
+impl Eq for EnumDescriptor {}
+
+impl fmt::Debug for EnumDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("EnumDescriptor").field(&self.full_name()).finish()
+    }
+}
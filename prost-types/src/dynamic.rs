@@ -0,0 +1,962 @@
+//! Messages whose schema is only known at runtime.
+//!
+//! A `DynamicMessage` is an instance of a message type described by a `MessageDescriptor`. It
+//! implements `prost::Message`, so it can be encoded and decoded like a generated message, and
+//! its fields can be read and written by name or number as `Value`s.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use bytes::{Buf, BufMut, IntoBuf};
+use prost::encoding::{
+    bytes,
+    encode_key,
+    encode_varint,
+    encoded_len_varint,
+    group,
+    key_len,
+    merge_loop,
+    message,
+    string,
+    DecodeContext,
+    SizeCache,
+    WireType,
+};
+use prost::encoding;
+use prost::{DecodeError, DecodeOptions, Message, UnknownFields};
+
+use descriptor::{
+    Cardinality,
+    FieldDescriptor,
+    FieldInfo,
+    KindIndex,
+    MessageDescriptor,
+};
+
+/// An instance of a message type described by a `MessageDescriptor`.
+///
+/// Fields which are not known to the message type are preserved while decoding, and re-emitted
+/// when the message is encoded.
+#[derive(Clone, PartialEq)]
+pub struct DynamicMessage {
+    descriptor: MessageDescriptor,
+    fields: BTreeMap<u32, Value>,
+    unknown_fields: UnknownFields,
+}
+
+/// The value of a field of a `DynamicMessage`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A `bool` field.
+    Bool(bool),
+    /// An `int32`, `sint32` or `sfixed32` field.
+    I32(i32),
+    /// An `int64`, `sint64` or `sfixed64` field.
+    I64(i64),
+    /// A `uint32` or `fixed32` field.
+    U32(u32),
+    /// A `uint64` or `fixed64` field.
+    U64(u64),
+    /// A `float` field.
+    F32(f32),
+    /// A `double` field.
+    F64(f64),
+    /// A `string` field.
+    String(String),
+    /// A `bytes` field.
+    Bytes(Vec<u8>),
+    /// An enum field, represented by its number so that unknown values are preserved.
+    EnumNumber(i32),
+    /// A message or group field.
+    Message(DynamicMessage),
+    /// A repeated field.
+    List(Vec<Value>),
+    /// A map field.
+    Map(BTreeMap<MapKey, Value>),
+}
+
+/// The key of a map field entry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MapKey {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    String(String),
+}
+
+/// An error which occurs when setting a field of a `DynamicMessage`.
+#[derive(Debug, PartialEq)]
+pub enum SetFieldError {
+    /// The message type has no field with the provided name or number.
+    NotFound,
+    /// The value's type does not match the type of the field.
+    InvalidType {
+        field: FieldDescriptor,
+        value: Value,
+    },
+}
+
+impl fmt::Display for SetFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetFieldError::NotFound => write!(f, "field not found"),
+            SetFieldError::InvalidType { ref field, ref value } => {
+                write!(f, "invalid value {:?} for field {}", value, field.name())
+            },
+        }
+    }
+}
+
+impl error::Error for SetFieldError {
+    fn description(&self) -> &str {
+        match *self {
+            SetFieldError::NotFound => "field not found",
+            SetFieldError::InvalidType { .. } => "invalid value type for field",
+        }
+    }
+}
+
+impl DynamicMessage {
+
+    /// Creates a message of the provided type with no fields set.
+    pub fn new(descriptor: MessageDescriptor) -> DynamicMessage {
+        DynamicMessage {
+            descriptor,
+            fields: BTreeMap::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    /// Decodes a message of the provided type from a buffer.
+    ///
+    /// The entire buffer will be consumed.
+    pub fn decode<B>(descriptor: MessageDescriptor, buf: B) -> Result<DynamicMessage, DecodeError>
+    where B: IntoBuf {
+        DynamicMessage::decode_with_options(descriptor, buf, &DecodeOptions::default())
+    }
+
+    /// Decodes a message of the provided type from a buffer, using the provided decode options.
+    ///
+    /// The entire buffer will be consumed.
+    pub fn decode_with_options<B>(descriptor: MessageDescriptor,
+                                  buf: B,
+                                  options: &DecodeOptions) -> Result<DynamicMessage, DecodeError>
+    where B: IntoBuf {
+        let mut message = DynamicMessage::new(descriptor);
+        message.merge_with_options(buf, options)?;
+        Ok(message)
+    }
+
+    /// Returns the message's type.
+    pub fn descriptor(&self) -> &MessageDescriptor {
+        &self.descriptor
+    }
+
+    /// Returns the fields which were not recognized while decoding the message.
+    pub fn unknown_fields(&self) -> &UnknownFields {
+        &self.unknown_fields
+    }
+
+    /// Returns an iterator over the fields which are set, in field number order.
+    pub fn fields<'a>(&'a self) -> impl Iterator<Item=(FieldDescriptor, &'a Value)> + 'a {
+        self.fields.iter().filter_map(move |(&number, value)| {
+            self.descriptor.get_field(number).map(|field| (field, value))
+        })
+    }
+
+    /// Returns `true` if the field is set.
+    pub fn has_field(&self, field: &FieldDescriptor) -> bool {
+        self.fields.contains_key(&field.number())
+    }
+
+    /// Returns the value of the field, or its default value if it is not set.
+    pub fn get_field<'a>(&'a self, field: &FieldDescriptor) -> Cow<'a, Value> {
+        match self.fields.get(&field.number()) {
+            Some(value) => Cow::Borrowed(value),
+            None => Cow::Owned(Value::default_value(field)),
+        }
+    }
+
+    /// Returns a mutable reference to the value of the field, setting it to its default value
+    /// first if it is not set.
+    pub fn get_field_mut(&mut self, field: &FieldDescriptor) -> &mut Value {
+        if !self.fields.contains_key(&field.number()) {
+            self.clear_oneof(field);
+        }
+        self.fields.entry(field.number()).or_insert_with(|| Value::default_value(field))
+    }
+
+    /// Sets the value of the field.
+    ///
+    /// Setting a member of a oneof clears the other members of the oneof.
+    pub fn set_field(&mut self, field: &FieldDescriptor, value: Value) -> Result<(), SetFieldError> {
+        if !value.is_valid_for_field(field) {
+            return Err(SetFieldError::InvalidType { field: field.clone(), value });
+        }
+        self.clear_oneof(field);
+        self.fields.insert(field.number(), value);
+        Ok(())
+    }
+
+    /// Clears the field, returning its value if it was set.
+    pub fn clear_field(&mut self, field: &FieldDescriptor) -> Option<Value> {
+        self.fields.remove(&field.number())
+    }
+
+    /// Returns `true` if the field with the provided number is set.
+    pub fn has_field_by_number(&self, number: u32) -> bool {
+        self.fields.contains_key(&number)
+    }
+
+    /// Returns the value of the field with the provided number, or `None` if the message type
+    /// has no such field.
+    pub fn get_field_by_number<'a>(&'a self, number: u32) -> Option<Cow<'a, Value>> {
+        self.descriptor.get_field(number).map(|field| self.get_field(&field))
+    }
+
+    /// Returns a mutable reference to the value of the field with the provided number, or `None`
+    /// if the message type has no such field.
+    pub fn get_field_by_number_mut(&mut self, number: u32) -> Option<&mut Value> {
+        match self.descriptor.get_field(number) {
+            Some(field) => Some(self.get_field_mut(&field)),
+            None => None,
+        }
+    }
+
+    /// Sets the value of the field with the provided number.
+    pub fn set_field_by_number(&mut self, number: u32, value: Value) -> Result<(), SetFieldError> {
+        match self.descriptor.get_field(number) {
+            Some(field) => self.set_field(&field, value),
+            None => Err(SetFieldError::NotFound),
+        }
+    }
+
+    /// Clears the field with the provided number, returning its value if it was set.
+    pub fn clear_field_by_number(&mut self, number: u32) -> Option<Value> {
+        self.fields.remove(&number)
+    }
+
+    /// Returns `true` if the field with the provided name is set.
+    pub fn has_field_by_name(&self, name: &str) -> bool {
+        self.descriptor.get_field_by_name(name).map_or(false, |field| self.has_field(&field))
+    }
+
+    /// Returns the value of the field with the provided name, or `None` if the message type has
+    /// no such field.
+    pub fn get_field_by_name<'a>(&'a self, name: &str) -> Option<Cow<'a, Value>> {
+        self.descriptor.get_field_by_name(name).map(|field| self.get_field(&field))
+    }
+
+    /// Returns a mutable reference to the value of the field with the provided name, or `None`
+    /// if the message type has no such field.
+    pub fn get_field_by_name_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match self.descriptor.get_field_by_name(name) {
+            Some(field) => Some(self.get_field_mut(&field)),
+            None => None,
+        }
+    }
+
+    /// Sets the value of the field with the provided name.
+    pub fn set_field_by_name(&mut self, name: &str, value: Value) -> Result<(), SetFieldError> {
+        match self.descriptor.get_field_by_name(name) {
+            Some(field) => self.set_field(&field, value),
+            None => Err(SetFieldError::NotFound),
+        }
+    }
+
+    /// Clears the field with the provided name, returning its value if it was set.
+    pub fn clear_field_by_name(&mut self, name: &str) -> Option<Value> {
+        match self.descriptor.get_field_by_name(name) {
+            Some(field) => self.clear_field(&field),
+            None => None,
+        }
+    }
+
+    /// Clears the members of the oneof containing the field, if any.
+    fn clear_oneof(&mut self, field: &FieldDescriptor) {
+        if let Some(oneof_index) = field.oneof_index() {
+            let members = self.descriptor
+                              .fields()
+                              .filter(|member| member.oneof_index() == Some(oneof_index))
+                              .map(|member| member.number())
+                              .collect::<Vec<_>>();
+            for number in members {
+                self.fields.remove(&number);
+            }
+        }
+    }
+
+    /// Returns `true` if the field should be skipped when encoding: fields without presence
+    /// which are set to their default value are not emitted.
+    fn skip_field(&self, field: &FieldInfo, value: &Value) -> bool {
+        let implicit_presence = self.descriptor.is_proto3()
+            && field.oneof_index.is_none()
+            && field.cardinality != Cardinality::Repeated;
+        match *value {
+            Value::Message(_) => false,
+            Value::List(ref values) => values.is_empty(),
+            Value::Map(ref values) => values.is_empty(),
+            ref value => implicit_presence && value.is_zero(),
+        }
+    }
+
+    fn take_entry_field(&mut self, number: u32) -> Value {
+        match self.fields.remove(&number) {
+            Some(value) => value,
+            None => {
+                let field = self.descriptor.get_field(number).expect("invalid map entry type");
+                Value::default_value(&field)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for DynamicMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut builder = f.debug_struct(self.descriptor.name());
+        for (field, value) in self.fields() {
+            builder.field(field.name(), value);
+        }
+        if !self.unknown_fields.is_empty() {
+            builder.field("unknown_fields", &self.unknown_fields);
+        }
+        builder.finish()
+    }
+}
+
+impl Message for DynamicMessage {
+    fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        let mut cache = SizeCache::new();
+        self.cache_sizes(&mut cache);
+        self.encode_cached(buf, &mut cache);
+    }
+
+    fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+        self.fields.iter().map(|(&number, value)| {
+            let field = match self.descriptor.field_index(number) {
+                Some(index) => self.descriptor.field_info(index),
+                None => return 0,
+            };
+            if self.skip_field(field, value) {
+                0
+            } else {
+                cached_len_field(&self.descriptor, field, value, cache)
+            }
+        }).sum::<usize>() + self.unknown_fields.encoded_len()
+    }
+
+    fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        for (&number, value) in &self.fields {
+            let field = match self.descriptor.field_index(number) {
+                Some(index) => self.descriptor.field_info(index),
+                None => continue,
+            };
+            if !self.skip_field(field, value) {
+                encode_field(&self.descriptor, field, value, buf, cache);
+            }
+        }
+        self.unknown_fields.encode_raw(buf);
+    }
+
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        let field = match self.descriptor.get_field(tag) {
+            Some(field) => field,
+            None => return self.unknown_fields.merge_field(tag, wire_type, buf, ctx),
+        };
+        let info = field.info();
+
+        if info.map {
+            let entry_descriptor = match info.kind {
+                KindIndex::Message(index) => self.descriptor.message(index),
+                _ => unreachable!(),
+            };
+            let mut entry = DynamicMessage::new(entry_descriptor);
+            message::merge(wire_type, &mut entry, buf, ctx)?;
+            let key = entry.take_entry_field(1).into_map_key()
+                           .ok_or_else(|| DecodeError::new("invalid map key type"))?;
+            let value = entry.take_entry_field(2);
+            if let Value::Map(ref mut map) = *self.get_field_mut(&field) {
+                map.insert(key, value);
+            }
+            Ok(())
+        } else if info.cardinality == Cardinality::Repeated {
+            let descriptor = self.descriptor.clone();
+            let values = match *self.get_field_mut(&field) {
+                Value::List(ref mut values) => values,
+                _ => unreachable!(),
+            };
+            if wire_type == WireType::LengthDelimited && is_packable(info.kind) {
+                merge_loop(values, buf, ctx, |values, buf, ctx| {
+                    let mut value = default_scalar(info.kind);
+                    merge_value(&descriptor, info, tag, scalar_wire_type(info.kind),
+                                &mut value, buf, ctx)?;
+                    values.push(value);
+                    Ok(())
+                })
+            } else {
+                let mut value = match info.kind {
+                    KindIndex::Message(index) => {
+                        Value::Message(DynamicMessage::new(descriptor.message(index)))
+                    },
+                    kind => default_scalar(kind),
+                };
+                merge_value(&descriptor, info, tag, wire_type, &mut value, buf, ctx)?;
+                values.push(value);
+                Ok(())
+            }
+        } else {
+            let descriptor = self.descriptor.clone();
+            let value = self.get_field_mut(&field);
+            merge_value(&descriptor, info, tag, wire_type, value, buf, ctx)
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.fields.iter().map(|(&number, value)| {
+            let field = match self.descriptor.field_index(number) {
+                Some(index) => self.descriptor.field_info(index),
+                None => return 0,
+            };
+            if self.skip_field(field, value) {
+                0
+            } else {
+                encoded_len_field(&self.descriptor, field, value)
+            }
+        }).sum::<usize>() + self.unknown_fields.encoded_len()
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+fn is_packable(kind: KindIndex) -> bool {
+    match kind {
+        KindIndex::String | KindIndex::Bytes | KindIndex::Message(_) => false,
+        _ => true,
+    }
+}
+
+fn scalar_wire_type(kind: KindIndex) -> WireType {
+    match kind {
+        KindIndex::Double | KindIndex::Fixed64 | KindIndex::Sfixed64 => WireType::SixtyFourBit,
+        KindIndex::Float | KindIndex::Fixed32 | KindIndex::Sfixed32 => WireType::ThirtyTwoBit,
+        KindIndex::String | KindIndex::Bytes | KindIndex::Message(_) => WireType::LengthDelimited,
+        _ => WireType::Varint,
+    }
+}
+
+/// Returns the zero value of a non-message field type.
+fn default_scalar(kind: KindIndex) -> Value {
+    match kind {
+        KindIndex::Double => Value::F64(0.0),
+        KindIndex::Float => Value::F32(0.0),
+        KindIndex::Int32 | KindIndex::Sint32 | KindIndex::Sfixed32 => Value::I32(0),
+        KindIndex::Int64 | KindIndex::Sint64 | KindIndex::Sfixed64 => Value::I64(0),
+        KindIndex::Uint32 | KindIndex::Fixed32 => Value::U32(0),
+        KindIndex::Uint64 | KindIndex::Fixed64 => Value::U64(0),
+        KindIndex::Bool => Value::Bool(false),
+        KindIndex::String => Value::String(String::new()),
+        KindIndex::Bytes => Value::Bytes(Vec::new()),
+        KindIndex::Enum(_) => Value::EnumNumber(0),
+        KindIndex::Message(_) => panic!("message fields have no scalar default"),
+    }
+}
+
+/// Decodes a single value of the field from the buffer, and merges it into `value`.
+fn merge_value<B>(descriptor: &MessageDescriptor,
+                  field: &FieldInfo,
+                  tag: u32,
+                  wire_type: WireType,
+                  value: &mut Value,
+                  buf: &mut B,
+                  ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+    match (field.kind, value) {
+        (KindIndex::Double, &mut Value::F64(ref mut value)) => encoding::double::merge(wire_type, value, buf, ctx),
+        (KindIndex::Float, &mut Value::F32(ref mut value)) => encoding::float::merge(wire_type, value, buf, ctx),
+        (KindIndex::Int32, &mut Value::I32(ref mut value)) => encoding::int32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Int64, &mut Value::I64(ref mut value)) => encoding::int64::merge(wire_type, value, buf, ctx),
+        (KindIndex::Uint32, &mut Value::U32(ref mut value)) => encoding::uint32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Uint64, &mut Value::U64(ref mut value)) => encoding::uint64::merge(wire_type, value, buf, ctx),
+        (KindIndex::Sint32, &mut Value::I32(ref mut value)) => encoding::sint32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Sint64, &mut Value::I64(ref mut value)) => encoding::sint64::merge(wire_type, value, buf, ctx),
+        (KindIndex::Fixed32, &mut Value::U32(ref mut value)) => encoding::fixed32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Fixed64, &mut Value::U64(ref mut value)) => encoding::fixed64::merge(wire_type, value, buf, ctx),
+        (KindIndex::Sfixed32, &mut Value::I32(ref mut value)) => encoding::sfixed32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Sfixed64, &mut Value::I64(ref mut value)) => encoding::sfixed64::merge(wire_type, value, buf, ctx),
+        (KindIndex::Bool, &mut Value::Bool(ref mut value)) => encoding::bool::merge(wire_type, value, buf, ctx),
+        (KindIndex::String, &mut Value::String(ref mut value)) => string::merge(wire_type, value, buf, ctx),
+        (KindIndex::Bytes, &mut Value::Bytes(ref mut value)) => bytes::merge(wire_type, value, buf, ctx),
+        (KindIndex::Enum(_), &mut Value::EnumNumber(ref mut value)) => encoding::int32::merge(wire_type, value, buf, ctx),
+        (KindIndex::Message(_), &mut Value::Message(ref mut value)) if field.group => {
+            group::merge(tag, wire_type, value, buf, ctx)
+        },
+        (KindIndex::Message(_), &mut Value::Message(ref mut value)) => {
+            message::merge(wire_type, value, buf, ctx)
+        },
+        (_, value) => {
+            // The value was set to an invalid type through `get_field_mut`; replace it.
+            *value = match field.kind {
+                KindIndex::Message(index) => Value::Message(DynamicMessage::new(descriptor.message(index))),
+                kind => default_scalar(kind),
+            };
+            merge_value(descriptor, field, tag, wire_type, value, buf, ctx)
+        },
+    }
+}
+
+/// Returns the varint representation of a varint-encoded value.
+fn varint_value(kind: KindIndex, value: &Value) -> u64 {
+    match (kind, value) {
+        (KindIndex::Sint32, &Value::I32(value)) => ((value << 1) ^ (value >> 31)) as u32 as u64,
+        (KindIndex::Sint64, &Value::I64(value)) => ((value << 1) ^ (value >> 63)) as u64,
+        (_, &Value::I32(value)) | (_, &Value::EnumNumber(value)) => value as i64 as u64,
+        (_, &Value::I64(value)) => value as u64,
+        (_, &Value::U32(value)) => value as u64,
+        (_, &Value::U64(value)) => value,
+        (_, &Value::Bool(value)) => value as u64,
+        _ => panic!("invalid value {:?} for varint field", value),
+    }
+}
+
+/// Encodes a scalar value without its key.
+fn encode_scalar<B>(kind: KindIndex, value: &Value, buf: &mut B) where B: BufMut {
+    match (kind, value) {
+        (KindIndex::Double, &Value::F64(value)) => buf.put_f64_le(value),
+        (KindIndex::Float, &Value::F32(value)) => buf.put_f32_le(value),
+        (KindIndex::Fixed32, &Value::U32(value)) => buf.put_u32_le(value),
+        (KindIndex::Fixed64, &Value::U64(value)) => buf.put_u64_le(value),
+        (KindIndex::Sfixed32, &Value::I32(value)) => buf.put_i32_le(value),
+        (KindIndex::Sfixed64, &Value::I64(value)) => buf.put_i64_le(value),
+        (kind, value) => encode_varint(varint_value(kind, value), buf),
+    }
+}
+
+/// Returns the encoded length of a scalar value without its key.
+fn encoded_len_scalar(kind: KindIndex, value: &Value) -> usize {
+    match scalar_wire_type(kind) {
+        WireType::SixtyFourBit => 8,
+        WireType::ThirtyTwoBit => 4,
+        _ => encoded_len_varint(varint_value(kind, value)),
+    }
+}
+
+/// Encodes a single value of the field, with its key, taking the lengths of nested messages from
+/// the size cache.
+fn encode_value<B>(field: &FieldInfo,
+                   number: u32,
+                   value: &Value,
+                   buf: &mut B,
+                   cache: &mut SizeCache) where B: BufMut {
+    match *value {
+        Value::String(ref value) => string::encode(number, value, buf),
+        Value::Bytes(ref value) => bytes::encode(number, value, buf),
+        Value::Message(ref value) if field.group => group::encode_cached(number, value, buf, cache),
+        Value::Message(ref value) => message::encode_cached(number, value, buf, cache),
+        ref value => {
+            encode_key(number, scalar_wire_type(field.kind), buf);
+            encode_scalar(field.kind, value, buf);
+        },
+    }
+}
+
+/// Returns the encoded length of a single value of the field, with its key.
+fn encoded_len_value(field: &FieldInfo, number: u32, value: &Value) -> usize {
+    match *value {
+        Value::String(ref value) => string::encoded_len(number, value),
+        Value::Bytes(ref value) => bytes::encoded_len(number, value),
+        Value::Message(ref value) if field.group => group::encoded_len(number, value),
+        Value::Message(ref value) => message::encoded_len(number, value),
+        ref value => key_len(number) + encoded_len_scalar(field.kind, value),
+    }
+}
+
+/// Returns the encoded length of a single value of the field, with its key, recording the lengths
+/// of nested messages in the size cache.
+fn cached_len_value(field: &FieldInfo, number: u32, value: &Value, cache: &mut SizeCache) -> usize {
+    match *value {
+        Value::Message(ref value) if field.group => group::cached_len(number, value, cache),
+        Value::Message(ref value) => message::cached_len(number, value, cache),
+        ref value => encoded_len_value(field, number, value),
+    }
+}
+
+/// Returns the key and value fields of a map field's entry type.
+fn map_entry_fields(descriptor: &MessageDescriptor, field: &FieldInfo) -> (MessageDescriptor, usize, usize) {
+    let entry = match field.kind {
+        KindIndex::Message(index) => descriptor.message(index),
+        _ => unreachable!(),
+    };
+    let key = entry.field_index(1).expect("map entry type has no key field");
+    let value = entry.field_index(2).expect("map entry type has no value field");
+    (entry, key, value)
+}
+
+fn encode_field<B>(descriptor: &MessageDescriptor,
+                   field: &FieldInfo,
+                   value: &Value,
+                   buf: &mut B,
+                   cache: &mut SizeCache) where B: BufMut {
+    match *value {
+        Value::List(ref values) if field.packed => {
+            encode_key(field.number, WireType::LengthDelimited, buf);
+            let len: usize = values.iter().map(|value| encoded_len_scalar(field.kind, value)).sum();
+            encode_varint(len as u64, buf);
+            for value in values {
+                encode_scalar(field.kind, value, buf);
+            }
+        },
+        Value::List(ref values) => {
+            for value in values {
+                encode_value(field, field.number, value, buf, cache);
+            }
+        },
+        Value::Map(ref map) => {
+            let (entry, key_index, value_index) = map_entry_fields(descriptor, field);
+            let key_field = entry.field_info(key_index);
+            let value_field = entry.field_info(value_index);
+            for (key, value) in map {
+                let key = Value::from(key.clone());
+                encode_key(field.number, WireType::LengthDelimited, buf);
+                encode_varint(cache.next_len() as u64, buf);
+                encode_value(key_field, 1, &key, buf, cache);
+                encode_value(value_field, 2, value, buf, cache);
+            }
+        },
+        ref value => encode_value(field, field.number, value, buf, cache),
+    }
+}
+
+fn encoded_len_field(descriptor: &MessageDescriptor, field: &FieldInfo, value: &Value) -> usize {
+    match *value {
+        Value::List(ref values) if field.packed => {
+            let len: usize = values.iter().map(|value| encoded_len_scalar(field.kind, value)).sum();
+            key_len(field.number) + encoded_len_varint(len as u64) + len
+        },
+        Value::List(ref values) => {
+            values.iter().map(|value| encoded_len_value(field, field.number, value)).sum()
+        },
+        Value::Map(ref map) => {
+            let (entry, key_index, value_index) = map_entry_fields(descriptor, field);
+            let key_field = entry.field_info(key_index);
+            let value_field = entry.field_info(value_index);
+            map.iter().map(|(key, value)| {
+                let len = encoded_len_value(key_field, 1, &Value::from(key.clone()))
+                        + encoded_len_value(value_field, 2, value);
+                key_len(field.number) + encoded_len_varint(len as u64) + len
+            }).sum()
+        },
+        ref value => encoded_len_value(field, field.number, value),
+    }
+}
+
+/// Returns the encoded length of the field, recording the lengths of nested messages and map
+/// entries in the size cache.
+fn cached_len_field(descriptor: &MessageDescriptor,
+                    field: &FieldInfo,
+                    value: &Value,
+                    cache: &mut SizeCache) -> usize {
+    match *value {
+        Value::List(_) if field.packed => encoded_len_field(descriptor, field, value),
+        Value::List(ref values) => {
+            values.iter().map(|value| cached_len_value(field, field.number, value, cache)).sum()
+        },
+        Value::Map(ref map) => {
+            let (entry, key_index, value_index) = map_entry_fields(descriptor, field);
+            let key_field = entry.field_info(key_index);
+            let value_field = entry.field_info(value_index);
+            map.iter().map(|(key, value)| {
+                let len = cache.cache_with(|cache| {
+                    encoded_len_value(key_field, 1, &Value::from(key.clone()))
+                        + cached_len_value(value_field, 2, value, cache)
+                });
+                key_len(field.number) + encoded_len_varint(len as u64) + len
+            }).sum()
+        },
+        ref value => cached_len_value(field, field.number, value, cache),
+    }
+}
+
+/// Parses a proto2 `default_value` for a scalar field, as it appears in a
+/// `FieldDescriptorProto`.
+fn parse_default(field: &FieldDescriptor, default: &str) -> Option<Value> {
+    fn parse_float(default: &str) -> Option<f64> {
+        match default {
+            "inf" => Some(::std::f64::INFINITY),
+            "-inf" => Some(::std::f64::NEG_INFINITY),
+            "nan" => Some(::std::f64::NAN),
+            _ => default.parse().ok(),
+        }
+    }
+
+    Some(match field.info().kind {
+        KindIndex::Double => Value::F64(parse_float(default)?),
+        KindIndex::Float => Value::F32(parse_float(default)? as f32),
+        KindIndex::Int32 | KindIndex::Sint32 | KindIndex::Sfixed32 => Value::I32(default.parse().ok()?),
+        KindIndex::Int64 | KindIndex::Sint64 | KindIndex::Sfixed64 => Value::I64(default.parse().ok()?),
+        KindIndex::Uint32 | KindIndex::Fixed32 => Value::U32(default.parse().ok()?),
+        KindIndex::Uint64 | KindIndex::Fixed64 => Value::U64(default.parse().ok()?),
+        KindIndex::Bool => Value::Bool(default.parse().ok()?),
+        KindIndex::String => Value::String(default.to_owned()),
+        KindIndex::Bytes => Value::Bytes(unescape_c_escape_string(default)),
+        KindIndex::Enum(_) => match field.kind() {
+            ::descriptor::Kind::Enum(enum_type) => Value::EnumNumber(enum_type.get_value_number(default)?),
+            _ => unreachable!(),
+        },
+        KindIndex::Message(_) => return None,
+    })
+}
+
+/// Unescapes a C-escaped string, as used for the default values of `bytes` fields.
+fn unescape_c_escape_string(s: &str) -> Vec<u8> {
+    let src = s.as_bytes();
+    let mut dst = Vec::with_capacity(src.len());
+    let mut p = 0;
+    while p < src.len() {
+        if src[p] != b'\\' {
+            dst.push(src[p]);
+            p += 1;
+            continue;
+        }
+        p += 1;
+        if p == src.len() {
+            break;
+        }
+        match src[p] {
+            b'n' => { dst.push(b'\n'); p += 1; },
+            b'r' => { dst.push(b'\r'); p += 1; },
+            b't' => { dst.push(b'\t'); p += 1; },
+            b'x' | b'X' => {
+                p += 1;
+                let mut octet = 0u8;
+                let mut digits = 0;
+                while digits < 2 && p < src.len() && (src[p] as char).is_digit(16) {
+                    octet = octet * 16 + (src[p] as char).to_digit(16).unwrap() as u8;
+                    p += 1;
+                    digits += 1;
+                }
+                dst.push(octet);
+            },
+            b'0'..=b'7' => {
+                let mut octet = 0u8;
+                let mut digits = 0;
+                while digits < 3 && p < src.len() && src[p] >= b'0' && src[p] <= b'7' {
+                    octet = octet.wrapping_mul(8).wrapping_add(src[p] - b'0');
+                    p += 1;
+                    digits += 1;
+                }
+                dst.push(octet);
+            },
+            c => { dst.push(c); p += 1; },
+        }
+    }
+    dst
+}
+
+impl Value {
+
+    /// Returns the default value of the field: an empty list or map for repeated fields, an
+    /// empty message for message fields, and otherwise the field's declared default or the zero
+    /// value of its type.
+    pub fn default_value(field: &FieldDescriptor) -> Value {
+        let info = field.info();
+        if info.map {
+            return Value::Map(BTreeMap::new());
+        }
+        if info.cardinality == Cardinality::Repeated {
+            return Value::List(Vec::new());
+        }
+        if let Some(value) = info.proto.default_value.as_ref().and_then(|default| parse_default(field, default)) {
+            return value;
+        }
+        match field.kind() {
+            ::descriptor::Kind::Message(message) => Value::Message(DynamicMessage::new(message)),
+            ::descriptor::Kind::Enum(enum_type) => Value::EnumNumber(enum_type.default_value()),
+            _ => default_scalar(info.kind),
+        }
+    }
+
+    /// Returns `true` if the value can be set as the value of the field.
+    pub fn is_valid_for_field(&self, field: &FieldDescriptor) -> bool {
+        let info = field.info();
+        match *self {
+            Value::List(ref values) => {
+                field.is_list() && values.iter().all(|value| value.is_valid_for_kind(field))
+            },
+            Value::Map(ref map) => {
+                field.is_map() && match field.kind() {
+                    ::descriptor::Kind::Message(entry) => {
+                        let key = entry.get_field(1).expect("map entry type has no key field");
+                        let value = entry.get_field(2).expect("map entry type has no value field");
+                        map.iter().all(|(k, v)| {
+                            Value::from(k.clone()).is_valid_for_kind(&key) && v.is_valid_for_kind(&value)
+                        })
+                    },
+                    _ => false,
+                }
+            },
+            ref value => info.cardinality != Cardinality::Repeated && value.is_valid_for_kind(field),
+        }
+    }
+
+    /// Returns `true` if the value is a single (not repeated) value of the field's type.
+    fn is_valid_for_kind(&self, field: &FieldDescriptor) -> bool {
+        match (field.info().kind, self) {
+            (KindIndex::Double, &Value::F64(_))
+                | (KindIndex::Float, &Value::F32(_))
+                | (KindIndex::Int32, &Value::I32(_))
+                | (KindIndex::Sint32, &Value::I32(_))
+                | (KindIndex::Sfixed32, &Value::I32(_))
+                | (KindIndex::Int64, &Value::I64(_))
+                | (KindIndex::Sint64, &Value::I64(_))
+                | (KindIndex::Sfixed64, &Value::I64(_))
+                | (KindIndex::Uint32, &Value::U32(_))
+                | (KindIndex::Fixed32, &Value::U32(_))
+                | (KindIndex::Uint64, &Value::U64(_))
+                | (KindIndex::Fixed64, &Value::U64(_))
+                | (KindIndex::Bool, &Value::Bool(_))
+                | (KindIndex::String, &Value::String(_))
+                | (KindIndex::Bytes, &Value::Bytes(_))
+                | (KindIndex::Enum(_), &Value::EnumNumber(_)) => true,
+            (KindIndex::Message(_), &Value::Message(ref message)) => match field.kind() {
+                ::descriptor::Kind::Message(descriptor) => *message.descriptor() == descriptor,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the value is the zero value of its type.
+    pub(crate) fn is_zero(&self) -> bool {
+        match *self {
+            Value::Bool(value) => !value,
+            Value::I32(value) | Value::EnumNumber(value) => value == 0,
+            Value::I64(value) => value == 0,
+            Value::U32(value) => value == 0,
+            Value::U64(value) => value == 0,
+            Value::F32(value) => value == 0.0,
+            Value::F64(value) => value == 0.0,
+            Value::String(ref value) => value.is_empty(),
+            Value::Bytes(ref value) => value.is_empty(),
+            Value::Message(_) => false,
+            Value::List(ref values) => values.is_empty(),
+            Value::Map(ref values) => values.is_empty(),
+        }
+    }
+
+    /// Converts the value to a map key, if it is of a type which can be used as a map key.
+    pub fn into_map_key(self) -> Option<MapKey> {
+        match self {
+            Value::Bool(value) => Some(MapKey::Bool(value)),
+            Value::I32(value) => Some(MapKey::I32(value)),
+            Value::I64(value) => Some(MapKey::I64(value)),
+            Value::U32(value) => Some(MapKey::U32(value)),
+            Value::U64(value) => Some(MapKey::U64(value)),
+            Value::String(value) => Some(MapKey::String(value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a `bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self { Value::Bool(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is an `i32`.
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self { Value::I32(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self { Value::I64(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is a `u32`.
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self { Value::U32(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self { Value::U64(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is an `f32`.
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self { Value::F32(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is an `f64`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self { Value::F64(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Value::String(ref value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is a byte string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self { Value::Bytes(ref value) => Some(value), _ => None }
+    }
+
+    /// Returns the number of the value if it is an enum value.
+    pub fn as_enum_number(&self) -> Option<i32> {
+        match *self { Value::EnumNumber(value) => Some(value), _ => None }
+    }
+
+    /// Returns the value if it is a message.
+    pub fn as_message(&self) -> Option<&DynamicMessage> {
+        match *self { Value::Message(ref value) => Some(value), _ => None }
+    }
+
+    /// Returns a mutable reference to the value if it is a message.
+    pub fn as_message_mut(&mut self) -> Option<&mut DynamicMessage> {
+        match *self { Value::Message(ref mut value) => Some(value), _ => None }
+    }
+
+    /// Returns the values if the value is a list.
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match *self { Value::List(ref values) => Some(values), _ => None }
+    }
+
+    /// Returns a mutable reference to the values if the value is a list.
+    pub fn as_list_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match *self { Value::List(ref mut values) => Some(values), _ => None }
+    }
+
+    /// Returns the entries if the value is a map.
+    pub fn as_map(&self) -> Option<&BTreeMap<MapKey, Value>> {
+        match *self { Value::Map(ref values) => Some(values), _ => None }
+    }
+
+    /// Returns a mutable reference to the entries if the value is a map.
+    pub fn as_map_mut(&mut self) -> Option<&mut BTreeMap<MapKey, Value>> {
+        match *self { Value::Map(ref mut values) => Some(values), _ => None }
+    }
+}
+
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Value {
+        match key {
+            MapKey::Bool(value) => Value::Bool(value),
+            MapKey::I32(value) => Value::I32(value),
+            MapKey::I64(value) => Value::I64(value),
+            MapKey::U32(value) => Value::U32(value),
+            MapKey::U64(value) => Value::U64(value),
+            MapKey::String(value) => Value::String(value),
+        }
+    }
+}
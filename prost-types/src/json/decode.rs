@@ -0,0 +1,543 @@
+//! Conversion of JSON values to dynamic messages.
+
+use std::collections::BTreeMap;
+
+use prost::Message;
+
+use descriptor::{FieldDescriptor, Kind, MessageDescriptor};
+use dynamic::{DynamicMessage, MapKey, Value};
+use json::encode::{has_special_representation, resolve_type_url};
+use json::value::JsonValue;
+use json::{
+    decode_base64,
+    days_from_civil,
+    civil_from_days,
+    JsonError,
+    JsonOptions,
+    MAX_DURATION_SECONDS,
+    MAX_TIMESTAMP_SECONDS,
+    MIN_TIMESTAMP_SECONDS,
+};
+
+pub fn decode_message(descriptor: MessageDescriptor,
+                      json: JsonValue,
+                      options: &JsonOptions) -> Result<DynamicMessage, JsonError> {
+    let mut message = DynamicMessage::new(descriptor.clone());
+    match descriptor.full_name() {
+        "google.protobuf.Any" => decode_any(&mut message, json, options)?,
+        "google.protobuf.Timestamp" => decode_timestamp(&mut message, json)?,
+        "google.protobuf.Duration" => decode_duration(&mut message, json)?,
+        "google.protobuf.FieldMask" => decode_field_mask(&mut message, json)?,
+        "google.protobuf.Struct" => decode_struct(&mut message, json)?,
+        "google.protobuf.ListValue" => decode_list_value(&mut message, json)?,
+        "google.protobuf.Value" => decode_value(&mut message, json)?,
+        "google.protobuf.BoolValue"
+            | "google.protobuf.BytesValue"
+            | "google.protobuf.DoubleValue"
+            | "google.protobuf.FloatValue"
+            | "google.protobuf.Int32Value"
+            | "google.protobuf.Int64Value"
+            | "google.protobuf.StringValue"
+            | "google.protobuf.UInt32Value"
+            | "google.protobuf.UInt64Value" => {
+            let field = descriptor.get_field(1).expect("wrapper type has no value field");
+            if let Some(value) = decode_single(&field, json, options)? {
+                set_field(&mut message, &field, value)?;
+            }
+        },
+        _ => match json {
+            JsonValue::Object(members) => decode_fields(&mut message, members, options)?,
+            json => return Err(unexpected(descriptor.full_name(), "object", &json)),
+        },
+    }
+    Ok(message)
+}
+
+fn unexpected(target: &str, expected: &str, found: &JsonValue) -> JsonError {
+    JsonError::new(format!("invalid value for {}: expected {}, found {}",
+                           target, expected, found.type_name()))
+}
+
+fn set_field(message: &mut DynamicMessage, field: &FieldDescriptor, value: Value) -> Result<(), JsonError> {
+    message.set_field(field, value).map_err(|error| JsonError::new(error.to_string()))
+}
+
+/// Returns `true` if JSON `null` is a value of the field's type, rather than an absent value.
+fn accepts_null(field: &FieldDescriptor) -> bool {
+    match field.kind() {
+        Kind::Message(ref message) => message.full_name() == "google.protobuf.Value",
+        Kind::Enum(ref enum_type) => enum_type.full_name() == "google.protobuf.NullValue",
+        _ => false,
+    }
+}
+
+fn decode_fields(message: &mut DynamicMessage,
+                 members: Vec<(String, JsonValue)>,
+                 options: &JsonOptions) -> Result<(), JsonError> {
+    let descriptor = message.descriptor().clone();
+    let mut seen_fields = Vec::new();
+    let mut seen_oneofs = Vec::new();
+    for (name, json) in members {
+        let field = match descriptor.get_field_by_json_name(&name)
+                                    .or_else(|| descriptor.get_field_by_name(&name)) {
+            Some(field) => field,
+            None if options.ignore_unknown_fields => continue,
+            None => {
+                return Err(JsonError::new(format!("unknown field {} in {}", name, descriptor.full_name())));
+            },
+        };
+        if seen_fields.contains(&field.number()) {
+            return Err(JsonError::new(format!("duplicate field {} in {}", name, descriptor.full_name())));
+        }
+        seen_fields.push(field.number());
+
+        if json == JsonValue::Null && !accepts_null(&field) {
+            continue;
+        }
+        if let Some(oneof_index) = field.oneof_index() {
+            if seen_oneofs.contains(&oneof_index) {
+                return Err(JsonError::new(format!("multiple members of a oneof set in {}",
+                                                  descriptor.full_name())));
+            }
+            seen_oneofs.push(oneof_index);
+        }
+        if let Some(value) = decode_field(&field, json, options)? {
+            set_field(message, &field, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn decode_field(field: &FieldDescriptor,
+                json: JsonValue,
+                options: &JsonOptions) -> Result<Option<Value>, JsonError> {
+    if field.is_map() {
+        let entry = match field.kind() {
+            Kind::Message(entry) => entry,
+            _ => panic!("map field {} is not a message", field.name()),
+        };
+        let key_field = entry.get_field(1).expect("map entry type has no key field");
+        let value_field = entry.get_field(2).expect("map entry type has no value field");
+        let members = match json {
+            JsonValue::Object(members) => members,
+            json => return Err(unexpected(field.name(), "object", &json)),
+        };
+        let mut map = BTreeMap::new();
+        for (key, json) in members {
+            if json == JsonValue::Null && !accepts_null(&value_field) {
+                return Err(unexpected(field.name(), "map value", &json));
+            }
+            let key = decode_map_key(&key_field, &key)?;
+            if let Some(value) = decode_single(&value_field, json, options)? {
+                map.insert(key, value);
+            }
+        }
+        Ok(Some(Value::Map(map)))
+    } else if field.is_list() {
+        let values = match json {
+            JsonValue::Array(values) => values,
+            json => return Err(unexpected(field.name(), "array", &json)),
+        };
+        let mut list = Vec::with_capacity(values.len());
+        for json in values {
+            if json == JsonValue::Null && !accepts_null(field) {
+                return Err(unexpected(field.name(), "list element", &json));
+            }
+            if let Some(value) = decode_single(field, json, options)? {
+                list.push(value);
+            }
+        }
+        Ok(Some(Value::List(list)))
+    } else {
+        decode_single(field, json, options)
+    }
+}
+
+fn decode_map_key(field: &FieldDescriptor, key: &str) -> Result<MapKey, JsonError> {
+    let json = JsonValue::String(key.to_owned());
+    Ok(match field.kind() {
+        Kind::Bool => match key {
+            "true" => MapKey::Bool(true),
+            "false" => MapKey::Bool(false),
+            _ => return Err(JsonError::new(format!("invalid bool map key {}", key))),
+        },
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+            MapKey::I32(decode_i64(&json, i64::from(i32::min_value()), i64::from(i32::max_value()))? as i32)
+        },
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            MapKey::I64(decode_i64(&json, i64::min_value(), i64::max_value())?)
+        },
+        Kind::Uint32 | Kind::Fixed32 => MapKey::U32(decode_u64(&json, u64::from(u32::max_value()))? as u32),
+        Kind::Uint64 | Kind::Fixed64 => MapKey::U64(decode_u64(&json, u64::max_value())?),
+        Kind::String => MapKey::String(key.to_owned()),
+        _ => return Err(JsonError::new(format!("invalid map key type for field {}", field.name()))),
+    })
+}
+
+/// Decodes a single (not repeated) value of the field's type.
+///
+/// Returns `None` if the value is an unknown enum value name which is ignored.
+fn decode_single(field: &FieldDescriptor,
+                 json: JsonValue,
+                 options: &JsonOptions) -> Result<Option<Value>, JsonError> {
+    let value = match field.kind() {
+        Kind::Double => Value::F64(decode_f64(&json)?),
+        Kind::Float => Value::F32(decode_f32(&json)?),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+            Value::I32(decode_i64(&json, i64::from(i32::min_value()), i64::from(i32::max_value()))? as i32)
+        },
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            Value::I64(decode_i64(&json, i64::min_value(), i64::max_value())?)
+        },
+        Kind::Uint32 | Kind::Fixed32 => Value::U32(decode_u64(&json, u64::from(u32::max_value()))? as u32),
+        Kind::Uint64 | Kind::Fixed64 => Value::U64(decode_u64(&json, u64::max_value())?),
+        Kind::Bool => match json {
+            JsonValue::Bool(value) => Value::Bool(value),
+            json => return Err(unexpected(field.name(), "boolean", &json)),
+        },
+        Kind::String => match json {
+            JsonValue::String(value) => Value::String(value),
+            json => return Err(unexpected(field.name(), "string", &json)),
+        },
+        Kind::Bytes => match json {
+            JsonValue::String(value) => Value::Bytes(decode_base64(&value)?),
+            json => return Err(unexpected(field.name(), "base64 string", &json)),
+        },
+        Kind::Enum(enum_type) => match json {
+            JsonValue::Null if enum_type.full_name() == "google.protobuf.NullValue" => Value::EnumNumber(0),
+            JsonValue::String(name) => match enum_type.get_value_number(&name) {
+                Some(number) => Value::EnumNumber(number),
+                None if options.ignore_unknown_fields => return Ok(None),
+                None => {
+                    return Err(JsonError::new(format!("unknown value {} of enum {}",
+                                                      name, enum_type.full_name())));
+                },
+            },
+            json @ JsonValue::Number(_) => {
+                Value::EnumNumber(decode_i64(&json, i64::from(i32::min_value()), i64::from(i32::max_value()))? as i32)
+            },
+            json => return Err(unexpected(field.name(), "enum value", &json)),
+        },
+        Kind::Message(descriptor) => Value::Message(decode_message(descriptor, json, options)?),
+    };
+    Ok(Some(value))
+}
+
+/// Returns the text of a number, or of a string which holds a number.
+fn number_text(json: &JsonValue) -> Option<String> {
+    match *json {
+        JsonValue::Number(ref text) => Some(text.clone()),
+        JsonValue::String(ref text) => match JsonValue::parse(text) {
+            Ok(JsonValue::Number(number)) if number.len() == text.len() => Some(number),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn decode_i64(json: &JsonValue, min: i64, max: i64) -> Result<i64, JsonError> {
+    let text = number_text(json).ok_or_else(|| unexpected("integer field", "integer", json))?;
+    let value = match text.parse::<i64>() {
+        Ok(value) => value,
+        Err(_) => {
+            // Integers may also be written with a fraction or an exponent.
+            let value = text.parse::<f64>().map_err(|_| JsonError::new(format!("invalid integer {}", text)))?;
+            if value.fract() != 0.0 || value < -9_223_372_036_854_775_808.0 || value >= 9_223_372_036_854_775_808.0 {
+                return Err(JsonError::new(format!("invalid integer {}", text)));
+            }
+            value as i64
+        },
+    };
+    if value < min || value > max {
+        return Err(JsonError::new(format!("integer {} out of range", text)));
+    }
+    Ok(value)
+}
+
+fn decode_u64(json: &JsonValue, max: u64) -> Result<u64, JsonError> {
+    let text = number_text(json).ok_or_else(|| unexpected("integer field", "integer", json))?;
+    let value = match text.parse::<u64>() {
+        Ok(value) => value,
+        Err(_) => {
+            let value = text.parse::<f64>().map_err(|_| JsonError::new(format!("invalid integer {}", text)))?;
+            if value.fract() != 0.0 || value < 0.0 || value >= 18_446_744_073_709_551_616.0 {
+                return Err(JsonError::new(format!("invalid integer {}", text)));
+            }
+            value as u64
+        },
+    };
+    if value > max {
+        return Err(JsonError::new(format!("integer {} out of range", text)));
+    }
+    Ok(value)
+}
+
+/// Returns the special floating point value named by a string, if any.
+fn special_float(json: &JsonValue) -> Option<f64> {
+    match *json {
+        JsonValue::String(ref text) => match &text[..] {
+            "NaN" => Some(::std::f64::NAN),
+            "Infinity" => Some(::std::f64::INFINITY),
+            "-Infinity" => Some(::std::f64::NEG_INFINITY),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn decode_f64(json: &JsonValue) -> Result<f64, JsonError> {
+    if let Some(value) = special_float(json) {
+        return Ok(value);
+    }
+    let text = number_text(json).ok_or_else(|| unexpected("double field", "number", json))?;
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(JsonError::new(format!("double {} out of range", text))),
+    }
+}
+
+fn decode_f32(json: &JsonValue) -> Result<f32, JsonError> {
+    if let Some(value) = special_float(json) {
+        return Ok(value as f32);
+    }
+    let text = number_text(json).ok_or_else(|| unexpected("float field", "number", json))?;
+    match text.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(JsonError::new(format!("float {} out of range", text))),
+    }
+}
+
+/// Returns the message type of a message field of a well-known type.
+fn message_field(descriptor: &MessageDescriptor, number: u32) -> MessageDescriptor {
+    let field = descriptor.get_field(number).expect("well-known type field not found");
+    match field.kind() {
+        Kind::Message(message) => message,
+        _ => panic!("well-known type field {} is not a message", field.name()),
+    }
+}
+
+fn expect_string(target: &str, json: JsonValue) -> Result<String, JsonError> {
+    match json {
+        JsonValue::String(value) => Ok(value),
+        json => Err(unexpected(target, "string", &json)),
+    }
+}
+
+/// Parses exactly `len` ASCII digits at the start of `input`.
+fn parse_digits(input: &str, len: usize) -> Option<u32> {
+    if input.len() < len || !input.as_bytes()[..len].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    input[..len].parse().ok()
+}
+
+/// Parses an optional fraction of a second, with 1 to 9 digits, returning the nanoseconds and
+/// the remaining input.
+fn parse_nanos(input: &str) -> Option<(i32, &str)> {
+    if !input.starts_with('.') {
+        return Some((0, input));
+    }
+    let digits = input[1..].bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let nanos = input[1..=digits].parse::<i32>().ok()? * 10i32.pow(9 - digits as u32);
+    Some((nanos, &input[1 + digits..]))
+}
+
+/// Parses an RFC 3339 timestamp, for example `1972-01-01T10:00:20.021Z`.
+fn parse_timestamp(input: &str) -> Option<(i64, i32)> {
+    let bytes = input.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'T'
+        || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let year = parse_digits(input, 4)?;
+    let month = parse_digits(&input[5..], 2)?;
+    let day = parse_digits(&input[8..], 2)?;
+    let hour = parse_digits(&input[11..], 2)?;
+    let minute = parse_digits(&input[14..], 2)?;
+    let second = parse_digits(&input[17..], 2)?;
+    if month < 1 || month > 12 || day < 1 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(i64::from(year), month, day);
+    if civil_from_days(days) != (i64::from(year), month, day) {
+        // The day is past the end of the month.
+        return None;
+    }
+
+    let (nanos, rest) = parse_nanos(&input[19..])?;
+    let offset = match rest.as_bytes().first() {
+        Some(&b'Z') if rest.len() == 1 => 0,
+        Some(&sign) if (sign == b'+' || sign == b'-') && rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let hours = parse_digits(&rest[1..], 2)?;
+            let minutes = parse_digits(&rest[4..], 2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = i64::from(hours * 3600 + minutes * 60);
+            if sign == b'-' { -offset } else { offset }
+        },
+        _ => return None,
+    };
+
+    let seconds = days * 86_400
+                + i64::from(hour * 3600 + minute * 60 + second)
+                - offset;
+    Some((seconds, nanos))
+}
+
+fn decode_timestamp(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let text = expect_string("google.protobuf.Timestamp", json)?;
+    let (seconds, nanos) = match parse_timestamp(&text) {
+        Some(timestamp) => timestamp,
+        None => return Err(JsonError::new(format!("invalid Timestamp {}", text))),
+    };
+    if seconds < MIN_TIMESTAMP_SECONDS || seconds > MAX_TIMESTAMP_SECONDS {
+        return Err(JsonError::new(format!("Timestamp {} out of range", text)));
+    }
+    message.set_field_by_number(1, Value::I64(seconds)).expect("invalid Timestamp type");
+    message.set_field_by_number(2, Value::I32(nanos)).expect("invalid Timestamp type");
+    Ok(())
+}
+
+/// Parses a duration in seconds with an `s` suffix, for example `-1.5s`.
+fn parse_duration(input: &str) -> Option<(i64, i32)> {
+    if !input.ends_with('s') {
+        return None;
+    }
+    let (negative, input) = if input.starts_with('-') {
+        (true, &input[1..input.len() - 1])
+    } else {
+        (false, &input[..input.len() - 1])
+    };
+    let digits = input.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return None;
+    }
+    let seconds = input[..digits].parse::<i64>().ok()?;
+    let (nanos, rest) = parse_nanos(&input[digits..])?;
+    if !rest.is_empty() || seconds > MAX_DURATION_SECONDS {
+        return None;
+    }
+    Some(if negative { (-seconds, -nanos) } else { (seconds, nanos) })
+}
+
+fn decode_duration(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let text = expect_string("google.protobuf.Duration", json)?;
+    let (seconds, nanos) = match parse_duration(&text) {
+        Some(duration) => duration,
+        None => return Err(JsonError::new(format!("invalid Duration {}", text))),
+    };
+    message.set_field_by_number(1, Value::I64(seconds)).expect("invalid Duration type");
+    message.set_field_by_number(2, Value::I32(nanos)).expect("invalid Duration type");
+    Ok(())
+}
+
+fn decode_field_mask(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let text = expect_string("google.protobuf.FieldMask", json)?;
+    let mut paths = Vec::new();
+    for json_path in text.split(',').filter(|path| !path.is_empty()) {
+        let mut path = String::with_capacity(json_path.len() + 4);
+        for c in json_path.chars() {
+            if c == '_' {
+                return Err(JsonError::new(format!("invalid FieldMask path {}", json_path)));
+            }
+            if c.is_ascii_uppercase() {
+                path.push('_');
+                path.push(c.to_ascii_lowercase());
+            } else {
+                path.push(c);
+            }
+        }
+        paths.push(Value::String(path));
+    }
+    message.set_field_by_number(1, Value::List(paths)).expect("invalid FieldMask type");
+    Ok(())
+}
+
+fn decode_struct(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let members = match json {
+        JsonValue::Object(members) => members,
+        json => return Err(unexpected("google.protobuf.Struct", "object", &json)),
+    };
+    let value_type = message_field(&message_field(message.descriptor(), 1), 2);
+    let mut fields = BTreeMap::new();
+    for (key, json) in members {
+        let mut value = DynamicMessage::new(value_type.clone());
+        decode_value(&mut value, json)?;
+        fields.insert(MapKey::String(key), Value::Message(value));
+    }
+    message.set_field_by_number(1, Value::Map(fields)).expect("invalid Struct type");
+    Ok(())
+}
+
+fn decode_list_value(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let values = match json {
+        JsonValue::Array(values) => values,
+        json => return Err(unexpected("google.protobuf.ListValue", "array", &json)),
+    };
+    let value_type = message_field(message.descriptor(), 1);
+    let mut list = Vec::with_capacity(values.len());
+    for json in values {
+        let mut value = DynamicMessage::new(value_type.clone());
+        decode_value(&mut value, json)?;
+        list.push(Value::Message(value));
+    }
+    message.set_field_by_number(1, Value::List(list)).expect("invalid ListValue type");
+    Ok(())
+}
+
+fn decode_value(message: &mut DynamicMessage, json: JsonValue) -> Result<(), JsonError> {
+    let (number, value) = match json {
+        JsonValue::Null => (1, Value::EnumNumber(0)),
+        json @ JsonValue::Number(_) => (2, Value::F64(decode_f64(&json)?)),
+        JsonValue::String(value) => (3, Value::String(value)),
+        JsonValue::Bool(value) => (4, Value::Bool(value)),
+        json @ JsonValue::Object(_) => {
+            let mut value = DynamicMessage::new(message_field(message.descriptor(), 5));
+            decode_struct(&mut value, json)?;
+            (5, Value::Message(value))
+        },
+        json @ JsonValue::Array(_) => {
+            let mut value = DynamicMessage::new(message_field(message.descriptor(), 6));
+            decode_list_value(&mut value, json)?;
+            (6, Value::Message(value))
+        },
+    };
+    message.set_field_by_number(number, value).expect("invalid Value type");
+    Ok(())
+}
+
+fn decode_any(message: &mut DynamicMessage, json: JsonValue, options: &JsonOptions) -> Result<(), JsonError> {
+    let mut members = match json {
+        JsonValue::Object(members) => members,
+        json => return Err(unexpected("google.protobuf.Any", "object", &json)),
+    };
+    if members.is_empty() {
+        return Ok(());
+    }
+    let type_url = match members.iter().position(|&(ref key, _)| key == "@type") {
+        Some(index) => expect_string("google.protobuf.Any @type", members.remove(index).1)?,
+        None => return Err(JsonError::new("Any is missing @type")),
+    };
+
+    let descriptor = resolve_type_url(message.descriptor(), &type_url)?;
+    let json = if has_special_representation(descriptor.full_name()) {
+        match members.iter().position(|&(ref key, _)| key == "value") {
+            Some(index) if members.len() == 1 => members.remove(index).1,
+            _ => return Err(JsonError::new(format!("Any of type {} must have only a value member",
+                                                   type_url))),
+        }
+    } else {
+        JsonValue::Object(members)
+    };
+    let value = decode_message(descriptor, json, options)?;
+    let mut buf = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut buf).expect("insufficient buffer capacity");
+
+    message.set_field_by_number(1, Value::String(type_url)).expect("invalid Any type");
+    message.set_field_by_number(2, Value::Bytes(buf)).expect("invalid Any type");
+    Ok(())
+}
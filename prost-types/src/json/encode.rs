@@ -0,0 +1,308 @@
+//! Conversion of dynamic messages to JSON values.
+
+use descriptor::{FieldDescriptor, Kind, MessageDescriptor};
+use dynamic::{DynamicMessage, MapKey, Value};
+use json::value::JsonValue;
+use json::{
+    civil_from_days,
+    encode_base64,
+    JsonError,
+    MAX_DURATION_SECONDS,
+    MAX_TIMESTAMP_SECONDS,
+    MIN_TIMESTAMP_SECONDS,
+};
+
+pub fn encode_message(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    match message.descriptor().full_name() {
+        "google.protobuf.Any" => encode_any(message),
+        "google.protobuf.Timestamp" => encode_timestamp(message),
+        "google.protobuf.Duration" => encode_duration(message),
+        "google.protobuf.FieldMask" => encode_field_mask(message),
+        "google.protobuf.Struct" => encode_struct(message),
+        "google.protobuf.ListValue" => encode_list_value(message),
+        "google.protobuf.Value" => encode_value(message),
+        "google.protobuf.BoolValue"
+            | "google.protobuf.BytesValue"
+            | "google.protobuf.DoubleValue"
+            | "google.protobuf.FloatValue"
+            | "google.protobuf.Int32Value"
+            | "google.protobuf.Int64Value"
+            | "google.protobuf.StringValue"
+            | "google.protobuf.UInt32Value"
+            | "google.protobuf.UInt64Value" => {
+            let field = message.descriptor().get_field(1).expect("wrapper type has no value field");
+            encode_single(&field, &message.get_field(&field))
+        },
+        _ => encode_fields(message).map(JsonValue::Object),
+    }
+}
+
+/// Encodes the fields of a message as object members, skipping fields without presence which
+/// are set to their default value.
+fn encode_fields(message: &DynamicMessage) -> Result<Vec<(String, JsonValue)>, JsonError> {
+    let mut members = Vec::new();
+    for field in message.descriptor().fields() {
+        if !message.has_field(&field) {
+            continue;
+        }
+        let value = message.get_field(&field);
+        let skip = match *value {
+            Value::List(ref values) => values.is_empty(),
+            Value::Map(ref values) => values.is_empty(),
+            ref value => !field.supports_presence() && value.is_zero(),
+        };
+        if !skip {
+            members.push((field.json_name().to_owned(), encode_field(&field, &value)?));
+        }
+    }
+    Ok(members)
+}
+
+fn encode_field(field: &FieldDescriptor, value: &Value) -> Result<JsonValue, JsonError> {
+    match *value {
+        Value::List(ref values) => {
+            values.iter()
+                  .map(|value| encode_single(field, value))
+                  .collect::<Result<Vec<_>, _>>()
+                  .map(JsonValue::Array)
+        },
+        Value::Map(ref entries) => {
+            let value_field = match field.kind() {
+                Kind::Message(entry) => entry.get_field(2).expect("map entry type has no value field"),
+                _ => panic!("map field {} is not a message", field.name()),
+            };
+            let mut members = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = match *key {
+                    MapKey::Bool(key) => key.to_string(),
+                    MapKey::I32(key) => key.to_string(),
+                    MapKey::I64(key) => key.to_string(),
+                    MapKey::U32(key) => key.to_string(),
+                    MapKey::U64(key) => key.to_string(),
+                    MapKey::String(ref key) => key.clone(),
+                };
+                members.push((key, encode_single(&value_field, value)?));
+            }
+            Ok(JsonValue::Object(members))
+        },
+        ref value => encode_single(field, value),
+    }
+}
+
+/// Encodes a single (not repeated) value of the field's type.
+fn encode_single(field: &FieldDescriptor, value: &Value) -> Result<JsonValue, JsonError> {
+    Ok(match *value {
+        Value::Bool(value) => JsonValue::Bool(value),
+        Value::I32(value) => JsonValue::Number(value.to_string()),
+        Value::U32(value) => JsonValue::Number(value.to_string()),
+        Value::I64(value) => JsonValue::String(value.to_string()),
+        Value::U64(value) => JsonValue::String(value.to_string()),
+        Value::F32(value) => encode_float(value.is_nan(), value.is_infinite(), value.to_string()),
+        Value::F64(value) => encode_float(value.is_nan(), value.is_infinite(), value.to_string()),
+        Value::String(ref value) => JsonValue::String(value.clone()),
+        Value::Bytes(ref value) => JsonValue::String(encode_base64(value)),
+        Value::EnumNumber(number) => match field.kind() {
+            Kind::Enum(ref enum_type) if enum_type.full_name() == "google.protobuf.NullValue" => {
+                JsonValue::Null
+            },
+            Kind::Enum(ref enum_type) => match enum_type.get_value_name(number) {
+                Some(name) => JsonValue::String(name.to_owned()),
+                None => JsonValue::Number(number.to_string()),
+            },
+            _ => JsonValue::Number(number.to_string()),
+        },
+        Value::Message(ref message) => encode_message(message)?,
+        Value::List(_) | Value::Map(_) => {
+            return Err(JsonError::new(format!("invalid nested collection in field {}", field.name())));
+        },
+    })
+}
+
+fn encode_float(is_nan: bool, is_infinite: bool, value: String) -> JsonValue {
+    if is_nan {
+        JsonValue::String("NaN".to_owned())
+    } else if is_infinite {
+        JsonValue::String(if value.starts_with('-') { "-Infinity" } else { "Infinity" }.to_owned())
+    } else {
+        JsonValue::Number(value)
+    }
+}
+
+fn get_i64(message: &DynamicMessage, number: u32) -> i64 {
+    message.get_field_by_number(number).and_then(|value| value.as_i64()).unwrap_or(0)
+}
+
+fn get_i32(message: &DynamicMessage, number: u32) -> i32 {
+    message.get_field_by_number(number).and_then(|value| value.as_i32()).unwrap_or(0)
+}
+
+/// Formats nanoseconds as a fraction with 0, 3, 6 or 9 digits.
+fn format_nanos(nanos: i32) -> String {
+    if nanos == 0 {
+        String::new()
+    } else if nanos % 1_000_000 == 0 {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    }
+}
+
+fn encode_timestamp(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let seconds = get_i64(message, 1);
+    let nanos = get_i32(message, 2);
+    if seconds < MIN_TIMESTAMP_SECONDS || seconds > MAX_TIMESTAMP_SECONDS || nanos < 0 || nanos > 999_999_999 {
+        return Err(JsonError::new("Timestamp out of range"));
+    }
+    let days = if seconds >= 0 { seconds / 86_400 } else { (seconds - 86_399) / 86_400 };
+    let second_of_day = seconds - days * 86_400;
+    let (year, month, day) = civil_from_days(days);
+    Ok(JsonValue::String(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+                                 year,
+                                 month,
+                                 day,
+                                 second_of_day / 3600,
+                                 second_of_day / 60 % 60,
+                                 second_of_day % 60,
+                                 format_nanos(nanos))))
+}
+
+fn encode_duration(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let seconds = get_i64(message, 1);
+    let nanos = get_i32(message, 2);
+    if seconds < -MAX_DURATION_SECONDS || seconds > MAX_DURATION_SECONDS
+        || nanos <= -1_000_000_000 || nanos >= 1_000_000_000
+        || (seconds > 0 && nanos < 0) || (seconds < 0 && nanos > 0) {
+        return Err(JsonError::new("Duration out of range"));
+    }
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    Ok(JsonValue::String(format!("{}{}{}s", sign, seconds.abs(), format_nanos(nanos.abs()))))
+}
+
+fn encode_field_mask(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let paths = message.get_field_by_number(1);
+    let mut json_paths = Vec::new();
+    for path in paths.as_ref().and_then(|paths| paths.as_list()).unwrap_or(&[]) {
+        let path = path.as_str().unwrap_or("");
+        // Only paths which round-trip through lowerCamelCase can be represented.
+        let mut json_path = String::with_capacity(path.len());
+        let mut capitalize = false;
+        for c in path.chars() {
+            if c.is_ascii_uppercase() || (capitalize && !c.is_ascii_lowercase()) {
+                return Err(JsonError::new(format!("FieldMask path {} can't be converted to JSON", path)));
+            }
+            if c == '_' {
+                capitalize = true;
+            } else if capitalize {
+                json_path.push(c.to_ascii_uppercase());
+                capitalize = false;
+            } else {
+                json_path.push(c);
+            }
+        }
+        if capitalize {
+            return Err(JsonError::new(format!("FieldMask path {} can't be converted to JSON", path)));
+        }
+        json_paths.push(json_path);
+    }
+    Ok(JsonValue::String(json_paths.join(",")))
+}
+
+fn encode_struct(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let mut members = Vec::new();
+    if let Some(fields) = message.get_field_by_number(1) {
+        for (key, value) in fields.as_map().into_iter().flat_map(|map| map.iter()) {
+            let key = match *key {
+                MapKey::String(ref key) => key.clone(),
+                _ => return Err(JsonError::new("invalid Struct key")),
+            };
+            let value = match *value {
+                Value::Message(ref value) => encode_value(value)?,
+                _ => return Err(JsonError::new("invalid Struct value")),
+            };
+            members.push((key, value));
+        }
+    }
+    Ok(JsonValue::Object(members))
+}
+
+fn encode_list_value(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let mut values = Vec::new();
+    if let Some(list) = message.get_field_by_number(1) {
+        for value in list.as_list().unwrap_or(&[]) {
+            match *value {
+                Value::Message(ref value) => values.push(encode_value(value)?),
+                _ => return Err(JsonError::new("invalid ListValue value")),
+            }
+        }
+    }
+    Ok(JsonValue::Array(values))
+}
+
+fn encode_value(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let (field, value) = match message.fields().next() {
+        Some(field) => field,
+        None => return Err(JsonError::new("Value has no kind set")),
+    };
+    match (field.number(), value) {
+        (1, _) => Ok(JsonValue::Null),
+        (2, &Value::F64(number)) => {
+            if number.is_finite() {
+                Ok(JsonValue::Number(number.to_string()))
+            } else {
+                Err(JsonError::new("Value number must be finite"))
+            }
+        },
+        (3, &Value::String(ref value)) => Ok(JsonValue::String(value.clone())),
+        (4, &Value::Bool(value)) => Ok(JsonValue::Bool(value)),
+        (5, &Value::Message(ref value)) => encode_struct(value),
+        (6, &Value::Message(ref value)) => encode_list_value(value),
+        _ => Err(JsonError::new("invalid Value")),
+    }
+}
+
+fn encode_any(message: &DynamicMessage) -> Result<JsonValue, JsonError> {
+    let type_url = match message.get_field_by_number(1) {
+        Some(type_url) => type_url.as_str().unwrap_or("").to_owned(),
+        None => String::new(),
+    };
+    let bytes = match message.get_field_by_number(2) {
+        Some(bytes) => bytes.as_bytes().unwrap_or(&[]).to_owned(),
+        None => Vec::new(),
+    };
+    if type_url.is_empty() {
+        return Ok(JsonValue::Object(Vec::new()));
+    }
+
+    let descriptor = resolve_type_url(message.descriptor(), &type_url)?;
+    let value = DynamicMessage::decode(descriptor, &bytes[..])
+        .map_err(|error| JsonError::new(format!("invalid Any value: {}", error)))?;
+
+    let mut members = vec![("@type".to_owned(), JsonValue::String(type_url))];
+    match encode_message(&value)? {
+        JsonValue::Object(ref fields) if !has_special_representation(value.descriptor().full_name()) => {
+            members.extend(fields.iter().cloned());
+        },
+        special => members.push(("value".to_owned(), special)),
+    }
+    Ok(JsonValue::Object(members))
+}
+
+/// Returns `true` if the well-known type is not represented as a JSON object of its fields, and
+/// so is embedded in an `Any` as a `value` member.
+pub fn has_special_representation(full_name: &str) -> bool {
+    full_name.starts_with("google.protobuf.") && match &full_name["google.protobuf.".len()..] {
+        "Any" | "Timestamp" | "Duration" | "FieldMask" | "Struct" | "ListValue" | "Value"
+            | "BoolValue" | "BytesValue" | "DoubleValue" | "FloatValue" | "Int32Value"
+            | "Int64Value" | "StringValue" | "UInt32Value" | "UInt64Value" => true,
+        _ => false,
+    }
+}
+
+/// Looks up the message type named by an `Any` type URL in the pool of the `Any` message type.
+pub fn resolve_type_url(any: &MessageDescriptor, type_url: &str) -> Result<MessageDescriptor, JsonError> {
+    any.pool()
+       .get_message_by_type_url(type_url)
+       .ok_or_else(|| JsonError::new(format!("unknown Any type {}", type_url)))
+}
@@ -0,0 +1,240 @@
+//! The Protobuf canonical JSON mapping.
+//!
+//! Messages are converted to and from JSON through their descriptors, following the [proto3 JSON
+//! mapping][1]: fields are named by their lowerCamelCase JSON name, enums are written as value
+//! names, 64-bit integers as strings and `bytes` as base64, and the well-known types such as
+//! `Timestamp`, `Duration`, `FieldMask`, `Struct`, `Any` and the wrapper types use their special
+//! representations.
+//!
+//! Messages generated by `prost-build` with `Config::json` implement `JsonMessage`; any other
+//! message can be converted as a `DynamicMessage` with `encode` and `decode`.
+//!
+//! [1]: https://developers.google.com/protocol-buffers/docs/proto3#json
+
+mod decode;
+mod encode;
+mod value;
+
+use std::error;
+use std::fmt;
+
+use prost::Message;
+
+use descriptor::MessageDescriptor;
+use dynamic::DynamicMessage;
+use reflect::ReflectMessage;
+
+/// An error which occurs when a message can't be converted to or from JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError {
+    description: String,
+}
+
+impl JsonError {
+    fn new<S>(description: S) -> JsonError where S: Into<String> {
+        JsonError { description: description.into() }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to convert JSON: {}", self.description)
+    }
+}
+
+impl error::Error for JsonError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// Options for parsing messages from JSON.
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+    ignore_unknown_fields: bool,
+}
+
+impl JsonOptions {
+
+    /// Creates a new set of options with the default values.
+    pub fn new() -> JsonOptions {
+        JsonOptions::default()
+    }
+
+    /// Sets whether object members and enum value names which are not known to the message type
+    /// are ignored, rather than rejected. Defaults to `false`.
+    pub fn ignore_unknown_fields(&mut self, ignore_unknown_fields: bool) -> &mut JsonOptions {
+        self.ignore_unknown_fields = ignore_unknown_fields;
+        self
+    }
+}
+
+/// Encodes a message as JSON.
+///
+/// Fails if the message holds a value which has no JSON representation, such as a `Timestamp`
+/// outside of the supported range, or an `Any` whose type is not in the message's pool.
+pub fn encode(message: &DynamicMessage) -> Result<String, JsonError> {
+    let value = encode::encode_message(message)?;
+    let mut json = String::new();
+    value.write(&mut json);
+    Ok(json)
+}
+
+/// Decodes a message of the provided type from JSON.
+pub fn decode(descriptor: MessageDescriptor, json: &str) -> Result<DynamicMessage, JsonError> {
+    decode_with_options(descriptor, json, &JsonOptions::default())
+}
+
+/// Decodes a message of the provided type from JSON, using the provided options.
+pub fn decode_with_options(descriptor: MessageDescriptor,
+                           json: &str,
+                           options: &JsonOptions) -> Result<DynamicMessage, JsonError> {
+    let value = value::JsonValue::parse(json)?;
+    decode::decode_message(descriptor, value, options)
+}
+
+/// A message which can be converted to and from the canonical JSON representation.
+pub trait JsonMessage: ReflectMessage {
+
+    /// Encodes the message as JSON.
+    fn to_json(&self) -> Result<String, JsonError> where Self: Sized {
+        let view = self.try_reflect().map_err(|error| JsonError::new(error.to_string()))?;
+        encode(&view)
+    }
+
+    /// Decodes an instance of the message from JSON.
+    fn from_json(json: &str) -> Result<Self, JsonError> where Self: Default {
+        Self::from_json_with_options(json, &JsonOptions::default())
+    }
+
+    /// Decodes an instance of the message from JSON, using the provided options.
+    fn from_json_with_options(json: &str, options: &JsonOptions) -> Result<Self, JsonError>
+    where Self: Default {
+        let message = decode_with_options(Self::default().descriptor(), json, options)?;
+        let mut buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut buf).expect("insufficient buffer capacity");
+        Self::decode(&buf).map_err(|error| JsonError::new(error.to_string()))
+    }
+}
+
+const BASE64_ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as padded, standard alphabet base64.
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes base64 in either the standard or the URL-safe alphabet, with or without padding.
+fn decode_base64(input: &str) -> Result<Vec<u8>, JsonError> {
+    let input = input.trim_end_matches('=');
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(JsonError::new("invalid base64")),
+        };
+        acc = (acc << 6) | u32::from(digit);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    if bits >= 6 {
+        return Err(JsonError::new("invalid base64 length"));
+    }
+    Ok(out)
+}
+
+/// Converts a count of days since 1970-01-01 to a (year, month, day) civil date.
+///
+/// Based on Howard Hinnant's [`civil_from_days`][1].
+///
+/// [1]: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) civil date to a count of days since 1970-01-01.
+///
+/// Based on Howard Hinnant's [`days_from_civil`][1].
+///
+/// [1]: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The seconds of 0001-01-01T00:00:00Z, the earliest `Timestamp` with a JSON representation.
+const MIN_TIMESTAMP_SECONDS: i64 = -62_135_596_800;
+
+/// The seconds of 9999-12-31T23:59:59Z, the latest `Timestamp` with a JSON representation.
+const MAX_TIMESTAMP_SECONDS: i64 = 253_402_300_799;
+
+/// The largest magnitude of a `Duration`'s seconds, approximately 10,000 years.
+const MAX_DURATION_SECONDS: i64 = 315_576_000_000;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for &(bytes, encoded) in &[(&b""[..], ""),
+                                   (b"f", "Zg=="),
+                                   (b"fo", "Zm8="),
+                                   (b"foo", "Zm9v"),
+                                   (b"foob", "Zm9vYg=="),
+                                   (b"\xfb\xff", "+/8=")] {
+            assert_eq!(encode_base64(bytes), encoded);
+            assert_eq!(decode_base64(encoded).unwrap(), bytes);
+        }
+        assert_eq!(decode_base64("-_8").unwrap(), b"\xfb\xff");
+        assert!(decode_base64("Z").is_err());
+        assert!(decode_base64("Zm9v!").is_err());
+    }
+
+    #[test]
+    fn civil_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1, 1, 1) * 86_400, MIN_TIMESTAMP_SECONDS);
+        assert_eq!(days_from_civil(9999, 12, 31) * 86_400 + 86_399, MAX_TIMESTAMP_SECONDS);
+        assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
@@ -0,0 +1,308 @@
+//! A minimal JSON document model, with a parser and a compact writer.
+
+use std::char;
+use std::fmt::Write;
+
+use json::JsonError;
+
+/// The maximum nesting depth of arrays and objects accepted by the parser.
+const RECURSION_LIMIT: u32 = 100;
+
+/// A parsed JSON value.
+///
+/// Numbers are kept in their source form, so that they can be converted to the target field type
+/// without an intermediate loss of precision. Object members are kept in source order, so that
+/// duplicate keys can be detected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+
+    /// Parses a JSON document.
+    pub fn parse(json: &str) -> Result<JsonValue, JsonError> {
+        let mut parser = Parser { input: json.as_bytes(), pos: 0 };
+        let value = parser.parse_value(RECURSION_LIMIT)?;
+        parser.skip_whitespace();
+        if parser.pos != parser.input.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// Returns a short description of the type of the value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "boolean",
+            JsonValue::Number(_) => "number",
+            JsonValue::String(_) => "string",
+            JsonValue::Array(_) => "array",
+            JsonValue::Object(_) => "object",
+        }
+    }
+
+    /// Writes the value to a string, without insignificant whitespace.
+    pub fn write(&self, out: &mut String) {
+        match *self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(value) => out.push_str(if value { "true" } else { "false" }),
+            JsonValue::Number(ref value) => out.push_str(value),
+            JsonValue::String(ref value) => write_string(value, out),
+            JsonValue::Array(ref values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            },
+            JsonValue::Object(ref members) => {
+                out.push('{');
+                for (i, &(ref key, ref value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            },
+        }
+    }
+}
+
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl <'a> Parser<'a> {
+
+    fn error(&self, description: &str) -> JsonError {
+        JsonError::new(format!("invalid JSON at offset {}: {}", self.pos, description))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() {
+            match self.input[self.pos] {
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), JsonError> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn parse_value(&mut self, depth: u32) -> Result<JsonValue, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.expect("null").map(|_| JsonValue::Null),
+            Some(b't') => self.expect("true").map(|_| JsonValue::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| JsonValue::Bool(false)),
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b'[') => {
+                if depth == 0 {
+                    return Err(self.error("recursion limit reached"));
+                }
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(values));
+                }
+                loop {
+                    values.push(self.parse_value(depth - 1)?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Array(values));
+                        },
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            },
+            Some(b'{') => {
+                if depth == 0 {
+                    return Err(self.error("recursion limit reached"));
+                }
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected object key"));
+                    }
+                    let key = self.parse_string()?;
+                    self.skip_whitespace();
+                    if self.peek() != Some(b':') {
+                        return Err(self.error("expected ':'"));
+                    }
+                    self.pos += 1;
+                    let value = self.parse_value(depth - 1)?;
+                    members.push((key, value));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Object(members));
+                        },
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            },
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number().map(JsonValue::Number),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<String, JsonError> {
+        let start = self.pos;
+        let digits = |parser: &mut Parser| {
+            let start = parser.pos;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.pos += 1;
+            }
+            parser.pos > start
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err(self.error("invalid number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        Ok(String::from_utf8(self.input[start..self.pos].to_vec()).unwrap())
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        if self.pos + 4 > self.input.len() {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let hex = ::std::str::from_utf8(&self.input[self.pos..self.pos + 4])
+                             .map_err(|_| self.error("invalid unicode escape"))?;
+        let value = u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        // Skip the opening quote.
+        self.pos += 1;
+        let mut value = Vec::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = match self.peek() {
+                        Some(escape) => escape,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' => value.push(b'"'),
+                        b'\\' => value.push(b'\\'),
+                        b'/' => value.push(b'/'),
+                        b'b' => value.push(b'\x08'),
+                        b'f' => value.push(b'\x0c'),
+                        b'n' => value.push(b'\n'),
+                        b'r' => value.push(b'\r'),
+                        b't' => value.push(b'\t'),
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if code >= 0xD800 && code < 0xDC00 {
+                                // A high surrogate, which must be followed by a low surrogate.
+                                if !self.input[self.pos..].starts_with(b"\\u") {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                if low < 0xDC00 || low >= 0xE000 {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            let c = char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))?;
+                            let mut utf8 = [0; 4];
+                            value.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                },
+                c if c < 0x20 => return Err(self.error("control character in string")),
+                c => value.push(c),
+            }
+        }
+        String::from_utf8(value).map_err(|_| self.error("invalid UTF-8"))
+    }
+}
@@ -9,6 +9,10 @@
 //!
 //! [1]: https://developers.google.com/protocol-buffers/docs/reference/google.protobuf
 
+extern crate bytes;
+#[macro_use]
+extern crate lazy_static;
+extern crate prost;
 #[macro_use]
 extern crate prost_derive;
 
@@ -21,6 +25,12 @@ pub mod compiler {
     include!("compiler.rs");
 }
 
+pub mod descriptor;
+pub mod dynamic;
+pub mod json;
+pub mod reflect;
+pub mod text_format;
+
 // The Protobuf `Duration` and `Timestamp` types can't delegate to the standard library equivalents
 // because the Protobuf versions are signed. To make them easier to work with, `From` conversions
 // are defined in both directions.
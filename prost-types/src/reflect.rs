@@ -0,0 +1,73 @@
+//! Runtime reflection for generated messages.
+//!
+//! Messages generated by `prost-build` with `Config::reflect` implement `ReflectMessage`, which
+//! links each message type back to its descriptor. Generic code, such as loggers, validators and
+//! redactors, can use it to walk the fields of any message as dynamic `Value`s.
+
+use std::borrow::Cow;
+
+use std::u32;
+
+use prost::{DecodeError, DecodeOptions, Message};
+
+use DescriptorProto;
+use descriptor::MessageDescriptor;
+use dynamic::{DynamicMessage, Value};
+
+/// A message whose type is described by a runtime `MessageDescriptor`.
+pub trait ReflectMessage: Message {
+
+    /// Returns the fully-qualified name of the message type, for example
+    /// `"google.protobuf.Timestamp"`.
+    fn full_name(&self) -> &'static str;
+
+    /// Returns the descriptor of the message type.
+    fn descriptor(&self) -> MessageDescriptor;
+
+    /// Returns the message type's `DescriptorProto`.
+    fn descriptor_proto(&self) -> DescriptorProto {
+        self.descriptor().descriptor_proto().clone()
+    }
+
+    /// Returns a dynamic view of the message.
+    ///
+    /// The view is a copy of the message, made by encoding it and decoding the result as a
+    /// `DynamicMessage`; changes to the view are not reflected in the message.
+    ///
+    /// Panics if the message can't be decoded by its descriptor, which is only the case if the
+    /// descriptor doesn't match the generated code; use `try_reflect` to handle that case.
+    fn reflect(&self) -> DynamicMessage where Self: Sized {
+        self.try_reflect().expect("message does not match its descriptor")
+    }
+
+    /// Returns a dynamic view of the message, or an error if the message can't be decoded by its
+    /// descriptor.
+    ///
+    /// The view is decoded without a recursion limit, since the message it is copied from is
+    /// already in memory, however deeply it is nested.
+    fn try_reflect(&self) -> Result<DynamicMessage, DecodeError> where Self: Sized {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut buf).expect("insufficient buffer capacity");
+        let mut options = DecodeOptions::new();
+        options.recursion_limit(u32::MAX);
+        DynamicMessage::decode_with_options(self.descriptor(), &buf, &options)
+    }
+
+    /// Returns the value of the field with the provided name, or `None` if the message type has
+    /// no such field.
+    ///
+    /// Each call creates a new dynamic view of the message; use `reflect` to access several
+    /// fields.
+    fn get_field_by_name(&self, name: &str) -> Option<Value> where Self: Sized {
+        self.reflect().get_field_by_name(name).map(Cow::into_owned)
+    }
+
+    /// Returns the value of the field with the provided number, or `None` if the message type has
+    /// no such field.
+    ///
+    /// Each call creates a new dynamic view of the message; use `reflect` to access several
+    /// fields.
+    fn get_field_by_number(&self, number: u32) -> Option<Value> where Self: Sized {
+        self.reflect().get_field_by_number(number).map(Cow::into_owned)
+    }
+}
@@ -0,0 +1,111 @@
+//! The Protobuf text format.
+//!
+//! The text format is the human-readable representation of messages emitted by `protoc
+//! --decode` and the `DebugString` methods of other Protobuf implementations. Unlike the `Debug`
+//! output of generated messages, it can be parsed back into a message.
+//!
+//! Messages generated by `prost-build` with `Config::text_format` implement
+//! `TextFormatMessage`; any other message can be converted as a `DynamicMessage` with `encode`
+//! and `decode`.
+
+mod parse;
+mod print;
+
+use std::error;
+use std::fmt;
+
+use prost::Message;
+
+use descriptor::MessageDescriptor;
+use dynamic::DynamicMessage;
+use reflect::ReflectMessage;
+
+/// An error which occurs when a message can't be parsed from the text format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextFormatError {
+    description: String,
+}
+
+impl TextFormatError {
+    fn new<S>(description: S) -> TextFormatError where S: Into<String> {
+        TextFormatError { description: description.into() }
+    }
+}
+
+impl fmt::Display for TextFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to parse text format: {}", self.description)
+    }
+}
+
+impl error::Error for TextFormatError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// Options for printing messages in the text format.
+#[derive(Clone, Debug)]
+pub struct TextFormatOptions {
+    print_unknown_fields: bool,
+}
+
+impl Default for TextFormatOptions {
+    fn default() -> TextFormatOptions {
+        TextFormatOptions {
+            print_unknown_fields: true,
+        }
+    }
+}
+
+impl TextFormatOptions {
+
+    /// Creates a new set of options with the default values.
+    pub fn new() -> TextFormatOptions {
+        TextFormatOptions::default()
+    }
+
+    /// Sets whether fields which were not recognized while decoding the message are printed, by
+    /// field number. Such output can't be parsed back into a message. Defaults to `true`.
+    pub fn print_unknown_fields(&mut self, print_unknown_fields: bool) -> &mut TextFormatOptions {
+        self.print_unknown_fields = print_unknown_fields;
+        self
+    }
+}
+
+/// Prints a message in the text format.
+pub fn encode(message: &DynamicMessage) -> String {
+    encode_with_options(message, &TextFormatOptions::default())
+}
+
+/// Prints a message in the text format, using the provided options.
+pub fn encode_with_options(message: &DynamicMessage, options: &TextFormatOptions) -> String {
+    print::print_message(message, options)
+}
+
+/// Parses a message of the provided type from the text format.
+pub fn decode(descriptor: MessageDescriptor, text: &str) -> Result<DynamicMessage, TextFormatError> {
+    parse::parse_message(descriptor, text)
+}
+
+/// A message which can be printed in and parsed from the text format.
+pub trait TextFormatMessage: ReflectMessage {
+
+    /// Prints the message in the text format.
+    fn to_text_format(&self) -> String where Self: Sized {
+        encode(&self.reflect())
+    }
+
+    /// Prints the message in the text format, using the provided options.
+    fn to_text_format_with_options(&self, options: &TextFormatOptions) -> String where Self: Sized {
+        encode_with_options(&self.reflect(), options)
+    }
+
+    /// Parses an instance of the message from the text format.
+    fn from_text_format(text: &str) -> Result<Self, TextFormatError> where Self: Default {
+        let message = decode(Self::default().descriptor(), text)?;
+        let mut buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut buf).expect("insufficient buffer capacity");
+        Self::decode(&buf).map_err(|error| TextFormatError::new(error.to_string()))
+    }
+}
@@ -0,0 +1,533 @@
+//! Parsing of dynamic messages from the text format.
+
+use std::char;
+
+use prost::Message;
+
+use descriptor::{FieldDescriptor, Kind, MessageDescriptor};
+use dynamic::{DynamicMessage, Value};
+use text_format::TextFormatError;
+
+/// The maximum nesting depth of messages accepted by the parser.
+const RECURSION_LIMIT: u32 = 100;
+
+pub fn parse_message(descriptor: MessageDescriptor, text: &str) -> Result<DynamicMessage, TextFormatError> {
+    let mut parser = Parser {
+        input: text.as_bytes(),
+        pos: 0,
+        token_pos: 0,
+        peeked: None,
+    };
+    let mut message = DynamicMessage::new(descriptor);
+    parser.parse_fields(&mut message, None, RECURSION_LIMIT)?;
+    Ok(message)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    String(Vec<u8>),
+    Symbol(char),
+    Eof,
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// The position of the start of the most recently read token, for error messages.
+    token_pos: usize,
+    peeked: Option<(usize, Token)>,
+}
+
+impl <'a> Parser<'a> {
+
+    fn error<S>(&self, description: S) -> TextFormatError where S: AsRef<str> {
+        let consumed = &self.input[..self.token_pos];
+        let line = consumed.iter().filter(|&&b| b == b'\n').count() + 1;
+        let column = consumed.iter().rev().take_while(|&&b| b != b'\n').count() + 1;
+        TextFormatError::new(format!("{}:{}: {}", line, column, description.as_ref()))
+    }
+
+    // Tokenizer.
+
+    fn peek(&mut self) -> Result<&Token, TextFormatError> {
+        if self.peeked.is_none() {
+            let token = self.read_token()?;
+            self.peeked = Some(token);
+        }
+        Ok(&self.peeked.as_ref().unwrap().1)
+    }
+
+    fn next(&mut self) -> Result<Token, TextFormatError> {
+        let (pos, token) = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.read_token()?,
+        };
+        self.token_pos = pos;
+        Ok(token)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() {
+            match self.input[self.pos] {
+                b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c' => self.pos += 1,
+                b'#' => {
+                    while self.pos < self.input.len() && self.input[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Result<(usize, Token), TextFormatError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.token_pos = start;
+        let c = match self.input.get(self.pos) {
+            Some(&c) => c,
+            None => return Ok((start, Token::Eof)),
+        };
+        let token = match c {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while let Some(b'a'..=b'z') | Some(b'A'..=b'Z') | Some(b'0'..=b'9') | Some(b'_') = self.input.get(self.pos) {
+                    self.pos += 1;
+                }
+                Token::Ident(String::from_utf8(self.input[start..self.pos].to_vec()).unwrap())
+            },
+            b'0'..=b'9' => self.read_number(),
+            b'.' if self.input.get(self.pos + 1).map_or(false, u8::is_ascii_digit) => self.read_number(),
+            b'"' | b'\'' => Token::String(self.read_string(c)?),
+            c if c.is_ascii() => {
+                self.pos += 1;
+                Token::Symbol(c as char)
+            },
+            _ => return Err(self.error("unexpected character")),
+        };
+        Ok((start, token))
+    }
+
+    fn read_number(&mut self) -> Token {
+        let start = self.pos;
+        let hex = self.input[self.pos..].starts_with(b"0x") || self.input[self.pos..].starts_with(b"0X");
+        while let Some(&c) = self.input.get(self.pos) {
+            match c {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' => self.pos += 1,
+                b'+' | b'-' if !hex && (self.input[self.pos - 1] == b'e' || self.input[self.pos - 1] == b'E') => {
+                    self.pos += 1;
+                },
+                _ => break,
+            }
+        }
+        Token::Number(String::from_utf8(self.input[start..self.pos].to_vec()).unwrap())
+    }
+
+    /// Reads up to `max` digits of the provided radix.
+    fn read_digits(&mut self, radix: u32, max: usize) -> u32 {
+        let mut value = 0;
+        let mut count = 0;
+        while count < max {
+            match self.input.get(self.pos).and_then(|&c| (c as char).to_digit(radix)) {
+                Some(digit) => {
+                    value = value * radix + digit;
+                    self.pos += 1;
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+        value
+    }
+
+    fn read_string(&mut self, quote: u8) -> Result<Vec<u8>, TextFormatError> {
+        // Skip the opening quote.
+        self.pos += 1;
+        let mut value = Vec::new();
+        loop {
+            let c = match self.input.get(self.pos) {
+                Some(&c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += 1;
+            match c {
+                b'\n' => return Err(self.error("unterminated string")),
+                c if c == quote => return Ok(value),
+                b'\\' => {
+                    let escape = match self.input.get(self.pos) {
+                        Some(&escape) => escape,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'a' => value.push(b'\x07'),
+                        b'b' => value.push(b'\x08'),
+                        b'f' => value.push(b'\x0c'),
+                        b'n' => value.push(b'\n'),
+                        b'r' => value.push(b'\r'),
+                        b't' => value.push(b'\t'),
+                        b'v' => value.push(b'\x0b'),
+                        b'\\' | b'\'' | b'"' | b'?' => value.push(escape),
+                        b'0'..=b'7' => {
+                            self.pos -= 1;
+                            let byte = self.read_digits(8, 3);
+                            if byte > 0xff {
+                                return Err(self.error("invalid octal escape"));
+                            }
+                            value.push(byte as u8);
+                        },
+                        b'x' | b'X' => {
+                            let start = self.pos;
+                            let byte = self.read_digits(16, 2);
+                            if self.pos == start {
+                                return Err(self.error("invalid hex escape"));
+                            }
+                            value.push(byte as u8);
+                        },
+                        b'u' | b'U' => {
+                            let len = if escape == b'u' { 4 } else { 8 };
+                            let start = self.pos;
+                            let code = self.read_digits(16, len);
+                            let c = match char::from_u32(code) {
+                                Some(c) if self.pos - start == len => c,
+                                _ => return Err(self.error("invalid unicode escape")),
+                            };
+                            let mut utf8 = [0; 4];
+                            value.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn consume_symbol(&mut self, symbol: char) -> Result<bool, TextFormatError> {
+        if *self.peek()? == Token::Symbol(symbol) {
+            self.next()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), TextFormatError> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            token => Err(self.error(format!("expected '{}', found {:?}", symbol, token))),
+        }
+    }
+
+    // Parser.
+
+    /// Parses fields into the message until the end of the input, or until the closing symbol if
+    /// one is provided.
+    fn parse_fields(&mut self,
+                    message: &mut DynamicMessage,
+                    end: Option<char>,
+                    depth: u32) -> Result<(), TextFormatError> {
+        if depth == 0 {
+            return Err(self.error("recursion limit reached"));
+        }
+        let mut seen_fields = Vec::new();
+        let mut seen_oneofs = Vec::new();
+        loop {
+            match (self.peek()?.clone(), end) {
+                (Token::Eof, None) => return Ok(()),
+                (Token::Eof, Some(end)) => {
+                    self.next()?;
+                    return Err(self.error(format!("expected '{}'", end)));
+                },
+                (Token::Symbol(c), Some(end)) if c == end => {
+                    self.next()?;
+                    return Ok(());
+                },
+                _ => (),
+            }
+            self.parse_field(message, &mut seen_fields, &mut seen_oneofs, depth)?;
+            if !self.consume_symbol(',')? {
+                self.consume_symbol(';')?;
+            }
+        }
+    }
+
+    fn parse_field(&mut self,
+                   message: &mut DynamicMessage,
+                   seen_fields: &mut Vec<u32>,
+                   seen_oneofs: &mut Vec<u32>,
+                   depth: u32) -> Result<(), TextFormatError> {
+        let descriptor = message.descriptor().clone();
+
+        if self.consume_symbol('[')? {
+            let name = self.parse_bracketed_name()?;
+            if descriptor.full_name() == "google.protobuf.Any" && name.contains('/') {
+                if !seen_fields.is_empty() {
+                    return Err(self.error("Any already has a value"));
+                }
+                seen_fields.extend_from_slice(&[1, 2]);
+                return self.parse_any(message, name, depth);
+            }
+            return Err(self.error(format!("extension [{}] is not supported", name)));
+        }
+
+        let name = match self.next()? {
+            Token::Ident(name) => name,
+            token => return Err(self.error(format!("expected field name, found {:?}", token))),
+        };
+        let field = descriptor.get_field_by_name(&name).or_else(|| {
+            // Groups are named by their message type.
+            descriptor.fields().find(|field| field.is_group() && match field.kind() {
+                Kind::Message(ref group) => group.name() == name,
+                _ => false,
+            })
+        });
+        let field = match field {
+            Some(field) => field,
+            None => {
+                return Err(self.error(format!("unknown field {} in {}", name, descriptor.full_name())));
+            },
+        };
+
+        let repeated = field.is_list() || field.is_map();
+        if !repeated {
+            if seen_fields.contains(&field.number()) {
+                return Err(self.error(format!("non-repeated field {} specified multiple times", name)));
+            }
+            seen_fields.push(field.number());
+            if let Some(oneof_index) = field.oneof_index() {
+                if seen_oneofs.contains(&oneof_index) {
+                    return Err(self.error(format!("multiple members of a oneof set in {}",
+                                                  descriptor.full_name())));
+                }
+                seen_oneofs.push(oneof_index);
+            }
+        }
+
+        let is_message = match field.kind() {
+            Kind::Message(_) => true,
+            _ => false,
+        };
+        if !self.consume_symbol(':')? && !is_message {
+            self.next()?;
+            return Err(self.error(format!("expected ':' after field {}", name)));
+        }
+
+        if repeated && self.consume_symbol('[')? {
+            if self.consume_symbol(']')? {
+                message.get_field_mut(&field);
+                return Ok(());
+            }
+            loop {
+                let value = self.parse_value(&field, depth)?;
+                self.add_value(message, &field, value)?;
+                if !self.consume_symbol(',')? {
+                    break;
+                }
+            }
+            return self.expect_symbol(']');
+        }
+
+        let value = self.parse_value(&field, depth)?;
+        self.add_value(message, &field, value)
+    }
+
+    fn parse_bracketed_name(&mut self) -> Result<String, TextFormatError> {
+        let mut name = String::new();
+        loop {
+            match self.next()? {
+                Token::Symbol(']') => return Ok(name),
+                Token::Ident(ident) => name.push_str(&ident),
+                Token::Symbol(c) if c == '.' || c == '/' => name.push(c),
+                token => return Err(self.error(format!("unexpected {:?} in bracketed name", token))),
+            }
+        }
+    }
+
+    fn parse_any(&mut self, any: &mut DynamicMessage, type_url: String, depth: u32) -> Result<(), TextFormatError> {
+        let descriptor = match any.descriptor().pool().get_message_by_type_url(&type_url) {
+            Some(descriptor) => descriptor,
+            None => return Err(self.error(format!("unknown Any type {}", type_url))),
+        };
+        self.consume_symbol(':')?;
+        let end = self.parse_message_start()?;
+        let mut message = DynamicMessage::new(descriptor);
+        self.parse_fields(&mut message, Some(end), depth - 1)?;
+
+        let mut buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut buf).expect("insufficient buffer capacity");
+        any.set_field_by_number(1, Value::String(type_url)).expect("invalid Any type");
+        any.set_field_by_number(2, Value::Bytes(buf)).expect("invalid Any type");
+        Ok(())
+    }
+
+    /// Parses the opening symbol of a message value, and returns the matching closing symbol.
+    fn parse_message_start(&mut self) -> Result<char, TextFormatError> {
+        match self.next()? {
+            Token::Symbol('{') => Ok('}'),
+            Token::Symbol('<') => Ok('>'),
+            token => Err(self.error(format!("expected '{{' or '<', found {:?}", token))),
+        }
+    }
+
+    fn add_value(&mut self, message: &mut DynamicMessage, field: &FieldDescriptor, value: Value) -> Result<(), TextFormatError> {
+        if field.is_map() {
+            let entry = match value {
+                Value::Message(entry) => entry,
+                _ => unreachable!(),
+            };
+            let key = entry.get_field_by_number(1)
+                           .and_then(|key| key.into_owned().into_map_key())
+                           .ok_or_else(|| self.error(format!("invalid key for map field {}", field.name())))?;
+            let value = entry.get_field_by_number(2)
+                             .ok_or_else(|| self.error(format!("invalid value for map field {}", field.name())))?
+                             .into_owned();
+            message.get_field_mut(field).as_map_mut().expect("invalid map field").insert(key, value);
+        } else if field.is_list() {
+            message.get_field_mut(field).as_list_mut().expect("invalid list field").push(value);
+        } else {
+            message.set_field(field, value).map_err(|error| self.error(error.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self, field: &FieldDescriptor, depth: u32) -> Result<Value, TextFormatError> {
+        let kind = field.kind();
+        if let Kind::Message(descriptor) = kind {
+            let end = self.parse_message_start()?;
+            let mut message = DynamicMessage::new(descriptor);
+            self.parse_fields(&mut message, Some(end), depth - 1)?;
+            return Ok(Value::Message(message));
+        }
+
+        Ok(match kind {
+            Kind::String => {
+                let bytes = self.parse_string()?;
+                match String::from_utf8(bytes) {
+                    Ok(value) => Value::String(value),
+                    Err(_) => return Err(self.error(format!("invalid UTF-8 in field {}", field.name()))),
+                }
+            },
+            Kind::Bytes => Value::Bytes(self.parse_string()?),
+            Kind::Bool => match self.next()? {
+                Token::Ident(ref value) if value == "true" || value == "True" || value == "t" => Value::Bool(true),
+                Token::Ident(ref value) if value == "false" || value == "False" || value == "f" => Value::Bool(false),
+                Token::Number(ref value) if value == "1" => Value::Bool(true),
+                Token::Number(ref value) if value == "0" => Value::Bool(false),
+                token => return Err(self.error(format!("invalid bool {:?}", token))),
+            },
+            Kind::Enum(enum_type) => {
+                if let Token::Ident(name) = self.peek()?.clone() {
+                    self.next()?;
+                    match enum_type.get_value_number(&name) {
+                        Some(number) => Value::EnumNumber(number),
+                        None => {
+                            return Err(self.error(format!("unknown value {} of enum {}",
+                                                          name, enum_type.full_name())));
+                        },
+                    }
+                } else {
+                    Value::EnumNumber(self.parse_signed(i64::from(i32::min_value()), i64::from(i32::max_value()))? as i32)
+                }
+            },
+            Kind::Float => Value::F32(self.parse_float()? as f32),
+            Kind::Double => Value::F64(self.parse_float()?),
+            Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+                Value::I32(self.parse_signed(i64::from(i32::min_value()), i64::from(i32::max_value()))? as i32)
+            },
+            Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+                Value::I64(self.parse_signed(i64::min_value(), i64::max_value())?)
+            },
+            Kind::Uint32 | Kind::Fixed32 => Value::U32(self.parse_unsigned(u64::from(u32::max_value()))? as u32),
+            Kind::Uint64 | Kind::Fixed64 => Value::U64(self.parse_unsigned(u64::max_value())?),
+            Kind::Message(_) => unreachable!(),
+        })
+    }
+
+    /// Parses one or more adjacent string literals, which are concatenated.
+    fn parse_string(&mut self) -> Result<Vec<u8>, TextFormatError> {
+        let mut value = match self.next()? {
+            Token::String(value) => value,
+            token => return Err(self.error(format!("expected string, found {:?}", token))),
+        };
+        while let Token::String(_) = *self.peek()? {
+            if let Token::String(next) = self.next()? {
+                value.extend_from_slice(&next);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Parses an unsigned integer literal in decimal, hexadecimal or octal notation, returning
+    /// the integer and whether it was negated.
+    fn parse_integer(&mut self) -> Result<(bool, u64), TextFormatError> {
+        let negative = self.consume_symbol('-')?;
+        let text = match self.next()? {
+            Token::Number(text) => text,
+            token => return Err(self.error(format!("expected integer, found {:?}", token))),
+        };
+        let result = if text.starts_with("0x") || text.starts_with("0X") {
+            u64::from_str_radix(&text[2..], 16)
+        } else if text.starts_with('0') && text.len() > 1 {
+            u64::from_str_radix(&text[1..], 8)
+        } else {
+            text.parse::<u64>()
+        };
+        match result {
+            Ok(value) => Ok((negative, value)),
+            Err(_) => Err(self.error(format!("invalid integer {}", text))),
+        }
+    }
+
+    fn parse_signed(&mut self, min: i64, max: i64) -> Result<i64, TextFormatError> {
+        let (negative, magnitude) = self.parse_integer()?;
+        let value = if negative {
+            if magnitude > 1 << 63 {
+                return Err(self.error("integer out of range"));
+            }
+            (magnitude as i64).wrapping_neg()
+        } else {
+            if magnitude > i64::max_value() as u64 {
+                return Err(self.error("integer out of range"));
+            }
+            magnitude as i64
+        };
+        if value < min || value > max {
+            return Err(self.error("integer out of range"));
+        }
+        Ok(value)
+    }
+
+    fn parse_unsigned(&mut self, max: u64) -> Result<u64, TextFormatError> {
+        let (negative, value) = self.parse_integer()?;
+        if (negative && value != 0) || value > max {
+            return Err(self.error("integer out of range"));
+        }
+        Ok(value)
+    }
+
+    fn parse_float(&mut self) -> Result<f64, TextFormatError> {
+        let negative = self.consume_symbol('-')?;
+        let value = match self.next()? {
+            Token::Ident(ref ident) => match &ident.to_ascii_lowercase()[..] {
+                "inf" | "infinity" => ::std::f64::INFINITY,
+                "nan" => ::std::f64::NAN,
+                _ => return Err(self.error(format!("invalid float {}", ident))),
+            },
+            Token::Number(ref text) => {
+                let text = if text.starts_with("0x") || text.starts_with("0X") {
+                    text.as_str()
+                } else {
+                    text.trim_end_matches(|c| c == 'f' || c == 'F')
+                };
+                match text.parse::<f64>() {
+                    Ok(value) => value,
+                    Err(_) => return Err(self.error(format!("invalid float {}", text))),
+                }
+            },
+            token => return Err(self.error(format!("expected float, found {:?}", token))),
+        };
+        Ok(if negative { -value } else { value })
+    }
+}
@@ -0,0 +1,239 @@
+//! Printing of dynamic messages in the text format.
+
+use std::fmt::Write;
+
+use prost::{UnknownFields, UnknownValue};
+
+use descriptor::{FieldDescriptor, Kind};
+use dynamic::{DynamicMessage, MapKey, Value};
+use text_format::TextFormatOptions;
+
+pub fn print_message(message: &DynamicMessage, options: &TextFormatOptions) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        options,
+    };
+    printer.print_fields(message);
+    printer.out
+}
+
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    options: &'a TextFormatOptions,
+}
+
+impl <'a> Printer<'a> {
+
+    fn push_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn open(&mut self, name: &str) {
+        self.push_indent();
+        self.out.push_str(name);
+        self.out.push_str(" {\n");
+        self.indent += 1;
+    }
+
+    fn close(&mut self) {
+        self.indent -= 1;
+        self.push_indent();
+        self.out.push_str("}\n");
+    }
+
+    fn print_fields(&mut self, message: &DynamicMessage) {
+        if message.descriptor().full_name() == "google.protobuf.Any" && self.print_any(message) {
+            return;
+        }
+
+        for (field, value) in message.fields() {
+            let skip = match *value {
+                Value::List(ref values) => values.is_empty(),
+                Value::Map(ref values) => values.is_empty(),
+                ref value => !field.supports_presence() && value.is_zero(),
+            };
+            if skip {
+                continue;
+            }
+
+            // Groups are named by their message type rather than by their lowercased field name.
+            let name = match field.kind() {
+                Kind::Message(ref group) if field.is_group() => group.name().to_owned(),
+                _ => field.name().to_owned(),
+            };
+            match *value {
+                Value::List(ref values) => {
+                    for value in values {
+                        self.print_field(&name, &field, value);
+                    }
+                },
+                Value::Map(ref entries) => {
+                    let value_field = match field.kind() {
+                        Kind::Message(entry) => entry.get_field(2).expect("map entry type has no value field"),
+                        _ => panic!("map field {} is not a message", field.name()),
+                    };
+                    for (key, value) in entries {
+                        self.open(&name);
+                        self.push_indent();
+                        self.out.push_str("key: ");
+                        match *key {
+                            MapKey::Bool(key) => { let _ = write!(self.out, "{}", key); },
+                            MapKey::I32(key) => { let _ = write!(self.out, "{}", key); },
+                            MapKey::I64(key) => { let _ = write!(self.out, "{}", key); },
+                            MapKey::U32(key) => { let _ = write!(self.out, "{}", key); },
+                            MapKey::U64(key) => { let _ = write!(self.out, "{}", key); },
+                            MapKey::String(ref key) => write_string(key, &mut self.out),
+                        }
+                        self.out.push('\n');
+                        self.print_field("value", &value_field, value);
+                        self.close();
+                    }
+                },
+                ref value => self.print_field(&name, &field, value),
+            }
+        }
+
+        if self.options.print_unknown_fields {
+            self.print_unknown_fields(message.unknown_fields());
+        }
+    }
+
+    /// Prints the expanded form of an `Any`, with the packed message's fields nested under its
+    /// type URL. Returns `false` if the packed message's type is not known.
+    fn print_any(&mut self, any: &DynamicMessage) -> bool {
+        let type_url = match any.get_field_by_number(1) {
+            Some(type_url) => type_url.as_str().unwrap_or("").to_owned(),
+            None => return false,
+        };
+        let descriptor = match any.descriptor().pool().get_message_by_type_url(&type_url) {
+            Some(descriptor) => descriptor,
+            None => return false,
+        };
+        let message = {
+            let bytes = any.get_field_by_number(2);
+            match DynamicMessage::decode(descriptor, bytes.as_ref().and_then(|bytes| bytes.as_bytes()).unwrap_or(&[])) {
+                Ok(message) => message,
+                Err(_) => return false,
+            }
+        };
+        self.open(&format!("[{}]", type_url));
+        self.print_fields(&message);
+        self.close();
+        true
+    }
+
+    fn print_field(&mut self, name: &str, field: &FieldDescriptor, value: &Value) {
+        if let Value::Message(ref message) = *value {
+            self.open(name);
+            self.print_fields(message);
+            self.close();
+            return;
+        }
+
+        self.push_indent();
+        self.out.push_str(name);
+        self.out.push_str(": ");
+        match *value {
+            Value::Bool(value) => { let _ = write!(self.out, "{}", value); },
+            Value::I32(value) => { let _ = write!(self.out, "{}", value); },
+            Value::I64(value) => { let _ = write!(self.out, "{}", value); },
+            Value::U32(value) => { let _ = write!(self.out, "{}", value); },
+            Value::U64(value) => { let _ = write!(self.out, "{}", value); },
+            Value::F32(value) => write_float(value.is_nan(), value.is_infinite(), value.is_sign_negative(),
+                                             format!("{:?}", value), &mut self.out),
+            Value::F64(value) => write_float(value.is_nan(), value.is_infinite(), value.is_sign_negative(),
+                                             format!("{:?}", value), &mut self.out),
+            Value::String(ref value) => write_string(value, &mut self.out),
+            Value::Bytes(ref value) => write_bytes(value, &mut self.out),
+            Value::EnumNumber(number) => {
+                let name = match field.kind() {
+                    Kind::Enum(ref enum_type) => enum_type.get_value_name(number).map(ToOwned::to_owned),
+                    _ => None,
+                };
+                match name {
+                    Some(name) => self.out.push_str(&name),
+                    None => { let _ = write!(self.out, "{}", number); },
+                }
+            },
+            Value::Message(_) | Value::List(_) | Value::Map(_) => {
+                panic!("invalid value for field {}", field.name())
+            },
+        }
+        self.out.push('\n');
+    }
+
+    fn print_unknown_fields(&mut self, unknown_fields: &UnknownFields) {
+        for field in unknown_fields {
+            match *field.value() {
+                UnknownValue::Group(ref fields) => {
+                    self.open(&field.tag().to_string());
+                    self.print_unknown_fields(fields);
+                    self.close();
+                    continue;
+                },
+                _ => {
+                    self.push_indent();
+                    let _ = write!(self.out, "{}: ", field.tag());
+                },
+            }
+            match *field.value() {
+                UnknownValue::Varint(value) => { let _ = write!(self.out, "{}", value); },
+                UnknownValue::ThirtyTwoBit(value) => { let _ = write!(self.out, "0x{:08x}", value); },
+                UnknownValue::SixtyFourBit(value) => { let _ = write!(self.out, "0x{:016x}", value); },
+                UnknownValue::LengthDelimited(ref value) => write_bytes(value, &mut self.out),
+                UnknownValue::Group(_) => unreachable!(),
+            }
+            self.out.push('\n');
+        }
+    }
+}
+
+fn write_float(is_nan: bool, is_infinite: bool, is_negative: bool, value: String, out: &mut String) {
+    if is_nan {
+        out.push_str("nan");
+    } else if is_infinite {
+        out.push_str(if is_negative { "-inf" } else { "inf" });
+    } else {
+        out.push_str(&value);
+    }
+}
+
+/// Writes a quoted string, escaping quotes, backslashes and control characters. Other
+/// characters are written as is.
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        if c.is_ascii() {
+            write_escaped_byte(c as u8, out);
+        } else {
+            out.push(c);
+        }
+    }
+    out.push('"');
+}
+
+/// Writes quoted bytes, escaping every byte which is not printable ASCII.
+fn write_bytes(value: &[u8], out: &mut String) {
+    out.push('"');
+    for &b in value {
+        write_escaped_byte(b, out);
+    }
+    out.push('"');
+}
+
+fn write_escaped_byte(b: u8, out: &mut String) {
+    match b {
+        b'\n' => out.push_str("\\n"),
+        b'\r' => out.push_str("\\r"),
+        b'\t' => out.push_str("\\t"),
+        b'"' => out.push_str("\\\""),
+        b'\'' => out.push_str("\\'"),
+        b'\\' => out.push_str("\\\\"),
+        0x20..=0x7e => out.push(b as char),
+        b => { let _ = write!(out, "\\{:03o}", b); },
+    }
+}
@@ -15,7 +15,7 @@ use flate2::bufread::GzDecoder;
 use tar::Archive;
 use tempdir::TempDir;
 
-const VERSION: &'static str = "3.6.0.1";
+const VERSION: &'static str = "3.8.0";
 
 static TEST_PROTOS: &[&str] = &[
     "test_messages_proto2.proto",
@@ -78,15 +78,21 @@ fn main() {
     // that encode/decode roundtrips can use encoded output for comparison. Otherwise trying to
     // compare based on the Rust PartialEq implementations is difficult, due to presence of NaN
     // values.
-    let mut config = prost_build::Config::new();
-    config.btree_map(&["."]);
-
+    //
+    // Unknown fields are preserved in the conformance test messages, so that they survive a
+    // decode/encode roundtrip. The conformance test messages also support the JSON mapping and
+    // the text format, which are exercised by the JSON and text format conformance tests.
     prost_build::Config::new()
         .btree_map(&["."])
+        .preserve_unknown_fields(&[".protobuf_test_messages"])
+        .json(&[".protobuf_test_messages"])
+        .text_format(&[".protobuf_test_messages"])
         .compile_protos(&[
             test_includes.join("test_messages_proto2.proto"),
             test_includes.join("test_messages_proto3.proto"),
             test_includes.join("unittest.proto"),
+            test_includes.join("unittest_import.proto"),
+            test_includes.join("unittest_import_public.proto"),
         ], &[include_dir.to_path_buf()]).unwrap();
 
     // Emit an environment variable with the path to the build so that it can be located in the
@@ -0,0 +1,170 @@
+//! A UTF-8 string type backed by `Bytes`.
+
+use alloc::string::String;
+use core::borrow::Borrow;
+use core::fmt;
+use core::ops::Deref;
+use core::str;
+
+use bytes::Bytes;
+
+use encoding::StringAdapter;
+
+/// An immutable UTF-8 encoded string backed by a reference-counted `Bytes` buffer.
+///
+/// `ByteString` can be used as the Rust representation of a Protobuf `string` field. When a
+/// message is decoded from a `Bytes` buffer with `Message::decode_bytes`, `ByteString` fields
+/// share memory with the buffer instead of copying out of it.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteString {
+    bytes: Bytes,
+}
+
+impl ByteString {
+
+    /// Creates a new empty `ByteString`.
+    pub fn new() -> ByteString {
+        ByteString::default()
+    }
+
+    /// Creates a new `ByteString` from a static string, without copying.
+    pub fn from_static(value: &'static str) -> ByteString {
+        ByteString {
+            bytes: Bytes::from_static(value.as_bytes()),
+        }
+    }
+
+    /// Converts a `Bytes` buffer to a `ByteString`, without copying.
+    ///
+    /// Returns an error if the buffer is not valid UTF-8.
+    pub fn from_utf8(bytes: Bytes) -> Result<ByteString, str::Utf8Error> {
+        str::from_utf8(&bytes)?;
+        Ok(ByteString { bytes })
+    }
+
+    /// Converts a `Bytes` buffer to a `ByteString` without checking that it is valid UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must contain valid UTF-8. `ByteString` derefs to `str` without checking its
+    /// contents again, so invalid UTF-8 results in undefined behavior when the string is used.
+    pub unsafe fn from_utf8_unchecked(bytes: Bytes) -> ByteString {
+        ByteString { bytes }
+    }
+
+    /// Returns the string as a string slice.
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.bytes) }
+    }
+
+    /// Returns the underlying bytes of the string.
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Converts the string into its underlying bytes.
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    /// Returns the length of the string, in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns `true` if the string has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Truncates the string to a length of zero.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+impl StringAdapter for ByteString {
+    type Bytes = Bytes;
+
+    unsafe fn as_mut_bytes(&mut self) -> &mut Bytes {
+        &mut self.bytes
+    }
+}
+
+impl Deref for ByteString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ByteString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<[u8]> for ByteString {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Borrow<str> for ByteString {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<String> for ByteString {
+    fn from(value: String) -> ByteString {
+        ByteString {
+            bytes: Bytes::from(value),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ByteString {
+    fn from(value: &'a str) -> ByteString {
+        ByteString {
+            bytes: Bytes::from(value),
+        }
+    }
+}
+
+impl From<ByteString> for Bytes {
+    fn from(value: ByteString) -> Bytes {
+        value.bytes
+    }
+}
+
+impl PartialEq<str> for ByteString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for ByteString {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for ByteString {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl fmt::Debug for ByteString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ByteString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
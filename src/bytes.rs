@@ -0,0 +1,320 @@
+//! A minimal replacement for the buffer traits of the `bytes` crate, used when the `std` feature
+//! is disabled. `bytes` 0.4 requires `std`, so it can't be used by `#![no_std]` builds.
+//!
+//! The traits have the same names and methods as their counterparts in `bytes`, so code which is
+//! generic over `Buf` and `BufMut`, including generated code, builds with either. Only the methods
+//! used by `prost` are provided.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp;
+use core::ptr;
+use core::slice;
+use core::usize;
+
+/// A buffer from which bytes are read sequentially.
+pub trait Buf {
+
+    /// Returns the number of bytes between the current position and the end of the buffer.
+    fn remaining(&self) -> usize;
+
+    /// Returns a slice of the buffer starting at the current position, which may be shorter than
+    /// `remaining`. The slice is only empty if `remaining` is 0.
+    fn bytes(&self) -> &[u8];
+
+    /// Advances the current position of the buffer by `cnt` bytes.
+    ///
+    /// Panics if `cnt` is greater than `remaining`.
+    fn advance(&mut self, cnt: usize);
+
+    /// Returns `true` if there are any bytes remaining in the buffer.
+    fn has_remaining(&self) -> bool {
+        self.remaining() > 0
+    }
+
+    /// Copies bytes from the buffer into `dst`, and advances the buffer past them.
+    ///
+    /// Panics if fewer than `dst.len()` bytes remain in the buffer.
+    fn copy_to_slice(&mut self, dst: &mut [u8]) {
+        assert!(self.remaining() >= dst.len(), "buffer underflow");
+        let mut off = 0;
+        while off < dst.len() {
+            let cnt = {
+                let src = self.bytes();
+                let cnt = cmp::min(src.len(), dst.len() - off);
+                dst[off..off + cnt].copy_from_slice(&src[..cnt]);
+                cnt
+            };
+            off += cnt;
+            self.advance(cnt);
+        }
+    }
+
+    fn get_u8(&mut self) -> u8 {
+        let mut buf = [0; 1];
+        self.copy_to_slice(&mut buf);
+        buf[0]
+    }
+
+    fn get_u32_le(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.copy_to_slice(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn get_u64_le(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.copy_to_slice(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn get_i32_le(&mut self) -> i32 {
+        self.get_u32_le() as i32
+    }
+
+    fn get_i64_le(&mut self) -> i64 {
+        self.get_u64_le() as i64
+    }
+
+    fn get_f32_le(&mut self) -> f32 {
+        f32::from_bits(self.get_u32_le())
+    }
+
+    fn get_f64_le(&mut self) -> f64 {
+        f64::from_bits(self.get_u64_le())
+    }
+}
+
+/// A buffer to which bytes are written sequentially.
+pub trait BufMut {
+
+    /// Returns the number of bytes which can be written to the buffer.
+    fn remaining_mut(&self) -> usize;
+
+    /// Advances the current position of the buffer by `cnt` bytes.
+    ///
+    /// This is unsafe because the caller must have written to the bytes being advanced over,
+    /// through the slice returned by `bytes_mut`.
+    unsafe fn advance_mut(&mut self, cnt: usize);
+
+    /// Returns a mutable slice of the buffer starting at the current position, which may be
+    /// shorter than `remaining_mut`. The slice is only empty if `remaining_mut` is 0.
+    ///
+    /// This is unsafe to match `bytes::BufMut::bytes_mut`, which may return uninitialized memory.
+    /// The slices returned by the implementations in this module are always initialized.
+    unsafe fn bytes_mut(&mut self) -> &mut [u8];
+
+    /// Returns `true` if there is space remaining in the buffer.
+    fn has_remaining_mut(&self) -> bool {
+        self.remaining_mut() > 0
+    }
+
+    /// Writes all of `src` to the buffer.
+    ///
+    /// Panics if the buffer has fewer than `src.len()` bytes remaining.
+    fn put_slice(&mut self, src: &[u8]) {
+        assert!(self.remaining_mut() >= src.len(), "buffer overflow");
+        let mut off = 0;
+        while off < src.len() {
+            let cnt;
+            unsafe {
+                let dst = self.bytes_mut();
+                cnt = cmp::min(dst.len(), src.len() - off);
+                dst[..cnt].copy_from_slice(&src[off..off + cnt]);
+            }
+            unsafe { self.advance_mut(cnt); }
+            off += cnt;
+        }
+    }
+
+    fn put_u8(&mut self, n: u8) {
+        self.put_slice(&[n])
+    }
+
+    fn put_u32_le(&mut self, n: u32) {
+        self.put_slice(&n.to_le_bytes())
+    }
+
+    fn put_u64_le(&mut self, n: u64) {
+        self.put_slice(&n.to_le_bytes())
+    }
+
+    fn put_i32_le(&mut self, n: i32) {
+        self.put_u32_le(n as u32)
+    }
+
+    fn put_i64_le(&mut self, n: i64) {
+        self.put_u64_le(n as u64)
+    }
+
+    fn put_f32_le(&mut self, n: f32) {
+        self.put_u32_le(n.to_bits())
+    }
+
+    fn put_f64_le(&mut self, n: f64) {
+        self.put_u64_le(n.to_bits())
+    }
+}
+
+/// Conversion into a `Buf`.
+pub trait IntoBuf {
+
+    /// The buffer type.
+    type Buf: Buf;
+
+    /// Creates a buffer from the value.
+    fn into_buf(self) -> Self::Buf;
+}
+
+/// A `Buf` over an in-memory byte slice, which tracks the current position.
+#[derive(Clone, Debug, Default)]
+pub struct Cursor<T> {
+    inner: T,
+    pos: u64,
+}
+
+impl <T> Cursor<T> {
+
+    /// Creates a new cursor positioned at the start of `inner`.
+    pub fn new(inner: T) -> Cursor<T> {
+        Cursor { inner, pos: 0 }
+    }
+
+    /// Returns a reference to the underlying value.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the underlying value.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns the current position of the cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Sets the current position of the cursor.
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+}
+
+impl <T> Buf for Cursor<T> where T: AsRef<[u8]> {
+    fn remaining(&self) -> usize {
+        self.inner.as_ref().len().saturating_sub(self.pos as usize)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        let slice = self.inner.as_ref();
+        &slice[cmp::min(self.pos as usize, slice.len())..]
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining(), "cannot advance past the end of the buffer");
+        self.pos += cnt as u64;
+    }
+}
+
+impl <'a, T> Buf for &'a mut T where T: Buf + ?Sized {
+    fn remaining(&self) -> usize {
+        (**self).remaining()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        (**self).bytes()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        (**self).advance(cnt)
+    }
+}
+
+impl BufMut for Vec<u8> {
+    fn remaining_mut(&self) -> usize {
+        usize::MAX - self.len()
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        let len = self.len() + cnt;
+        assert!(len <= self.capacity(), "cannot advance past the end of the buffer");
+        self.set_len(len);
+    }
+
+    unsafe fn bytes_mut(&mut self) -> &mut [u8] {
+        if self.capacity() == self.len() {
+            self.reserve(64);
+        }
+        // The spare capacity is zeroed, so that the returned slice is initialized.
+        let len = self.len();
+        let spare = self.capacity() - len;
+        let ptr = self.as_mut_ptr().add(len);
+        ptr::write_bytes(ptr, 0, spare);
+        slice::from_raw_parts_mut(ptr, spare)
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.extend_from_slice(src);
+    }
+}
+
+impl <'a, T> BufMut for &'a mut T where T: BufMut + ?Sized {
+    fn remaining_mut(&self) -> usize {
+        (**self).remaining_mut()
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        (**self).advance_mut(cnt)
+    }
+
+    unsafe fn bytes_mut(&mut self) -> &mut [u8] {
+        (**self).bytes_mut()
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        (**self).put_slice(src)
+    }
+}
+
+impl <T> IntoBuf for T where T: Buf {
+    type Buf = T;
+    fn into_buf(self) -> T {
+        self
+    }
+}
+
+impl <'a> IntoBuf for &'a [u8] {
+    type Buf = Cursor<&'a [u8]>;
+    fn into_buf(self) -> Cursor<&'a [u8]> {
+        Cursor::new(self)
+    }
+}
+
+impl <'a> IntoBuf for &'a Vec<u8> {
+    type Buf = Cursor<&'a [u8]>;
+    fn into_buf(self) -> Cursor<&'a [u8]> {
+        Cursor::new(&self[..])
+    }
+}
+
+impl <'a> IntoBuf for &'a str {
+    type Buf = Cursor<&'a [u8]>;
+    fn into_buf(self) -> Cursor<&'a [u8]> {
+        Cursor::new(self.as_bytes())
+    }
+}
+
+impl IntoBuf for Vec<u8> {
+    type Buf = Cursor<Vec<u8>>;
+    fn into_buf(self) -> Cursor<Vec<u8>> {
+        Cursor::new(self)
+    }
+}
+
+impl IntoBuf for String {
+    type Buf = Cursor<Vec<u8>>;
+    fn into_buf(self) -> Cursor<Vec<u8>> {
+        Cursor::new(self.into_bytes())
+    }
+}
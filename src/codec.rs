@@ -0,0 +1,401 @@
+//! A `tokio-io` codec for streams of length-delimited messages.
+//!
+//! This module is available with the `codec` feature. `ProstCodec` implements the
+//! `tokio_io::codec::{Decoder, Encoder}` traits, so that it can be used with `Framed` to turn an
+//! `AsyncRead + AsyncWrite` transport into a `Stream` of incoming messages and a `Sink` of
+//! outgoing messages.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut, BytesMut, IntoBuf};
+use tokio_io::codec::{Decoder, Encoder};
+
+use DecodeError;
+use DecodeOptions;
+use EncodeError;
+use Message;
+use encoding::{encode_varint, encoded_len_varint, SizeCache};
+use stream::{decode_delimiter, DEFAULT_MAX_FRAME_SIZE, MAX_RESERVE};
+
+/// The encoding of the length prefix which precedes each message in the stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// A varint prefix, as written by `Message::encode_length_delimited`.
+    Varint,
+    /// A 4 byte little-endian prefix, as in the Protobuf `fixed32` encoding.
+    Fixed32,
+}
+
+/// An error which occurs while encoding or decoding a stream of messages with `ProstCodec`.
+#[derive(Debug)]
+pub enum CodecError {
+    /// The underlying transport failed.
+    Io(io::Error),
+    /// A message could not be decoded.
+    Decode(DecodeError),
+    /// A message could not be encoded.
+    Encode(EncodeError),
+    /// The length of a message exceeds the codec's maximum frame length.
+    FrameTooLarge {
+        /// The length of the message.
+        length: u64,
+        /// The codec's maximum frame length.
+        max_frame_length: usize,
+    },
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::Io(ref error) => write!(f, "I/O error: {}", error),
+            CodecError::Decode(ref error) => error.fmt(f),
+            CodecError::Encode(ref error) => error.fmt(f),
+            CodecError::FrameTooLarge { length, max_frame_length } => {
+                write!(f, "frame length {} exceeds maximum frame length {}", length, max_frame_length)
+            },
+        }
+    }
+}
+
+impl error::Error for CodecError {
+    fn description(&self) -> &str {
+        match *self {
+            CodecError::Io(_) => "I/O error",
+            CodecError::Decode(_) => "failed to decode Protobuf message",
+            CodecError::Encode(_) => "failed to encode Protobuf message",
+            CodecError::FrameTooLarge { .. } => "frame length exceeds maximum frame length",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            CodecError::Io(ref error) => Some(error),
+            CodecError::Decode(ref error) => Some(error),
+            CodecError::Encode(ref error) => Some(error),
+            CodecError::FrameTooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> CodecError {
+        CodecError::Io(error)
+    }
+}
+
+impl From<DecodeError> for CodecError {
+    fn from(error: DecodeError) -> CodecError {
+        CodecError::Decode(error)
+    }
+}
+
+impl From<EncodeError> for CodecError {
+    fn from(error: EncodeError) -> CodecError {
+        CodecError::Encode(error)
+    }
+}
+
+impl From<CodecError> for io::Error {
+    fn from(error: CodecError) -> io::Error {
+        match error {
+            CodecError::Io(error) => error,
+            CodecError::Decode(error) => error.into(),
+            CodecError::Encode(error) => error.into(),
+            error @ CodecError::FrameTooLarge { .. } => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+/// A codec which decodes incoming messages of type `In` and encodes outgoing messages of type
+/// `Out`, each preceded by its length.
+///
+/// ```
+/// # extern crate prost;
+/// # extern crate tokio_io;
+/// # use prost::codec::{LengthPrefix, ProstCodec};
+/// # use tokio_io::codec::Decoder;
+/// # fn main() {
+/// let mut codec = ProstCodec::<String, String>::new();
+/// codec.max_frame_length(1024)
+///      .length_prefix(LengthPrefix::Fixed32);
+/// let mut buf = [4, 0, 0, 0, 0x0a, 0x02, b'h', b'i'][..].into();
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some("hi".to_string()));
+/// # }
+/// ```
+pub struct ProstCodec<In, Out> {
+    max_frame_length: usize,
+    length_prefix: LengthPrefix,
+    options: DecodeOptions,
+    /// The length of the next incoming message, once its prefix has been decoded.
+    frame_len: Option<usize>,
+    marker: PhantomData<fn(Out) -> In>,
+}
+
+impl <In, Out> ProstCodec<In, Out> where In: Message + Default, Out: Message {
+
+    /// Creates a new codec with varint length prefixes.
+    pub fn new() -> ProstCodec<In, Out> {
+        ProstCodec {
+            max_frame_length: DEFAULT_MAX_FRAME_SIZE,
+            length_prefix: LengthPrefix::Varint,
+            options: DecodeOptions::default(),
+            frame_len: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum encoded length of a single message, in either direction.
+    ///
+    /// Decoding a longer message fails with `CodecError::FrameTooLarge` before it is buffered,
+    /// and so does encoding one. Defaults to `prost::stream::DEFAULT_MAX_FRAME_SIZE`.
+    pub fn max_frame_length(&mut self, max_frame_length: usize) -> &mut Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Sets the encoding of the length prefixes. Defaults to `LengthPrefix::Varint`.
+    pub fn length_prefix(&mut self, length_prefix: LengthPrefix) -> &mut Self {
+        self.length_prefix = length_prefix;
+        self
+    }
+
+    /// Sets the options used to decode incoming messages.
+    pub fn decode_options(&mut self, options: DecodeOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Decodes the length prefix from the start of the buffer, returning the frame length and
+    /// the length of the prefix, or `None` if the buffer ends before the prefix does.
+    fn decode_prefix(&self, src: &BytesMut) -> Result<Option<(u64, usize)>, CodecError> {
+        match self.length_prefix {
+            LengthPrefix::Varint => Ok(decode_delimiter(src)?),
+            LengthPrefix::Fixed32 if src.len() < 4 => Ok(None),
+            LengthPrefix::Fixed32 => Ok(Some((u64::from((&src[..4]).into_buf().get_u32_le()), 4))),
+        }
+    }
+}
+
+impl <In, Out> Default for ProstCodec<In, Out> where In: Message + Default, Out: Message {
+    fn default() -> ProstCodec<In, Out> {
+        ProstCodec::new()
+    }
+}
+
+impl <In, Out> Decoder for ProstCodec<In, Out> where In: Message + Default, Out: Message {
+    type Item = In;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<In>, CodecError> {
+        let frame_len = match self.frame_len {
+            Some(frame_len) => frame_len,
+            None => {
+                let (frame_len, prefix_len) = match self.decode_prefix(src)? {
+                    Some(prefix) => prefix,
+                    None => return Ok(None),
+                };
+                if frame_len > self.max_frame_length as u64 {
+                    return Err(CodecError::FrameTooLarge {
+                        length: frame_len,
+                        max_frame_length: self.max_frame_length,
+                    });
+                }
+                src.advance(prefix_len);
+                let frame_len = frame_len as usize;
+                self.frame_len = Some(frame_len);
+                frame_len
+            },
+        };
+
+        if src.len() < frame_len {
+            // A length prefix alone can't force a large allocation; the buffer grows as the
+            // rest of the frame arrives.
+            let additional = frame_len - src.len();
+            src.reserve(additional.min(MAX_RESERVE));
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_len);
+        self.frame_len = None;
+        Ok(Some(In::decode_with_options(&frame[..], &self.options)?))
+    }
+}
+
+impl <In, Out> Encoder for ProstCodec<In, Out> where In: Message + Default, Out: Message {
+    type Item = Out;
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Out, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let mut cache = SizeCache::new();
+        let len = item.cache_sizes(&mut cache);
+        if len > self.max_frame_length {
+            return Err(CodecError::FrameTooLarge {
+                length: len as u64,
+                max_frame_length: self.max_frame_length,
+            });
+        }
+
+        match self.length_prefix {
+            LengthPrefix::Varint => {
+                dst.reserve(encoded_len_varint(len as u64) + len);
+                encode_varint(len as u64, dst);
+            },
+            LengthPrefix::Fixed32 => {
+                if len > u32::max_value() as usize {
+                    return Err(CodecError::FrameTooLarge {
+                        length: len as u64,
+                        max_frame_length: u32::max_value() as usize,
+                    });
+                }
+                dst.reserve(4 + len);
+                dst.put_u32_le(len as u32);
+            },
+        }
+        item.encode_cached(dst, &mut cache);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+
+    use futures::{Async, Future, Poll, Sink, Stream};
+    use tokio_io::{AsyncRead, AsyncWrite};
+
+    use super::*;
+
+    /// One end of an in-memory duplex stream. Reads return `WouldBlock` when no data is buffered.
+    struct Duplex {
+        incoming: Rc<RefCell<VecDeque<u8>>>,
+        outgoing: Rc<RefCell<VecDeque<u8>>>,
+    }
+
+    fn duplex() -> (Duplex, Duplex) {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        (Duplex { incoming: a.clone(), outgoing: b.clone() }, Duplex { incoming: b, outgoing: a })
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut incoming = self.incoming.borrow_mut();
+            if incoming.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let len = buf.len().min(incoming.len());
+            for (dst, src) in buf.iter_mut().zip(incoming.drain(..len)) {
+                *dst = src;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.borrow_mut().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Duplex {}
+
+    impl AsyncWrite for Duplex {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn roundtrip(length_prefix: LengthPrefix) {
+        let (client, server) = duplex();
+        let mut codec = ProstCodec::<u64, String>::new();
+        codec.length_prefix(length_prefix);
+        let client = codec.framed(client);
+        let mut codec = ProstCodec::<String, u64>::new();
+        codec.length_prefix(length_prefix);
+        let server = codec.framed(server);
+
+        let long = "x".repeat(300);
+        let client = client.send("hello".to_string()).wait().unwrap()
+                           .send(long.clone()).wait().unwrap();
+        let (message, server) = server.into_future().wait().map_err(|(error, _)| error).unwrap();
+        assert_eq!(message, Some("hello".to_string()));
+        let (message, server) = server.into_future().wait().map_err(|(error, _)| error).unwrap();
+        assert_eq!(message, Some(long));
+
+        let server = server.send(42).wait().unwrap();
+        let (message, _) = client.into_future().wait().map_err(|(error, _)| error).unwrap();
+        assert_eq!(message, Some(42));
+        drop(server);
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        roundtrip(LengthPrefix::Varint);
+    }
+
+    #[test]
+    fn fixed32_roundtrip() {
+        roundtrip(LengthPrefix::Fixed32);
+    }
+
+    #[test]
+    fn partial_input() {
+        let mut codec = ProstCodec::<String, String>::new();
+        let mut encoded = BytesMut::new();
+        codec.encode("partial".to_string(), &mut encoded).unwrap();
+
+        let mut src = BytesMut::new();
+        for &b in &encoded[..encoded.len() - 1] {
+            src.extend_from_slice(&[b]);
+            assert_eq!(codec.decode(&mut src).unwrap(), None);
+        }
+        src.extend_from_slice(&encoded[encoded.len() - 1..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some("partial".to_string()));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn large_frame_reservation() {
+        let mut codec = ProstCodec::<String, String>::new();
+        codec.length_prefix(LengthPrefix::Fixed32);
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(DEFAULT_MAX_FRAME_SIZE as u32);
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert!(src.capacity() <= 2 * MAX_RESERVE);
+    }
+
+    #[test]
+    fn max_frame_length() {
+        let mut codec = ProstCodec::<String, String>::new();
+        codec.max_frame_length(4);
+
+        match codec.encode("abcde".to_string(), &mut BytesMut::new()) {
+            Err(CodecError::FrameTooLarge { length: 7, max_frame_length: 4 }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let mut src = BytesMut::from(&[0x80, 0x01][..]);
+        match codec.decode(&mut src) {
+            Err(CodecError::FrameTooLarge { length: 128, max_frame_length: 4 }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let mut src = BytesMut::from(&[0xff; 10][..]);
+        match codec.decode(&mut src) {
+            Err(CodecError::Decode(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -2,18 +2,29 @@
 //!
 //! Meant to be used only from `Message` implementations.
 
-use std::cmp::min;
-use std::str;
-use std::u32;
-use std::usize;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::cmp::min;
+use core::str;
+use core::u32;
+use core::usize;
 
 use bytes::{
     Buf,
     BufMut,
+    IntoBuf,
+};
+#[cfg(feature = "std")]
+use bytes::{
+    Bytes,
+    BytesMut,
 };
 
 use DecodeError;
+use DecodeErrorKind;
 use Message;
+use extension::{AnyExtension, ExtensionRegistry};
 
 /// Encodes an integer value into LEB128 variable length format, and writes it to the buffer.
 /// The buffer must have enough remaining space (maximum 10 bytes).
@@ -58,7 +69,7 @@ pub fn decode_varint<B>(buf: &mut B) -> Result<u64, DecodeError> where B: Buf {
             let bytes = buf.bytes();
             let len = bytes.len();
             if len == 0 {
-                return Err(DecodeError::new("invalid varint"));
+                return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "invalid varint"));
             }
 
             let byte = bytes[0];
@@ -116,7 +127,7 @@ fn decode_varint_slice(bytes: &[u8]) -> Result<(u64, usize), DecodeError> {
     b = bytes[9]; part2 += u32::from(b) <<  7; if b < 0x80 { return Ok((value + (u64::from(part2) << 56), 10)) };
 
     // We have overrun the maximum size of a varint (10 bytes). Assume the data is corrupt.
-    Err(DecodeError::new("invalid varint"))
+    Err(DecodeError::with_kind(DecodeErrorKind::InvalidVarint, "invalid varint"))
 }
 
 /// Decodes a LEB128-encoded variable length integer from the buffer, advancing the buffer as
@@ -124,7 +135,8 @@ fn decode_varint_slice(bytes: &[u8]) -> Result<(u64, usize), DecodeError> {
 #[inline(never)]
 fn decode_varint_slow<B>(buf: &mut B) -> Result<u64, DecodeError> where B: Buf {
     let mut value = 0;
-    for count in 0..min(10, buf.remaining()) {
+    let len = min(10, buf.remaining());
+    for count in 0..len {
         let byte = buf.get_u8();
         value |= u64::from(byte & 0x7F) << (count * 7);
         if byte <= 0x7F {
@@ -132,7 +144,9 @@ fn decode_varint_slow<B>(buf: &mut B) -> Result<u64, DecodeError> where B: Buf {
         }
     }
 
-    Err(DecodeError::new("invalid varint"))
+    // The buffer either ended in the middle of the varint, or the varint overran the maximum size.
+    let kind = if len < 10 { DecodeErrorKind::Truncated } else { DecodeErrorKind::InvalidVarint };
+    Err(DecodeError::with_kind(kind, "invalid varint"))
 }
 
 /// Returns the encoded length of the value in LEB128 variable length format.
@@ -144,13 +158,22 @@ pub fn encoded_len_varint(value: u64) -> usize {
     ((((value | 1).leading_zeros() ^ 63) * 9 + 73) / 64) as usize
 }
 
+/// The wire type of an encoded field, which determines how its value is delimited.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(u8)]
 pub enum WireType {
+    /// A varint encoded integer.
     Varint = 0,
+    /// A little-endian 64-bit value.
     SixtyFourBit = 1,
+    /// A length-prefixed value.
     LengthDelimited = 2,
-    ThirtyTwoBit = 5
+    /// The start of a group.
+    StartGroup = 3,
+    /// The end of a group.
+    EndGroup = 4,
+    /// A little-endian 32-bit value.
+    ThirtyTwoBit = 5,
 }
 
 pub const MIN_TAG: u32 = 1;
@@ -164,8 +187,11 @@ impl WireType {
             0 => Ok(WireType::Varint),
             1 => Ok(WireType::SixtyFourBit),
             2 => Ok(WireType::LengthDelimited),
+            3 => Ok(WireType::StartGroup),
+            4 => Ok(WireType::EndGroup),
             5 => Ok(WireType::ThirtyTwoBit),
-            _ => Err(DecodeError::new(format!("invalid wire type value: {}", val))),
+            _ => Err(DecodeError::with_kind(DecodeErrorKind::InvalidWireType,
+                                            format!("invalid wire type value: {}", val))),
         }
     }
 }
@@ -185,13 +211,13 @@ pub fn encode_key<B>(tag: u32, wire_type: WireType, buf: &mut B) where B: BufMut
 pub fn decode_key<B>(buf: &mut B) -> Result<(u32, WireType), DecodeError> where B: Buf {
     let key = decode_varint(buf)?;
     if key > u64::from(u32::MAX) {
-        return Err(DecodeError::new(format!("invalid key value: {}", key)));
+        return Err(DecodeError::with_kind(DecodeErrorKind::InvalidTag, format!("invalid key value: {}", key)));
     }
     let wire_type = WireType::try_from(key as u8 & 0x07)?;
     let tag = key as u32 >> 3;
 
     if tag < MIN_TAG {
-        return Err(DecodeError::new("invalid tag value: 0"));
+        return Err(DecodeError::with_kind(DecodeErrorKind::InvalidTag, "invalid tag value: 0"));
     }
 
     Ok((tag, wire_type))
@@ -209,44 +235,288 @@ pub fn key_len(tag: u32) -> usize {
 #[inline]
 pub fn check_wire_type(expected: WireType, actual: WireType) -> Result<(), DecodeError> {
     if expected != actual {
-        return Err(DecodeError::new(format!("invalid wire type: {:?} (expected {:?})", actual, expected)));
+        return Err(DecodeError::with_kind(DecodeErrorKind::WireTypeMismatch,
+                                          format!("invalid wire type: {:?} (expected {:?})", actual, expected)));
+    }
+    Ok(())
+}
+
+/// Checks that the tag of an end group key matches the tag of the group being decoded, or
+/// returns an error result.
+#[inline]
+pub fn check_end_group(tag: u32, end_tag: u32) -> Result<(), DecodeError> {
+    if tag != end_tag {
+        return Err(DecodeError::with_kind(DecodeErrorKind::UnexpectedEndGroup,
+                                          format!("unexpected end group tag: {} (expected {})", end_tag, tag)));
     }
     Ok(())
 }
 
+/// The default maximum depth of nested messages which will be decoded before failing with a
+/// recursion limit error. This matches the default limit of the C++ Protobuf runtime.
+pub const RECURSION_LIMIT: u32 = 100;
+
+/// A reference counted pointer, used to share the extension registry between decode options and
+/// decode contexts.
+///
+/// `Arc` requires atomic compare-and-swap, which some embedded targets, such as `thumbv6m`, lack.
+/// `Rc` is used on those targets instead.
+#[cfg(target_has_atomic = "ptr")]
+pub type Shared<T> = ::alloc::sync::Arc<T>;
+#[cfg(not(target_has_atomic = "ptr"))]
+pub type Shared<T> = ::alloc::rc::Rc<T>;
+
+/// Additional information passed to every decode and merge function.
+///
+/// The context is passed by reference, so that decoding a field doesn't clone it. When decoding
+/// a nested message, pass it a new context created with `enter_recursion`.
+#[derive(Clone, Debug)]
+pub struct DecodeContext {
+    /// The maximum depth of nested messages.
+    recursion_limit: u32,
+    /// How many more times decoding can recurse in the current decode stack before the recursion
+    /// limit is reached.
+    recurse_count: u32,
+    /// The buffer being decoded, if it is a `Bytes`. `Bytes` fields are decoded as slices of the
+    /// source instead of being copied.
+    #[cfg(feature = "std")]
+    source: Option<Bytes>,
+    /// The extensions which are recognized while decoding.
+    extension_registry: Option<Shared<ExtensionRegistry>>,
+}
+
+impl Default for DecodeContext {
+    #[inline]
+    fn default() -> DecodeContext {
+        DecodeContext::new(RECURSION_LIMIT)
+    }
+}
+
+impl DecodeContext {
+
+    /// Creates a new decode context with the provided recursion limit.
+    #[inline]
+    pub fn new(recursion_limit: u32) -> DecodeContext {
+        DecodeContext {
+            recursion_limit: recursion_limit,
+            recurse_count: recursion_limit,
+            #[cfg(feature = "std")]
+            source: None,
+            extension_registry: None,
+        }
+    }
+
+    /// Sets the source buffer which the decoded data is read from.
+    ///
+    /// `Bytes` fields decoded with the context will share memory with the source buffer instead
+    /// of copying out of it.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn with_source(mut self, source: Bytes) -> DecodeContext {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the registry of extensions which are recognized while decoding.
+    #[inline]
+    pub fn with_extension_registry(mut self, registry: Shared<ExtensionRegistry>) -> DecodeContext {
+        self.extension_registry = Some(registry);
+        self
+    }
+
+    /// Returns a context for decoding a nested message.
+    ///
+    /// `limit_reached` should be checked before calling this method.
+    #[inline]
+    pub fn enter_recursion(&self) -> DecodeContext {
+        DecodeContext {
+            recursion_limit: self.recursion_limit,
+            recurse_count: self.recurse_count.saturating_sub(1),
+            #[cfg(feature = "std")]
+            source: self.source.clone(),
+            extension_registry: self.extension_registry.clone(),
+        }
+    }
+
+    /// Returns the registered extension of the message type with the provided field number, or
+    /// `None` if the extension is not registered.
+    #[inline]
+    pub fn extension(&self, extendee: TypeId, number: u32) -> Option<&'static dyn AnyExtension> {
+        self.extension_registry
+            .as_ref()
+            .and_then(|registry| registry.get(extendee, number))
+    }
+
+    /// Checks whether the recursion limit has been reached in the current decode stack, and
+    /// returns an error if so.
+    #[inline]
+    pub fn limit_reached(&self) -> Result<(), DecodeError> {
+        if self.recurse_count == 0 {
+            Err(DecodeError::with_kind(DecodeErrorKind::RecursionLimitReached,
+                                       format!("recursion limit of {} reached", self.recursion_limit)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns a slice of the source buffer holding the next `len` bytes of `buf`, or `None` if
+    /// the context has no source buffer, or `buf` is not currently reading from it.
+    ///
+    /// The buffer is not advanced.
+    #[cfg(feature = "std")]
+    pub fn source_slice<B>(&self, buf: &B, len: usize) -> Option<Bytes> where B: Buf {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return None,
+        };
+        let chunk = buf.bytes();
+        if chunk.len() < len {
+            return None;
+        }
+
+        // The chunk is only shared if it lies entirely within the source buffer.
+        let start = chunk.as_ptr() as usize;
+        let source_start = source.as_ptr() as usize;
+        if start < source_start || start + len > source_start + source.len() {
+            return None;
+        }
+        let offset = start - source_start;
+        Some(source.slice(offset, offset + len))
+    }
+}
+
+/// Returns the error for a required field which is not set.
+///
+/// The message and field are pushed on to the error's location stack by the caller.
+pub fn missing_required_field() -> DecodeError {
+    DecodeError::with_kind(DecodeErrorKind::MissingRequiredField, "missing required field")
+}
+
+/// The encoded lengths of the nested messages of a message being encoded.
+///
+/// Encoding a message requires the length of each nested message up front, for its length
+/// delimiter. Computing it with `Message::encoded_len` at every level of nesting walks each
+/// subtree once per enclosing message, so encoding a deeply nested message is quadratic.
+/// Instead, `Message::cache_sizes` walks the message once and records the length of every nested
+/// message in the order in which they are encoded, and `Message::encode_cached` then takes the
+/// lengths in the same order.
+///
+/// The cache also carries the encoding mode. In deterministic mode, map entries are encoded in
+/// key order rather than in the iteration order of the map, and include default keys and values.
+#[derive(Clone, Debug, Default)]
+pub struct SizeCache {
+    sizes: Vec<usize>,
+    next: usize,
+    deterministic: bool,
+}
+
+impl SizeCache {
+
+    /// Creates a new, empty size cache.
+    #[inline]
+    pub fn new() -> SizeCache {
+        SizeCache::default()
+    }
+
+    /// Creates a new, empty size cache for deterministic encoding.
+    #[inline]
+    pub fn deterministic() -> SizeCache {
+        SizeCache {
+            deterministic: true,
+            ..SizeCache::default()
+        }
+    }
+
+    /// Returns `true` if map entries are encoded in key order.
+    #[inline]
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Computes the length of a length-delimited value with `f`, recording it ahead of the lengths
+    /// which `f` records for the value's own nested messages.
+    #[inline]
+    pub fn cache_with<F>(&mut self, f: F) -> usize where F: FnOnce(&mut SizeCache) -> usize {
+        let index = self.sizes.len();
+        self.sizes.push(0);
+        let len = f(self);
+        self.sizes[index] = len;
+        len
+    }
+
+    /// Computes the encoded length of a nested message, recording it and the lengths of the
+    /// message's own nested messages.
+    #[inline]
+    pub fn cache_len<M>(&mut self, msg: &M) -> usize where M: Message {
+        self.cache_with(|cache| msg.cache_sizes(cache))
+    }
+
+    /// Returns the next recorded length.
+    ///
+    /// Panics if the lengths are taken in a different order than they were recorded in.
+    #[inline]
+    pub fn next_len(&mut self) -> usize {
+        let len = self.sizes[self.next];
+        self.next += 1;
+        len
+    }
+}
+
 /// Helper function which abstracts reading a length delimiter prefix followed
 /// by decoding values until the length of bytes is exhausted.
-pub fn merge_loop<T, M, B>(value: &mut T, buf: &mut B, mut merge: M) -> Result<(), DecodeError>
+pub fn merge_loop<T, M, B>(value: &mut T, buf: &mut B, ctx: &DecodeContext, mut merge: M) -> Result<(), DecodeError>
 where
-    M: FnMut(&mut T, &mut B) -> Result<(), DecodeError>,
+    M: FnMut(&mut T, &mut B, &DecodeContext) -> Result<(), DecodeError>,
     B: Buf {
         let len = decode_varint(buf)?;
         let remaining = buf.remaining();
         if len > remaining as u64 {
-            return Err(DecodeError::new("buffer underflow"))
+            return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"))
         }
 
         let limit = remaining - len as usize;
         while buf.remaining() > limit {
-            merge(value, buf)?;
+            merge(value, buf, ctx)?;
         }
 
         if buf.remaining() != limit {
-            return Err(DecodeError::new("delimited length exceeded"))
+            return Err(DecodeError::with_kind(DecodeErrorKind::LengthOverflow, "delimited length exceeded"))
         }
         Ok(())
 }
 
-pub fn skip_field<B>(wire_type: WireType, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+/// Skips the value of a field with the provided wire type and tag.
+///
+/// Groups are skipped along with all of their nested fields, up to and including the matching
+/// end group key.
+pub fn skip_field<B>(wire_type: WireType,
+                     tag: u32,
+                     buf: &mut B,
+                     ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
     let len = match wire_type {
         WireType::Varint => decode_varint(buf).map(|_| 0)?,
         WireType::ThirtyTwoBit => 4,
         WireType::SixtyFourBit => 8,
         WireType::LengthDelimited => decode_varint(buf)?,
+        WireType::StartGroup => {
+            ctx.limit_reached()?;
+            let ctx = ctx.enter_recursion();
+            loop {
+                let (inner_tag, inner_wire_type) = decode_key(buf)?;
+                if inner_wire_type == WireType::EndGroup {
+                    check_end_group(tag, inner_tag)?;
+                    break 0;
+                }
+                skip_field(inner_wire_type, inner_tag, buf, &ctx)?;
+            }
+        },
+        WireType::EndGroup => {
+            return Err(DecodeError::with_kind(DecodeErrorKind::UnexpectedEndGroup, "unexpected end group tag"));
+        },
     };
 
     if len > buf.remaining() as u64 {
-        return Err(DecodeError::new("buffer underflow"));
+        return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
     }
 
     buf.advance(len as usize);
@@ -272,13 +542,14 @@ macro_rules! merge_repeated_numeric {
      $merge_repeated:ident) => (
         pub fn $merge_repeated<B>(wire_type: WireType,
                                   values: &mut Vec<$ty>,
-                                  buf: &mut B)
+                                  buf: &mut B,
+                                  ctx: &DecodeContext)
                                   -> Result<(), DecodeError> where B: Buf {
             if wire_type == WireType::LengthDelimited {
                 // Packed.
-                merge_loop(values, buf, |values, buf| {
+                merge_loop(values, buf, ctx, |values, buf, ctx| {
                     let mut value = Default::default();
-                    $merge($wire_type, &mut value, buf)?;
+                    $merge($wire_type, &mut value, buf, ctx)?;
                     values.push(value);
                     Ok(())
                 })
@@ -286,7 +557,7 @@ macro_rules! merge_repeated_numeric {
                 // Unpacked.
                 check_wire_type($wire_type, wire_type)?;
                 let mut value = Default::default();
-                $merge(wire_type, &mut value, buf)?;
+                $merge(wire_type, &mut value, buf, ctx)?;
                 values.push(value);
                 Ok(())
             }
@@ -318,7 +589,10 @@ macro_rules! varint {
                 encode_varint($to_uint64, buf);
             }
 
-            pub fn merge<B>(wire_type: WireType, value: &mut $ty, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+            pub fn merge<B>(wire_type: WireType,
+                            value: &mut $ty,
+                            buf: &mut B,
+                            _ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
                 check_wire_type(WireType::Varint, wire_type)?;
                 let $from_uint64_value = decode_varint(buf)?;
                 *value = $from_uint64;
@@ -421,6 +695,202 @@ varint!(i64, sint64,
             ((value >> 1) as i64) ^ (-((value & 1) as i64))
         });
 
+/// Encoding functions for open enumeration fields, which are represented as `OpenEnum`.
+///
+/// Values which are not variants of the Rust enum are preserved as `OpenEnum::Unknown`.
+pub mod open_enum {
+    use ::encoding::*;
+    use Enumeration;
+    use OpenEnum;
+
+    pub fn encode<E, B>(tag: u32, value: &OpenEnum<E>, buf: &mut B) where E: Enumeration, B: BufMut {
+        int32::encode(tag, &value.to_i32(), buf);
+    }
+
+    pub fn merge<E, B>(wire_type: WireType,
+                       value: &mut OpenEnum<E>,
+                       buf: &mut B,
+                       ctx: &DecodeContext) -> Result<(), DecodeError> where E: Enumeration, B: Buf {
+        let mut raw = 0;
+        int32::merge(wire_type, &mut raw, buf, ctx)?;
+        *value = OpenEnum::from_i32(raw);
+        Ok(())
+    }
+
+    pub fn encode_repeated<E, B>(tag: u32, values: &[OpenEnum<E>], buf: &mut B)
+    where E: Enumeration, B: BufMut {
+        for value in values {
+            encode(tag, value, buf);
+        }
+    }
+
+    pub fn encode_packed<E, B>(tag: u32, values: &[OpenEnum<E>], buf: &mut B)
+    where E: Enumeration, B: BufMut {
+        if values.is_empty() { return; }
+
+        encode_key(tag, WireType::LengthDelimited, buf);
+        let len: usize = values.iter().map(|value| encoded_len_varint(value.to_i32() as u64)).sum();
+        encode_varint(len as u64, buf);
+
+        for value in values {
+            encode_varint(value.to_i32() as u64, buf);
+        }
+    }
+
+    pub fn merge_repeated<E, B>(wire_type: WireType,
+                                values: &mut Vec<OpenEnum<E>>,
+                                buf: &mut B,
+                                ctx: &DecodeContext) -> Result<(), DecodeError>
+    where E: Enumeration, B: Buf {
+        let mut raw = Vec::new();
+        int32::merge_repeated(wire_type, &mut raw, buf, ctx)?;
+        values.extend(raw.into_iter().map(OpenEnum::from_i32));
+        Ok(())
+    }
+
+    #[inline]
+    pub fn encoded_len<E>(tag: u32, value: &OpenEnum<E>) -> usize where E: Enumeration {
+        int32::encoded_len(tag, &value.to_i32())
+    }
+
+    #[inline]
+    pub fn encoded_len_repeated<E>(tag: u32, values: &[OpenEnum<E>]) -> usize where E: Enumeration {
+        key_len(tag) * values.len() + values.iter().map(|value| {
+            encoded_len_varint(value.to_i32() as u64)
+        }).sum::<usize>()
+    }
+
+    #[inline]
+    pub fn encoded_len_packed<E>(tag: u32, values: &[OpenEnum<E>]) -> usize where E: Enumeration {
+        if values.is_empty() {
+            0
+        } else {
+            let len = values.iter()
+                            .map(|value| encoded_len_varint(value.to_i32() as u64))
+                            .sum::<usize>();
+            key_len(tag) + encoded_len_varint(len as u64) + len
+        }
+    }
+}
+
+/// Encoding functions for closed (proto2) enumeration fields, which are represented as the Rust
+/// enum itself.
+///
+/// Decoded values which are not variants of the enum are not stored in the field. Instead they
+/// are added to the unknown fields of the containing message, if it has any, and are otherwise
+/// dropped.
+pub mod closed_enum {
+    use ::encoding::*;
+    use Enumeration;
+    use UnknownFields;
+    use UnknownValue;
+
+    /// Stores an unknown enumeration value as an unknown varint field.
+    fn merge_unknown(tag: u32, value: i32, unknown_fields: &mut Option<&mut UnknownFields>) {
+        if let Some(ref mut unknown_fields) = *unknown_fields {
+            unknown_fields.push(tag, UnknownValue::Varint(value as u64));
+        }
+    }
+
+    pub fn encode<E, B>(tag: u32, value: &E, buf: &mut B) where E: Enumeration, B: BufMut {
+        int32::encode(tag, &value.to_i32(), buf);
+    }
+
+    pub fn merge<E, B>(tag: u32,
+                       wire_type: WireType,
+                       value: &mut E,
+                       mut unknown_fields: Option<&mut UnknownFields>,
+                       buf: &mut B,
+                       ctx: &DecodeContext) -> Result<(), DecodeError> where E: Enumeration, B: Buf {
+        let mut raw = 0;
+        int32::merge(wire_type, &mut raw, buf, ctx)?;
+        match E::from_i32(raw) {
+            Some(known) => *value = known,
+            None => merge_unknown(tag, raw, &mut unknown_fields),
+        }
+        Ok(())
+    }
+
+    /// Merges a value into an optional field, which is left unchanged if the value is unknown.
+    pub fn merge_optional<E, B>(tag: u32,
+                                wire_type: WireType,
+                                value: &mut Option<E>,
+                                mut unknown_fields: Option<&mut UnknownFields>,
+                                buf: &mut B,
+                                ctx: &DecodeContext) -> Result<(), DecodeError>
+    where E: Enumeration, B: Buf {
+        let mut raw = 0;
+        int32::merge(wire_type, &mut raw, buf, ctx)?;
+        match E::from_i32(raw) {
+            Some(known) => *value = Some(known),
+            None => merge_unknown(tag, raw, &mut unknown_fields),
+        }
+        Ok(())
+    }
+
+    pub fn encode_repeated<E, B>(tag: u32, values: &[E], buf: &mut B) where E: Enumeration, B: BufMut {
+        for value in values {
+            encode(tag, value, buf);
+        }
+    }
+
+    pub fn encode_packed<E, B>(tag: u32, values: &[E], buf: &mut B) where E: Enumeration, B: BufMut {
+        if values.is_empty() { return; }
+
+        encode_key(tag, WireType::LengthDelimited, buf);
+        let len: usize = values.iter().map(|value| encoded_len_varint(value.to_i32() as u64)).sum();
+        encode_varint(len as u64, buf);
+
+        for value in values {
+            encode_varint(value.to_i32() as u64, buf);
+        }
+    }
+
+    /// Merges packed or unpacked values into a repeated field. Unknown values are recorded as
+    /// individual unpacked unknown fields.
+    pub fn merge_repeated<E, B>(tag: u32,
+                                wire_type: WireType,
+                                values: &mut Vec<E>,
+                                mut unknown_fields: Option<&mut UnknownFields>,
+                                buf: &mut B,
+                                ctx: &DecodeContext) -> Result<(), DecodeError>
+    where E: Enumeration, B: Buf {
+        let mut raw = Vec::new();
+        int32::merge_repeated(wire_type, &mut raw, buf, ctx)?;
+        for raw in raw {
+            match E::from_i32(raw) {
+                Some(known) => values.push(known),
+                None => merge_unknown(tag, raw, &mut unknown_fields),
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn encoded_len<E>(tag: u32, value: &E) -> usize where E: Enumeration {
+        int32::encoded_len(tag, &value.to_i32())
+    }
+
+    #[inline]
+    pub fn encoded_len_repeated<E>(tag: u32, values: &[E]) -> usize where E: Enumeration {
+        key_len(tag) * values.len() + values.iter().map(|value| {
+            encoded_len_varint(value.to_i32() as u64)
+        }).sum::<usize>()
+    }
+
+    #[inline]
+    pub fn encoded_len_packed<E>(tag: u32, values: &[E]) -> usize where E: Enumeration {
+        if values.is_empty() {
+            0
+        } else {
+            let len = values.iter()
+                            .map(|value| encoded_len_varint(value.to_i32() as u64))
+                            .sum::<usize>();
+            key_len(tag) + encoded_len_varint(len as u64) + len
+        }
+    }
+}
+
 /// Macro which emits a module containing a set of encoding functions for a
 /// fixed width numeric type.
 macro_rules! fixed_width {
@@ -438,10 +908,13 @@ macro_rules! fixed_width {
                 buf.$put(*value);
             }
 
-            pub fn merge<B>(wire_type: WireType, value: &mut $ty, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+            pub fn merge<B>(wire_type: WireType,
+                            value: &mut $ty,
+                            buf: &mut B,
+                            _ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
                 check_wire_type($wire_type, wire_type)?;
                 if buf.remaining() < $width {
-                    return Err(DecodeError::new("buffer underflow"));
+                    return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
                 }
                 *value = buf.$get();
                 Ok(())
@@ -521,28 +994,45 @@ fixed_width!(i32, 4, WireType::ThirtyTwoBit, sfixed32, put_i32_le, get_i32_le);
 fixed_width!(i64, 8, WireType::SixtyFourBit, sfixed64, put_i64_le, get_i64_le);
 
 /// Macro which emits encoding functions for a length-delimited type.
+///
+/// The functions are generic over the Rust representation of the type. Values are encoded from
+/// any type implementing `AsRef<$target>`, and decoded into any type implementing `$adapter`.
+/// Tests use the `$test_ty` representation.
 macro_rules! length_delimited {
-    ($ty:ty) => (
+    ($adapter:ident, $target:ty, $test_ty:ty) => (
 
-        encode_repeated!($ty);
+         pub fn encode_repeated<A, B>(tag: u32, values: &[A], buf: &mut B)
+         where A: AsRef<$target>,
+               B: BufMut {
+             for value in values {
+                 encode(tag, value, buf);
+             }
+         }
 
-         pub fn merge_repeated<B>(wire_type: WireType, values: &mut Vec<$ty>, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+         pub fn merge_repeated<A, B>(wire_type: WireType,
+                                     values: &mut Vec<A>,
+                                     buf: &mut B,
+                                     ctx: &DecodeContext) -> Result<(), DecodeError>
+         where A: $adapter,
+               B: Buf {
                 check_wire_type(WireType::LengthDelimited, wire_type)?;
                 let mut value = Default::default();
-                merge(wire_type, &mut value, buf)?;
+                merge(wire_type, &mut value, buf, ctx)?;
                 values.push(value);
                 Ok(())
          }
 
          #[inline]
-         pub fn encoded_len(tag: u32, value: &$ty) -> usize {
-             key_len(tag) + encoded_len_varint(value.len() as u64) + value.len()
+         pub fn encoded_len<A>(tag: u32, value: &A) -> usize where A: AsRef<$target> {
+             let len = value.as_ref().len();
+             key_len(tag) + encoded_len_varint(len as u64) + len
          }
 
          #[inline]
-         pub fn encoded_len_repeated(tag: u32, values: &[$ty]) -> usize {
+         pub fn encoded_len_repeated<A>(tag: u32, values: &[A]) -> usize where A: AsRef<$target> {
              key_len(tag) * values.len() + values.iter().map(|value| {
-                 encoded_len_varint(value.len() as u64) + value.len()
+                 let len = value.as_ref().len();
+                 encoded_len_varint(len as u64) + len
              }).sum::<usize>()
          }
 
@@ -557,99 +1047,286 @@ macro_rules! length_delimited {
             };
 
              quickcheck! {
-                 fn check(value: $ty, tag: u32) -> TestResult {
-                     super::test::check_type(value, tag, WireType::LengthDelimited,
-                                             encode, merge, encoded_len)
+                 fn check(value: $test_ty, tag: u32) -> TestResult {
+                     super::test::check_type::<$test_ty, $test_ty>(
+                         value, tag, WireType::LengthDelimited, encode, merge, encoded_len)
                  }
-                 fn check_repeated(value: Vec<$ty>, tag: u32) -> TestResult {
-                     super::test::check_collection_type(value, tag, WireType::LengthDelimited,
-                                                        encode_repeated, merge_repeated,
-                                                        encoded_len_repeated)
+                 fn check_repeated(value: Vec<$test_ty>, tag: u32) -> TestResult {
+                     super::test::check_collection_type::<_, [$test_ty], _, _, _>(
+                         value, tag, WireType::LengthDelimited, encode_repeated, merge_repeated,
+                         encoded_len_repeated)
                  }
              }
          }
     )
 }
 
+/// A type which can be decoded into as the Rust representation of a Protobuf `bytes` value.
+///
+/// Implemented for `Vec<u8>`, which always owns its data, and `Bytes`, which shares memory with
+/// the decoded buffer when possible.
+pub trait BytesAdapter: AsRef<[u8]> + Default + Sized {
+
+    /// Appends the next `len` bytes of the buffer to the value, and advances the buffer past them.
+    ///
+    /// The caller must ensure that the buffer has at least `len` bytes remaining.
+    fn append<B>(&mut self, len: usize, buf: &mut B, ctx: &DecodeContext) where B: Buf;
+}
+
+/// Copies the next `len` bytes of the buffer into `dst`, chunk by chunk.
+fn copy_to<B, F>(len: usize, buf: &mut B, mut dst: F) where B: Buf, F: FnMut(&[u8]) {
+    let mut remaining = len;
+    while remaining > 0 {
+        let len = {
+            let bytes = buf.bytes();
+            debug_assert!(!bytes.is_empty(), "Buf::bytes returned empty slice");
+            let len = min(remaining, bytes.len());
+            dst(&bytes[..len]);
+            len
+        };
+        remaining -= len;
+        buf.advance(len);
+    }
+}
+
+impl BytesAdapter for Vec<u8> {
+    fn append<B>(&mut self, len: usize, buf: &mut B, _ctx: &DecodeContext) where B: Buf {
+        self.reserve(len);
+        copy_to(len, buf, |bytes| self.extend_from_slice(bytes));
+    }
+}
+
+#[cfg(feature = "std")]
+impl BytesAdapter for Bytes {
+    fn append<B>(&mut self, len: usize, buf: &mut B, ctx: &DecodeContext) where B: Buf {
+        if self.is_empty() {
+            if let Some(bytes) = ctx.source_slice(buf, len) {
+                buf.advance(len);
+                *self = bytes;
+                return;
+            }
+        }
+
+        let mut value = BytesMut::with_capacity(self.len() + len);
+        value.extend_from_slice(self);
+        copy_to(len, buf, |bytes| value.extend_from_slice(bytes));
+        *self = value.freeze();
+    }
+}
+
+/// A type which can be decoded into as the Rust representation of a Protobuf `string` value.
+///
+/// Implemented for `String`, and `ByteString`, which is backed by `Bytes`.
+pub trait StringAdapter: AsRef<str> + Default + Sized {
+
+    /// The representation of the string's UTF-8 encoded bytes.
+    type Bytes: BytesAdapter;
+
+    /// Returns a mutable reference to the bytes of the string.
+    ///
+    /// This is unsafe because the caller must ensure that the bytes are valid UTF-8 before the
+    /// string is used again.
+    unsafe fn as_mut_bytes(&mut self) -> &mut Self::Bytes;
+}
+
+impl StringAdapter for String {
+    type Bytes = Vec<u8>;
+
+    unsafe fn as_mut_bytes(&mut self) -> &mut Vec<u8> {
+        self.as_mut_vec()
+    }
+}
+
+/// A buffer over a byte slice, from which borrowed messages are decoded.
+pub type SliceBuf<'a> = <&'a [u8] as IntoBuf>::Buf;
+
+/// Returns the next `len` bytes of a slice buffer as a subslice with the lifetime of the
+/// underlying slice, and advances the buffer past them.
+///
+/// The caller must ensure that the buffer has at least `len` bytes remaining.
+fn split_slice<'a>(len: usize, buf: &mut SliceBuf<'a>) -> &'a [u8] {
+    let slice: &'a [u8] = buf.get_ref();
+    let start = buf.position() as usize;
+    buf.set_position((start + len) as u64);
+    &slice[start..start + len]
+}
+
 pub mod string {
     use super::*;
 
-    pub fn encode<B>(tag: u32,
-                     value: &String,
-                     buf: &mut B) where B: BufMut {
+    pub fn encode<A, B>(tag: u32,
+                        value: &A,
+                        buf: &mut B) where A: AsRef<str>, B: BufMut {
+        let value = value.as_ref();
         encode_key(tag, WireType::LengthDelimited, buf);
         encode_varint(value.len() as u64, buf);
         buf.put_slice(value.as_bytes());
     }
-    pub fn merge<B>(wire_type: WireType,
-                    value: &mut String,
-                    buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+    pub fn merge<A, B>(wire_type: WireType,
+                       value: &mut A,
+                       buf: &mut B,
+                       ctx: &DecodeContext) -> Result<(), DecodeError> where A: StringAdapter, B: Buf {
         unsafe {
-            // String::as_mut_vec is unsafe because it doesn't check that the bytes
-            // inserted into it the resulting vec are valid UTF-8. We check
-            // explicitly in order to ensure this is safe.
-            super::bytes::merge(wire_type, value.as_mut_vec(), buf)?;
-            str::from_utf8(value.as_bytes()).map_err(|_| {
-                DecodeError::new("invalid string value: data is not UTF-8 encoded")
-            })?;
+            // StringAdapter::as_mut_bytes is unsafe because it doesn't check that the bytes
+            // inserted into the string are valid UTF-8. We check explicitly in order to ensure
+            // this is safe, and clear the value if the check fails.
+            let bytes = value.as_mut_bytes();
+            super::bytes::merge(wire_type, bytes, buf, ctx)?;
+            if str::from_utf8(bytes.as_ref()).is_err() {
+                *bytes = Default::default();
+                return Err(DecodeError::with_kind(DecodeErrorKind::InvalidUtf8,
+                                                  "invalid string value: data is not UTF-8 encoded"));
+            }
         }
         Ok(())
     }
 
-    length_delimited!(String);
+    /// Decodes a string which borrows from the buffer into `value`.
+    ///
+    /// Unlike `merge`, the previous value is replaced rather than appended to.
+    pub fn merge_borrowed<'a>(wire_type: WireType,
+                              value: &mut &'a str,
+                              buf: &mut SliceBuf<'a>,
+                              ctx: &DecodeContext) -> Result<(), DecodeError> {
+        let mut bytes: &'a [u8] = &[];
+        super::bytes::merge_borrowed(wire_type, &mut bytes, buf, ctx)?;
+        *value = str::from_utf8(bytes).map_err(|_| {
+            DecodeError::with_kind(DecodeErrorKind::InvalidUtf8, "invalid string value: data is not UTF-8 encoded")
+        })?;
+        Ok(())
+    }
+
+    pub fn merge_repeated_borrowed<'a>(wire_type: WireType,
+                                       values: &mut Vec<&'a str>,
+                                       buf: &mut SliceBuf<'a>,
+                                       ctx: &DecodeContext) -> Result<(), DecodeError> {
+        let mut value = "";
+        merge_borrowed(wire_type, &mut value, buf, ctx)?;
+        values.push(value);
+        Ok(())
+    }
+
+    length_delimited!(StringAdapter, str, String);
 }
 
 pub mod bytes {
     use super::*;
 
-    pub fn encode<B>(tag: u32, value: &Vec<u8>, buf: &mut B) where B: BufMut {
+    pub fn encode<A, B>(tag: u32, value: &A, buf: &mut B) where A: AsRef<[u8]>, B: BufMut {
+        let value = value.as_ref();
         encode_key(tag, WireType::LengthDelimited, buf);
         encode_varint(value.len() as u64, buf);
         buf.put_slice(value);
     }
 
-    pub fn merge<B>(wire_type: WireType, value: &mut Vec<u8>, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
+    pub fn merge<A, B>(wire_type: WireType,
+                       value: &mut A,
+                       buf: &mut B,
+                       ctx: &DecodeContext) -> Result<(), DecodeError> where A: BytesAdapter, B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
         let len = decode_varint(buf)?;
         if len > buf.remaining() as u64 {
-            return Err(DecodeError::new("buffer underflow"));
-        }
-
-        let mut remaining = len as usize;
-        while remaining > 0 {
-            let len = {
-                let bytes = buf.bytes();
-                debug_assert!(!bytes.is_empty(), "Buf::bytes returned empty slice");
-                let len = min(remaining, bytes.len());
-                let bytes = &bytes[..len];
-                value.extend_from_slice(bytes);
-                len
-            };
-            remaining -= len;
-            buf.advance(len);
+            return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
+        }
+
+        value.append(len as usize, buf, ctx);
+        Ok(())
+    }
+
+    /// Decodes a byte slice which borrows from the buffer into `value`.
+    ///
+    /// Unlike `merge`, the previous value is replaced rather than appended to.
+    pub fn merge_borrowed<'a>(wire_type: WireType,
+                              value: &mut &'a [u8],
+                              buf: &mut SliceBuf<'a>,
+                              _ctx: &DecodeContext) -> Result<(), DecodeError> {
+        check_wire_type(WireType::LengthDelimited, wire_type)?;
+        let len = decode_varint(buf)?;
+        if len > buf.remaining() as u64 {
+            return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
         }
+
+        *value = split_slice(len as usize, buf);
         Ok(())
     }
 
-    length_delimited!(Vec<u8>);
+    pub fn merge_repeated_borrowed<'a>(wire_type: WireType,
+                                       values: &mut Vec<&'a [u8]>,
+                                       buf: &mut SliceBuf<'a>,
+                                       ctx: &DecodeContext) -> Result<(), DecodeError> {
+        let mut value: &'a [u8] = &[];
+        merge_borrowed(wire_type, &mut value, buf, ctx)?;
+        values.push(value);
+        Ok(())
+    }
+
+    length_delimited!(BytesAdapter, [u8], Vec<u8>);
+
+    #[cfg(all(test, feature = "std"))]
+    mod shared_test {
+        use bytes::IntoBuf;
+
+        use super::*;
+
+        #[test]
+        fn merge_shares_source() {
+            // Values short enough to be stored inline in a `Bytes` are always copied.
+            let data = vec![42u8; 64];
+            let mut buf = Vec::new();
+            encode(1, &data, &mut buf);
+            let source = Bytes::from(buf);
+
+            let mut buf = source.clone().into_buf();
+            let ctx = DecodeContext::default().with_source(source.clone());
+            let (_, wire_type) = decode_key(&mut buf).unwrap();
+            let mut value = Bytes::new();
+            merge(wire_type, &mut value, &mut buf, &ctx).unwrap();
+
+            assert_eq!(&value[..], &data[..]);
+            assert_eq!(value.as_ptr(), source[2..].as_ptr());
+            assert!(!buf.has_remaining());
+        }
+
+        #[test]
+        fn merge_copies_without_source() {
+            let mut buf = Vec::new();
+            encode(1, &b"hello world".to_vec(), &mut buf);
+            let source = Bytes::from(buf);
+
+            let mut buf = source.clone().into_buf();
+            let (_, wire_type) = decode_key(&mut buf).unwrap();
+            let mut value = Bytes::from(&b"foo"[..]);
+            merge(wire_type, &mut value, &mut buf, &DecodeContext::default()).unwrap();
+
+            assert_eq!(&value[..], b"foohello world");
+        }
+    }
 }
 
 pub mod message {
     use super::*;
 
+    /// Encodes a message field. The lengths of the message and its nested messages are computed
+    /// once, with a size cache local to the field.
     pub fn encode<M, B>(tag: u32, msg: &M, buf: &mut B)
     where M: Message,
           B: BufMut {
+        let mut cache = SizeCache::new();
+        let len = msg.cache_sizes(&mut cache);
         encode_key(tag, WireType::LengthDelimited, buf);
-        encode_varint(msg.encoded_len() as u64, buf);
-        msg.encode_raw(buf);
+        encode_varint(len as u64, buf);
+        msg.encode_cached(buf, &mut cache);
     }
 
-    pub fn merge<M, B>(wire_type: WireType, msg: &mut M, buf: &mut B) -> Result<(), DecodeError>
+    pub fn merge<M, B>(wire_type: WireType, msg: &mut M, buf: &mut B, ctx: &DecodeContext) -> Result<(), DecodeError>
     where M: Message,
           B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
-        merge_loop(msg, buf, M::merge_field)
+        ctx.limit_reached()?;
+        merge_loop(msg, buf, &ctx.enter_recursion(), |msg: &mut M, buf: &mut B, ctx| {
+            let (tag, wire_type) = decode_key(buf)?;
+            msg.merge_field(tag, wire_type, buf, ctx)
+        })
     }
 
     pub fn encode_repeated<M, B>(tag: u32, messages: &[M], buf: &mut B)
@@ -660,20 +1337,39 @@ pub mod message {
         }
     }
 
-    pub fn merge_repeated<M, B>(wire_type: WireType, messages: &mut Vec<M>, buf: &mut B) -> Result<(), DecodeError>
+    pub fn merge_repeated<M, B>(wire_type: WireType,
+                                messages: &mut Vec<M>,
+                                buf: &mut B,
+                                ctx: &DecodeContext) -> Result<(), DecodeError>
     where M: Message + Default,
           B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
         let mut msg = M::default();
-        merge(WireType::LengthDelimited, &mut msg, buf)?;
+        merge(WireType::LengthDelimited, &mut msg, buf, ctx)?;
         messages.push(msg);
         Ok(())
     }
 
+    /// Returns an error naming the first required field which is not set in the message or in its
+    /// nested messages.
+    #[inline]
+    pub fn check_initialized<M>(msg: &M) -> Result<(), DecodeError> where M: Message {
+        msg.check_initialized()
+    }
+
+    pub fn check_initialized_repeated<'m, M, I>(messages: I) -> Result<(), DecodeError>
+    where M: Message + 'm,
+          I: IntoIterator<Item=&'m M> {
+        for msg in messages {
+            msg.check_initialized()?;
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn encoded_len<M>(tag: u32, msg: &M) -> usize where M: Message {
         let len = msg.encoded_len();
-        key_len(tag) + encoded_len_varint(len as u64) + msg.encoded_len()
+        key_len(tag) + encoded_len_varint(len as u64) + len
     }
 
     #[inline]
@@ -684,14 +1380,308 @@ pub mod message {
                       .map(|len| len + encoded_len_varint(len as u64))
                       .sum::<usize>()
     }
+
+    /// Encodes a message field, taking its length from the size cache.
+    pub fn encode_cached<M, B>(tag: u32, msg: &M, buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(cache.next_len() as u64, buf);
+        msg.encode_cached(buf, cache);
+    }
+
+    pub fn encode_repeated_cached<M, B>(tag: u32, messages: &[M], buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode_cached(tag, msg, buf, cache);
+        }
+    }
+
+    /// Returns the encoded length of a message field, recording the message's length in the size
+    /// cache.
+    #[inline]
+    pub fn cached_len<M>(tag: u32, msg: &M, cache: &mut SizeCache) -> usize where M: Message {
+        let len = cache.cache_len(msg);
+        key_len(tag) + encoded_len_varint(len as u64) + len
+    }
+
+    #[inline]
+    pub fn cached_len_repeated<M>(tag: u32, messages: &[M], cache: &mut SizeCache) -> usize where M: Message {
+        messages.iter().map(|msg| cached_len(tag, msg, cache)).sum()
+    }
+}
+
+/// Encoding functions for proto2 group fields.
+///
+/// A group is encoded as its fields delimited by start group and end group keys sharing the
+/// group field's tag, rather than by a length prefix.
+pub mod group {
+    use super::*;
+
+    /// Encodes a group field. The lengths of the group's nested messages are computed once, with a
+    /// size cache local to the field.
+    pub fn encode<M, B>(tag: u32, msg: &M, buf: &mut B)
+    where M: Message,
+          B: BufMut {
+        let mut cache = SizeCache::new();
+        msg.cache_sizes(&mut cache);
+        encode_cached(tag, msg, buf, &mut cache);
+    }
+
+    pub fn merge<M, B>(tag: u32,
+                       wire_type: WireType,
+                       msg: &mut M,
+                       buf: &mut B,
+                       ctx: &DecodeContext) -> Result<(), DecodeError>
+    where M: Message,
+          B: Buf {
+        check_wire_type(WireType::StartGroup, wire_type)?;
+        ctx.limit_reached()?;
+        let ctx = ctx.enter_recursion();
+        loop {
+            let (field_tag, field_wire_type) = decode_key(buf)?;
+            if field_wire_type == WireType::EndGroup {
+                return check_end_group(tag, field_tag);
+            }
+            msg.merge_field(field_tag, field_wire_type, buf, &ctx)?;
+        }
+    }
+
+    pub fn encode_repeated<M, B>(tag: u32, messages: &[M], buf: &mut B)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode(tag, msg, buf);
+        }
+    }
+
+    pub fn merge_repeated<M, B>(tag: u32,
+                                wire_type: WireType,
+                                messages: &mut Vec<M>,
+                                buf: &mut B,
+                                ctx: &DecodeContext) -> Result<(), DecodeError>
+    where M: Message + Default,
+          B: Buf {
+        check_wire_type(WireType::StartGroup, wire_type)?;
+        let mut msg = M::default();
+        merge(tag, WireType::StartGroup, &mut msg, buf, ctx)?;
+        messages.push(msg);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn check_initialized<M>(msg: &M) -> Result<(), DecodeError> where M: Message {
+        msg.check_initialized()
+    }
+
+    pub fn check_initialized_repeated<M>(messages: &[M]) -> Result<(), DecodeError> where M: Message {
+        for msg in messages {
+            msg.check_initialized()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn encoded_len<M>(tag: u32, msg: &M) -> usize where M: Message {
+        2 * key_len(tag) + msg.encoded_len()
+    }
+
+    #[inline]
+    pub fn encoded_len_repeated<M>(tag: u32, messages: &[M]) -> usize where M: Message {
+        2 * key_len(tag) * messages.len()
+            + messages.iter()
+                      .map(Message::encoded_len)
+                      .sum::<usize>()
+    }
+
+    /// Encodes a group field, taking the lengths of its nested messages from the size cache.
+    pub fn encode_cached<M, B>(tag: u32, msg: &M, buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        encode_key(tag, WireType::StartGroup, buf);
+        msg.encode_cached(buf, cache);
+        encode_key(tag, WireType::EndGroup, buf);
+    }
+
+    pub fn encode_repeated_cached<M, B>(tag: u32, messages: &[M], buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode_cached(tag, msg, buf, cache);
+        }
+    }
+
+    /// Returns the encoded length of a group field, recording the lengths of its nested messages
+    /// in the size cache. Groups are not length delimited, so the group's own length is not
+    /// recorded.
+    #[inline]
+    pub fn cached_len<M>(tag: u32, msg: &M, cache: &mut SizeCache) -> usize where M: Message {
+        2 * key_len(tag) + msg.cache_sizes(cache)
+    }
+
+    #[inline]
+    pub fn cached_len_repeated<M>(tag: u32, messages: &[M], cache: &mut SizeCache) -> usize where M: Message {
+        messages.iter().map(|msg| cached_len(tag, msg, cache)).sum()
+    }
+}
+
+/// Encoding functions for message fields of a `BorrowedMessage`.
+pub mod borrowed_message {
+    use super::*;
+
+    /// A message type which can be a field of a `BorrowedMessage` with lifetime `'a`.
+    ///
+    /// Implemented for all owned `Message` types, and by `#[derive(Message)]` for borrowed
+    /// message types.
+    pub trait NestedMessage<'a>: Default {
+        fn encode_raw<B>(&self, buf: &mut B) where B: BufMut;
+        fn encoded_len(&self) -> usize;
+        fn cache_sizes(&self, cache: &mut SizeCache) -> usize;
+        fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut;
+        fn merge_field(&mut self,
+                       tag: u32,
+                       wire_type: WireType,
+                       buf: &mut SliceBuf<'a>,
+                       ctx: &DecodeContext) -> Result<(), DecodeError>;
+        fn check_initialized(&self) -> Result<(), DecodeError>;
+    }
+
+    impl <'a, M> NestedMessage<'a> for M where M: Message + Default {
+        fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+            Message::encode_raw(self, buf)
+        }
+        fn encoded_len(&self) -> usize {
+            Message::encoded_len(self)
+        }
+        fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+            Message::cache_sizes(self, cache)
+        }
+        fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+            Message::encode_cached(self, buf, cache)
+        }
+        fn merge_field(&mut self,
+                       tag: u32,
+                       wire_type: WireType,
+                       buf: &mut SliceBuf<'a>,
+                       ctx: &DecodeContext) -> Result<(), DecodeError> {
+            Message::merge_field(self, tag, wire_type, buf, ctx)
+        }
+        fn check_initialized(&self) -> Result<(), DecodeError> {
+            Message::check_initialized(self)
+        }
+    }
+
+    /// Encodes a message field. The lengths of the message and its nested messages are computed
+    /// once, with a size cache local to the field.
+    pub fn encode<'a, M, B>(tag: u32, msg: &M, buf: &mut B)
+    where M: NestedMessage<'a>,
+          B: BufMut {
+        let mut cache = SizeCache::new();
+        let len = msg.cache_sizes(&mut cache);
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(len as u64, buf);
+        msg.encode_cached(buf, &mut cache);
+    }
+
+    pub fn merge<'a, M>(wire_type: WireType,
+                        msg: &mut M,
+                        buf: &mut SliceBuf<'a>,
+                        ctx: &DecodeContext) -> Result<(), DecodeError>
+    where M: NestedMessage<'a> {
+        check_wire_type(WireType::LengthDelimited, wire_type)?;
+        ctx.limit_reached()?;
+        merge_loop(msg, buf, &ctx.enter_recursion(), |msg: &mut M, buf: &mut SliceBuf<'a>, ctx| {
+            let (tag, wire_type) = decode_key(buf)?;
+            msg.merge_field(tag, wire_type, buf, ctx)
+        })
+    }
+
+    pub fn encode_repeated<'a, M, B>(tag: u32, messages: &[M], buf: &mut B)
+    where M: NestedMessage<'a>,
+          B: BufMut {
+        for msg in messages {
+            encode(tag, msg, buf);
+        }
+    }
+
+    pub fn merge_repeated<'a, M>(wire_type: WireType,
+                                 messages: &mut Vec<M>,
+                                 buf: &mut SliceBuf<'a>,
+                                 ctx: &DecodeContext) -> Result<(), DecodeError>
+    where M: NestedMessage<'a> {
+        check_wire_type(WireType::LengthDelimited, wire_type)?;
+        let mut msg = M::default();
+        merge(WireType::LengthDelimited, &mut msg, buf, ctx)?;
+        messages.push(msg);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn check_initialized<'a, M>(msg: &M) -> Result<(), DecodeError> where M: NestedMessage<'a> {
+        msg.check_initialized()
+    }
+
+    pub fn check_initialized_repeated<'a, M>(messages: &[M]) -> Result<(), DecodeError>
+    where M: NestedMessage<'a> {
+        for msg in messages {
+            msg.check_initialized()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn encoded_len<'a, M>(tag: u32, msg: &M) -> usize where M: NestedMessage<'a> {
+        let len = msg.encoded_len();
+        key_len(tag) + encoded_len_varint(len as u64) + len
+    }
+
+    #[inline]
+    pub fn encoded_len_repeated<'a, M>(tag: u32, messages: &[M]) -> usize where M: NestedMessage<'a> {
+        key_len(tag) * messages.len()
+            + messages.iter()
+                      .map(NestedMessage::encoded_len)
+                      .map(|len| len + encoded_len_varint(len as u64))
+                      .sum::<usize>()
+    }
+
+    /// Encodes a message field, taking its length from the size cache.
+    pub fn encode_cached<'a, M, B>(tag: u32, msg: &M, buf: &mut B, cache: &mut SizeCache)
+    where M: NestedMessage<'a>,
+          B: BufMut {
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(cache.next_len() as u64, buf);
+        msg.encode_cached(buf, cache);
+    }
+
+    pub fn encode_repeated_cached<'a, M, B>(tag: u32, messages: &[M], buf: &mut B, cache: &mut SizeCache)
+    where M: NestedMessage<'a>,
+          B: BufMut {
+        for msg in messages {
+            encode_cached(tag, msg, buf, cache);
+        }
+    }
+
+    /// Returns the encoded length of a message field, recording the message's length in the size
+    /// cache.
+    #[inline]
+    pub fn cached_len<'a, M>(tag: u32, msg: &M, cache: &mut SizeCache) -> usize where M: NestedMessage<'a> {
+        let len = cache.cache_with(|cache| msg.cache_sizes(cache));
+        key_len(tag) + encoded_len_varint(len as u64) + len
+    }
+
+    #[inline]
+    pub fn cached_len_repeated<'a, M>(tag: u32, messages: &[M], cache: &mut SizeCache) -> usize
+    where M: NestedMessage<'a> {
+        messages.iter().map(|msg| cached_len(tag, msg, cache)).sum()
+    }
 }
 
 /// Rust doesn't have a `Map` trait, so macros are currently the best way to be
 /// generic over `HashMap` and `BTreeMap`.
 macro_rules! map {
-    ($map_ty:ident) => (
-        use std::collections::$map_ty;
-        use std::hash::Hash;
+    ($map_ty:ident, $sorted:expr) => (
+        use core::hash::Hash;
 
         use ::encoding::*;
 
@@ -718,14 +1708,15 @@ macro_rules! map {
         pub fn merge<K, V, B, KM, VM>(key_merge: KM,
                                       val_merge: VM,
                                       values: &mut $map_ty<K, V>,
-                                      buf: &mut B)
+                                      buf: &mut B,
+                                      ctx: &DecodeContext)
                                       -> Result<(), DecodeError>
         where K: Default + Eq + Hash + Ord,
               V: Default,
               B: Buf,
-              KM: Fn(WireType, &mut K, &mut B) -> Result<(), DecodeError>,
-              VM: Fn(WireType, &mut V, &mut B) -> Result<(), DecodeError> {
-            merge_with_default(key_merge, val_merge, V::default(), values, buf)
+              KM: Fn(WireType, &mut K, &mut B, &DecodeContext) -> Result<(), DecodeError>,
+              VM: Fn(WireType, &mut V, &mut B, &DecodeContext) -> Result<(), DecodeError> {
+            merge_with_default(key_merge, val_merge, V::default(), values, buf, ctx)
         }
 
         /// Generic protobuf map encode function.
@@ -787,21 +1778,24 @@ macro_rules! map {
                                                    val_merge: VM,
                                                    val_default: V,
                                                    values: &mut $map_ty<K, V>,
-                                                   buf: &mut B)
+                                                   buf: &mut B,
+                                                   ctx: &DecodeContext)
                                                    -> Result<(), DecodeError>
         where K: Default + Eq + Hash + Ord,
               B: Buf,
-              KM: Fn(WireType, &mut K, &mut B) -> Result<(), DecodeError>,
-              VM: Fn(WireType, &mut V, &mut B) -> Result<(), DecodeError> {
+              KM: Fn(WireType, &mut K, &mut B, &DecodeContext) -> Result<(), DecodeError>,
+              VM: Fn(WireType, &mut V, &mut B, &DecodeContext) -> Result<(), DecodeError> {
 
             let mut key = Default::default();
             let mut val = val_default;
-            merge_loop(&mut (&mut key, &mut val), buf, |&mut (ref mut key, ref mut val), buf| {
+            ctx.limit_reached()?;
+            merge_loop(&mut (&mut key, &mut val), buf, &ctx.enter_recursion(),
+                       |&mut (ref mut key, ref mut val), buf, ctx| {
                 let (tag, wire_type) = decode_key(buf)?;
                 match tag {
-                    1 => key_merge(wire_type, key, buf),
-                    2 => val_merge(wire_type, val, buf),
-                    _ => skip_field(wire_type, buf),
+                    1 => key_merge(wire_type, key, buf, ctx),
+                    2 => val_merge(wire_type, val, buf, ctx),
+                    _ => skip_field(wire_type, tag, buf, ctx),
                 }
             })?;
             values.insert(key, val);
@@ -829,15 +1823,95 @@ macro_rules! map {
                 encoded_len_varint(len as u64) + len
             }).sum::<usize>()
         }
+
+        /// Calls `f` with each entry of the map, in key order if the size cache is in
+        /// deterministic mode.
+        fn for_each_entry<K, V, F>(values: &$map_ty<K, V>, cache: &mut SizeCache, mut f: F)
+        where K: Eq + Hash + Ord,
+              F: FnMut(&K, &V, &mut SizeCache) {
+            if cache.is_deterministic() && !$sorted {
+                let mut entries = values.iter().collect::<Vec<_>>();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                for (key, val) in entries {
+                    f(key, val, cache);
+                }
+            } else {
+                for (key, val) in values.iter() {
+                    f(key, val, cache);
+                }
+            }
+        }
+
+        /// Generic protobuf map encode function, taking the lengths of the map entries and of
+        /// nested messages from the size cache.
+        ///
+        /// In deterministic mode, entries are encoded in key order and always include the key and
+        /// the value, as in the other protobuf implementations.
+        pub fn encode_cached<K, V, B, KE, VE>(key_encode: KE,
+                                              val_encode: VE,
+                                              val_default: &V,
+                                              tag: u32,
+                                              values: &$map_ty<K, V>,
+                                              buf: &mut B,
+                                              cache: &mut SizeCache)
+        where K: Default + Eq + Hash + Ord,
+              V: PartialEq,
+              B: BufMut,
+              KE: Fn(u32, &K, &mut B),
+              VE: Fn(u32, &V, &mut B, &mut SizeCache) {
+            let deterministic = cache.is_deterministic();
+            for_each_entry(values, cache, |key, val, cache| {
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_varint(cache.next_len() as u64, buf);
+                if deterministic || key != &K::default() {
+                    key_encode(1, key, buf);
+                }
+                if deterministic || val != val_default {
+                    val_encode(2, val, buf, cache);
+                }
+            });
+        }
+
+        /// Generic protobuf map encoded length function, recording the lengths of the map
+        /// entries and of nested messages in the size cache.
+        pub fn cached_len<K, V, KL, VL>(key_encoded_len: KL,
+                                        val_encoded_len: VL,
+                                        val_default: &V,
+                                        tag: u32,
+                                        values: &$map_ty<K, V>,
+                                        cache: &mut SizeCache)
+                                        -> usize
+        where K: Default + Eq + Hash + Ord,
+              V: PartialEq,
+              KL: Fn(u32, &K) -> usize,
+              VL: Fn(u32, &V, &mut SizeCache) -> usize {
+            let deterministic = cache.is_deterministic();
+            let mut len = key_len(tag) * values.len();
+            for_each_entry(values, cache, |key, val, cache| {
+                let entry_len = cache.cache_with(|cache| {
+                    (if deterministic || key != &K::default() { key_encoded_len(1, key) } else { 0 })
+                        + (if deterministic || val != val_default {
+                            val_encoded_len(2, val, cache)
+                        } else {
+                            0
+                        })
+                });
+                len += encoded_len_varint(entry_len as u64) + entry_len;
+            });
+            len
+        }
     )
 }
 
+#[cfg(feature = "std")]
 pub mod hash_map {
-    map!(HashMap);
+    use std::collections::HashMap;
+    map!(HashMap, false);
 }
 
 pub mod btree_map {
-    map!(BTreeMap);
+    use alloc::collections::BTreeMap;
+    map!(BTreeMap, true);
 }
 
 #[cfg(test)]
@@ -856,7 +1930,7 @@ mod test {
                             tag: u32,
                             wire_type: WireType,
                             encode: fn(u32, &B, &mut BytesMut),
-                            merge: fn(WireType, &mut T, &mut Cursor<Bytes>) -> Result<(), DecodeError>,
+                            merge: fn(WireType, &mut T, &mut Cursor<Bytes>, &DecodeContext) -> Result<(), DecodeError>,
                             encoded_len: fn(u32, &B) -> usize)
                             -> TestResult
     where T: Debug + Default + PartialEq + Borrow<B>,
@@ -915,7 +1989,7 @@ mod test {
         }
 
         let mut roundtrip_value = T::default();
-        if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf) {
+        if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf, &DecodeContext::default()) {
             return TestResult::error(error.to_string());
         };
 
@@ -941,7 +2015,7 @@ mod test {
     where T: Debug + Default + PartialEq + Borrow<B>,
           B: ?Sized,
           E: FnOnce(u32, &B, &mut BytesMut),
-          M: FnMut(WireType, &mut T, &mut Cursor<Bytes>) -> Result<(), DecodeError>,
+          M: FnMut(WireType, &mut T, &mut Cursor<Bytes>, &DecodeContext) -> Result<(), DecodeError>,
           L: FnOnce(u32, &B) -> usize {
 
         if tag > MAX_TAG || tag < MIN_TAG {
@@ -980,7 +2054,7 @@ mod test {
                             wire_type, decoded_wire_type));
             }
 
-            if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf) {
+            if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf, &DecodeContext::default()) {
                 return TestResult::error(error.to_string());
             };
         }
@@ -1048,6 +2122,29 @@ mod test {
         check(u64::MAX, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
     }
 
+    #[test]
+    fn skip_group() {
+        // A group with tag 1, containing a varint field and a nested group with tag 2.
+        let encoded: &[u8] = &[0x0B, 0x08, 0x96, 0x01, 0x13, 0x1A, 0x00, 0x14, 0x0C, 0x20, 0x01];
+        let mut buf = encoded.into_buf();
+        let (tag, wire_type) = decode_key(&mut buf).unwrap();
+        assert_eq!((tag, wire_type), (1, WireType::StartGroup));
+        skip_field(wire_type, tag, &mut buf, &DecodeContext::default()).unwrap();
+        assert_eq!(buf.remaining(), 2);
+
+        // Mismatched end group tag.
+        let mut buf = (&[0x08, 0x14][..]).into_buf();
+        skip_field(WireType::StartGroup, 1, &mut buf, &DecodeContext::default()).unwrap_err();
+
+        // Unterminated group.
+        let mut buf = (&[0x08, 0x01][..]).into_buf();
+        skip_field(WireType::StartGroup, 1, &mut buf, &DecodeContext::default()).unwrap_err();
+
+        // Stray end group.
+        let mut buf = (&[][..]).into_buf();
+        skip_field(WireType::EndGroup, 1, &mut buf, &DecodeContext::default()).unwrap_err();
+    }
+
     /// This big bowl o' macro soup generates a quickcheck encoding test for each
     /// combination of map type, scalar map key, and value type.
     /// TODO: these tests take a long time to compile, can this be improved?
@@ -1096,12 +2193,13 @@ mod test {
                                                                     values,
                                                                     buf)
                                               },
-                                              |wire_type, values, buf| {
+                                              |wire_type, values, buf, ctx| {
                                                   check_wire_type(WireType::LengthDelimited, wire_type)?;
                                                   $mod_name::merge($key_proto::merge,
                                                                    $val_proto::merge,
                                                                    values,
-                                                                   buf)
+                                                                   buf,
+                                                                   ctx)
                                               },
                                               |tag, values| {
                                                   $mod_name::encoded_len($key_proto::encoded_len,
@@ -0,0 +1,161 @@
+//! Typed representations of Protobuf enumeration fields.
+
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+/// A Rust enum which represents a Protobuf enumeration.
+///
+/// Implementations are provided by `#[derive(Enumeration)]`.
+pub trait Enumeration: Default + Sized {
+
+    /// Converts an `i32` to the enumeration, or `None` if `value` is not a known variant.
+    fn from_i32(value: i32) -> Option<Self>;
+
+    /// Returns the numeric value of the variant.
+    fn to_i32(&self) -> i32;
+
+    /// Converts a value name or alias, as written in the `.proto` file, to the enumeration, or
+    /// `None` if `name` is not a known value name.
+    fn from_str_name(name: &str) -> Option<Self>;
+
+    /// Returns the name of the variant, as written in the `.proto` file.
+    fn as_str_name(&self) -> &'static str;
+}
+
+/// The error returned when converting an `i32` which is not a variant to an enumeration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownEnumValue(pub i32);
+
+impl fmt::Display for UnknownEnumValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown enumeration value: {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for UnknownEnumValue {
+    fn description(&self) -> &str {
+        "unknown enumeration value"
+    }
+}
+
+/// The error returned when parsing a name which is not a value name of an enumeration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownEnumName {
+    name: String,
+}
+
+impl UnknownEnumName {
+
+    /// Creates a new `UnknownEnumName`.
+    pub fn new(name: &str) -> UnknownEnumName {
+        UnknownEnumName {
+            name: String::from(name),
+        }
+    }
+
+    /// Returns the name which failed to parse.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for UnknownEnumName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown enumeration value name: {}", self.name)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for UnknownEnumName {
+    fn description(&self) -> &str {
+        "unknown enumeration value name"
+    }
+}
+
+/// The value of an open (proto3) enumeration field.
+///
+/// Open enumerations may hold values which are not variants of the Rust enum, for instance when
+/// the message was written with a newer version of the schema. Such values are kept as
+/// `Unknown`, so that they are visible to the application and survive a round trip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OpenEnum<E> {
+    /// A variant of the enumeration.
+    Known(E),
+    /// A value which is not a variant of the enumeration.
+    Unknown(i32),
+}
+
+impl<E> OpenEnum<E> where E: Enumeration {
+
+    /// Converts an `i32` to an open enumeration value.
+    pub fn from_i32(value: i32) -> OpenEnum<E> {
+        match E::from_i32(value) {
+            Some(known) => OpenEnum::Known(known),
+            None => OpenEnum::Unknown(value),
+        }
+    }
+
+    /// Returns the numeric value.
+    pub fn to_i32(&self) -> i32 {
+        match *self {
+            OpenEnum::Known(ref known) => known.to_i32(),
+            OpenEnum::Unknown(value) => value,
+        }
+    }
+
+    /// Returns `true` if the value is a variant of the enumeration.
+    pub fn is_known(&self) -> bool {
+        match *self {
+            OpenEnum::Known(..) => true,
+            OpenEnum::Unknown(..) => false,
+        }
+    }
+
+    /// Returns the variant, or `None` if the value is unknown.
+    pub fn known(self) -> Option<E> {
+        match self {
+            OpenEnum::Known(known) => Some(known),
+            OpenEnum::Unknown(..) => None,
+        }
+    }
+
+    /// Returns the raw value if it is not a variant of the enumeration.
+    pub fn unknown(&self) -> Option<i32> {
+        match *self {
+            OpenEnum::Known(..) => None,
+            OpenEnum::Unknown(value) => Some(value),
+        }
+    }
+
+    /// Returns the variant, or the default variant if the value is unknown.
+    pub fn known_or_default(self) -> E {
+        self.known().unwrap_or_default()
+    }
+}
+
+impl<E> Default for OpenEnum<E> where E: Enumeration {
+    fn default() -> OpenEnum<E> {
+        OpenEnum::Known(E::default())
+    }
+}
+
+impl<E> From<E> for OpenEnum<E> where E: Enumeration {
+    fn from(value: E) -> OpenEnum<E> {
+        OpenEnum::Known(value)
+    }
+}
+
+impl<E> From<i32> for OpenEnum<E> where E: Enumeration {
+    fn from(value: i32) -> OpenEnum<E> {
+        OpenEnum::from_i32(value)
+    }
+}
+
+impl<E> From<OpenEnum<E>> for i32 where E: Enumeration {
+    fn from(value: OpenEnum<E>) -> i32 {
+        value.to_i32()
+    }
+}
@@ -1,8 +1,11 @@
 //! Protobuf encoding and decoding errors.
 
-use std::borrow::Cow;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::error;
-use std::fmt;
+#[cfg(feature = "std")]
 use std::io;
 
 /// A Protobuf message decoding error.
@@ -12,12 +15,71 @@ use std::io;
 /// general it is not possible to exactly pinpoint why data is malformed.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DecodeError {
+    /// The category of the error.
+    kind: DecodeErrorKind,
     /// A 'best effort' root cause description.
     description: Cow<'static, str>,
-    /// A stack of (message, field) name pairs, which identify the specific
+    /// A stack of (message, field, tag) locations, which identify the specific
     /// message type and field where decoding failed. The stack contains an
     /// entry per level of nesting.
-    stack: Vec<(&'static str, &'static str)>,
+    stack: Vec<DecodeErrorFrame>,
+    /// The offset in the input at which decoding failed, if known.
+    offset: Option<usize>,
+}
+
+/// The category of a `DecodeError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DecodeErrorKind {
+    /// The input ended in the middle of a value. Reading more input may allow the message to be
+    /// decoded.
+    Truncated,
+    /// A varint is longer than the maximum of 10 bytes.
+    InvalidVarint,
+    /// A field key has a wire type value which is not defined by the Protobuf encoding.
+    InvalidWireType,
+    /// A field has a different wire type than its declared type is encoded with.
+    WireTypeMismatch,
+    /// A field key has a tag which is out of range.
+    InvalidTag,
+    /// An end group key doesn't match the group being decoded, or occurs outside of a group.
+    UnexpectedEndGroup,
+    /// A length delimiter exceeds the maximum supported length, or the value it delimits extends
+    /// past its length.
+    LengthOverflow,
+    /// A string field does not contain valid UTF-8.
+    InvalidUtf8,
+    /// Nested messages exceed the recursion limit.
+    RecursionLimitReached,
+    /// A required field is missing from the message.
+    MissingRequiredField,
+    /// The input is invalid for another reason.
+    Other,
+}
+
+/// A location in a message at which decoding failed: a field of a message type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeErrorFrame {
+    message: &'static str,
+    field: &'static str,
+    tag: u32,
+}
+
+impl DecodeErrorFrame {
+
+    /// Returns the name of the message type.
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// Returns the name of the field.
+    pub fn field(&self) -> &'static str {
+        self.field
+    }
+
+    /// Returns the tag of the field.
+    pub fn tag(&self) -> u32 {
+        self.tag
+    }
 }
 
 impl DecodeError {
@@ -27,37 +89,82 @@ impl DecodeError {
     /// Meant to be used only by `Message` implementations.
     #[doc(hidden)]
     pub fn new<S>(description: S) -> DecodeError where S: Into<Cow<'static, str>> {
+        DecodeError::with_kind(DecodeErrorKind::Other, description)
+    }
+
+    /// Creates a new `DecodeError` of the provided kind with a 'best effort' root cause
+    /// description.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    pub fn with_kind<S>(kind: DecodeErrorKind, description: S) -> DecodeError
+    where S: Into<Cow<'static, str>> {
         DecodeError {
+            kind,
             description: description.into(),
             stack: Vec::new(),
+            offset: None,
         }
     }
 
-    /// Pushes a (message, field) name location pair on to the location stack.
+    /// Pushes a (message, field, tag) location on to the location stack.
     ///
     /// Meant to be used only by `Message` implementations.
     #[doc(hidden)]
-    pub fn push(&mut self, message: &'static str, field: &'static str) {
-        self.stack.push((message, field));
+    pub fn push(&mut self, message: &'static str, field: &'static str, tag: u32) {
+        self.stack.push(DecodeErrorFrame { message, field, tag });
+    }
+
+    /// Sets the offset in the input at which decoding failed.
+    pub(crate) fn set_offset(&mut self, offset: usize) {
+        self.offset = Some(offset);
+    }
+
+    /// Returns the category of the error.
+    pub fn kind(&self) -> DecodeErrorKind {
+        self.kind
+    }
+
+    /// Returns `true` if the input ended in the middle of a value, as opposed to being corrupt.
+    pub fn is_truncated(&self) -> bool {
+        self.kind == DecodeErrorKind::Truncated
+    }
+
+    /// Returns the locations of the field where decoding failed, innermost first: the first frame
+    /// is the field of the message in which the error occurred, and each following frame is the
+    /// field containing the message of the previous frame.
+    pub fn stack(&self) -> &[DecodeErrorFrame] {
+        &self.stack
+    }
+
+    /// Returns the offset in the input at which decoding failed, if known.
+    ///
+    /// The offset is counted from the start of the buffer passed to the decode or merge method,
+    /// and is 'best effort': it is where the error was detected, which may be after the start of
+    /// the invalid value.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
     }
 }
 
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("failed to decode Protobuf message: ")?;
-        for &(message, field) in &self.stack {
-            write!(f, "{}.{}: ", message, field)?;
+        for frame in &self.stack {
+            write!(f, "{}.{}: ", frame.message, frame.field)?;
         }
         f.write_str(&self.description)
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for DecodeError {
     fn description(&self) -> &str {
         &self.description
     }
 }
 
+#[cfg(feature = "std")]
 impl From<DecodeError> for io::Error {
     fn from(error: DecodeError) -> io::Error {
         io::Error::new(io::ErrorKind::InvalidData, error)
@@ -66,13 +173,17 @@ impl From<DecodeError> for io::Error {
 
 /// A Protobuf message encoding error.
 ///
-/// `EncodeError` always indicates that a message failed to encode because the
-/// provided buffer had insufficient capacity. Message encoding is otherwise
-/// infallible.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// `EncodeError` indicates that a message failed to encode because the provided
+/// buffer had insufficient capacity, or, with `Message::encode_checked`, because
+/// a required field is not set. Message encoding is otherwise infallible.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct EncodeError {
     required: usize,
     remaining: usize,
+    /// If the message is not initialized, a stack of (message, field, tag)
+    /// locations which identify the required field which is not set, with an
+    /// entry per level of nesting. Empty otherwise.
+    stack: Vec<DecodeErrorFrame>,
 }
 
 impl EncodeError {
@@ -82,6 +193,17 @@ impl EncodeError {
         EncodeError {
             required,
             remaining,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Creates a new `EncodeError` for a message which is not initialized, from the error
+    /// returned by `Message::check_initialized`.
+    pub(crate) fn missing_required_field(error: DecodeError) -> EncodeError {
+        EncodeError {
+            required: 0,
+            remaining: 0,
+            stack: error.stack,
         }
     }
 
@@ -94,21 +216,53 @@ impl EncodeError {
     pub fn remaining(&self) -> usize {
         self.remaining
     }
+
+    /// Returns `true` if the message failed to encode because a required field is not set.
+    pub fn is_missing_required_field(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    /// Returns the required field which is not set, if the message failed to encode because it
+    /// is not initialized. The frame is the field of the innermost message missing it; `stack`
+    /// returns the full path to the field.
+    pub fn missing_field(&self) -> Option<DecodeErrorFrame> {
+        self.stack.first().cloned()
+    }
+
+    /// Returns the locations of the required field which is not set, innermost first, in the
+    /// same order as `DecodeError::stack`. Empty if the message failed to encode because the
+    /// buffer had insufficient capacity.
+    pub fn stack(&self) -> &[DecodeErrorFrame] {
+        &self.stack
+    }
 }
 
 impl fmt::Display for EncodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(error::Error::description(self))?;
-        write!(f, " (required: {}, remaining: {})", self.required, self.remaining)
+        f.write_str("failed to encode Protobuf message: ")?;
+        if self.stack.is_empty() {
+            return write!(f, "insufficient buffer capacity (required: {}, remaining: {})",
+                          self.required, self.remaining);
+        }
+        for frame in &self.stack {
+            write!(f, "{}.{}: ", frame.message, frame.field)?;
+        }
+        f.write_str("missing required field")
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for EncodeError {
     fn description(&self) -> &str {
-        "failed to encode Protobuf message: insufficient buffer capacity"
+        if self.is_missing_required_field() {
+            "failed to encode Protobuf message: missing required field"
+        } else {
+            "failed to encode Protobuf message: insufficient buffer capacity"
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<EncodeError> for io::Error {
     fn from(error: EncodeError) -> io::Error {
         io::Error::new(io::ErrorKind::InvalidInput, error)
@@ -0,0 +1,625 @@
+//! Protocol Buffers extensions.
+//!
+//! Extensions are fields declared outside of the message they extend, using an `extend` block in
+//! a `.proto` file. `prost-build` generates a typed `Extension` static for each declared
+//! extension, and an `ExtensionSet` field for each message which declares extension ranges.
+//!
+//! Extension fields are only recognized while decoding if the extension has been registered in
+//! the `ExtensionRegistry` passed in through `DecodeOptions::extension_registry`. Fields of
+//! unregistered extensions are treated as unknown fields.
+//!
+//! This module also contains the extension field types, which determine the Rust type of an
+//! extension's value and how it is encoded.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::fmt;
+use core::marker::PhantomData;
+
+use bytes::{Buf, BufMut};
+
+use DecodeError;
+use encoding::{self, DecodeContext, WireType};
+
+/// A typed descriptor of an extension field.
+///
+/// `M` is the extended message type, and `T` is the field type of the extension, for example
+/// `prost::extension::Int32` or `prost::extension::Repeated<prost::extension::String>`.
+pub struct Extension<M, T> {
+    number: u32,
+    name: &'static str,
+    marker: PhantomData<fn() -> (M, T)>,
+}
+
+impl <M, T> Extension<M, T> {
+
+    /// Creates a new extension descriptor with the provided field number and fully qualified
+    /// Protobuf name.
+    pub const fn new(number: u32, name: &'static str) -> Extension<M, T> {
+        Extension {
+            number: number,
+            name: name,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the field number of the extension.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Returns the fully qualified Protobuf name of the extension, e.g. `foo.bar.baz`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl <M, T> fmt::Debug for Extension<M, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extension")
+         .field("number", &self.number)
+         .field("name", &self.name)
+         .finish()
+    }
+}
+
+/// A message which declares extension ranges, and holds the values of its extension fields in an
+/// `ExtensionSet`.
+pub trait Extendable: ::Message + Sized + 'static {
+
+    /// Returns the message's extension fields.
+    fn extension_set(&self) -> &ExtensionSet;
+
+    /// Returns the message's extension fields, mutably.
+    fn extension_set_mut(&mut self) -> &mut ExtensionSet;
+
+    /// Returns `true` if the extension field is set.
+    fn has_extension<T>(&self, extension: &Extension<Self, T>) -> bool where T: ExtensionType {
+        self.get_extension(extension).is_some()
+    }
+
+    /// Returns the value of the extension field, or `None` if it is not set.
+    fn get_extension<T>(&self, extension: &Extension<Self, T>) -> Option<&T::Value>
+    where T: ExtensionType {
+        self.extension_set().get(extension)
+    }
+
+    /// Returns the value of the extension field mutably, or `None` if it is not set.
+    fn get_extension_mut<T>(&mut self, extension: &Extension<Self, T>) -> Option<&mut T::Value>
+    where T: ExtensionType {
+        self.extension_set_mut().get_mut(extension)
+    }
+
+    /// Sets the value of the extension field, replacing any previous value.
+    fn set_extension<T>(&mut self, extension: &'static Extension<Self, T>, value: T::Value)
+    where T: ExtensionType {
+        self.extension_set_mut().set(extension, value)
+    }
+
+    /// Clears the extension field, returning its previous value.
+    fn clear_extension<T>(&mut self, extension: &Extension<Self, T>) -> Option<T::Value>
+    where T: ExtensionType {
+        self.extension_set_mut().remove(extension)
+    }
+}
+
+/// The values of the extension fields of a message.
+#[derive(Default)]
+pub struct ExtensionSet {
+    fields: BTreeMap<u32, ExtensionField>,
+}
+
+struct ExtensionField {
+    extension: &'static dyn AnyExtension,
+    value: Box<dyn Any + Send + Sync>,
+}
+
+impl ExtensionSet {
+
+    /// Creates an empty extension set.
+    pub fn new() -> ExtensionSet {
+        ExtensionSet::default()
+    }
+
+    /// Returns `true` if no extension fields are set.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of extension fields which are set.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns the value of the extension field, or `None` if it is not set.
+    pub fn get<M, T>(&self, extension: &Extension<M, T>) -> Option<&T::Value> where T: ExtensionType {
+        self.fields
+            .get(&extension.number)
+            .and_then(|field| field.value.downcast_ref())
+    }
+
+    /// Returns the value of the extension field mutably, or `None` if it is not set.
+    pub fn get_mut<M, T>(&mut self, extension: &Extension<M, T>) -> Option<&mut T::Value>
+    where T: ExtensionType {
+        self.fields
+            .get_mut(&extension.number)
+            .and_then(|field| field.value.downcast_mut())
+    }
+
+    /// Sets the value of the extension field, replacing any previous value.
+    pub fn set<M, T>(&mut self, extension: &'static Extension<M, T>, value: T::Value)
+    where M: 'static,
+          T: ExtensionType {
+        self.fields.insert(extension.number, ExtensionField {
+            extension: extension,
+            value: Box::new(value),
+        });
+    }
+
+    /// Clears the extension field, returning its previous value.
+    pub fn remove<M, T>(&mut self, extension: &Extension<M, T>) -> Option<T::Value>
+    where T: ExtensionType {
+        if self.get(extension).is_none() {
+            return None;
+        }
+        self.fields
+            .remove(&extension.number)
+            .and_then(|field| field.value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Clears all extension fields.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    /// Decodes the value of a registered extension field from the buffer, and merges it into the
+    /// set.
+    #[doc(hidden)]
+    pub fn merge_field<B>(&mut self,
+                          extension: &'static dyn AnyExtension,
+                          wire_type: WireType,
+                          buf: &mut B,
+                          ctx: &DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        let field = self.fields.entry(extension.number()).or_insert_with(|| ExtensionField {
+            extension: extension,
+            value: extension.new_value(),
+        });
+        if field.extension.name() != extension.name() {
+            field.extension = extension;
+            field.value = extension.new_value();
+        }
+        let buf: &mut dyn Buf = buf;
+        field.extension.merge(&mut *field.value, wire_type, buf, ctx)
+    }
+
+    /// Encodes the extension fields to the buffer, in field number order.
+    #[doc(hidden)]
+    pub fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        let buf: &mut dyn BufMut = buf;
+        for field in self.fields.values() {
+            field.extension.encode(&*field.value, buf);
+        }
+    }
+
+    /// Returns the encoded length of the extension fields.
+    #[doc(hidden)]
+    pub fn encoded_len(&self) -> usize {
+        self.fields
+            .values()
+            .map(|field| field.extension.encoded_len(&*field.value))
+            .sum()
+    }
+}
+
+impl Clone for ExtensionSet {
+    fn clone(&self) -> ExtensionSet {
+        ExtensionSet {
+            fields: self.fields.iter().map(|(&number, field)| {
+                (number, ExtensionField {
+                    extension: field.extension,
+                    value: field.extension.clone_value(&*field.value),
+                })
+            }).collect(),
+        }
+    }
+}
+
+impl PartialEq for ExtensionSet {
+    fn eq(&self, other: &ExtensionSet) -> bool {
+        self.fields.len() == other.fields.len()
+            && self.fields.iter().zip(other.fields.iter()).all(|((a_number, a), (b_number, b))| {
+                a_number == b_number
+                    && a.extension.name() == b.extension.name()
+                    && a.extension.eq_value(&*a.value, &*b.value)
+            })
+    }
+}
+
+impl fmt::Debug for ExtensionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Value<'a>(&'a ExtensionField);
+        impl <'a> fmt::Debug for Value<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.extension.fmt_value(&*self.0.value, f)
+            }
+        }
+
+        f.debug_map()
+         .entries(self.fields.values().map(|field| (field.extension.name(), Value(field))))
+         .finish()
+    }
+}
+
+/// A set of extensions which are recognized while decoding.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut registry = ExtensionRegistry::new();
+/// registry.register(&my_package::MY_EXTENSION);
+///
+/// let mut options = DecodeOptions::new();
+/// options.extension_registry(registry);
+/// let message = MyMessage::decode_with_options(buf, &options)?;
+/// ```
+#[derive(Clone, Default)]
+pub struct ExtensionRegistry {
+    extensions: BTreeMap<(TypeId, u32), &'static dyn AnyExtension>,
+}
+
+impl ExtensionRegistry {
+
+    /// Creates an empty extension registry.
+    pub fn new() -> ExtensionRegistry {
+        ExtensionRegistry::default()
+    }
+
+    /// Registers an extension, replacing any extension of the same message with the same field
+    /// number.
+    pub fn register<M, T>(&mut self, extension: &'static Extension<M, T>) -> &mut Self
+    where M: Extendable,
+          T: ExtensionType {
+        self.extensions.insert((TypeId::of::<M>(), extension.number), extension);
+        self
+    }
+
+    /// Returns `true` if no extensions are registered.
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Returns the number of registered extensions.
+    pub fn len(&self) -> usize {
+        self.extensions.len()
+    }
+
+    /// Returns the registered extension of the message type with the provided field number.
+    #[doc(hidden)]
+    pub fn get(&self, extendee: TypeId, number: u32) -> Option<&'static dyn AnyExtension> {
+        self.extensions.get(&(extendee, number)).cloned()
+    }
+}
+
+impl fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+         .entries(self.extensions.values().map(|extension| extension.name()))
+         .finish()
+    }
+}
+
+/// An extension descriptor with its extended message and field types erased.
+///
+/// Meant to be used only by `ExtensionSet` and `ExtensionRegistry`. The value arguments must be
+/// of the extension's value type.
+#[doc(hidden)]
+pub trait AnyExtension: Send + Sync {
+    fn number(&self) -> u32;
+    fn name(&self) -> &'static str;
+    fn new_value(&self) -> Box<dyn Any + Send + Sync>;
+    fn merge(&self,
+             value: &mut (dyn Any + Send + Sync),
+             wire_type: WireType,
+             buf: &mut dyn Buf,
+             ctx: &DecodeContext) -> Result<(), DecodeError>;
+    fn encode(&self, value: &(dyn Any + Send + Sync), buf: &mut dyn BufMut);
+    fn encoded_len(&self, value: &(dyn Any + Send + Sync)) -> usize;
+    fn clone_value(&self, value: &(dyn Any + Send + Sync)) -> Box<dyn Any + Send + Sync>;
+    fn eq_value(&self, a: &(dyn Any + Send + Sync), b: &(dyn Any + Send + Sync)) -> bool;
+    fn fmt_value(&self, value: &(dyn Any + Send + Sync), f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl <M, T> Extension<M, T> where T: ExtensionType {
+    fn value<'a>(&self, value: &'a (dyn Any + Send + Sync)) -> &'a T::Value {
+        value.downcast_ref().expect("extension value has the wrong type")
+    }
+}
+
+impl <M, T> AnyExtension for Extension<M, T> where T: ExtensionType {
+    fn number(&self) -> u32 {
+        self.number
+    }
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn new_value(&self) -> Box<dyn Any + Send + Sync> {
+        Box::new(T::Value::default())
+    }
+    fn merge(&self,
+             value: &mut (dyn Any + Send + Sync),
+             wire_type: WireType,
+             mut buf: &mut dyn Buf,
+             ctx: &DecodeContext) -> Result<(), DecodeError> {
+        let value = value.downcast_mut().expect("extension value has the wrong type");
+        T::merge(self.number, wire_type, value, &mut buf, ctx)
+    }
+    fn encode(&self, value: &(dyn Any + Send + Sync), mut buf: &mut dyn BufMut) {
+        T::encode(self.number, self.value(value), &mut buf)
+    }
+    fn encoded_len(&self, value: &(dyn Any + Send + Sync)) -> usize {
+        T::encoded_len(self.number, self.value(value))
+    }
+    fn clone_value(&self, value: &(dyn Any + Send + Sync)) -> Box<dyn Any + Send + Sync> {
+        Box::new(self.value(value).clone())
+    }
+    fn eq_value(&self, a: &(dyn Any + Send + Sync), b: &(dyn Any + Send + Sync)) -> bool {
+        self.value(a) == self.value(b)
+    }
+    fn fmt_value(&self, value: &(dyn Any + Send + Sync), f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.value(value), f)
+    }
+}
+
+/// The field type of an extension, which determines the Rust type of the extension's value and
+/// how it is encoded.
+pub trait ExtensionType: 'static {
+    /// The Rust type of the extension's value.
+    type Value: Clone + fmt::Debug + PartialEq + Default + Send + Sync + 'static;
+
+    #[doc(hidden)]
+    fn encode<B>(number: u32, value: &Self::Value, buf: &mut B) where B: BufMut;
+
+    #[doc(hidden)]
+    fn merge<B>(number: u32,
+                wire_type: WireType,
+                value: &mut Self::Value,
+                buf: &mut B,
+                ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf;
+
+    #[doc(hidden)]
+    fn encoded_len(number: u32, value: &Self::Value) -> usize;
+}
+
+/// A field type which may be used as the element type of a repeated extension.
+#[doc(hidden)]
+pub trait RepeatedType: ExtensionType {
+    fn encode_repeated<B>(number: u32, values: &[Self::Value], buf: &mut B) where B: BufMut;
+    fn merge_repeated<B>(number: u32,
+                         wire_type: WireType,
+                         values: &mut Vec<Self::Value>,
+                         buf: &mut B,
+                         ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf;
+    fn encoded_len_repeated(number: u32, values: &[Self::Value]) -> usize;
+}
+
+/// A field type which may be used as the element type of a packed repeated extension.
+#[doc(hidden)]
+pub trait PackedType: RepeatedType {
+    fn encode_packed<B>(number: u32, values: &[Self::Value], buf: &mut B) where B: BufMut;
+    fn encoded_len_packed(number: u32, values: &[Self::Value]) -> usize;
+}
+
+/// Macro which emits a field type for a scalar Protobuf type.
+macro_rules! scalar {
+    ($(#[$attr:meta])* $name:ident, $proto_ty:ident, $ty:ty) => (
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name;
+
+        impl ExtensionType for $name {
+            type Value = $ty;
+
+            fn encode<B>(number: u32, value: &$ty, buf: &mut B) where B: BufMut {
+                encoding::$proto_ty::encode(number, value, buf)
+            }
+            fn merge<B>(_number: u32,
+                        wire_type: WireType,
+                        value: &mut $ty,
+                        buf: &mut B,
+                        ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+                encoding::$proto_ty::merge(wire_type, value, buf, ctx)
+            }
+            fn encoded_len(number: u32, value: &$ty) -> usize {
+                encoding::$proto_ty::encoded_len(number, value)
+            }
+        }
+
+        impl RepeatedType for $name {
+            fn encode_repeated<B>(number: u32, values: &[$ty], buf: &mut B) where B: BufMut {
+                encoding::$proto_ty::encode_repeated(number, values, buf)
+            }
+            fn merge_repeated<B>(_number: u32,
+                                 wire_type: WireType,
+                                 values: &mut Vec<$ty>,
+                                 buf: &mut B,
+                                 ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+                encoding::$proto_ty::merge_repeated(wire_type, values, buf, ctx)
+            }
+            fn encoded_len_repeated(number: u32, values: &[$ty]) -> usize {
+                encoding::$proto_ty::encoded_len_repeated(number, values)
+            }
+        }
+    );
+
+    ($(#[$attr:meta])* $name:ident, $proto_ty:ident, $ty:ty, packed) => (
+        scalar!($(#[$attr])* $name, $proto_ty, $ty);
+
+        impl PackedType for $name {
+            fn encode_packed<B>(number: u32, values: &[$ty], buf: &mut B) where B: BufMut {
+                encoding::$proto_ty::encode_packed(number, values, buf)
+            }
+            fn encoded_len_packed(number: u32, values: &[$ty]) -> usize {
+                encoding::$proto_ty::encoded_len_packed(number, values)
+            }
+        }
+    );
+}
+
+scalar!(/// The `double` field type.
+        Double, double, f64, packed);
+scalar!(/// The `float` field type.
+        Float, float, f32, packed);
+scalar!(/// The `int32` field type.
+        Int32, int32, i32, packed);
+scalar!(/// The `int64` field type.
+        Int64, int64, i64, packed);
+scalar!(/// The `uint32` field type.
+        Uint32, uint32, u32, packed);
+scalar!(/// The `uint64` field type.
+        Uint64, uint64, u64, packed);
+scalar!(/// The `sint32` field type.
+        Sint32, sint32, i32, packed);
+scalar!(/// The `sint64` field type.
+        Sint64, sint64, i64, packed);
+scalar!(/// The `fixed32` field type.
+        Fixed32, fixed32, u32, packed);
+scalar!(/// The `fixed64` field type.
+        Fixed64, fixed64, u64, packed);
+scalar!(/// The `sfixed32` field type.
+        Sfixed32, sfixed32, i32, packed);
+scalar!(/// The `sfixed64` field type.
+        Sfixed64, sfixed64, i64, packed);
+scalar!(/// The `bool` field type.
+        Bool, bool, bool, packed);
+scalar!(/// The enum field type. Values are represented as `i32`, as with enum fields of messages.
+        Enumeration, int32, i32, packed);
+scalar!(/// The `string` field type.
+        String, string, ::alloc::string::String);
+scalar!(/// The `bytes` field type.
+        Bytes, bytes, Vec<u8>);
+
+/// The message field type.
+pub struct Message<M>(PhantomData<fn() -> M>);
+
+impl <M> ExtensionType for Message<M>
+where M: ::Message + Clone + PartialEq + Default + 'static {
+    type Value = M;
+
+    fn encode<B>(number: u32, value: &M, buf: &mut B) where B: BufMut {
+        encoding::message::encode(number, value, buf)
+    }
+    fn merge<B>(_number: u32,
+                wire_type: WireType,
+                value: &mut M,
+                buf: &mut B,
+                ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        encoding::message::merge(wire_type, value, buf, ctx)
+    }
+    fn encoded_len(number: u32, value: &M) -> usize {
+        encoding::message::encoded_len(number, value)
+    }
+}
+
+impl <M> RepeatedType for Message<M>
+where M: ::Message + Clone + PartialEq + Default + 'static {
+    fn encode_repeated<B>(number: u32, values: &[M], buf: &mut B) where B: BufMut {
+        encoding::message::encode_repeated(number, values, buf)
+    }
+    fn merge_repeated<B>(_number: u32,
+                         wire_type: WireType,
+                         values: &mut Vec<M>,
+                         buf: &mut B,
+                         ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        encoding::message::merge_repeated(wire_type, values, buf, ctx)
+    }
+    fn encoded_len_repeated(number: u32, values: &[M]) -> usize {
+        encoding::message::encoded_len_repeated(number, values)
+    }
+}
+
+/// The group field type.
+pub struct Group<M>(PhantomData<fn() -> M>);
+
+impl <M> ExtensionType for Group<M>
+where M: ::Message + Clone + PartialEq + Default + 'static {
+    type Value = M;
+
+    fn encode<B>(number: u32, value: &M, buf: &mut B) where B: BufMut {
+        encoding::group::encode(number, value, buf)
+    }
+    fn merge<B>(number: u32,
+                wire_type: WireType,
+                value: &mut M,
+                buf: &mut B,
+                ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        encoding::group::merge(number, wire_type, value, buf, ctx)
+    }
+    fn encoded_len(number: u32, value: &M) -> usize {
+        encoding::group::encoded_len(number, value)
+    }
+}
+
+impl <M> RepeatedType for Group<M>
+where M: ::Message + Clone + PartialEq + Default + 'static {
+    fn encode_repeated<B>(number: u32, values: &[M], buf: &mut B) where B: BufMut {
+        encoding::group::encode_repeated(number, values, buf)
+    }
+    fn merge_repeated<B>(number: u32,
+                         wire_type: WireType,
+                         values: &mut Vec<M>,
+                         buf: &mut B,
+                         ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        encoding::group::merge_repeated(number, wire_type, values, buf, ctx)
+    }
+    fn encoded_len_repeated(number: u32, values: &[M]) -> usize {
+        encoding::group::encoded_len_repeated(number, values)
+    }
+}
+
+/// The field type of a repeated extension with elements of type `T`.
+pub struct Repeated<T>(PhantomData<fn() -> T>);
+
+impl <T> ExtensionType for Repeated<T> where T: RepeatedType {
+    type Value = Vec<T::Value>;
+
+    fn encode<B>(number: u32, values: &Vec<T::Value>, buf: &mut B) where B: BufMut {
+        T::encode_repeated(number, values, buf)
+    }
+    fn merge<B>(number: u32,
+                wire_type: WireType,
+                values: &mut Vec<T::Value>,
+                buf: &mut B,
+                ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        T::merge_repeated(number, wire_type, values, buf, ctx)
+    }
+    fn encoded_len(number: u32, values: &Vec<T::Value>) -> usize {
+        T::encoded_len_repeated(number, values)
+    }
+}
+
+/// The field type of a packed repeated extension with elements of type `T`.
+///
+/// Packed and unpacked elements are both accepted when decoding.
+pub struct Packed<T>(PhantomData<fn() -> T>);
+
+impl <T> ExtensionType for Packed<T> where T: PackedType {
+    type Value = Vec<T::Value>;
+
+    fn encode<B>(number: u32, values: &Vec<T::Value>, buf: &mut B) where B: BufMut {
+        T::encode_packed(number, values, buf)
+    }
+    fn merge<B>(number: u32,
+                wire_type: WireType,
+                values: &mut Vec<T::Value>,
+                buf: &mut B,
+                ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        T::merge_repeated(number, wire_type, values, buf, ctx)
+    }
+    fn encoded_len(number: u32, values: &Vec<T::Value>) -> usize {
+        T::encoded_len_packed(number, values)
+    }
+}
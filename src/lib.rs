@@ -1,20 +1,53 @@
 #![doc(html_root_url = "https://docs.rs/prost/0.4.0")]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-extern crate bytes;
+#[cfg(feature = "std")]
+extern crate core;
+#[macro_use]
+extern crate alloc;
+/// The `bytes` crate, re-exported so that generated code can refer to `bytes::Bytes` without a
+/// direct dependency on a compatible version of `bytes`.
+#[cfg(feature = "std")]
+pub extern crate bytes;
+/// Replacements for the buffer traits of the `bytes` crate, which requires `std`.
+#[cfg(not(feature = "std"))]
+pub mod bytes;
+#[cfg(feature = "codec")]
+extern crate tokio_io;
+
+#[cfg(all(test, feature = "codec"))]
+extern crate futures;
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 
+#[cfg(feature = "std")]
+mod byte_string;
+mod enumeration;
 mod error;
 mod message;
 mod types;
+mod unknown;
 
 #[doc(hidden)]
 pub mod encoding;
+pub mod extension;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod stream;
 
-pub use message::Message;
-pub use error::{DecodeError, EncodeError};
+#[cfg(feature = "std")]
+pub use byte_string::ByteString;
+pub use encoding::WireType;
+pub use enumeration::{Enumeration, OpenEnum, UnknownEnumName, UnknownEnumValue};
+pub use message::{BorrowedMessage, DecodeOptions, Message};
+pub use error::{DecodeError, DecodeErrorFrame, DecodeErrorKind, EncodeError};
+pub use extension::{Extendable, Extension, ExtensionRegistry, ExtensionSet, ExtensionType};
+#[cfg(feature = "std")]
+pub use stream::LengthDelimitedDecoder;
+pub use unknown::{UnknownField, UnknownFields, UnknownValue};
 
 use bytes::{
     BufMut,
@@ -59,15 +92,17 @@ pub fn length_delimiter_len(length: usize) -> usize {
 ///
 /// An error may be returned in two cases:
 ///
-///  * If the supplied buffer contains fewer than 10 bytes, then an error indicates that more
-///    input is required to decode the full delimiter.
+///  * If the supplied buffer contains fewer than 10 bytes, then an error of kind
+///    `DecodeErrorKind::Truncated` indicates that more input is required to decode the full
+///    delimiter.
 ///  * If the supplied buffer contains more than 10 bytes, then the buffer contains an invalid
 ///    delimiter, and typically the buffer should be considered corrupt.
 pub fn decode_length_delimiter<B>(buf: B) -> Result<usize, DecodeError> where B: IntoBuf {
     let mut buf = buf.into_buf();
     let length = decode_varint(&mut buf)?;
     if length > usize::max_value() as u64 {
-        return Err(DecodeError::new("length delimiter exceeds maximum usize value"));
+        return Err(DecodeError::with_kind(DecodeErrorKind::LengthOverflow,
+                                          "length delimiter exceeds maximum usize value"));
     }
     Ok(length as usize)
 }
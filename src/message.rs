@@ -1,10 +1,15 @@
-use std::fmt::Debug;
-use std::usize;
+use alloc::boxed::Box;
+use core::fmt::Debug;
+use core::usize;
 
 use bytes::{Buf, BufMut, IntoBuf};
+#[cfg(feature = "std")]
+use bytes::Bytes;
 
 use DecodeError;
+use DecodeErrorKind;
 use EncodeError;
+use ExtensionRegistry;
 use encoding::*;
 
 /// A Protocol Buffers message.
@@ -18,41 +23,126 @@ pub trait Message: Debug + Send + Sync {
     #[doc(hidden)]
     fn encode_raw<B>(&self, buf: &mut B) where B: BufMut, Self: Sized;
 
-    /// Decodes a field from a buffer, and merges it into `self`.
+    /// Decodes the value of a field with the provided tag and wire type from a buffer, and merges
+    /// it into `self`. The field key must already have been consumed from the buffer.
     ///
     /// Meant to be used only by `Message` implementations.
     #[doc(hidden)]
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf, Self: Sized;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError>
+    where B: Buf, Self: Sized;
 
     /// Returns the encoded length of the message without a length delimiter.
     fn encoded_len(&self) -> usize;
 
+    /// Returns an error naming the first required field which is not set, in the message or in
+    /// one of its nested messages.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    fn check_initialized(&self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    /// Returns `true` if all required fields of the message and of its nested messages are set.
+    ///
+    /// Only proto2 messages have required fields; other messages are always initialized. A
+    /// `required` field can only be unset if it tracks presence, i.e. is stored in an `Option`
+    /// (see `prost_build::Config::required_presence`); plain `required` fields are always set.
+    fn is_initialized(&self) -> bool {
+        self.check_initialized().is_ok()
+    }
+
+    /// Returns the encoded length of the message without a length delimiter, recording the
+    /// lengths of its nested messages in the size cache for `encode_cached`.
+    ///
+    /// Meant to be used only by `Message` implementations. The default implementation records
+    /// nothing, and must be overridden together with `encode_cached`.
+    #[doc(hidden)]
+    fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+        let _ = cache;
+        self.encoded_len()
+    }
+
+    /// Encodes the message to a buffer, taking the lengths of its nested messages from a size
+    /// cache filled in by `cache_sizes`.
+    ///
+    /// This method will panic if the buffer has insufficient capacity.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut, Self: Sized {
+        let _ = cache;
+        self.encode_raw(buf)
+    }
+
     /// Encodes the message to a buffer.
     ///
     /// An error will be returned if the buffer does not have sufficient capacity.
     fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
-        let required = self.encoded_len();
+        let mut cache = SizeCache::new();
+        let required = self.cache_sizes(&mut cache);
         let remaining = buf.remaining_mut();
         if required > buf.remaining_mut() {
             return Err(EncodeError::new(required, remaining));
         }
 
-        self.encode_raw(buf);
+        self.encode_cached(buf, &mut cache);
         Ok(())
     }
 
+    /// Encodes the message to a buffer, if all of its required fields are set.
+    ///
+    /// An error will be returned if a required field of the message or of one of its nested
+    /// messages is not set, or if the buffer does not have sufficient capacity. Nothing is written
+    /// to the buffer in either case.
+    fn encode_checked<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        self.check_initialized().map_err(EncodeError::missing_required_field)?;
+        self.encode(buf)
+    }
+
     /// Encodes the message with a length-delimiter to a buffer.
     ///
     /// An error will be returned if the buffer does not have sufficient capacity.
     fn encode_length_delimited<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
-        let len = self.encoded_len();
+        let mut cache = SizeCache::new();
+        let len = self.cache_sizes(&mut cache);
         let required = len + encoded_len_varint(len as u64);
         let remaining = buf.remaining_mut();
         if required > remaining {
             return Err(EncodeError::new(required, remaining));
         }
         encode_varint(len as u64, buf);
-        self.encode_raw(buf);
+        self.encode_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Encodes the message to a buffer deterministically.
+    ///
+    /// Map entries are encoded in key order regardless of the map type, and always include the
+    /// key and the value, so that equal messages are encoded to the same bytes by the same version
+    /// of the program. As with other protobuf implementations, deterministic output is not
+    /// canonical: it may change between versions, and should not be relied on to compare messages
+    /// across programs. Unknown fields and extension fields are encoded as they are stored.
+    ///
+    /// Derived messages, borrowed messages and `DynamicMessage` implement `cache_sizes` and
+    /// `encode_cached`, which carry the deterministic mode down to nested maps. A hand-written
+    /// implementation which only implements `encode_raw` is encoded as `encode` would encode it,
+    /// including the order of any maps it contains.
+    ///
+    /// An error will be returned if the buffer does not have sufficient capacity.
+    fn encode_deterministic<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        let mut cache = SizeCache::deterministic();
+        let required = self.cache_sizes(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+
+        self.encode_cached(buf, &mut cache);
         Ok(())
     }
 
@@ -64,6 +154,27 @@ pub trait Message: Debug + Send + Sync {
         Self::merge(&mut message, &mut buf.into_buf()).map(|_| message)
     }
 
+    /// Decodes an instance of the message from a buffer, using the provided decode options.
+    ///
+    /// The entire buffer will be consumed.
+    fn decode_with_options<B>(buf: B, options: &DecodeOptions) -> Result<Self, DecodeError>
+    where B: IntoBuf, Self: Default {
+        let mut message = Self::default();
+        Self::merge_with_options(&mut message, buf, options).map(|_| message)
+    }
+
+    /// Decodes an instance of the message from a `Bytes` buffer.
+    ///
+    /// Unlike `decode`, `bytes::Bytes` and `prost::ByteString` fields of the decoded message
+    /// share memory with the buffer instead of copying out of it.
+    ///
+    /// The entire buffer will be consumed.
+    #[cfg(feature = "std")]
+    fn decode_bytes(buf: Bytes) -> Result<Self, DecodeError> where Self: Default {
+        let mut message = Self::default();
+        Self::merge_bytes(&mut message, buf).map(|_| message)
+    }
+
     /// Decodes a length-delimited instance of the message from the buffer.
     fn decode_length_delimited<B>(buf: B) -> Result<Self, DecodeError> where B: IntoBuf, Self: Default {
         let mut message = Self::default();
@@ -71,37 +182,441 @@ pub trait Message: Debug + Send + Sync {
         Ok(message)
     }
 
+    /// Decodes a length-delimited instance of the message from the buffer, using the provided
+    /// decode options.
+    fn decode_length_delimited_with_options<B>(buf: B, options: &DecodeOptions) -> Result<Self, DecodeError>
+    where B: IntoBuf, Self: Default {
+        let mut message = Self::default();
+        message.merge_length_delimited_with_options(buf, options)?;
+        Ok(message)
+    }
+
     /// Decodes an instance of the message from a buffer, and merges it into `self`.
     ///
     /// The entire buffer will be consumed.
     fn merge<B>(&mut self, buf: B) -> Result<(), DecodeError> where B: IntoBuf, Self: Sized {
-        let mut buf = buf.into_buf();
-        while buf.has_remaining() {
-            self.merge_field(&mut buf)?;
+        self.merge_with_options(buf, &DecodeOptions::default())
+    }
+
+    /// Decodes an instance of the message from a buffer using the provided decode options, and
+    /// merges it into `self`.
+    ///
+    /// The entire buffer will be consumed.
+    fn merge_with_options<B>(&mut self, buf: B, options: &DecodeOptions) -> Result<(), DecodeError>
+    where B: IntoBuf, Self: Sized {
+        let ctx = options.context();
+        track_offset(&mut buf.into_buf(), |buf| {
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(buf)?;
+                self.merge_field(tag, wire_type, buf, &ctx)?;
+            }
+            Ok(())
+        })?;
+        // Required fields are checked in the merged message, since they may have been set before
+        // the merge, or by an earlier occurrence of a nested message.
+        if options.check_required {
+            self.check_initialized()?;
         }
         Ok(())
     }
 
+    /// Decodes an instance of the message from a `Bytes` buffer, and merges it into `self`.
+    ///
+    /// Unlike `merge`, `bytes::Bytes` and `prost::ByteString` fields of the decoded message
+    /// share memory with the buffer instead of copying out of it.
+    ///
+    /// The entire buffer will be consumed.
+    #[cfg(feature = "std")]
+    fn merge_bytes(&mut self, buf: Bytes) -> Result<(), DecodeError> where Self: Sized {
+        let ctx = DecodeContext::default().with_source(buf.clone());
+        track_offset(&mut buf.into_buf(), |buf| {
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(buf)?;
+                self.merge_field(tag, wire_type, buf, &ctx)?;
+            }
+            Ok(())
+        })
+    }
+
     /// Decodes a length-delimited instance of the message from buffer, and
     /// merges it into `self`.
     fn merge_length_delimited<B>(&mut self, buf: B) -> Result<(), DecodeError> where B: IntoBuf, Self: Sized {
-        message::merge(WireType::LengthDelimited, self, &mut buf.into_buf())
+        self.merge_length_delimited_with_options(buf, &DecodeOptions::default())
+    }
+
+    /// Decodes a length-delimited instance of the message from buffer using the provided decode
+    /// options, and merges it into `self`.
+    fn merge_length_delimited_with_options<B>(&mut self, buf: B, options: &DecodeOptions) -> Result<(), DecodeError>
+    where B: IntoBuf, Self: Sized {
+        track_offset(&mut buf.into_buf(), |buf| {
+            message::merge(WireType::LengthDelimited, self, buf, &options.context())
+        })?;
+        if options.check_required {
+            self.check_initialized()?;
+        }
+        Ok(())
     }
 
     /// Clears the message, resetting all fields to their default.
     fn clear(&mut self);
 }
 
+/// A Protocol Buffers message which borrows its `string`, `bytes`, and nested message fields from
+/// the buffer it is decoded from, instead of copying them into owned values.
+///
+/// Borrowed messages are structs with a lifetime parameter, and are decoded from a `&'a [u8]`.
+/// Repeated fields are still collected into a `Vec`, but the elements reference the buffer.
+pub trait BorrowedMessage<'a>: Debug + Send + Sync {
+
+    /// Encodes the message to a buffer.
+    ///
+    /// This method will panic if the buffer has insufficient capacity.
+    ///
+    /// Meant to be used only by `BorrowedMessage` implementations.
+    #[doc(hidden)]
+    fn encode_raw<B>(&self, buf: &mut B) where B: BufMut, Self: Sized;
+
+    /// Decodes the value of a field with the provided tag and wire type from a buffer, and merges
+    /// it into `self`. The field key must already have been consumed from the buffer.
+    ///
+    /// Meant to be used only by `BorrowedMessage` implementations.
+    #[doc(hidden)]
+    fn merge_field(&mut self,
+                   tag: u32,
+                   wire_type: WireType,
+                   buf: &mut SliceBuf<'a>,
+                   ctx: &DecodeContext) -> Result<(), DecodeError>
+    where Self: Sized;
+
+    /// Returns the encoded length of the message without a length delimiter.
+    fn encoded_len(&self) -> usize;
+
+    /// Returns an error naming the first required field which is not set, in the message or in
+    /// one of its nested messages.
+    ///
+    /// Meant to be used only by `BorrowedMessage` implementations.
+    #[doc(hidden)]
+    fn check_initialized(&self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    /// Returns `true` if all required fields of the message and of its nested messages are set.
+    ///
+    /// Only proto2 messages have required fields; other messages are always initialized.
+    fn is_initialized(&self) -> bool {
+        self.check_initialized().is_ok()
+    }
+
+    /// Returns the encoded length of the message without a length delimiter, recording the
+    /// lengths of its nested messages in the size cache for `encode_cached`.
+    ///
+    /// Meant to be used only by `BorrowedMessage` implementations. The default implementation
+    /// records nothing, and must be overridden together with `encode_cached`.
+    #[doc(hidden)]
+    fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+        let _ = cache;
+        self.encoded_len()
+    }
+
+    /// Encodes the message to a buffer, taking the lengths of its nested messages from a size
+    /// cache filled in by `cache_sizes`.
+    ///
+    /// This method will panic if the buffer has insufficient capacity.
+    ///
+    /// Meant to be used only by `BorrowedMessage` implementations.
+    #[doc(hidden)]
+    fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut, Self: Sized {
+        let _ = cache;
+        self.encode_raw(buf)
+    }
+
+    /// Encodes the message to a buffer.
+    ///
+    /// An error will be returned if the buffer does not have sufficient capacity.
+    fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        let mut cache = SizeCache::new();
+        let required = self.cache_sizes(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+
+        self.encode_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Encodes the message to a buffer, if all of its required fields are set.
+    ///
+    /// An error will be returned if a required field of the message or of one of its nested
+    /// messages is not set, or if the buffer does not have sufficient capacity. Nothing is written
+    /// to the buffer in either case.
+    fn encode_checked<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        self.check_initialized().map_err(EncodeError::missing_required_field)?;
+        self.encode(buf)
+    }
+
+    /// Encodes the message with a length-delimiter to a buffer.
+    ///
+    /// An error will be returned if the buffer does not have sufficient capacity.
+    fn encode_length_delimited<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        let mut cache = SizeCache::new();
+        let len = self.cache_sizes(&mut cache);
+        let required = len + encoded_len_varint(len as u64);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+        encode_varint(len as u64, buf);
+        self.encode_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Encodes the message to a buffer deterministically.
+    ///
+    /// See `Message::encode_deterministic`.
+    ///
+    /// An error will be returned if the buffer does not have sufficient capacity.
+    fn encode_deterministic<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut, Self: Sized {
+        let mut cache = SizeCache::deterministic();
+        let required = self.cache_sizes(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+
+        self.encode_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Decodes an instance of the message from a buffer, borrowing from it.
+    ///
+    /// The entire buffer will be consumed.
+    fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> where Self: Default {
+        let mut message = Self::default();
+        Self::merge(&mut message, buf).map(|_| message)
+    }
+
+    /// Decodes an instance of the message from a buffer using the provided decode options,
+    /// borrowing from it.
+    ///
+    /// The entire buffer will be consumed.
+    fn decode_with_options(buf: &'a [u8], options: &DecodeOptions) -> Result<Self, DecodeError>
+    where Self: Default {
+        let mut message = Self::default();
+        Self::merge_with_options(&mut message, buf, options).map(|_| message)
+    }
+
+    /// Decodes a length-delimited instance of the message from the buffer, borrowing from it.
+    fn decode_length_delimited(buf: &'a [u8]) -> Result<Self, DecodeError> where Self: Default {
+        let mut message = Self::default();
+        message.merge_length_delimited(buf)?;
+        Ok(message)
+    }
+
+    /// Decodes a length-delimited instance of the message from the buffer using the provided
+    /// decode options, borrowing from it.
+    fn decode_length_delimited_with_options(buf: &'a [u8], options: &DecodeOptions) -> Result<Self, DecodeError>
+    where Self: Default {
+        let mut message = Self::default();
+        message.merge_length_delimited_with_options(buf, options)?;
+        Ok(message)
+    }
+
+    /// Decodes an instance of the message from a buffer, and merges it into `self`.
+    ///
+    /// The entire buffer will be consumed.
+    fn merge(&mut self, buf: &'a [u8]) -> Result<(), DecodeError> where Self: Sized {
+        self.merge_with_options(buf, &DecodeOptions::default())
+    }
+
+    /// Decodes an instance of the message from a buffer using the provided decode options, and
+    /// merges it into `self`.
+    ///
+    /// The entire buffer will be consumed.
+    fn merge_with_options(&mut self, buf: &'a [u8], options: &DecodeOptions) -> Result<(), DecodeError>
+    where Self: Sized {
+        let ctx = options.context();
+        track_offset(&mut buf.into_buf(), |buf| {
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(buf)?;
+                self.merge_field(tag, wire_type, buf, &ctx)?;
+            }
+            Ok(())
+        })?;
+        // Required fields are checked in the merged message, since they may have been set before
+        // the merge, or by an earlier occurrence of a nested message.
+        if options.check_required {
+            self.check_initialized()?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a length-delimited instance of the message from buffer, and merges it into `self`.
+    fn merge_length_delimited(&mut self, buf: &'a [u8]) -> Result<(), DecodeError> where Self: Sized {
+        self.merge_length_delimited_with_options(buf, &DecodeOptions::default())
+    }
+
+    /// Decodes a length-delimited instance of the message from buffer using the provided decode
+    /// options, and merges it into `self`.
+    fn merge_length_delimited_with_options(&mut self, buf: &'a [u8], options: &DecodeOptions)
+                                           -> Result<(), DecodeError> where Self: Sized {
+        let mut cursor = buf.into_buf();
+        let len = decode_varint(&mut cursor)?;
+        if len > cursor.remaining() as u64 {
+            return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
+        }
+        let start = cursor.position() as usize;
+        self.merge_with_options(&buf[start..start + len as usize], options).map_err(|mut error| {
+            if let Some(offset) = error.offset() {
+                error.set_offset(start + offset);
+            }
+            error
+        })
+    }
+
+    /// Clears the message, resetting all fields to their default.
+    fn clear(&mut self);
+}
+
+/// Decodes from the buffer with `decode`, recording the offset in the buffer at which decoding
+/// failed in the error.
+fn track_offset<B, T, F>(buf: &mut B, decode: F) -> Result<T, DecodeError>
+where B: Buf,
+      F: FnOnce(&mut B) -> Result<T, DecodeError> {
+    let len = buf.remaining();
+    decode(buf).map_err(|mut error| {
+        error.set_offset(len - buf.remaining());
+        error
+    })
+}
+
+/// Options which control how messages are decoded.
+///
+/// # Examples
+///
+/// ```
+/// # use prost::{DecodeOptions, Message};
+/// let mut options = DecodeOptions::new();
+/// options.recursion_limit(32);
+/// let value = u32::decode_with_options(&[0x08, 0x2a][..], &options).unwrap();
+/// assert_eq!(42, value);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DecodeOptions {
+    recursion_limit: u32,
+    extension_registry: Option<Shared<ExtensionRegistry>>,
+    check_required: bool,
+}
+
+impl DecodeOptions {
+
+    /// Creates a new set of decode options with default values.
+    pub fn new() -> DecodeOptions {
+        DecodeOptions::default()
+    }
+
+    /// Sets the maximum depth of nested messages which will be decoded.
+    ///
+    /// Decoding a message which is nested more deeply than the limit fails with an error, instead
+    /// of recursing without bound on untrusted input. Defaults to 100.
+    pub fn recursion_limit(&mut self, limit: u32) -> &mut Self {
+        self.recursion_limit = limit;
+        self
+    }
+
+    /// Sets the registry of extensions which are recognized while decoding.
+    ///
+    /// Fields of registered extensions are decoded into the `ExtensionSet` of the extended
+    /// message. Fields of unregistered extensions are treated as unknown fields.
+    pub fn extension_registry(&mut self, registry: ExtensionRegistry) -> &mut Self {
+        self.extension_registry = Some(Shared::new(registry));
+        self
+    }
+
+    /// Sets whether decoded messages must contain all of their required fields.
+    ///
+    /// A proto2 message is only valid if all of its `required` fields are set. When enabled, the
+    /// merged message is checked with `Message::is_initialized` once decoding finishes, and
+    /// decoding fails with an error naming the first missing field, including in nested messages.
+    /// Otherwise, missing required fields are left unset. Only `required` fields which track
+    /// presence can be detected as missing; plain `required` fields are left at their default
+    /// values. Defaults to `false`.
+    pub fn check_required(&mut self, check_required: bool) -> &mut Self {
+        self.check_required = check_required;
+        self
+    }
+
+    /// Returns a decode context for decoding a message with the options.
+    fn context(&self) -> DecodeContext {
+        let ctx = DecodeContext::new(self.recursion_limit);
+        match self.extension_registry {
+            Some(ref registry) => ctx.with_extension_registry(registry.clone()),
+            None => ctx,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            recursion_limit: RECURSION_LIMIT,
+            extension_registry: None,
+            check_required: false,
+        }
+    }
+}
+
+impl <'a, M> BorrowedMessage<'a> for Box<M> where M: BorrowedMessage<'a> {
+    fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        (**self).encode_raw(buf)
+    }
+    fn merge_field(&mut self,
+                   tag: u32,
+                   wire_type: WireType,
+                   buf: &mut SliceBuf<'a>,
+                   ctx: &DecodeContext) -> Result<(), DecodeError> {
+        (**self).merge_field(tag, wire_type, buf, ctx)
+    }
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+    fn check_initialized(&self) -> Result<(), DecodeError> {
+        (**self).check_initialized()
+    }
+    fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+        (**self).cache_sizes(cache)
+    }
+    fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        (**self).encode_cached(buf, cache)
+    }
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+}
+
 impl <M> Message for Box<M> where M: Message {
     fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
         (**self).encode_raw(buf)
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        (**self).merge_field(buf)
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        (**self).merge_field(tag, wire_type, buf, ctx)
     }
     fn encoded_len(&self) -> usize {
         (**self).encoded_len()
     }
+    fn check_initialized(&self) -> Result<(), DecodeError> {
+        (**self).check_initialized()
+    }
+    fn cache_sizes(&self, cache: &mut SizeCache) -> usize {
+        (**self).cache_sizes(cache)
+    }
+    fn encode_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        (**self).encode_cached(buf, cache)
+    }
     fn clear(&mut self) {
         (**self).clear()
     }
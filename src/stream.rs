@@ -0,0 +1,300 @@
+//! Incremental decoding of length-delimited message streams.
+//!
+//! A stream of messages written with `Message::encode_length_delimited` may arrive in arbitrarily
+//! sized chunks, for example from a socket or a file. `LengthDelimitedDecoder` buffers the chunks
+//! and yields each message once its delimiter and body are complete.
+
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::io;
+
+use bytes::{BytesMut, IntoBuf};
+
+use DecodeError;
+use DecodeErrorKind;
+use DecodeOptions;
+use Message;
+use encoding::decode_varint;
+
+/// The default maximum size of a single message in the stream: 64 MiB.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// The maximum length of a length delimiter, in bytes.
+const MAX_DELIMITER_LEN: usize = 10;
+
+/// The most buffer space reserved ahead of a partially received frame. A length delimiter alone
+/// can't force a large allocation; the buffer grows as the rest of the frame arrives.
+pub(crate) const MAX_RESERVE: usize = 8 * 1024;
+
+/// An incremental decoder for a stream of length-delimited messages of type `M`.
+///
+/// Input is provided in chunks of any size with `push`, and complete messages are taken out with
+/// `decode`, which returns `Ok(None)` when more input is needed to complete the next message.
+/// Alternatively, `read_from` pulls input from a `std::io::Read` until a message is complete.
+///
+/// ```
+/// # use prost::Message;
+/// # use prost::stream::LengthDelimitedDecoder;
+/// let mut buf = Vec::new();
+/// "hello".to_string().encode_length_delimited(&mut buf).unwrap();
+///
+/// let mut decoder = LengthDelimitedDecoder::<String>::new();
+/// decoder.push(&buf[..3]);
+/// assert_eq!(decoder.decode().unwrap(), None);
+/// decoder.push(&buf[3..]);
+/// assert_eq!(decoder.decode().unwrap(), Some("hello".to_string()));
+/// ```
+pub struct LengthDelimitedDecoder<M> {
+    buf: BytesMut,
+    /// The length of the next message, once its delimiter has been decoded.
+    frame_len: Option<usize>,
+    max_frame_size: usize,
+    options: DecodeOptions,
+    marker: PhantomData<fn() -> M>,
+}
+
+impl <M> LengthDelimitedDecoder<M> where M: Message + Default {
+
+    /// Creates a new decoder with an empty buffer.
+    pub fn new() -> LengthDelimitedDecoder<M> {
+        LengthDelimitedDecoder {
+            buf: BytesMut::new(),
+            frame_len: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            options: DecodeOptions::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum encoded length of a single message.
+    ///
+    /// A delimiter announcing a longer message is treated as corrupt input, so that an untrusted
+    /// peer can't make the decoder buffer without bound. Defaults to `DEFAULT_MAX_FRAME_SIZE`.
+    pub fn max_frame_size(&mut self, max_frame_size: usize) -> &mut Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Sets the options used to decode each message.
+    pub fn decode_options(&mut self, options: DecodeOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Appends a chunk of input to the decoder's buffer.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Returns the number of buffered bytes which have not yet been decoded.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the decoder is between messages, with no partial message buffered.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty() && self.frame_len.is_none()
+    }
+
+    /// Decodes the next message from the buffered input.
+    ///
+    /// Returns `Ok(None)` if the buffered input ends before the next message does; the decoder
+    /// resumes once more input is pushed. An error indicates that the input is corrupt:
+    ///
+    ///  * If the length delimiter is invalid or exceeds the maximum frame size, the stream can't
+    ///    be resynchronized, and the decoder keeps returning the error.
+    ///  * If the message itself fails to decode, its frame is discarded, and decoding may continue
+    ///    with the next message.
+    pub fn decode(&mut self) -> Result<Option<M>, DecodeError> {
+        let frame_len = match self.frame_len {
+            Some(frame_len) => frame_len,
+            None => {
+                let (frame_len, delimiter_len) = match decode_delimiter(&self.buf)? {
+                    Some(delimiter) => delimiter,
+                    None => return Ok(None),
+                };
+                if frame_len > self.max_frame_size as u64 {
+                    return Err(DecodeError::with_kind(DecodeErrorKind::LengthOverflow, format!(
+                        "length delimiter {} exceeds maximum frame size {}",
+                        frame_len, self.max_frame_size)));
+                }
+                self.buf.advance(delimiter_len);
+                let frame_len = frame_len as usize;
+                self.frame_len = Some(frame_len);
+                frame_len
+            },
+        };
+
+        if self.buf.len() < frame_len {
+            let additional = frame_len - self.buf.len();
+            self.buf.reserve(additional.min(MAX_RESERVE));
+            return Ok(None);
+        }
+
+        let frame = self.buf.split_to(frame_len);
+        self.frame_len = None;
+        M::decode_with_options(&frame[..], &self.options).map(Some)
+    }
+
+    /// Reads from `reader` until the next message is complete, and decodes it.
+    ///
+    /// Returns `Ok(None)` if the reader reaches end of file between messages, and an
+    /// `UnexpectedEof` error if it does so in the middle of a message. Decoding errors are
+    /// returned as `InvalidData` errors.
+    ///
+    /// Input which has been read is retained if the reader fails, so the call may be retried once
+    /// the reader is ready again, for example after a non-blocking reader returns `WouldBlock`.
+    #[cfg(feature = "std")]
+    pub fn read_from<R>(&mut self, reader: &mut R) -> io::Result<Option<M>> where R: io::Read {
+        let mut chunk = [0; 8 * 1024];
+        loop {
+            if let Some(message) = self.decode()? {
+                return Ok(Some(message));
+            }
+
+            let len = match reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            if len == 0 {
+                if self.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "stream ended in the middle of a length-delimited message"));
+            }
+            self.push(&chunk[..len]);
+        }
+    }
+}
+
+impl <M> Default for LengthDelimitedDecoder<M> where M: Message + Default {
+    fn default() -> LengthDelimitedDecoder<M> {
+        LengthDelimitedDecoder::new()
+    }
+}
+
+/// Decodes a length delimiter from the start of the slice, returning the delimited length and the
+/// length of the delimiter itself, or `None` if the slice ends before the delimiter does.
+pub(crate) fn decode_delimiter(buf: &[u8]) -> Result<Option<(u64, usize)>, DecodeError> {
+    let delimiter_len = match buf.iter().take(MAX_DELIMITER_LEN).position(|&b| b < 0x80) {
+        Some(position) => position + 1,
+        None if buf.len() < MAX_DELIMITER_LEN => return Ok(None),
+        None => return Err(DecodeError::with_kind(DecodeErrorKind::InvalidVarint, "invalid length delimiter")),
+    };
+    let value = decode_varint(&mut (&buf[..delimiter_len]).into_buf())?;
+    Ok(Some((value, delimiter_len)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::*;
+
+    fn encode_all(messages: &[&str]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for message in messages {
+            message.to_string().encode_length_delimited(&mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn decode_byte_at_a_time() {
+        let long = "x".repeat(300);
+        let buf = encode_all(&["a", "", &long]);
+
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        let mut decoded = Vec::new();
+        for &b in &buf {
+            decoder.push(&[b]);
+            while let Some(message) = decoder.decode().unwrap() {
+                decoded.push(message);
+            }
+        }
+        assert_eq!(decoded, vec!["a".to_string(), String::new(), long]);
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn decode_corrupt() {
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        decoder.push(&[0xff; 9]);
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.push(&[0xff]);
+        assert!(decoder.decode().is_err());
+
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        decoder.max_frame_size(6);
+        decoder.push(&encode_all(&["abcd", "abcde"]));
+        assert_eq!(decoder.decode().unwrap(), Some("abcd".to_string()));
+        assert!(decoder.decode().is_err());
+
+        // An invalid message is skipped.
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        decoder.push(&[2, 0x0a, 0x05]);
+        decoder.push(&encode_all(&["ok"]));
+        assert!(decoder.decode().is_err());
+        assert_eq!(decoder.decode().unwrap(), Some("ok".to_string()));
+    }
+
+    #[test]
+    fn decode_large_frame() {
+        let mut buf = Vec::new();
+        ::encode_length_delimiter(DEFAULT_MAX_FRAME_SIZE, &mut buf).unwrap();
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        decoder.push(&buf);
+        assert_eq!(decoder.decode().unwrap(), None);
+        assert!(decoder.buf.capacity() <= 2 * MAX_RESERVE);
+    }
+
+    /// A reader which returns one byte per call, with a `WouldBlock` error before each byte.
+    struct Trickle<'a> {
+        buf: &'a [u8],
+        ready: bool,
+    }
+
+    impl <'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.ready {
+                self.ready = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.ready = false;
+            if self.buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.buf[0];
+            self.buf = &self.buf[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn read_from() {
+        let buf = encode_all(&["a", "bc"]);
+        let mut decoder = LengthDelimitedDecoder::<String>::new();
+        let mut reader = &buf[..];
+        assert_eq!(decoder.read_from(&mut reader).unwrap(), Some("a".to_string()));
+        assert_eq!(decoder.read_from(&mut reader).unwrap(), Some("bc".to_string()));
+        assert_eq!(decoder.read_from(&mut reader).unwrap(), None);
+
+        let mut reader = Trickle { buf: &buf, ready: false };
+        let mut decoded = Vec::new();
+        loop {
+            match decoder.read_from(&mut reader) {
+                Ok(Some(message)) => decoded.push(message),
+                Ok(None) => break,
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(error) => panic!("unexpected error: {}", error),
+            }
+        }
+        assert_eq!(decoded, vec!["a".to_string(), "bc".to_string()]);
+
+        let mut reader = &buf[..buf.len() - 1];
+        assert_eq!(decoder.read_from(&mut reader).unwrap(), Some("a".to_string()));
+        assert_eq!(decoder.read_from(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -5,6 +5,9 @@
 //! the `prost-types` crate in order to avoid a cyclic dependency between `prost` and
 //! `prost-build`.
 
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use bytes::{Buf, BufMut};
 
 use DecodeError;
@@ -18,12 +21,15 @@ impl Message for bool {
             bool::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            bool::merge(wire_type, self, buf)
+            bool::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -41,12 +47,15 @@ impl Message for u32 {
             uint32::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            uint32::merge(wire_type, self, buf)
+            uint32::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -64,12 +73,15 @@ impl Message for u64 {
             uint64::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            uint64::merge(wire_type, self, buf)
+            uint64::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -87,12 +99,15 @@ impl Message for i32 {
             int32::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            int32::merge(wire_type, self, buf)
+            int32::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -110,12 +125,15 @@ impl Message for i64 {
             int64::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            int64::merge(wire_type, self, buf)
+            int64::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -133,12 +151,15 @@ impl Message for f32 {
             float::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            float::merge(wire_type, self, buf)
+            float::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -156,12 +177,15 @@ impl Message for f64 {
             double::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            double::merge(wire_type, self, buf)
+            double::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -179,12 +203,15 @@ impl Message for String {
             string::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            string::merge(wire_type, self, buf)
+            string::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -202,12 +229,15 @@ impl Message for Vec<u8> {
             bytes::encode(1, self, buf)
         }
     }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (tag, wire_type) = decode_key(buf)?;
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
         if tag == 1 {
-            bytes::merge(wire_type, self, buf)
+            bytes::merge(wire_type, self, buf, ctx)
         } else {
-            skip_field(wire_type, buf)
+            skip_field(wire_type, tag, buf, ctx)
         }
     }
     fn encoded_len(&self) -> usize {
@@ -221,9 +251,12 @@ impl Message for Vec<u8> {
 /// `google.protobuf.Empty`
 impl Message for () {
     fn encode_raw<B>(&self, _buf: &mut B) where B: BufMut { }
-    fn merge_field<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
-        let (_, wire_type) = decode_key(buf)?;
-        skip_field(wire_type, buf)
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut B,
+                      ctx: &DecodeContext) -> Result<(), DecodeError> where B: Buf {
+        skip_field(wire_type, tag, buf, ctx)
     }
     fn encoded_len(&self) -> usize { 0 }
     fn clear(&mut self) { }
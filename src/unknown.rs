@@ -0,0 +1,221 @@
+//! Storage for fields which are not recognized when decoding a message.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+use bytes::{
+    Buf,
+    BufMut,
+};
+
+use DecodeError;
+use DecodeErrorKind;
+use encoding::{
+    bytes,
+    check_end_group,
+    decode_key,
+    decode_varint,
+    DecodeContext,
+    encode_key,
+    encode_varint,
+    encoded_len_varint,
+    key_len,
+    WireType,
+};
+
+/// The set of fields encountered while decoding a message which do not correspond to any field
+/// known to the message type.
+///
+/// Messages which preserve unknown fields re-emit them, in the order they were decoded, after
+/// all known fields when encoded. This allows data written with a newer version of a schema to
+/// survive a round trip through code generated from an older version.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct UnknownFields {
+    fields: Vec<UnknownField>,
+}
+
+/// A single field which was not recognized while decoding a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownField {
+    tag: u32,
+    value: UnknownValue,
+}
+
+/// The raw value of an unknown field, as determined by its wire type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnknownValue {
+    /// A value with the varint wire type.
+    Varint(u64),
+    /// A value with the 64-bit wire type.
+    SixtyFourBit(u64),
+    /// A value with the length-delimited wire type.
+    LengthDelimited(Vec<u8>),
+    /// A group, containing the fields between its start and end group keys.
+    Group(UnknownFields),
+    /// A value with the 32-bit wire type.
+    ThirtyTwoBit(u32),
+}
+
+impl UnknownFields {
+
+    /// Creates an empty set of unknown fields.
+    pub fn new() -> UnknownFields {
+        UnknownFields::default()
+    }
+
+    /// Returns `true` if no unknown fields are present.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of unknown fields, counting each occurrence of a repeated tag.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns an iterator over the unknown fields in the order they were decoded.
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, UnknownField> {
+        self.fields.iter()
+    }
+
+    /// Appends an unknown field to the set.
+    pub fn push(&mut self, tag: u32, value: UnknownValue) {
+        self.fields.push(UnknownField { tag, value });
+    }
+
+    /// Removes all unknown fields from the set.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    /// Decodes the value of a field with an unrecognized tag from the buffer, and appends it to
+    /// the set.
+    #[doc(hidden)]
+    pub fn merge_field<B>(&mut self,
+                          tag: u32,
+                          wire_type: WireType,
+                          buf: &mut B,
+                          ctx: &DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        let value = match wire_type {
+            WireType::Varint => UnknownValue::Varint(decode_varint(buf)?),
+            WireType::SixtyFourBit => {
+                if buf.remaining() < 8 {
+                    return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
+                }
+                UnknownValue::SixtyFourBit(buf.get_u64_le())
+            },
+            WireType::LengthDelimited => {
+                let mut value = Vec::new();
+                bytes::merge(wire_type, &mut value, buf, ctx)?;
+                UnknownValue::LengthDelimited(value)
+            },
+            WireType::StartGroup => {
+                ctx.limit_reached()?;
+                let ctx = ctx.enter_recursion();
+                let mut group = UnknownFields::new();
+                loop {
+                    let (field_tag, field_wire_type) = decode_key(buf)?;
+                    if field_wire_type == WireType::EndGroup {
+                        check_end_group(tag, field_tag)?;
+                        break;
+                    }
+                    group.merge_field(field_tag, field_wire_type, buf, &ctx)?;
+                }
+                UnknownValue::Group(group)
+            },
+            WireType::EndGroup => {
+                return Err(DecodeError::with_kind(DecodeErrorKind::UnexpectedEndGroup, "unexpected end group tag"));
+            },
+            WireType::ThirtyTwoBit => {
+                if buf.remaining() < 4 {
+                    return Err(DecodeError::with_kind(DecodeErrorKind::Truncated, "buffer underflow"));
+                }
+                UnknownValue::ThirtyTwoBit(buf.get_u32_le())
+            },
+        };
+        self.push(tag, value);
+        Ok(())
+    }
+
+    /// Encodes the unknown fields to the buffer.
+    #[doc(hidden)]
+    pub fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        for field in &self.fields {
+            field.encode(buf);
+        }
+    }
+
+    /// Returns the encoded length of the unknown fields.
+    #[doc(hidden)]
+    pub fn encoded_len(&self) -> usize {
+        self.fields.iter().map(UnknownField::encoded_len).sum()
+    }
+}
+
+impl fmt::Debug for UnknownFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.fields.iter()).finish()
+    }
+}
+
+impl<'a> IntoIterator for &'a UnknownFields {
+    type Item = &'a UnknownField;
+    type IntoIter = slice::Iter<'a, UnknownField>;
+
+    fn into_iter(self) -> slice::Iter<'a, UnknownField> {
+        self.fields.iter()
+    }
+}
+
+impl UnknownField {
+
+    /// Returns the field's tag.
+    pub fn tag(&self) -> u32 {
+        self.tag
+    }
+
+    /// Returns the field's raw value.
+    pub fn value(&self) -> &UnknownValue {
+        &self.value
+    }
+
+    /// Returns the field's wire type.
+    pub fn wire_type(&self) -> WireType {
+        match self.value {
+            UnknownValue::Varint(..) => WireType::Varint,
+            UnknownValue::SixtyFourBit(..) => WireType::SixtyFourBit,
+            UnknownValue::LengthDelimited(..) => WireType::LengthDelimited,
+            UnknownValue::Group(..) => WireType::StartGroup,
+            UnknownValue::ThirtyTwoBit(..) => WireType::ThirtyTwoBit,
+        }
+    }
+
+    fn encode<B>(&self, buf: &mut B) where B: BufMut {
+        encode_key(self.tag, self.wire_type(), buf);
+        match self.value {
+            UnknownValue::Varint(value) => encode_varint(value, buf),
+            UnknownValue::SixtyFourBit(value) => buf.put_u64_le(value),
+            UnknownValue::LengthDelimited(ref value) => {
+                encode_varint(value.len() as u64, buf);
+                buf.put_slice(value);
+            },
+            UnknownValue::Group(ref fields) => {
+                fields.encode_raw(buf);
+                encode_key(self.tag, WireType::EndGroup, buf);
+            },
+            UnknownValue::ThirtyTwoBit(value) => buf.put_u32_le(value),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        key_len(self.tag) + match self.value {
+            UnknownValue::Varint(value) => encoded_len_varint(value),
+            UnknownValue::SixtyFourBit(..) => 8,
+            UnknownValue::LengthDelimited(ref value) => encoded_len_varint(value.len() as u64) + value.len(),
+            UnknownValue::Group(ref fields) => fields.encoded_len() + key_len(self.tag),
+            UnknownValue::ThirtyTwoBit(..) => 4,
+        }
+    }
+}
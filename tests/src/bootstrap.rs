@@ -17,6 +17,18 @@ fn bootstrap() {
     let mut config = prost_build::Config::new();
     config.compile_well_known_types();
     config.btree_map(&["."]);
+    // Custom options are preserved as unknown fields of the options messages.
+    config.preserve_unknown_fields(&[
+        ".google.protobuf.FileOptions",
+        ".google.protobuf.MessageOptions",
+        ".google.protobuf.FieldOptions",
+        ".google.protobuf.OneofOptions",
+        ".google.protobuf.ExtensionRangeOptions",
+        ".google.protobuf.EnumOptions",
+        ".google.protobuf.EnumValueOptions",
+        ".google.protobuf.ServiceOptions",
+        ".google.protobuf.MethodOptions",
+    ]);
     config.out_dir(tempdir.path());
     config.compile_protos(&[
                             // Protobuf Plugins.
@@ -25,7 +25,10 @@ fn main() {
     prost_build.field_attribute("Foo.Custom.Attrs.Msg.field.a", "/// Oneof A docs");
     prost_build.field_attribute("Foo.Custom.Attrs.Msg.field.b", "/// Oneof B docs");
 
-    prost_build.compile_protos(&["src/packages/widget_factory.proto"],
+    prost_build.compile_protos(&["src/packages/widget_factory.proto",
+                                 "src/packages/widget.proto",
+                                 "src/packages/gizmo.proto",
+                                 "src/packages/root.proto"],
                                &["src/packages"]).unwrap();
 
     prost_build.compile_protos(&["src/ident_conversion.proto"],
@@ -48,4 +51,13 @@ fn main() {
 
     prost_build.compile_protos(&["src/default_enum_value.proto"],
                                &["src"]).unwrap();
+
+    prost_build.reflect(&[".reflect"]);
+    prost_build.compile_protos(&["src/reflect.proto"],
+                               &["src"]).unwrap();
+
+    prost_build.json(&[".json_mapping"]);
+    prost_build.text_format(&[".json_mapping"]);
+    prost_build.compile_protos(&["src/json_mapping.proto"],
+                               &["src"]).unwrap();
 }
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+
+use prost::Message;
+use prost_types::descriptor::DescriptorPool;
+use prost_types::dynamic::{DynamicMessage, MapKey, SetFieldError, Value};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto,
+    EnumDescriptorProto,
+    EnumValueDescriptorProto,
+    FieldDescriptorProto,
+    FileDescriptorProto,
+    MessageOptions,
+    OneofDescriptorProto,
+};
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Inner {
+    #[prost(string, tag="1")]
+    pub name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum Color {
+    Red = 0,
+    Green = 1,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Outer {
+    #[prost(int32, tag="1")]
+    pub int32: i32,
+    #[prost(sint64, tag="2")]
+    pub sint64: i64,
+    #[prost(fixed32, repeated, tag="3")]
+    pub fixed32s: Vec<u32>,
+    #[prost(string, repeated, tag="4")]
+    pub strings: Vec<String>,
+    #[prost(enumeration="Color", tag="5")]
+    pub color: i32,
+    #[prost(message, optional, tag="6")]
+    pub inner: Option<Inner>,
+    #[prost(message, repeated, tag="7")]
+    pub inners: Vec<Inner>,
+    #[prost(map="string, int32", tag="8")]
+    pub counts: HashMap<String, i32>,
+    #[prost(oneof="Choice", tags="9, 10")]
+    pub choice: Option<Choice>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum Choice {
+    #[prost(bytes, tag="9")]
+    Data(Vec<u8>),
+    #[prost(double, tag="10")]
+    Number(f64),
+}
+
+fn field(name: &str, number: i32, label: Label, ty: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+    FieldDescriptorProto {
+        name: Some(name.to_owned()),
+        number: Some(number),
+        label: Some(label as i32),
+        type_: Some(ty as i32),
+        type_name: type_name.map(ToOwned::to_owned),
+        ..Default::default()
+    }
+}
+
+fn pool() -> DescriptorPool {
+    let mut choice_data = field("data", 9, Label::Optional, Type::Bytes, None);
+    choice_data.oneof_index = Some(0);
+    let mut choice_number = field("number", 10, Label::Optional, Type::Double, None);
+    choice_number.oneof_index = Some(0);
+
+    let counts_entry = DescriptorProto {
+        name: Some("CountsEntry".to_owned()),
+        field: vec![
+            field("key", 1, Label::Optional, Type::String, None),
+            field("value", 2, Label::Optional, Type::Int32, None),
+        ],
+        options: Some(MessageOptions { map_entry: Some(true), ..Default::default() }),
+        ..Default::default()
+    };
+
+    let file = FileDescriptorProto {
+        name: Some("dynamic.proto".to_owned()),
+        package: Some("dynamic".to_owned()),
+        syntax: Some("proto3".to_owned()),
+        message_type: vec![
+            DescriptorProto {
+                name: Some("Inner".to_owned()),
+                field: vec![field("name", 1, Label::Optional, Type::String, None)],
+                ..Default::default()
+            },
+            DescriptorProto {
+                name: Some("Outer".to_owned()),
+                field: vec![
+                    field("int32", 1, Label::Optional, Type::Int32, None),
+                    field("sint64", 2, Label::Optional, Type::Sint64, None),
+                    field("fixed32s", 3, Label::Repeated, Type::Fixed32, None),
+                    field("strings", 4, Label::Repeated, Type::String, None),
+                    field("color", 5, Label::Optional, Type::Enum, Some(".dynamic.Color")),
+                    field("inner", 6, Label::Optional, Type::Message, Some("Inner")),
+                    field("inners", 7, Label::Repeated, Type::Message, Some(".dynamic.Inner")),
+                    field("counts", 8, Label::Repeated, Type::Message, Some("Outer.CountsEntry")),
+                    choice_data,
+                    choice_number,
+                ],
+                nested_type: vec![counts_entry],
+                oneof_decl: vec![OneofDescriptorProto {
+                    name: Some("choice".to_owned()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ],
+        enum_type: vec![EnumDescriptorProto {
+            name: Some("Color".to_owned()),
+            value: vec![
+                EnumValueDescriptorProto { name: Some("RED".to_owned()), number: Some(0), options: None },
+                EnumValueDescriptorProto { name: Some("GREEN".to_owned()), number: Some(1), options: None },
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    DescriptorPool::from_files(vec![file]).unwrap()
+}
+
+#[test]
+fn dynamic_message_roundtrip() {
+    let pool = pool();
+    let descriptor = pool.get_message_by_name("dynamic.Outer").unwrap();
+    assert!(descriptor.get_field_by_name("counts").unwrap().is_map());
+    assert!(descriptor.get_field_by_name("fixed32s").unwrap().is_packed());
+
+    let mut counts = HashMap::new();
+    counts.insert("a".to_owned(), 1);
+    counts.insert("b".to_owned(), 2);
+    let outer = Outer {
+        int32: -5,
+        sint64: -6,
+        fixed32s: vec![1, 2, 3],
+        strings: vec!["x".to_owned(), "".to_owned()],
+        color: Color::Green as i32,
+        inner: Some(Inner { name: "inner".to_owned() }),
+        inners: vec![Inner::default(), Inner { name: "second".to_owned() }],
+        counts,
+        choice: Some(Choice::Number(1.5)),
+    };
+    let mut buf = Vec::new();
+    outer.encode(&mut buf).unwrap();
+
+    let dynamic = DynamicMessage::decode(descriptor.clone(), &buf).unwrap();
+    assert_eq!(dynamic.get_field_by_name("int32").unwrap().as_i32(), Some(-5));
+    assert_eq!(dynamic.get_field_by_number(2).unwrap().as_i64(), Some(-6));
+    assert_eq!(dynamic.get_field_by_name("fixed32s").unwrap().as_list().unwrap(),
+               &[Value::U32(1), Value::U32(2), Value::U32(3)][..]);
+    assert_eq!(dynamic.get_field_by_name("color").unwrap().as_enum_number(), Some(1));
+    assert_eq!(dynamic.get_field_by_name("inner").unwrap()
+                      .as_message().unwrap()
+                      .get_field_by_name("name").unwrap()
+                      .as_str(),
+               Some("inner"));
+    assert_eq!(dynamic.get_field_by_name("inners").unwrap().as_list().unwrap().len(), 2);
+    assert_eq!(dynamic.get_field_by_name("counts").unwrap().as_map().unwrap()
+                      .get(&MapKey::String("b".to_owned())),
+               Some(&Value::I32(2)));
+    assert_eq!(dynamic.get_field_by_name("number").unwrap().as_f64(), Some(1.5));
+    assert!(!dynamic.has_field_by_name("data"));
+    assert!(dynamic.unknown_fields().is_empty());
+
+    // Map entries are encoded in key order, so compare by decoding rather than by bytes.
+    assert_eq!(dynamic.encoded_len(), buf.len());
+    let mut dynamic_buf = Vec::new();
+    dynamic.encode(&mut dynamic_buf).unwrap();
+    assert_eq!(Outer::decode(&dynamic_buf).unwrap(), outer);
+}
+
+#[test]
+fn dynamic_message_deterministic() {
+    let pool = pool();
+    let descriptor = pool.get_message_by_name("dynamic.Outer").unwrap();
+
+    let mut counts = HashMap::new();
+    counts.insert("b".to_owned(), 2);
+    counts.insert("".to_owned(), 5);
+    counts.insert("a".to_owned(), 0);
+    let outer = Outer {
+        int32: 1,
+        inner: Some(Inner { name: "x".to_owned() }),
+        counts,
+        ..Outer::default()
+    };
+    let mut buf = Vec::new();
+    outer.encode(&mut buf).unwrap();
+    let dynamic = DynamicMessage::decode(descriptor, &buf).unwrap();
+
+    // The deterministic serialization of the C++ and Java implementations.
+    let expected: &[u8] = &[
+        0x08, 0x01,
+        0x32, 0x03, 0x0a, 0x01, b'x',
+        0x42, 0x04, 0x0a, 0x00, 0x10, 0x05,
+        0x42, 0x05, 0x0a, 0x01, b'a', 0x10, 0x00,
+        0x42, 0x05, 0x0a, 0x01, b'b', 0x10, 0x02,
+    ];
+    let mut dynamic_buf = Vec::new();
+    dynamic.encode_deterministic(&mut dynamic_buf).unwrap();
+    assert_eq!(expected, &dynamic_buf[..]);
+
+    let mut outer_buf = Vec::new();
+    outer.encode_deterministic(&mut outer_buf).unwrap();
+    assert_eq!(outer_buf, dynamic_buf);
+}
+
+#[test]
+fn dynamic_message_set_fields() {
+    let pool = pool();
+    let descriptor = pool.get_message_by_name("dynamic.Outer").unwrap();
+    let inner_descriptor = pool.get_message_by_name("dynamic.Inner").unwrap();
+
+    let mut dynamic = DynamicMessage::new(descriptor);
+    dynamic.set_field_by_name("int32", Value::I32(42)).unwrap();
+    dynamic.set_field_by_number(3, Value::List(vec![Value::U32(7)])).unwrap();
+    let mut inner = DynamicMessage::new(inner_descriptor);
+    inner.set_field_by_name("name", Value::String("set".to_owned())).unwrap();
+    dynamic.set_field_by_name("inner", Value::Message(inner)).unwrap();
+    let mut counts = BTreeMap::new();
+    counts.insert(MapKey::String("k".to_owned()), Value::I32(9));
+    dynamic.set_field_by_name("counts", Value::Map(counts)).unwrap();
+    dynamic.set_field_by_name("data", Value::Bytes(b"data".to_vec())).unwrap();
+
+    assert_eq!(dynamic.set_field_by_name("missing", Value::I32(0)), Err(SetFieldError::NotFound));
+    match dynamic.set_field_by_name("int32", Value::U64(0)) {
+        Err(SetFieldError::InvalidType { .. }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // Proto3 fields without presence which are set to their default value are not encoded.
+    dynamic.set_field_by_name("sint64", Value::I64(0)).unwrap();
+
+    let mut buf = Vec::new();
+    dynamic.encode(&mut buf).unwrap();
+    let mut counts = HashMap::new();
+    counts.insert("k".to_owned(), 9);
+    assert_eq!(Outer::decode(&buf).unwrap(), Outer {
+        int32: 42,
+        fixed32s: vec![7],
+        inner: Some(Inner { name: "set".to_owned() }),
+        counts,
+        choice: Some(Choice::Data(b"data".to_vec())),
+        ..Default::default()
+    });
+    assert_eq!(dynamic.encoded_len(), buf.len());
+
+    // Setting a oneof member clears the other members.
+    dynamic.set_field_by_name("number", Value::F64(2.0)).unwrap();
+    assert!(!dynamic.has_field_by_name("data"));
+
+    *dynamic.get_field_by_name_mut("int32").unwrap() = Value::I32(1);
+    assert_eq!(dynamic.clear_field_by_name("int32"), Some(Value::I32(1)));
+    assert_eq!(dynamic.get_field_by_name("int32").unwrap().as_i32(), Some(0));
+
+    dynamic.clear();
+    assert_eq!(dynamic.encoded_len(), 0);
+}
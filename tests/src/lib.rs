@@ -13,6 +13,7 @@ pub mod unittest;
 
 #[cfg(test)] mod bootstrap;
 #[cfg(test)] mod debug;
+#[cfg(test)] mod dynamic;
 #[cfg(test)] mod message_encoding;
 #[cfg(test)] mod no_unused_results;
 
@@ -57,6 +58,14 @@ pub mod default_enum_value {
     include!(concat!(env!("OUT_DIR"), "/default_enum_value.rs"));
 }
 
+pub mod reflect {
+    include!(concat!(env!("OUT_DIR"), "/reflect.rs"));
+}
+
+pub mod json_mapping {
+    include!(concat!(env!("OUT_DIR"), "/json_mapping.rs"));
+}
+
 use std::error::Error;
 
 use bytes::{Buf, IntoBuf};
@@ -274,6 +283,60 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_recursion_limit() {
+        use prost::{DecodeOptions, encode_length_delimiter};
+        use prost::encoding::{encode_key, WireType};
+        use recursive_oneof::{a, A, C};
+
+        /// Encodes an `A` with `depth` levels of nested messages, from the inside out.
+        fn nested(depth: usize) -> Vec<u8> {
+            let mut buf = Vec::new();
+            A { kind: Some(a::Kind::C(C {})) }.encode(&mut buf).unwrap();
+            for _ in 1..depth {
+                let mut outer = Vec::new();
+                encode_key(1, WireType::LengthDelimited, &mut outer);
+                encode_length_delimiter(buf.len(), &mut outer).unwrap();
+                outer.extend_from_slice(&buf);
+                buf = outer;
+            }
+            buf
+        }
+
+        // The default limit allows 100 levels of nesting.
+        A::decode(&nested(100)).unwrap();
+        let error = A::decode(&nested(101)).unwrap_err();
+        assert!(error.to_string().contains("recursion limit of 100 reached"), "{}", error);
+
+        // The limit can be raised or lowered per call.
+        let mut options = DecodeOptions::new();
+        options.recursion_limit(200);
+        A::decode_with_options(&nested(101), &options).unwrap();
+
+        options.recursion_limit(10);
+        A::decode_with_options(&nested(10), &options).unwrap();
+        let error = A::decode_with_options(&nested(11), &options).unwrap_err();
+        assert!(error.to_string().contains("recursion limit of 10 reached"), "{}", error);
+
+        // Length-delimited decoding takes the same options. The delimited message counts as a
+        // level of nesting.
+        let delimited = |depth| {
+            let msg = nested(depth);
+            let mut buf = Vec::new();
+            encode_length_delimiter(msg.len(), &mut buf).unwrap();
+            buf.extend_from_slice(&msg);
+            buf
+        };
+        A::decode_length_delimited(&delimited(99)).unwrap();
+        assert!(A::decode_length_delimited(&delimited(100)).is_err());
+        A::decode_length_delimited_with_options(&delimited(9), &options).unwrap();
+        let error = A::decode_length_delimited_with_options(&delimited(10), &options).unwrap_err();
+        assert!(error.to_string().contains("recursion limit of 10 reached"), "{}", error);
+        options.recursion_limit(200);
+        let mut msg = A::default();
+        msg.merge_length_delimited_with_options(&delimited(100), &options).unwrap();
+    }
+
     #[test]
     fn test_default_enum() {
         let msg = default_enum_value::Test::default();
@@ -281,4 +344,164 @@ mod tests {
         assert_eq!(msg.privacy_level_3(), default_enum_value::PrivacyLevel::PrivacyLevelThree);
         assert_eq!(msg.privacy_level_4(), default_enum_value::PrivacyLevel::PrivacyLevelprivacyLevelFour);
     }
+
+    #[test]
+    fn test_reflect() {
+        use prost_types::dynamic::{MapKey, Value};
+        use prost_types::reflect::ReflectMessage;
+        use reflect::{event, Event};
+
+        let mut labels = BTreeMap::new();
+        labels.insert("k".to_string(), "v".to_string());
+        let msg = Event {
+            name: "event".to_string(),
+            counts: vec![1, 2],
+            time: Some(prost_types::Timestamp { seconds: 10, nanos: 0 }),
+            detail: Some(event::Detail { labels }),
+        };
+
+        assert_eq!(msg.full_name(), "reflect.Event");
+        assert_eq!(msg.descriptor_proto().name(), "Event");
+        assert_eq!(event::Detail::default().descriptor().full_name(), "reflect.Event.Detail");
+
+        assert_eq!(msg.get_field_by_name("name"), Some(Value::String("event".to_string())));
+        assert_eq!(msg.get_field_by_number(2), Some(Value::List(vec![Value::I32(1), Value::I32(2)])));
+        assert_eq!(msg.get_field_by_name("missing"), None);
+
+        let view = msg.reflect();
+        let time = view.get_field_by_name("time").unwrap();
+        assert_eq!(time.as_message().unwrap().descriptor().full_name(), "google.protobuf.Timestamp");
+        assert_eq!(time.as_message().unwrap().get_field_by_name("seconds").unwrap().as_i64(), Some(10));
+        let detail = view.get_field_by_name("detail").unwrap();
+        let labels = detail.as_message().unwrap().get_field_by_name("labels").unwrap();
+        assert_eq!(labels.as_map().unwrap().get(&MapKey::String("k".to_string())),
+                   Some(&Value::String("v".to_string())));
+    }
+
+    #[test]
+    fn test_json() {
+        use prost_types::json::{JsonMessage, JsonOptions};
+        use json_mapping::{record, Record};
+
+        let mut names = BTreeMap::new();
+        names.insert(1, "one".to_string());
+        let msg = Record {
+            record_id: 7,
+            big_number: -9_007_199_254_740_993,
+            payload: vec![1, 2],
+            kind: record::Kind::Alpha as i32,
+            values: vec![1.5, ::std::f64::NEG_INFINITY],
+            names,
+            created_at: Some(prost_types::Timestamp { seconds: 63_108_020, nanos: 21_000_000 }),
+            ttl: Some(prost_types::Duration { seconds: -1, nanos: -500_000_000 }),
+            mask: Some(prost_types::FieldMask { paths: vec!["foo_bar".to_string(), "baz".to_string()] }),
+            metadata: None,
+            limit: Some(5),
+            detail: Some(prost_types::Any {
+                type_url: "type.googleapis.com/google.protobuf.Duration".to_string(),
+                value: vec![8, 1],
+            }),
+        };
+
+        let json = msg.to_json().unwrap();
+        assert_eq!(json, concat!(r#"{"recordId":7,"bigNumber":"-9007199254740993","payload":"AQI=","#,
+                                 r#""kind":"KIND_ALPHA","values":[1.5,"-Infinity"],"names":{"1":"one"},"#,
+                                 r#""createdAt":"1972-01-01T10:00:20.021Z","ttl":"-1.500s","#,
+                                 r#""mask":"fooBar,baz","limit":"5","#,
+                                 r#""detail":{"@type":"type.googleapis.com/google.protobuf.Duration","value":"1s"}}"#));
+        assert_eq!(Record::from_json(&json).unwrap(), msg);
+
+        // Original field names, numbers as strings and enum numbers are accepted when parsing.
+        let msg = Record::from_json(r#"{"record_id": "7", "kind": 1, "metadata": {"a": [null, true]}}"#).unwrap();
+        assert_eq!(msg.record_id, 7);
+        assert_eq!(msg.kind, record::Kind::Alpha as i32);
+        assert_eq!(msg.to_json().unwrap(), r#"{"recordId":7,"kind":"KIND_ALPHA","metadata":{"a":[null,true]}}"#);
+
+        assert!(Record::from_json(r#"{"unknown": 1}"#).is_err());
+        assert!(Record::from_json(r#"{"createdAt": "1972-02-30T00:00:00Z"}"#).is_err());
+        let mut options = JsonOptions::new();
+        options.ignore_unknown_fields(true);
+        assert_eq!(Record::from_json_with_options(r#"{"unknown": 1}"#, &options).unwrap(), Record::default());
+    }
+
+    #[test]
+    fn test_text_format() {
+        use prost_types::text_format::TextFormatMessage;
+        use json_mapping::{record, Record};
+
+        let mut names = BTreeMap::new();
+        names.insert(1, "one".to_string());
+        let msg = Record {
+            record_id: 7,
+            payload: vec![1, b'"'],
+            kind: record::Kind::Alpha as i32,
+            values: vec![1.5, ::std::f64::NEG_INFINITY],
+            names,
+            created_at: Some(prost_types::Timestamp { seconds: 10, nanos: 0 }),
+            detail: Some(prost_types::Any {
+                type_url: "type.googleapis.com/google.protobuf.Duration".to_string(),
+                value: vec![8, 1],
+            }),
+            ..Record::default()
+        };
+
+        let text = msg.to_text_format();
+        assert_eq!(text, concat!("record_id: 7\n",
+                                 "payload: \"\\001\\\"\"\n",
+                                 "kind: KIND_ALPHA\n",
+                                 "values: 1.5\n",
+                                 "values: -inf\n",
+                                 "names {\n",
+                                 "  key: 1\n",
+                                 "  value: \"one\"\n",
+                                 "}\n",
+                                 "created_at {\n",
+                                 "  seconds: 10\n",
+                                 "}\n",
+                                 "detail {\n",
+                                 "  [type.googleapis.com/google.protobuf.Duration] {\n",
+                                 "    seconds: 1\n",
+                                 "  }\n",
+                                 "}\n"));
+        assert_eq!(Record::from_text_format(&text).unwrap(), msg);
+
+        // Lists, angle brackets, separators, comments and numeric enum values are also accepted.
+        let parsed = Record::from_text_format(r#"
+            # A record.
+            record_id: 0x7; kind: 1, values: [1.5, -inf]
+            created_at < seconds: 10 >
+        "#).unwrap();
+        assert_eq!(parsed.record_id, 7);
+        assert_eq!(parsed.kind, record::Kind::Alpha as i32);
+        assert_eq!(parsed.values, vec![1.5, ::std::f64::NEG_INFINITY]);
+        assert_eq!(parsed.created_at, msg.created_at);
+
+        assert!(Record::from_text_format("record_id: \"7\"").is_err());
+        assert!(Record::from_text_format("record_id: 1 record_id: 2").is_err());
+        assert!(Record::from_text_format("unknown: 1").is_err());
+    }
+
+    #[test]
+    fn test_reflect_deeply_nested() {
+        use prost_types::json::JsonMessage;
+        use prost_types::reflect::ReflectMessage;
+        use prost_types::text_format::TextFormatMessage;
+        use prost_types::{value, ListValue, Struct, Value};
+        use json_mapping::Record;
+
+        // Each list is two levels of message nesting, so the view exceeds the default recursion
+        // limit of 100, although the message itself is valid.
+        let mut value = Value { kind: Some(value::Kind::BoolValue(true)) };
+        for _ in 0..60 {
+            value = Value { kind: Some(value::Kind::ListValue(ListValue { values: vec![value] })) };
+        }
+        let mut fields = BTreeMap::new();
+        fields.insert("a".to_string(), value);
+        let msg = Record { metadata: Some(Struct { fields }), ..Record::default() };
+
+        assert!(msg.try_reflect().is_ok());
+        let json = msg.to_json().unwrap();
+        assert_eq!(json, format!(r#"{{"metadata":{{"a":{}true{}}}}}"#, "[".repeat(60), "]".repeat(60)));
+        assert!(msg.to_text_format().contains("bool_value: true"));
+    }
 }
@@ -1,4 +1,4 @@
-use prost::Message;
+use prost::{DecodeErrorKind, DecodeOptions, Message};
 
 use check_message;
 use check_serialize_equivalent;
@@ -340,3 +340,865 @@ pub enum BasicOneof {
     #[prost(string, tag="9")]
     String(String),
 }
+
+/// A version of `Basic` which only knows about a subset of its fields, and preserves the rest as
+/// unknown fields.
+#[derive(Clone, PartialEq, Message)]
+pub struct BasicUnknownFields {
+    #[prost(int32, tag="1")]
+    pub int32: i32,
+
+    #[prost(string, tag="3")]
+    pub string: String,
+
+    #[prost(unknown_fields)]
+    pub unknown_fields: ::prost::UnknownFields,
+}
+
+#[test]
+fn check_unknown_fields() {
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    basic.bools = vec![true, false];
+    basic.string = "foo".to_string();
+    basic.enumeration = BasicEnumeration::TWO as i32;
+    basic.string_map.insert("bar".to_string(), "baz".to_string());
+    basic.oneof = Some(BasicOneof::Int(7));
+
+    let mut buf = Vec::new();
+    basic.encode(&mut buf).unwrap();
+
+    let unknown = BasicUnknownFields::decode(&buf).unwrap();
+    assert_eq!(unknown.int32, 42);
+    assert_eq!(&unknown.string, "foo");
+    assert_eq!(unknown.unknown_fields.iter().map(|field| field.tag()).collect::<Vec<_>>(),
+               vec![2, 2, 5, 7, 8]);
+    assert_eq!(unknown.unknown_fields.iter().next().unwrap().value(),
+               &::prost::UnknownValue::Varint(1));
+    check_message(&unknown);
+
+    let mut roundtrip = Vec::new();
+    unknown.encode(&mut roundtrip).unwrap();
+    assert_eq!(buf.len(), roundtrip.len());
+    assert_eq!(basic, Basic::decode(&roundtrip).unwrap());
+
+    let mut cleared = unknown.clone();
+    cleared.clear();
+    assert!(cleared.unknown_fields.is_empty());
+    assert_eq!(0, cleared.encoded_len());
+}
+
+/// A message with `Bytes` backed `bytes` and `string` fields.
+#[derive(Clone, PartialEq, Message)]
+pub struct SharedBytes {
+    #[prost(bytes="bytes", tag="1")]
+    pub blob: ::prost::bytes::Bytes,
+
+    #[prost(string="bytes", tag="2")]
+    pub name: ::prost::ByteString,
+
+    #[prost(bytes="bytes", repeated, tag="3")]
+    pub blobs: Vec<::prost::bytes::Bytes>,
+
+    #[prost(string="bytes", optional, tag="4", default="foo")]
+    pub label: Option<::prost::ByteString>,
+
+    #[prost(bytes="vec", tag="5")]
+    pub vec: Vec<u8>,
+}
+
+#[test]
+fn check_shared_bytes() {
+    use bytes::Bytes;
+
+    let msg = SharedBytes {
+        blob: Bytes::from(vec![0xAB; 4096]),
+        name: "blob".into(),
+        blobs: vec![Bytes::from(&b"a"[..]), Bytes::new()],
+        label: None,
+        vec: b"vec".to_vec(),
+    };
+    check_message(&msg);
+    assert_eq!(msg.label(), "foo");
+
+    let mut buf = Vec::new();
+    msg.encode(&mut buf).unwrap();
+    let buf = Bytes::from(buf);
+
+    let decoded = SharedBytes::decode_bytes(buf.clone()).unwrap();
+    assert_eq!(msg, decoded);
+
+    // The decoded field points into the encoded buffer instead of a copy of it. Short values,
+    // such as `name`, are stored inline by `Bytes` and are always copied.
+    let range = buf.as_ptr() as usize..buf.as_ptr() as usize + buf.len();
+    assert!(range.contains(&(decoded.blob.as_ptr() as usize)));
+
+    let copied = SharedBytes::decode(&buf).unwrap();
+    assert_eq!(msg, copied);
+    assert!(!range.contains(&(copied.blob.as_ptr() as usize)));
+}
+
+/// A version of `Basic` which borrows its string fields from the decoded buffer.
+#[derive(Clone, PartialEq, Message)]
+pub struct BorrowedBasic<'a> {
+    #[prost(int32, tag="1")]
+    pub int32: i32,
+
+    #[prost(string="str", tag="3")]
+    pub string: &'a str,
+
+    #[prost(string="str", optional, tag="4", default="foo")]
+    pub optional_string: Option<&'a str>,
+
+    #[prost(hash_map="string, string", tag="7")]
+    pub string_map: ::std::collections::HashMap<String, String>,
+
+    #[prost(oneof="BorrowedBasicOneof", tags="8, 9")]
+    pub oneof: Option<BorrowedBasicOneof<'a>>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum BorrowedBasicOneof<'a> {
+    #[prost(int32, tag="8")]
+    Int(i32),
+    #[prost(string="str", tag="9")]
+    String(&'a str),
+}
+
+/// A version of `Compound` which borrows from the decoded buffer.
+#[derive(Clone, PartialEq, Message)]
+pub struct BorrowedCompound<'a> {
+    #[prost(message, optional, tag="1")]
+    pub optional_message: Option<BorrowedBasic<'a>>,
+
+    #[prost(message, required, tag="2")]
+    pub required_message: BorrowedBasic<'a>,
+
+    #[prost(message, repeated, tag="3")]
+    pub repeated_message: Vec<BorrowedBasic<'a>>,
+
+    #[prost(message, repeated, tag="4")]
+    pub owned_message: Vec<Basic>,
+
+    #[prost(bytes="slice", repeated, tag="6")]
+    pub blobs: Vec<&'a [u8]>,
+}
+
+#[test]
+fn check_borrowed_message() {
+    use prost::BorrowedMessage;
+
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    basic.string = "foo".to_string();
+    basic.string_map.insert("bar".to_string(), "baz".to_string());
+    basic.oneof = Some(BasicOneof::String("oneof".to_string()));
+
+    let compound = Compound {
+        optional_message: Some(basic.clone()),
+        required_message: Basic::default(),
+        repeated_message: vec![basic.clone(), Basic::default()],
+        message_map: ::std::collections::HashMap::new(),
+        message_btree_map: ::std::collections::BTreeMap::new(),
+    };
+
+    let mut buf = Vec::new();
+    compound.encode(&mut buf).unwrap();
+
+    let borrowed = BorrowedCompound::decode(&buf).unwrap();
+    let optional = borrowed.optional_message.as_ref().unwrap();
+    assert_eq!(optional.int32, 42);
+    assert_eq!(optional.string, "foo");
+    assert_eq!(optional.optional_string(), "foo");
+    assert_eq!(optional.oneof, Some(BorrowedBasicOneof::String("oneof")));
+    assert_eq!(borrowed.repeated_message.len(), 2);
+
+    // The decoded strings point into the encoded buffer.
+    let range = buf.as_ptr() as usize..buf.as_ptr() as usize + buf.len();
+    assert!(range.contains(&(optional.string.as_ptr() as usize)));
+
+    let mut roundtrip = Vec::new();
+    BorrowedMessage::encode(&borrowed, &mut roundtrip).unwrap();
+    assert_eq!(buf, roundtrip);
+    assert_eq!(compound, Compound::decode(&roundtrip).unwrap());
+
+    let mut cleared = borrowed.clone();
+    BorrowedMessage::clear(&mut cleared);
+    assert_eq!(BorrowedCompound::default(), cleared);
+
+    let owned = BorrowedCompound {
+        owned_message: vec![basic.clone()],
+        blobs: vec![b"blob", b""],
+        .. BorrowedCompound::default()
+    };
+    let mut buf = Vec::new();
+    owned.encode(&mut buf).unwrap();
+    assert_eq!(owned, BorrowedCompound::decode(&buf).unwrap());
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GroupContents {
+    #[prost(int32, tag="2")]
+    pub a: i32,
+    #[prost(string, repeated, tag="3")]
+    pub b: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Groups {
+    #[prost(group, optional, tag="1")]
+    pub optional_group: Option<GroupContents>,
+    #[prost(group, repeated, tag="4")]
+    pub repeated_group: Vec<GroupContents>,
+    #[prost(int32, tag="5")]
+    pub after: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SkippedGroups {
+    #[prost(int32, tag="5")]
+    pub after: i32,
+}
+
+#[test]
+fn check_groups() {
+    let contents = GroupContents {
+        a: 42,
+        b: vec!["foo".to_string()],
+    };
+    let groups = Groups {
+        optional_group: Some(contents.clone()),
+        repeated_group: vec![contents.clone(), GroupContents::default()],
+        after: 7,
+    };
+    check_message(&groups);
+
+    // Groups are delimited by start and end group keys instead of a length prefix.
+    let mut buf = Vec::new();
+    Groups { optional_group: Some(GroupContents { a: 1, b: Vec::new() }), ..Groups::default() }
+        .encode(&mut buf)
+        .unwrap();
+    assert_eq!(buf, &[0x0B, 0x10, 0x01, 0x0C]);
+
+    // Groups with unknown tags are skipped.
+    let mut buf = Vec::new();
+    groups.encode(&mut buf).unwrap();
+    assert_eq!(SkippedGroups { after: 7 }, SkippedGroups::decode(&buf).unwrap());
+
+    // A group terminated by the wrong end group key fails to decode.
+    Groups::decode(&[0x0B, 0x10, 0x01, 0x24][..]).unwrap_err();
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Extendee {
+    #[prost(int32, tag="1")]
+    pub a: i32,
+    #[prost(extension_set)]
+    pub extension_set: prost::ExtensionSet,
+    #[prost(unknown_fields)]
+    pub unknown_fields: prost::UnknownFields,
+}
+
+pub static INT_EXTENSION: prost::Extension<Extendee, prost::extension::Int32> =
+    prost::Extension::new(100, "tests.int_extension");
+pub static STRINGS_EXTENSION: prost::Extension<Extendee,
+                                               prost::extension::Repeated<prost::extension::String>> =
+    prost::Extension::new(101, "tests.strings_extension");
+pub static MESSAGE_EXTENSION: prost::Extension<Extendee, prost::extension::Message<Basic>> =
+    prost::Extension::new(102, "tests.message_extension");
+pub static PACKED_EXTENSION: prost::Extension<Extendee,
+                                              prost::extension::Packed<prost::extension::Sint64>> =
+    prost::Extension::new(103, "tests.packed_extension");
+
+#[test]
+fn check_extensions() {
+    use prost::{DecodeOptions, Extendable, ExtensionRegistry};
+
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+
+    let mut extendee = Extendee::default();
+    extendee.a = 1;
+    extendee.set_extension(&INT_EXTENSION, 7);
+    extendee.set_extension(&STRINGS_EXTENSION, vec!["foo".to_string(), "bar".to_string()]);
+    extendee.set_extension(&MESSAGE_EXTENSION, basic.clone());
+    extendee.set_extension(&PACKED_EXTENSION, vec![-1, 0, 1]);
+    assert_eq!(Some(&7), extendee.get_extension(&INT_EXTENSION));
+
+    let mut buf = Vec::new();
+    extendee.encode(&mut buf).unwrap();
+    assert_eq!(buf.len(), extendee.encoded_len());
+
+    // Without a registry, extension fields are unknown fields.
+    let decoded = Extendee::decode(&buf).unwrap();
+    assert!(!decoded.has_extension(&INT_EXTENSION));
+    assert_eq!(5, decoded.unknown_fields.len());
+
+    let mut registry = ExtensionRegistry::new();
+    registry.register(&INT_EXTENSION)
+            .register(&STRINGS_EXTENSION)
+            .register(&MESSAGE_EXTENSION)
+            .register(&PACKED_EXTENSION);
+    let mut options = DecodeOptions::new();
+    options.extension_registry(registry);
+
+    let decoded = Extendee::decode_with_options(&buf, &options).unwrap();
+    assert_eq!(extendee, decoded);
+    assert!(decoded.unknown_fields.is_empty());
+    assert_eq!(Some(&basic), decoded.get_extension(&MESSAGE_EXTENSION));
+
+    let mut cleared = decoded.clone();
+    assert_eq!(Some(7), cleared.clear_extension(&INT_EXTENSION));
+    assert!(!cleared.has_extension(&INT_EXTENSION));
+    cleared.get_extension_mut(&STRINGS_EXTENSION).unwrap().push("baz".to_string());
+    assert_eq!(3, cleared.get_extension(&STRINGS_EXTENSION).unwrap().len());
+    cleared.clear();
+    assert_eq!(Extendee::default(), cleared);
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Tree {
+    #[prost(string, tag="1")]
+    pub label: String,
+    #[prost(message, optional, boxed, tag="2")]
+    pub left: Option<Box<Tree>>,
+    #[prost(message, repeated, tag="3")]
+    pub children: Vec<Tree>,
+    #[prost(group, optional, tag="4")]
+    pub group: Option<GroupContents>,
+    #[prost(oneof="TreeOneof", tags="6, 7")]
+    pub oneof: Option<TreeOneof>,
+    #[prost(btree_map="int32, message", tag="8")]
+    pub subtrees: ::std::collections::BTreeMap<i32, Tree>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum TreeOneof {
+    #[prost(message, tag="6")]
+    Right(Box<Tree>),
+    #[prost(int32, tag="7")]
+    Leaf(i32),
+}
+
+#[test]
+fn check_nested_encoded_lengths() {
+    // Nested message lengths are computed once per encode and recorded in a size cache; a tree
+    // mixing every kind of nested field checks that they are taken in the order they were
+    // recorded in.
+    let mut tree = Tree { label: "leaf".to_string(), oneof: Some(TreeOneof::Leaf(1)), ..Tree::default() };
+    for depth in 0..30 {
+        let mut subtrees = ::std::collections::BTreeMap::new();
+        subtrees.insert(depth, Tree { label: "sub".to_string(), ..Tree::default() });
+        tree = Tree {
+            label: "x".repeat(depth as usize * 10),
+            left: if depth % 2 == 0 { Some(Box::new(Tree::default())) } else { None },
+            children: vec![Tree::default(), tree],
+            group: Some(GroupContents { a: depth, b: vec!["b".to_string()] }),
+            oneof: Some(TreeOneof::Right(Box::new(Tree { label: "right".to_string(), ..Tree::default() }))),
+            subtrees,
+        };
+        if depth == 5 {
+            check_message(&tree);
+        }
+    }
+
+    let mut buf = Vec::new();
+    tree.encode(&mut buf).unwrap();
+    assert_eq!(buf.len(), tree.encoded_len());
+    assert_eq!(tree, Tree::decode(&buf).unwrap());
+
+    let mut raw = Vec::new();
+    tree.encode_raw(&mut raw);
+    assert_eq!(buf, raw);
+
+    // The deterministic encoding also writes the default key of the innermost subtree entry.
+    let mut deterministic = Vec::new();
+    tree.encode_deterministic(&mut deterministic).unwrap();
+    assert!(deterministic.len() > buf.len());
+    assert_eq!(tree, Tree::decode(&deterministic).unwrap());
+
+    let mut delimited = Vec::new();
+    tree.encode_length_delimited(&mut delimited).unwrap();
+    assert_eq!(tree, Tree::decode_length_delimited(&delimited).unwrap());
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Deterministic {
+    #[prost(oneof="DeterministicOneof", tags="1, 4")]
+    pub oneof: Option<DeterministicOneof>,
+    #[prost(int32, tag="2")]
+    pub number: i32,
+    #[prost(hash_map="string, int32", tag="3")]
+    pub counts: ::std::collections::HashMap<String, i32>,
+    #[prost(hash_map="int32, message", tag="5")]
+    pub children: ::std::collections::HashMap<i32, Deterministic>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum DeterministicOneof {
+    #[prost(string, tag="1")]
+    Name(String),
+    #[prost(int64, tag="4")]
+    Id(i64),
+}
+
+#[test]
+fn check_deterministic_encoding() {
+    let mut child = Deterministic::default();
+    child.counts.insert("z".to_string(), 1);
+    child.counts.insert("y".to_string(), 2);
+    let mut msg = Deterministic {
+        oneof: Some(DeterministicOneof::Id(7)),
+        number: 150,
+        ..Deterministic::default()
+    };
+    msg.counts.insert("b".to_string(), 2);
+    msg.counts.insert("c".to_string(), 3);
+    msg.counts.insert("a".to_string(), 1);
+    msg.children.insert(2, child);
+    msg.children.insert(1, Deterministic { number: 5, ..Deterministic::default() });
+
+    // Fields are in tag order, with the oneof at the tag of its active variant, and map entries
+    // are in key order.
+    let expected: &[u8] = &[
+        0x10, 0x96, 0x01,
+        0x1a, 0x05, 0x0a, 0x01, b'a', 0x10, 0x01,
+        0x1a, 0x05, 0x0a, 0x01, b'b', 0x10, 0x02,
+        0x1a, 0x05, 0x0a, 0x01, b'c', 0x10, 0x03,
+        0x20, 0x07,
+        0x2a, 0x06, 0x08, 0x01, 0x12, 0x02, 0x10, 0x05,
+        0x2a, 0x12, 0x08, 0x02, 0x12, 0x0e,
+            0x1a, 0x05, 0x0a, 0x01, b'y', 0x10, 0x02,
+            0x1a, 0x05, 0x0a, 0x01, b'z', 0x10, 0x01,
+    ];
+    let mut buf = Vec::new();
+    msg.encode_deterministic(&mut buf).unwrap();
+    assert_eq!(expected, &buf[..]);
+    assert_eq!(buf.len(), msg.encoded_len());
+    assert_eq!(msg, Deterministic::decode(&buf).unwrap());
+
+    // The non-deterministic encoding only differs in the order of map entries.
+    let mut unordered = Vec::new();
+    msg.encode(&mut unordered).unwrap();
+    assert_eq!(&expected[..3], &unordered[..3]);
+    assert_eq!(&expected[24..26], &unordered[24..26]);
+
+    msg.oneof = Some(DeterministicOneof::Name("n".to_string()));
+    buf.clear();
+    msg.encode_deterministic(&mut buf).unwrap();
+    assert_eq!(&[0x0a, 0x01, b'n'], &buf[..3]);
+    assert_eq!(&expected[..24], &buf[3..27]);
+    assert_eq!(&expected[26..], &buf[27..]);
+
+    // Maps with the same entries encode identically, whatever their insertion order.
+    let mut forward = Deterministic::default();
+    let mut backward = Deterministic::default();
+    for i in 0..100 {
+        forward.counts.insert(i.to_string(), i);
+        backward.counts.insert((99 - i).to_string(), 99 - i);
+    }
+    let mut forward_buf = Vec::new();
+    let mut backward_buf = Vec::new();
+    forward.encode_deterministic(&mut forward_buf).unwrap();
+    backward.encode_deterministic(&mut backward_buf).unwrap();
+    assert_eq!(forward_buf, backward_buf);
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GoldenInner {
+    #[prost(string, tag="1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Golden {
+    #[prost(int32, tag="1")]
+    pub id: i32,
+    #[prost(hash_map="string, int32", tag="2")]
+    pub counts: ::std::collections::HashMap<String, i32>,
+    #[prost(btree_map="int32, message", tag="3")]
+    pub children: ::std::collections::BTreeMap<i32, GoldenInner>,
+}
+
+#[test]
+fn check_deterministic_golden() {
+    let mut msg = Golden { id: 1, ..Golden::default() };
+    msg.counts.insert("b".to_string(), 2);
+    msg.counts.insert("".to_string(), 5);
+    msg.counts.insert("a".to_string(), 0);
+    msg.children.insert(3, GoldenInner { name: "x".to_string() });
+    msg.children.insert(-1, GoldenInner::default());
+
+    // The deterministic serialization of the C++ and Java implementations: map entries are
+    // sorted by key, and each entry has both its key and its value, even when they are defaults.
+    let expected: &[u8] = &[
+        0x08, 0x01,
+        0x12, 0x04, 0x0a, 0x00, 0x10, 0x05,
+        0x12, 0x05, 0x0a, 0x01, b'a', 0x10, 0x00,
+        0x12, 0x05, 0x0a, 0x01, b'b', 0x10, 0x02,
+        0x1a, 0x0d, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x12, 0x00,
+        0x1a, 0x07, 0x08, 0x03, 0x12, 0x03, 0x0a, 0x01, b'x',
+    ];
+    let mut buf = Vec::new();
+    msg.encode_deterministic(&mut buf).unwrap();
+    assert_eq!(expected, &buf[..]);
+    assert_eq!(msg, Golden::decode(&buf).unwrap());
+
+    // The regular encoding leaves out default keys and values.
+    let mut regular = Vec::new();
+    msg.encode(&mut regular).unwrap();
+    assert_eq!(regular.len(), msg.encoded_len());
+    assert_eq!(regular.len(), expected.len() - 6);
+    assert_eq!(msg, Golden::decode(&regular).unwrap());
+}
+
+#[test]
+fn check_borrowed_deterministic_encoding() {
+    use prost::BorrowedMessage;
+
+    let mut forward = Basic::default();
+    let mut backward = Basic::default();
+    for i in 0..100 {
+        forward.string_map.insert(i.to_string(), String::new());
+        backward.string_map.insert((99 - i).to_string(), String::new());
+    }
+    let mut forward_buf = Vec::new();
+    let mut backward_buf = Vec::new();
+    BorrowedCompound { owned_message: vec![forward.clone()], ..BorrowedCompound::default() }
+        .encode_deterministic(&mut forward_buf).unwrap();
+    BorrowedCompound { owned_message: vec![backward], ..BorrowedCompound::default() }
+        .encode_deterministic(&mut backward_buf).unwrap();
+    assert_eq!(forward_buf, backward_buf);
+
+    // Borrowed messages encode nested owned messages as the owned messages do.
+    let mut owned_buf = Vec::new();
+    forward.encode_deterministic(&mut owned_buf).unwrap();
+    assert!(forward_buf.windows(owned_buf.len()).any(|window| window == &owned_buf[..]));
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RequiredInner {
+    #[prost(int32, required, presence, tag="1")]
+    pub id: Option<i32>,
+    #[prost(int32, optional, tag="2")]
+    pub count: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RequiredOuter {
+    #[prost(message, required, presence, tag="1")]
+    pub inner: Option<RequiredInner>,
+    #[prost(message, repeated, tag="2")]
+    pub others: Vec<RequiredInner>,
+    #[prost(bool, required, presence, tag="3")]
+    pub flag: Option<bool>,
+    #[prost(map="int32, message", tag="4")]
+    pub by_id: ::std::collections::HashMap<i32, RequiredInner>,
+    #[prost(oneof="RequiredOneof", tags="5, 6")]
+    pub choice: Option<RequiredOneof>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum RequiredOneof {
+    #[prost(message, tag="5")]
+    Inner(RequiredInner),
+    #[prost(int32, tag="6")]
+    Id(i32),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RequiredPlain {
+    #[prost(int32, required, tag="1")]
+    pub id: i32,
+    #[prost(message, required, tag="2")]
+    pub inner: RequiredInner,
+}
+
+#[test]
+fn check_required_fields() {
+    let mut options = DecodeOptions::new();
+    options.check_required(true);
+
+    let inner = RequiredInner { id: Some(1), count: None };
+    let msg = RequiredOuter {
+        inner: Some(inner.clone()),
+        others: vec![inner.clone()],
+        flag: Some(false),
+        choice: Some(RequiredOneof::Id(0)),
+        ..RequiredOuter::default()
+    };
+    assert!(msg.is_initialized());
+    let mut buf = Vec::new();
+    msg.encode_checked(&mut buf).unwrap();
+    assert_eq!(msg, RequiredOuter::decode_with_options(&buf, &options).unwrap());
+
+    // The fields of a message which is split across several records are merged before the
+    // message is checked.
+    let buf = [0x0a, 0x02, 0x08, 0x01, 0x0a, 0x02, 0x10, 0x02, 0x18, 0x01];
+    let decoded = RequiredOuter::decode_with_options(&buf[..], &options).unwrap();
+    assert_eq!(Some(RequiredInner { id: Some(1), count: Some(2) }), decoded.inner);
+
+    // Merging in to a message checks the merged message, not the merged buffer.
+    let mut merged = inner.clone();
+    merged.merge_with_options(&[0x10, 0x02][..], &options).unwrap();
+    assert_eq!(RequiredInner { id: Some(1), count: Some(2) }, merged);
+    let error = RequiredInner::decode_with_options(&[0x10, 0x02][..], &options).unwrap_err();
+    assert_eq!(DecodeErrorKind::MissingRequiredField, error.kind());
+
+    let error = RequiredOuter::decode_with_options(&[0x18, 0x01][..], &options).unwrap_err();
+    assert_eq!("failed to decode Protobuf message: RequiredOuter.inner: missing required field",
+               error.to_string());
+
+    // The second element of `others` is missing its `id`.
+    let buf = [0x0a, 0x02, 0x08, 0x01, 0x12, 0x02, 0x08, 0x02, 0x12, 0x00, 0x18, 0x01];
+    let error = RequiredOuter::decode_with_options(&buf[..], &options).unwrap_err();
+    assert_eq!("failed to decode Protobuf message: RequiredInner.id: RequiredOuter.others: \
+                missing required field",
+               error.to_string());
+
+    // Missing required fields are left unset unless they are checked.
+    let decoded = RequiredOuter::decode(&buf[..]).unwrap();
+    assert_eq!(None, decoded.others[1].id);
+    assert!(!decoded.is_initialized());
+
+    // Map values and oneof variants are checked too.
+    let mut map = msg.clone();
+    map.by_id.insert(2, RequiredInner::default());
+    assert!(!map.is_initialized());
+    let mut buf = Vec::new();
+    map.encode(&mut buf).unwrap();
+    let error = RequiredOuter::decode_with_options(&buf, &options).unwrap_err();
+    assert_eq!("failed to decode Protobuf message: RequiredInner.id: RequiredOuter.by_id: \
+                missing required field",
+               error.to_string());
+
+    let oneof = RequiredOuter { choice: Some(RequiredOneof::Inner(RequiredInner::default())), ..msg.clone() };
+    assert!(!oneof.is_initialized());
+    let mut buf = Vec::new();
+    oneof.encode(&mut buf).unwrap();
+    let error = RequiredOuter::decode_with_options(&buf, &options).unwrap_err();
+    assert_eq!("failed to decode Protobuf message: RequiredInner.id: RequiredOuter.choice: \
+                missing required field",
+               error.to_string());
+
+    // Uninitialized messages are rejected by `encode_checked`, without writing to the buffer.
+    let mut buf = Vec::new();
+    let error = oneof.encode_checked(&mut buf).unwrap_err();
+    assert!(buf.is_empty());
+    assert!(error.is_missing_required_field());
+    let frame = error.missing_field().unwrap();
+    assert_eq!(("RequiredInner", "id", 1), (frame.message(), frame.field(), frame.tag()));
+    assert_eq!(vec![("RequiredInner", "id", 1), ("RequiredOuter", "choice", 5)],
+               error.stack().iter().map(|frame| (frame.message(), frame.field(), frame.tag()))
+                                   .collect::<Vec<_>>());
+    assert_eq!("failed to encode Protobuf message: RequiredInner.id: RequiredOuter.choice: \
+                missing required field",
+               error.to_string());
+
+    let error = RequiredOuter::default().encode_checked(&mut buf).unwrap_err();
+    assert_eq!(("RequiredOuter", "inner", 1),
+               error.missing_field().map(|frame| (frame.message(), frame.field(), frame.tag())).unwrap());
+    assert_eq!(1, error.stack().len());
+
+    // Plain required fields are always set, and are always encoded, but the messages nested in
+    // them are checked.
+    let plain = RequiredPlain::default();
+    assert!(!plain.is_initialized());
+    let error = RequiredPlain::decode_with_options(&[0x12, 0x00][..], &options).unwrap_err();
+    assert_eq!("failed to decode Protobuf message: RequiredInner.id: RequiredPlain.inner: \
+                missing required field",
+               error.to_string());
+    let plain = RequiredPlain { inner: inner.clone(), ..RequiredPlain::default() };
+    assert!(plain.is_initialized());
+    let mut buf = Vec::new();
+    plain.encode_checked(&mut buf).unwrap();
+    assert_eq!(&[0x08, 0x00, 0x12, 0x02, 0x08, 0x01][..], &buf[..]);
+    assert_eq!(plain, RequiredPlain::decode_with_options(&buf, &options).unwrap());
+}
+
+#[test]
+fn check_decode_errors() {
+    let error = Tree::decode(&[0x0a, 0x03, b'a', b'b'][..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::Truncated, error.kind());
+    assert!(error.is_truncated());
+    assert_eq!(Some(2), error.offset());
+    assert_eq!(1, error.stack().len());
+    assert_eq!(("Tree", "label", 1),
+               (error.stack()[0].message(), error.stack()[0].field(), error.stack()[0].tag()));
+    assert_eq!("failed to decode Protobuf message: Tree.label: buffer underflow", error.to_string());
+
+    let error = Tree::decode(&[0x12, 0x03, 0x0a, 0x01, 0xff][..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::InvalidUtf8, error.kind());
+    assert_eq!(Some(5), error.offset());
+    let stack = error.stack().iter().map(|frame| (frame.field(), frame.tag())).collect::<Vec<_>>();
+    assert_eq!(vec![("label", 1), ("left", 2)], stack);
+
+    let error = Tree::decode(&[0x08, 0x01][..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::WireTypeMismatch, error.kind());
+    assert!(!error.is_truncated());
+
+    let error = Tree::decode(&[0x00][..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::InvalidTag, error.kind());
+
+    // The nested message's field extends past the nested message's length.
+    let error = Tree::decode(&[0x12, 0x02, 0x0a, 0x02, b'a', b'b'][..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::LengthOverflow, error.kind());
+
+    // A varint which is cut short is truncated, but one which is too long is corrupt.
+    let error = Tree::decode(&[0x38, 0x80][..]).unwrap_err();
+    assert!(error.is_truncated());
+    let buf = [0x38, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80];
+    let error = Tree::decode(&buf[..]).unwrap_err();
+    assert_eq!(DecodeErrorKind::InvalidVarint, error.kind());
+}
+
+/// The wire representation of `OpenEnums` and `ClosedEnums`, with raw enumeration values.
+#[derive(Clone, PartialEq, Message)]
+pub struct RawEnums {
+    #[prost(int32, optional, tag="1")]
+    pub plain: Option<i32>,
+    #[prost(int32, repeated, tag="2")]
+    pub repeated: Vec<i32>,
+    #[prost(int32, optional, tag="3")]
+    pub oneof: Option<i32>,
+    #[prost(int32, optional, tag="4")]
+    pub required: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct OpenEnums {
+    #[prost(open_enumeration="BasicEnumeration", tag="1")]
+    pub plain: ::prost::OpenEnum<BasicEnumeration>,
+    #[prost(open_enumeration="BasicEnumeration", repeated, tag="2")]
+    pub repeated: Vec<::prost::OpenEnum<BasicEnumeration>>,
+    #[prost(oneof="OpenEnumsOneof", tags="3")]
+    pub oneof: Option<OpenEnumsOneof>,
+}
+
+#[derive(Clone, Oneof, PartialEq)]
+pub enum OpenEnumsOneof {
+    #[prost(open_enumeration="BasicEnumeration", tag="3")]
+    Enumeration(::prost::OpenEnum<BasicEnumeration>),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClosedEnums {
+    #[prost(closed_enumeration="BasicEnumeration", optional, tag="1")]
+    pub optional: Option<BasicEnumeration>,
+    #[prost(closed_enumeration="BasicEnumeration", repeated, tag="2")]
+    pub repeated: Vec<BasicEnumeration>,
+    #[prost(oneof="ClosedEnumsOneof", tags="3")]
+    pub oneof: Option<ClosedEnumsOneof>,
+    #[prost(closed_enumeration="BasicEnumeration", required, tag="4", default="TWO")]
+    pub required: BasicEnumeration,
+    #[prost(unknown_fields)]
+    pub unknown_fields: ::prost::UnknownFields,
+}
+
+#[derive(Clone, Oneof, PartialEq)]
+pub enum ClosedEnumsOneof {
+    #[prost(closed_enumeration="BasicEnumeration", tag="3")]
+    Enumeration(BasicEnumeration),
+}
+
+#[test]
+fn check_typed_enums() {
+    use prost::OpenEnum;
+
+    let raw = RawEnums {
+        plain: Some(7),
+        repeated: vec![1, 9, 3],
+        oneof: Some(8),
+        required: Some(5),
+    };
+    let mut buf = Vec::new();
+    raw.encode(&mut buf).unwrap();
+
+    // Open enumerations keep unknown values in the field.
+    let open = OpenEnums::decode(&buf).unwrap();
+    assert_eq!(OpenEnum::Unknown(7), open.plain);
+    assert_eq!(Some(7), open.plain.unknown());
+    assert_eq!(vec![OpenEnum::Known(BasicEnumeration::ONE),
+                    OpenEnum::Unknown(9),
+                    OpenEnum::Known(BasicEnumeration::THREE)],
+               open.repeated);
+    assert_eq!(Some(OpenEnumsOneof::Enumeration(OpenEnum::Unknown(8))), open.oneof);
+    check_message(&open);
+
+    let mut roundtrip = Vec::new();
+    open.encode(&mut roundtrip).unwrap();
+    assert_eq!(RawEnums { required: None, ..raw.clone() }, RawEnums::decode(&roundtrip).unwrap());
+
+    // Closed enumerations leave the field unchanged, and keep unknown values as unknown fields.
+    let closed = ClosedEnums::decode(&buf).unwrap();
+    assert_eq!(None, closed.optional);
+    assert_eq!(BasicEnumeration::ZERO, closed.optional());
+    assert_eq!(vec![BasicEnumeration::ONE, BasicEnumeration::THREE], closed.repeated);
+    assert_eq!(None, closed.oneof);
+    assert_eq!(BasicEnumeration::TWO, closed.required);
+    assert_eq!(vec![(1, 7), (2, 9), (3, 8), (4, 5)],
+               closed.unknown_fields.iter().map(|field| match *field.value() {
+                   ::prost::UnknownValue::Varint(value) => (field.tag(), value),
+                   ref other => panic!("unexpected unknown value: {:?}", other),
+               }).collect::<Vec<_>>());
+    check_message(&closed);
+
+    let mut roundtrip = Vec::new();
+    closed.encode(&mut roundtrip).unwrap();
+    assert_eq!(RawEnums { repeated: vec![1, 3, 9], ..raw }, RawEnums::decode(&roundtrip).unwrap());
+
+    // An unknown value doesn't replace the oneof's variant.
+    let buf = [0x18, 0x02, 0x18, 0x08];
+    let closed = ClosedEnums::decode(&buf[..]).unwrap();
+    assert_eq!(Some(ClosedEnumsOneof::Enumeration(BasicEnumeration::TWO)), closed.oneof);
+    let mut roundtrip = Vec::new();
+    closed.encode(&mut roundtrip).unwrap();
+    // The required field is always encoded, before the unknown value.
+    assert_eq!(&[0x18, 0x02, 0x20, 0x02, 0x18, 0x08][..], &roundtrip[..]);
+}
+
+/// An enum with `.proto` value names which differ from its variant names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum NamedEnumeration {
+    #[prost(name="NAMED_UNSPECIFIED")]
+    Unspecified = 0,
+    #[prost(name="NAMED_FIRST", alias="NAMED_ONE")]
+    First = 1,
+    Second = 2,
+}
+
+/// Aliases are associated constants, as generated by `prost-build`.
+#[allow(non_upper_case_globals)]
+impl NamedEnumeration {
+    /// Alias of `First`.
+    pub const One: NamedEnumeration = NamedEnumeration::First;
+}
+
+#[test]
+fn check_enumeration_names() {
+    use std::convert::TryFrom;
+    use prost::{Enumeration, UnknownEnumName, UnknownEnumValue};
+
+    assert_eq!("NAMED_FIRST", NamedEnumeration::First.as_str_name());
+    assert_eq!("Second", NamedEnumeration::Second.as_str_name());
+    assert_eq!("NAMED_UNSPECIFIED", NamedEnumeration::Unspecified.to_string());
+    assert_eq!(Some(NamedEnumeration::First), NamedEnumeration::from_str_name("NAMED_FIRST"));
+    assert_eq!(None, NamedEnumeration::from_str_name("First"));
+    assert_eq!(Some(NamedEnumeration::One), NamedEnumeration::from_str_name("NAMED_ONE"));
+    assert_eq!("NAMED_FIRST", NamedEnumeration::One.as_str_name());
+    assert_eq!(Ok(NamedEnumeration::Second), "Second".parse());
+    assert_eq!(Err(UnknownEnumName::new("NAMED_THIRD")), "NAMED_THIRD".parse::<NamedEnumeration>());
+
+    assert_eq!(Ok(NamedEnumeration::First), NamedEnumeration::try_from(1));
+    assert_eq!(Err(UnknownEnumValue(3)), NamedEnumeration::try_from(3));
+
+    assert_eq!(&[NamedEnumeration::Unspecified, NamedEnumeration::First, NamedEnumeration::Second],
+               NamedEnumeration::VARIANTS);
+    let names = NamedEnumeration::VARIANTS.iter()
+                                          .map(Enumeration::as_str_name)
+                                          .collect::<Vec<_>>();
+    assert_eq!(vec!["NAMED_UNSPECIFIED", "NAMED_FIRST", "Second"], names);
+    for variant in NamedEnumeration::VARIANTS {
+        assert_eq!(Some(*variant), <NamedEnumeration as Enumeration>::from_str_name(variant.as_str_name()));
+    }
+}